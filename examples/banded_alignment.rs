@@ -0,0 +1,101 @@
+//! Compare the banded aligner to the full aligner on the embedded reference sequences.
+//!
+//! Every 250th reference sequence, with some substitutions and a constant region
+//! attached, is numbered with the full aligner and with banded aligners of several
+//! band widths. For the banded aligners the time is compared to the full aligner,
+//! and the accuracy is the fraction of queries numbered the same as with it.
+//!
+//! Run with `cargo run --release --example banded_alignment`.
+use std::time::{Duration, Instant};
+
+use numerotator::imgt::{
+    annotations::Annotation,
+    find_best_reference_sequence_with_mode, new_aligner, new_banded_aligner,
+    numbering::NumberingScheme,
+    query::Query,
+    reference::{initialize_reference_sequences, ReferenceIndex},
+    AlignmentMode, PairwiseAligner,
+};
+
+/// The CH1 of human IgG1, attached to every query to make it long.
+const CONSTANT_REGION: &str = "ASTKGPSVFPLAPSSKSTSGGTAALGCLVKDYFPEPVTVSWNSGALTSGVHTFPAVLQSSGLYSLSSVVTVPSSSLGTQTYICNVNHKPSNTKVDKKVEPKSC";
+
+const BAND_WIDTHS: [usize; 4] = [2, 4, 8, 16];
+
+/// Substitute every 17th residue, so queries are not identical to their reference.
+fn mutate(sequence: &[u8]) -> Vec<u8> {
+    sequence
+        .iter()
+        .enumerate()
+        .map(|(i, residue)| match (i % 17, *residue) {
+            (16, b'A') => b'S',
+            (16, _) => b'A',
+            (_, residue) => residue,
+        })
+        .collect()
+}
+
+/// Number all queries with an aligner, returning how long it took.
+fn number_all<A: PairwiseAligner>(
+    queries: &[Query],
+    ref_seqs: &ReferenceIndex,
+    aligner: &mut A,
+) -> (Vec<Option<Vec<Annotation>>>, Duration) {
+    let scheme = NumberingScheme::default();
+    let start = Instant::now();
+    let numberings = queries
+        .iter()
+        .map(|query| {
+            find_best_reference_sequence_with_mode(
+                query.clone(),
+                ref_seqs,
+                aligner,
+                AlignmentMode::Local,
+            )
+            .ok()?
+            .numbered(&scheme)
+            .ok()
+        })
+        .collect();
+    (numberings, start.elapsed())
+}
+
+fn main() {
+    let ref_seqs = initialize_reference_sequences();
+    let mut names: Vec<_> = ref_seqs.keys().collect();
+    names.sort();
+    let queries: Vec<Query> = names
+        .iter()
+        .step_by(250)
+        .map(|name| {
+            let mut sequence = mutate(&ref_seqs[*name].get_sequence());
+            sequence.extend_from_slice(CONSTANT_REGION.as_bytes());
+            Query::new(name, &sequence)
+        })
+        .collect();
+
+    let (expected, full_time) = number_all(&queries, &ref_seqs, &mut new_aligner());
+    println!(
+        "full aligner: {} queries in {:.2?}, {} numbered",
+        queries.len(),
+        full_time,
+        expected.iter().flatten().count()
+    );
+    println!("band width\ttime\tspeedup\taccuracy");
+    for band_width in BAND_WIDTHS {
+        let (numberings, time) =
+            number_all(&queries, &ref_seqs, &mut new_banded_aligner(band_width));
+        let same = numberings
+            .iter()
+            .zip(&expected)
+            .filter(|(numbering, expected)| numbering == expected)
+            .count();
+        println!(
+            "{}\t{:.2?}\t{:.1}x\t{:.3}",
+            band_width,
+            time,
+            full_time.as_secs_f64() / time.as_secs_f64(),
+            same as f64 / queries.len() as f64
+        );
+    }
+}
@@ -0,0 +1,17 @@
+//! Number a single, hardcoded sequence with the embedded reference sequences.
+//!
+//! Run with `cargo run --release --example number_one`.
+use bio::io::fasta;
+use numerotator::imgt::{number_sequence, reference::initialize_reference_sequences};
+
+const SEQUENCE: &str = "QVQLVQSGAEVKKPGASVKVSCKASGYTFTSYGISWVRQAPGQGLEWMGWISAYNGNTNYAQKLQGRVTMTTDTSTSTAYMELRSLRSDDTAVYYCARMDVWGQGTTVTVSS";
+
+fn main() -> anyhow::Result<()> {
+    let ref_seqs = initialize_reference_sequences();
+    let record = fasta::Record::with_attrs("example", None, SEQUENCE.as_bytes());
+
+    for (position, residue) in number_sequence(&record, &ref_seqs)? {
+        println!("{}\t{}", position, residue as char);
+    }
+    Ok(())
+}
@@ -0,0 +1,2015 @@
+//! The `numerotator` command line, see [`run`].
+use std::{
+    io::Write,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use bio::io::fasta;
+use clap::{
+    builder::PossibleValue, error::ErrorKind, value_parser, CommandFactory, Parser, Subcommand,
+    ValueEnum,
+};
+use thiserror::Error;
+use tracing::{debug, error, info, trace, warn};
+
+use crate::imgt::{
+    airr::{write_airr_header, write_airr_row},
+    align_to_reference,
+    analysis::{
+        compare_v_genes, count_gene_families, cysteines, liabilities, GroupBy, NumberingStats,
+        SortBy, UNKNOWN_CHAIN,
+    },
+    annotations::VRegionAnnotation,
+    batch::{number_batch, BatchErr, BatchOptions, DEFAULT_CAPACITY, DEFAULT_CHUNK_SIZE},
+    chimera::{detect_cdr_graft, detect_chimera},
+    clonotype::cluster_cdr3s,
+    collier::write_collier,
+    columns::{write_columns_header, write_columns_row, Column},
+    consensus::NumberedSequence,
+    conserved_residues::ConservedResidueSpec,
+    encoding::encode_numbering,
+    engine::NumberingEngine,
+    find_best_reference_sequence_with_mode, find_tied_chain_alignments_with_mode,
+    input::{
+        check_duplicate_ids, is_gap, numbered_record, read_records, strip_gaps, DuplicateIdPolicy,
+        GappedQueryMode, InputErr, InputFormat,
+    },
+    junction::find_cdr3_by_motifs,
+    new_banded_aligner,
+    numbering::{
+        check_unique_positions, number_pre_aligned, Cdr3Definition, Cdr3InsertionPolicy,
+        ImgtRegion, NumberingScheme, DEFAULT_MAX_CDR3_LENGTH,
+    },
+    output::{
+        format_position, format_positions, gapped_regions, write_a2m,
+        write_annotations_with_template, write_encoded_numbering, write_gapped_regions,
+        write_germline_pair, write_paratope, CoordinateBase, IdTemplate, InsertionFormat,
+        OutputFormat, WrappingFastaWriter,
+    },
+    profile::{time_stage, Profile, Stage},
+    query::{Query, DEFAULT_N_TERMINAL_MARKER},
+    reference::{
+        build_consensus_reference,
+        functionality::{FunctionalityErr, GeneFunctionality, NonFunctionalPolicy},
+        index::{write_index, IndexReferences},
+        install::{install_references_with, invalid_alignment_ids_with},
+        j_genes,
+        source::{
+            load_j_genes, EmbeddedReferences, FastaReferences, FileReferences, ReferenceSource,
+            ReferenceSourceErr,
+        },
+        stitch_references,
+        stockholm::InsertStates,
+        ReferenceIndex, ReferenceSequence,
+    },
+    with_thread_aligner, AlignmentMode, AmbiguousChainPolicy, NumberingError, PairwiseAligner,
+    RefSeqErr, ReferenceAlignment, Thresholds,
+};
+
+/// The arguments of the command line.
+#[derive(Parser, Debug)]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    // Without a subcommand the sequences are numbered, as before there were subcommands.
+    #[command(flatten)]
+    number: NumberArgs,
+}
+
+#[derive(Subcommand, Debug)]
+// Parsed once, so the size of the number arguments does not matter.
+#[allow(clippy::large_enum_variant)]
+enum Command {
+    /// Number sequences. This is the default when no subcommand is given.
+    Number(NumberArgs),
+    /// Convert a Stockholm file of curated alignments into reference files.
+    Install(InstallArgs),
+    /// Write the ids of the alignments in a Stockholm file that lack the conserved residues.
+    Validate(ValidateArgs),
+    /// Number sequences and write how many were numbered, per chain type and per CDR3 length.
+    Stats(NumberArgs),
+    /// Write the ids of the reference sequences.
+    #[command(alias = "list-refs")]
+    ListReferences(ReferenceArgs),
+    /// Write the reference sequences to a binary index, which loads faster with --index.
+    BuildIndex(BuildIndexArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct ReferenceArgs {
+    #[arg(
+        long,
+        value_parser=value_parser!(PathBuf),
+        help = "Load the reference alignments from this file instead of the embedded ones."
+    )]
+    references: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_parser=value_parser!(PathBuf),
+        conflicts_with = "references",
+        help = "Load the reference sequences from an index written by build-index, which skips parsing and validating them."
+    )]
+    index: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_parser=value_parser!(PathBuf),
+        conflicts_with_all = ["references", "index"],
+        help = "Load ungapped reference sequences from this FASTA file, which are gapped by numbering them with the embedded references."
+    )]
+    reference_fasta: Option<PathBuf>,
+
+    #[arg(
+        long,
+        requires = "references",
+        help = "Fail when the reference alignments file has duplicate ids, instead of warning."
+    )]
+    strict_references: bool,
+
+    #[arg(
+        long,
+        default_value_t = 0,
+        requires = "references",
+        help = "Accept reference anchors (such as the 2nd-CYS) up to this many columns from their IMGT position, with only gaps in between. The anchors are moved onto their IMGT column, so they are numbered by their IMGT position."
+    )]
+    anchor_window: usize,
+
+    #[arg(
+        long,
+        value_name = "RESIDUES",
+        requires = "references",
+        help = "Accept these residues (such as AILMFWYVT) at position 89 of the references and of the sequences numbered with them, instead of the hydrophobic AILMFWYV. The cysteines and tryptophans are checked as before."
+    )]
+    hydrophobic_89: Option<String>,
+
+    #[arg(
+        long,
+        help = "Add references for every pairing of a V and a J gene of the same species and locus, so FR4 is aligned to the J gene of the sequence."
+    )]
+    use_vj: bool,
+
+    #[arg(
+        long,
+        value_parser=value_parser!(PathBuf),
+        requires = "use_vj",
+        help = "Pair the V genes with the J genes of this file, instead of with those of the references. Every line has the id of a J gene (such as Homo_sapiens_IGHJ2*01) and its IMGT-gapped alignment from position 111 to 128."
+    )]
+    j_genes: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Align every sequence to a single consensus of the references instead of finding the best reference, which is faster but less accurate and does not assign genes. Works best with references of a single chain type."
+    )]
+    universal_reference: bool,
+}
+
+impl ReferenceArgs {
+    fn load(&self) -> Result<ReferenceIndex, ReferenceSourceErr> {
+        debug!("Initializing reference sequences.");
+        match (&self.references, &self.index, &self.reference_fasta) {
+            (_, _, Some(path)) => FastaReferences::new(path).load(),
+            (_, Some(path), None) => IndexReferences::new(path).load(),
+            (Some(path), None, None) => {
+                let mut spec = ConservedResidueSpec {
+                    anchor_window: self.anchor_window,
+                    ..Default::default()
+                };
+                if let Some(residues) = &self.hydrophobic_89 {
+                    spec.hydrophobic_89 = residues.to_ascii_uppercase().into_bytes();
+                }
+                let mut source = FileReferences::new(path).with_spec(spec);
+                if self.strict_references {
+                    source = source.strict();
+                }
+                source.load()
+            }
+            (None, None, None) => EmbeddedReferences.load(),
+        }
+        .and_then(|ref_seqs| {
+            if !self.use_vj {
+                return Ok(ref_seqs);
+            }
+            let j_genes = match &self.j_genes {
+                Some(path) => load_j_genes(path)?,
+                None => j_genes(&ref_seqs),
+            };
+            Ok(stitch_references(&ref_seqs, &j_genes))
+        })
+        .and_then(|ref_seqs| {
+            if !self.universal_reference {
+                return Ok(ref_seqs);
+            }
+            let consensus = build_consensus_reference(&ref_seqs)
+                .map_err(|_error| ReferenceSourceErr::InvalidConsensus)?;
+            Ok(ReferenceIndex::from([(consensus.name.clone(), consensus)]))
+        })
+    }
+}
+
+#[derive(clap::Args, Debug)]
+struct InstallArgs {
+    #[arg(value_parser=value_parser!(PathBuf))]
+    stockholm_file: PathBuf,
+
+    #[arg(value_parser=value_parser!(PathBuf))]
+    output_fasta_file: PathBuf,
+
+    #[arg(value_parser=value_parser!(PathBuf))]
+    output_alignments_file: PathBuf,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = InsertStates::Reference,
+        help = "Recognise insert columns by the #=GC RF line only, or without one also by columns of only lowercase residues and '.'."
+    )]
+    insert_states: InsertStates,
+}
+
+#[derive(clap::Args, Debug)]
+struct BuildIndexArgs {
+    #[arg(value_parser=value_parser!(PathBuf))]
+    output: PathBuf,
+
+    #[command(flatten)]
+    references: ReferenceArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct ValidateArgs {
+    #[arg(value_parser=value_parser!(PathBuf))]
+    stockholm_file: PathBuf,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = InsertStates::Reference,
+        help = "Recognise insert columns by the #=GC RF line only, or without one also by columns of only lowercase residues and '.'."
+    )]
+    insert_states: InsertStates,
+}
+
+#[derive(clap::Args, Debug)]
+struct NumberArgs {
+    #[arg(index = 1, num_args=..)]
+    sequences: Vec<String>,
+    #[arg(short, long, value_parser=value_parser!(PathBuf))]
+    sequences_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = InputFormat::Fasta,
+        help = "Read the sequences file as FASTA, or as one sequence per line with the (zero based) line number as id, like sequences on the command line."
+    )]
+    input_format: InputFormat,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Only number the first N sequences, from the command line and then the sequences file, without reading the rest of the file."
+    )]
+    sample: Option<usize>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = DuplicateIdPolicy::Rename,
+        help = "Give sequences with an id that occurred before a unique suffix (_2, _3, ...), fail on them, or keep their id. Sequences from the command line are numbered from 0, which can clash with the ids in the sequences file."
+    )]
+    on_duplicate_id: DuplicateIdPolicy,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = GappedQueryMode::Strip,
+        help = "Remove gaps (- or .) from sequences before aligning them, or number sequences that are aligned to the IMGT positions by their columns. Pre-aligned sequences only get their numbering written."
+    )]
+    gapped_queries: GappedQueryMode,
+
+    #[command(flatten)]
+    references: ReferenceArgs,
+
+    #[arg(
+        long,
+        help = "Reject sequences whose best alignment score per aligned column is below this (at most 1). Must pass together with --min-identity."
+    )]
+    min_score: Option<f64>,
+
+    #[arg(
+        long,
+        help = "Reject sequences whose identity to the best reference (0 to 1) is below this. Must pass together with --min-score."
+    )]
+    min_identity: Option<f64>,
+
+    #[arg(
+        long,
+        conflicts_with = "threads",
+        help = "Align every sequence to the reference with this name, instead of the best reference."
+    )]
+    force_reference: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = AlignmentMode::Local,
+        help = "Align sequences locally, or all of the reference (semiglobal) or of both (global), which keeps divergent framework ends."
+    )]
+    alignment_mode: AlignmentMode,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["threads", "force_reference"],
+        help = "Align within this many residues of exact matches with the reference, which is much faster for long sequences but can miss the best alignment."
+    )]
+    band_width: Option<usize>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = AmbiguousChainPolicy::Best,
+        conflicts_with_all = ["threads", "force_reference"],
+        help = "For sequences that match several chain types (such as IGH and IGK) equally well, number with the first best reference, number with the best reference of each chain type (adding chain=IGH to the flags) or fail."
+    )]
+    on_ambiguous_chain: AmbiguousChainPolicy,
+
+    #[arg(
+        long,
+        value_parser=value_parser!(PathBuf),
+        conflicts_with = "threads",
+        help = "File of genes (such as IGHV1-18*01 or IGHV1-18) followed by their functionality (F, ORF or P), to warn about sequences assigned to an ORF or pseudogene."
+    )]
+    gene_functionality: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = NonFunctionalPolicy::Warn,
+        requires = "gene_functionality",
+        help = "Warn about sequences assigned to an ORF or pseudogene, or reject them."
+    )]
+    on_nonfunctional: NonFunctionalPolicy,
+
+    #[arg(short, long, help = "Annotate the regions as well.")]
+    annotate_regions: bool,
+
+    #[arg(
+        short,
+        long,
+        help = "Do not number the sequences. (Useful in combination with --annotate-regions)"
+    )]
+    no_number: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = Cdr3Definition::Imgt,
+        help = "Number the CDR3-IMGT (105-117) or the JUNCTION (104-118)."
+    )]
+    cdr3_definition: Cdr3Definition,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = Cdr3InsertionPolicy::Imgt,
+        help = "Place an odd CDR3 insertion on 112 (IMGT) or on 111 (ANARCI)."
+    )]
+    cdr3_insertion_policy: Cdr3InsertionPolicy,
+
+    #[arg(
+        long,
+        default_value_t = DEFAULT_MAX_CDR3_LENGTH,
+        help = "Fail on a CDR3-IMGT longer than this, which is likely an alignment artifact. Raise it (to about 70) to number the ultralong CDR3s of bovine heavy chains."
+    )]
+    max_cdr3_length: usize,
+
+    #[arg(
+        long,
+        help = "Reject sequences of which a conserved residue (23, 41, 89, 104 or 118) is not the canonical one, instead of numbering them by the aligned residue."
+    )]
+    strict_anchors: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        help = "Only number these regions (such as CDR3,FR4), so the other regions cannot make a sequence fail."
+    )]
+    regions: Option<Vec<ImgtRegion>>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["cysteines", "liabilities", "run_length", "gapped_regions"],
+        help = "Number by the columns of the alignment of the matched reference instead of IMGT positions."
+    )]
+    germline_frame: bool,
+
+    #[arg(
+        long,
+        help = "Write only the numbered cysteines, flagged as canonical (23 and 104) or non-canonical."
+    )]
+    cysteines: bool,
+
+    #[arg(
+        long,
+        conflicts_with = "cysteines",
+        help = "Write only the N-glycosylation (N-X-S/T) and deamidation (NG, NS) motifs, flagged with their kind and the region of their asparagine (as liability=deamidation,region=CDR2)."
+    )]
+    liabilities: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["germline_frame", "cysteines", "liabilities", "gapped_regions"],
+        help = "Write the numbering run-length encoded relative to the matched reference, see EncodedNumbering."
+    )]
+    run_length: bool,
+
+    #[arg(
+        long,
+        help = "Write the CDR1, CDR2 and CDR3 joined together (the paratope) instead of the numbering."
+    )]
+    paratope: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "threads", "annotate_regions", "no_number", "germline_frame", "cysteines", "liabilities",
+            "run_length", "paratope", "gapped_regions", "validate",
+        ],
+        help = "Write only the CDR3 (or JUNCTION), found by the motifs around the 2nd-CYS and J-TRP/J-PHE. Sequences with missing or ambiguous motifs, or all sequences when --min-score or --min-identity is set, are numbered instead. The flag cdr3_by=motifs or cdr3_by=numbering tells which."
+    )]
+    cdr3_only: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        conflicts_with_all = [
+            "threads", "cdr3_only", "annotate_regions", "germline_frame", "cysteines", "liabilities",
+            "run_length", "paratope", "gapped_regions",
+        ],
+        help = "Write the number of sequences per V gene family (such as IGHV1) of the matched reference instead of the numbering."
+    )]
+    group_by: Option<GroupBy>,
+
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        conflicts_with_all = [
+            "threads", "cdr3_only", "group_by", "annotate_regions", "germline_frame", "cysteines",
+            "liabilities", "run_length", "paratope", "gapped_regions",
+        ],
+        help = "Write a table with a header and a row per sequence with these columns (separated by commas) instead of the numbering."
+    )]
+    columns: Option<Vec<Column>>,
+
+    #[arg(
+        long,
+        value_name = "THRESHOLD",
+        conflicts_with_all = [
+            "threads", "cdr3_only", "group_by", "columns", "annotate_regions", "germline_frame",
+            "cysteines", "liabilities", "run_length", "paratope", "gapped_regions",
+        ],
+        help = "Write the CDR3 (or JUNCTION) of every sequence with the clonotype it belongs to (as clonotype=0), grouping CDR3s within this Hamming (same length) or Levenshtein distance of each other."
+    )]
+    cluster_cdr3: Option<u64>,
+
+    #[arg(
+        long,
+        value_enum,
+        conflicts_with_all = ["threads", "cdr3_only", "group_by", "columns", "cluster_cdr3"],
+        help = "Write the numbering sorted by the V gene of the matched reference, keeping the order of the input per gene. This keeps the output of all sequences in memory until the end."
+    )]
+    sort_by: Option<SortBy>,
+
+    #[arg(
+        long,
+        default_value_t = '\t',
+        requires = "columns",
+        help = "Separate the columns of --columns by this character."
+    )]
+    delimiter: char,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["germline_frame", "run_length", "cysteines", "liabilities"],
+        help = "Write the contiguous and IMGT-gapped sequence of every region instead of the numbering."
+    )]
+    gapped_regions: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        conflicts_with_all = ["gapped_regions", "germline_frame", "run_length"],
+        help = "Write insertions as 111.1 (dotted, the default) or as 111A (letter)."
+    )]
+    insertion_format: Option<InsertionFormat>,
+
+    #[arg(
+        long,
+        default_value = "{name}_{id}",
+        help = "Id of FASTA records of annotations, with the placeholders {id} (of the sequence), {name} (of the annotation), {region} (such as FR3-IMGT for position 104), {start} and {end}."
+    )]
+    id_template: IdTemplate,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = CoordinateBase::Zero,
+        help = "Write the start and end of annotations, regions and alignment offsets zero based and end exclusive (0) or one based and end inclusive (1)."
+    )]
+    coordinate_base: CoordinateBase,
+
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "Wrap the sequences of FASTA output (including failures) after this many residues, or not at all for 0."
+    )]
+    fasta_width: usize,
+
+    #[arg(
+        long,
+        help = "Number sequences that end before the J-TRP/J-PHE (118) up to their CDR3, leaving FR4 empty."
+    )]
+    allow_missing_fr4: bool,
+
+    #[arg(
+        long,
+        help = "Number the residues of a truncated FR1 that precede the first aligned residue by the germline positions before it, such as 5 and 6 for a read that starts at position 5 with two mutations."
+    )]
+    extend_truncated_fr1: bool,
+
+    #[arg(
+        long,
+        default_value_t = DEFAULT_N_TERMINAL_MARKER as char,
+        value_parser = parse_n_terminal_marker,
+        help = "Marker for a modified (pyroglutamate) first residue, which is aligned as a glutamine and flagged as modified_n_terminus. A lowercase first residue is accepted as well."
+    )]
+    n_terminal_marker: char,
+
+    #[arg(
+        long,
+        help = "Add the aligned ranges of the reference and the query (in the --coordinate-base) to the flags."
+    )]
+    alignment_offsets: bool,
+
+    #[arg(
+        long,
+        conflicts_with = "threads",
+        help = "Add the IMGT positions without a residue, between the first and last numbered position, to the flags (as deleted=10,73)."
+    )]
+    deleted_positions: bool,
+
+    #[arg(
+        long,
+        conflicts_with = "threads",
+        help = "Add the framework positions that the matched germline lacks, and that are therefore never numbered, to the flags (as germline_gaps=10,73)."
+    )]
+    germline_gaps: bool,
+
+    #[arg(
+        long,
+        conflicts_with = "threads",
+        help = "Realign both halves of every sequence and flag it as a chimera when they match different V genes much better, adding the genes to the flags (as chimera_n=IGHV1-18*01,chimera_c=IGHV3-23*01)."
+    )]
+    detect_chimeras: bool,
+
+    #[arg(
+        long,
+        conflicts_with = "threads",
+        help = "Realign the frameworks and the CDR1 and CDR2 of every sequence and flag it as a CDR graft when the CDRs match another V gene much better, adding the genes to the flags (as graft_fr=IGHV1-18*01,graft_cdr=IGHV3-23*01)."
+    )]
+    detect_cdr_grafts: bool,
+
+    #[arg(
+        long,
+        value_name = "ID",
+        num_args = 0..=1,
+        require_equals = true,
+        conflicts_with_all = ["threads", "cdr3_only", "group_by", "columns", "cluster_cdr3"],
+        help = "Write the alignment of every sequence (or only of the sequence with this id, as --show-alignment=ID) to its reference to stderr, with | between identical residues."
+    )]
+    show_alignment: Option<Option<String>>,
+
+    #[arg(
+        long,
+        default_value_t = 100,
+        requires = "show_alignment",
+        help = "Wrap the alignments of --show-alignment after this many columns."
+    )]
+    alignment_width: usize,
+
+    #[arg(
+        short,
+        long,
+        value_enum,
+        default_value_t = OutputFormat::Fasta,
+        help = "Write FASTA records, TSV lines, or one AIRR rearrangement line, A2M record (in the IMGT column frame) or germline and query pair of FASTA records (in the same IMGT frame) per sequence, or a TSV line per residue with its region and strand or loop of the IMGT collier de perles, instead of the numbering. The formats with a record per sequence or residue cannot be used with the options that write other annotations, such as --annotate-regions."
+    )]
+    format: OutputFormat,
+
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "annotate_regions", "no_number", "germline_frame", "cysteines", "liabilities", "paratope",
+            "gapped_regions", "alignment_offsets", "run_length", "only_productive", "validate",
+        ],
+        help = "Number on this many threads (0 for all cores), streaming the sequences in chunks. Only writes the numbering."
+    )]
+    threads: Option<usize>,
+
+    #[arg(
+        long,
+        default_value_t = DEFAULT_CHUNK_SIZE,
+        requires = "threads",
+        help = "Number of sequences that are numbered together with --threads."
+    )]
+    chunk_size: usize,
+
+    #[arg(
+        long,
+        default_value_t = DEFAULT_CAPACITY,
+        requires = "threads",
+        help = "Number of chunks that can wait to be numbered or written with --threads, which bounds the memory use."
+    )]
+    channel_capacity: usize,
+
+    #[arg(
+        long,
+        requires = "threads",
+        help = "Write the time spent loading references, aligning, numbering and writing output to stderr at the end, summed over the threads."
+    )]
+    profile: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 10,
+        requires = "profile",
+        help = "Number of the slowest sequences to write with --profile, with the seconds each took to number."
+    )]
+    slowest: usize,
+
+    #[arg(
+        long,
+        help = "Only write the records of sequences that could not be numbered, with the reason."
+    )]
+    only_failures: bool,
+
+    #[arg(
+        long,
+        help = "Fail on a numbering that gives the same position to more than one residue."
+    )]
+    validate: bool,
+
+    #[arg(
+        long,
+        help = "Skip sequences that are not productive, complete V-domains: in frame without stop codons, with all conserved residues and FR1 through FR4."
+    )]
+    only_productive: bool,
+
+    #[arg(
+        long,
+        value_parser=value_parser!(PathBuf),
+        help = "Write the records of sequences that could not be numbered to this file."
+    )]
+    failures_file: Option<PathBuf>,
+}
+
+/// Parse `--n-terminal-marker`, which has to be a single ASCII character that is not a gap.
+fn parse_n_terminal_marker(marker: &str) -> Result<char, String> {
+    let mut chars = marker.chars();
+    match (chars.next(), chars.next()) {
+        (Some(marker), None) if marker.is_ascii_graphic() && !is_gap(marker as u8) => Ok(marker),
+        _ => Err(format!(
+            "'{marker}' is not a single ASCII character other than a gap"
+        )),
+    }
+}
+
+/// Error that stops a run of the command line, see [`run`].
+#[derive(Debug, Error)]
+pub enum RunError {
+    /// Arguments that cannot be used together, which clap reports with the usage.
+    #[error(transparent)]
+    Usage(#[from] clap::Error),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error("Could not load reference sequences: {0}")]
+    References(#[from] ReferenceSourceErr),
+
+    #[error("Could not read sequences: {0}")]
+    Input(#[from] InputErr),
+
+    #[error(transparent)]
+    Functionality(#[from] FunctionalityErr),
+
+    #[error(transparent)]
+    Batch(#[from] BatchErr),
+
+    #[error("{0} alignments without the conserved residues.")]
+    InvalidAlignments(usize),
+}
+
+/// How many sequences a run of the command line read, and how many of them could not be numbered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RunStats {
+    pub sequences: usize,
+    /// Sequences that could not be numbered, once per reference with `--on-ambiguous-chain both`.
+    pub failures: usize,
+}
+
+/// The output of a run, shared by everything that writes to it.
+#[derive(Clone)]
+struct SharedWriter<'a>(Arc<Mutex<Box<dyn Write + Send + 'a>>>);
+
+impl<'a> SharedWriter<'a> {
+    fn new(writer: impl Write + Send + 'a) -> Self {
+        Self(Arc::new(Mutex::new(Box::new(writer))))
+    }
+}
+
+impl Write for SharedWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .lock()
+            .expect("Writer should not be poisoned.")
+            .write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0
+            .lock()
+            .expect("Writer should not be poisoned.")
+            .flush()
+    }
+}
+
+/// Run the command line, writing its output to `out` and the profile (see `--profile`) to `err`.
+///
+/// Logs go through `tracing`, so the caller sets up a subscriber for them.
+/// Failing to write the output of a sequence still panics.
+pub fn run<'a>(
+    cli: Cli,
+    out: impl Write + Send + 'a,
+    mut err: impl Write,
+) -> Result<RunStats, RunError> {
+    let out = SharedWriter::new(out);
+    let run_stats = match cli.command.unwrap_or(Command::Number(cli.number)) {
+        Command::Number(args) => number(args, false, out.clone(), &mut err)?,
+        Command::Stats(args) => number(args, true, out.clone(), &mut err)?,
+        Command::Install(args) => {
+            install(args)?;
+            RunStats::default()
+        }
+        Command::Validate(args) => {
+            validate(args, out.clone())?;
+            RunStats::default()
+        }
+        Command::ListReferences(args) => {
+            list_references(args, out.clone())?;
+            RunStats::default()
+        }
+        Command::BuildIndex(args) => {
+            build_index(args)?;
+            RunStats::default()
+        }
+    };
+    out.clone().flush()?;
+    Ok(run_stats)
+}
+
+fn install(args: InstallArgs) -> Result<(), RunError> {
+    info!(
+        input_file = args.stockholm_file.as_os_str().to_str(),
+        "Reading input file"
+    );
+    let alignment_data = std::fs::read_to_string(args.stockholm_file)?;
+    let installed = install_references_with(&alignment_data, args.insert_states);
+    debug!(
+        n_valid_alignments = installed.n_valid,
+        n_valid_lines = installed.n_lines,
+        "Validated lines."
+    );
+
+    std::fs::write(args.output_alignments_file, installed.alignments)?;
+    std::fs::write(args.output_fasta_file, installed.fasta)?;
+    Ok(())
+}
+
+fn validate(args: ValidateArgs, mut out: SharedWriter) -> Result<(), RunError> {
+    let alignment_data = std::fs::read_to_string(args.stockholm_file)?;
+    let invalid = invalid_alignment_ids_with(&alignment_data, args.insert_states);
+    for id in &invalid {
+        writeln!(out, "{}", id)?;
+    }
+    if !invalid.is_empty() {
+        return Err(RunError::InvalidAlignments(invalid.len()));
+    }
+    Ok(())
+}
+
+fn build_index(args: BuildIndexArgs) -> Result<(), RunError> {
+    let ref_seqs = args.references.load()?;
+    let file = std::fs::File::create(&args.output)?;
+    write_index(&ref_seqs, std::io::BufWriter::new(file))?;
+    info!(n_references = ref_seqs.len(), "Wrote reference index.");
+    Ok(())
+}
+
+fn list_references(args: ReferenceArgs, mut out: SharedWriter) -> Result<(), RunError> {
+    let mut ids: Vec<_> = args.load()?.into_keys().collect();
+    ids.sort();
+    for id in ids {
+        writeln!(out, "{}", id)?;
+    }
+    Ok(())
+}
+
+/// Number sequences, or only count them with `stats`.
+fn number(
+    args: NumberArgs,
+    stats: bool,
+    out: SharedWriter,
+    err: &mut dyn Write,
+) -> Result<RunStats, RunError> {
+    if args.threads.is_some() && args.format == OutputFormat::Airr {
+        return Err(Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--format airr cannot be used with --threads",
+            )
+            .into());
+    }
+    if !args.format.writes_annotations() {
+        let annotations = [
+            ("--annotate-regions", args.annotate_regions),
+            ("--paratope", args.paratope),
+            ("--gapped-regions", args.gapped_regions),
+            ("--germline-frame", args.germline_frame),
+            ("--run-length", args.run_length),
+            ("--cysteines", args.cysteines),
+            ("--liabilities", args.liabilities),
+            ("--cdr3-only", args.cdr3_only),
+            ("--cluster-cdr3", args.cluster_cdr3.is_some()),
+            (
+                "--gapped-queries pre-aligned",
+                args.gapped_queries == GappedQueryMode::PreAligned,
+            ),
+        ];
+        if let Some((option, _)) = annotations.iter().find(|(_, set)| *set) {
+            return Err(Cli::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    format!(
+                        "--format {} cannot be used with {}",
+                        args.format.to_possible_value().unwrap().get_name(),
+                        option
+                    ),
+                )
+                .into());
+        }
+    }
+    if args.insertion_format.is_some() && !args.format.writes_positions() {
+        return Err(Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                format!(
+                    "--format {} cannot be used with --insertion-format",
+                    args.format.to_possible_value().unwrap().get_name()
+                ),
+            )
+            .into());
+    }
+    if stats
+        && (args.threads.is_some()
+            || args.cdr3_only
+            || args.group_by.is_some()
+            || args.cluster_cdr3.is_some()
+            || args.sort_by.is_some())
+    {
+        return Err(Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "stats cannot be used with --threads, --cdr3-only, --group-by, --cluster-cdr3 or --sort-by",
+            )
+            .into());
+    }
+    if args.gapped_queries == GappedQueryMode::PreAligned
+        && (stats
+            || args.threads.is_some()
+            || args.cdr3_only
+            || args.group_by.is_some()
+            || args.cluster_cdr3.is_some()
+            || args.sort_by.is_some())
+    {
+        return Err(Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--gapped-queries pre-aligned cannot be used with stats, --threads, --cdr3-only, --group-by, --cluster-cdr3 or --sort-by",
+            )
+            .into());
+    }
+    // ASCII, see `parse_n_terminal_marker`.
+    let n_terminal_marker = args.n_terminal_marker as u8;
+    let thresholds = Thresholds {
+        min_score: args.min_score,
+        min_identity: args.min_identity,
+    };
+    let scheme = NumberingScheme {
+        cdr3_definition: args.cdr3_definition,
+        cdr3_insertion_policy: args.cdr3_insertion_policy,
+        allow_missing_fr4: args.allow_missing_fr4,
+        extend_truncated_fr1: args.extend_truncated_fr1,
+        max_cdr3_length: args.max_cdr3_length,
+        strict_anchors: args.strict_anchors,
+        regions: args.regions,
+    };
+
+    let gene_functionality = match &args.gene_functionality {
+        Some(path) => Some(GeneFunctionality::parse(&std::fs::read_to_string(path)?)?),
+        None => None,
+    };
+    let alignment_options = AlignmentOptions {
+        force_reference: args.force_reference.as_deref(),
+        mode: args.alignment_mode,
+        band_width: args.band_width,
+        on_ambiguous_chain: args.on_ambiguous_chain,
+        gene_functionality: gene_functionality.as_ref(),
+        on_nonfunctional: args.on_nonfunctional,
+    };
+
+    info!("Initializing...");
+    let profile = args
+        .profile
+        .then(|| Arc::new(Profile::with_slowest(args.slowest)));
+    let ref_seqs = time_stage(profile.as_deref(), Stage::LoadReferences, || {
+        args.references.load()
+    })?;
+
+    // Records are much nicer to deal with than simple strings, since they carry their own
+    // identifier and description. Now they don't have to be generated at the call site.
+    // It might not be great to be tied to fasta though.
+    debug!("Collecting sequences from command line.");
+    let sequences_from_command_line = args
+        .sequences
+        .into_iter()
+        .enumerate()
+        .map(|(i, seq)| numbered_record(i, "the command line", &seq));
+
+    // Reading stops at the first error, which is returned once the records before it are numbered.
+    let mut read_error = None;
+    let sequences_from_sequence_file = match args.sequences_file {
+        Some(path) => {
+            info!("Reading input sequences file.");
+            let records = read_records(std::fs::File::open(path)?, args.input_format);
+            Some(records.map_while(|record| record.map_err(|error| read_error = Some(error)).ok()))
+        }
+        None => None,
+    };
+
+    let fasta_width = args.fasta_width;
+    let failures_writer: Option<Box<dyn Write + Send>> = if args.only_failures {
+        Some(Box::new(WrappingFastaWriter::new(out.clone(), fasta_width)))
+    } else {
+        match args.failures_file {
+            Some(path) => Some(Box::new(WrappingFastaWriter::new(
+                std::fs::File::create(path)?,
+                fasta_width,
+            ))),
+            None => None,
+        }
+    };
+    let mut failures = Failures {
+        writer: failures_writer.map(fasta::Writer::new),
+        count: 0,
+    };
+    // TSV is never wrapped, as its lines are not FASTA sequences.
+    let output_width = match args.format {
+        OutputFormat::Tsv | OutputFormat::Airr | OutputFormat::Collier => 0,
+        OutputFormat::Fasta | OutputFormat::A2m | OutputFormat::Pair => fasta_width,
+    };
+    let output = || WrappingFastaWriter::new(out.clone(), output_width);
+    let write_numbering = !args.only_failures;
+    let mut duplicate_error = None;
+    let mut sequences = 0;
+    let records = check_duplicate_ids(
+        sequences_from_command_line
+            .chain(sequences_from_sequence_file.into_iter().flatten())
+            .take(args.sample.unwrap_or(usize::MAX)),
+        args.on_duplicate_id,
+    )
+    .map_while(|record| record.map_err(|error| duplicate_error = Some(error)).ok())
+    .inspect(|_record| sequences += 1);
+
+    if args.gapped_queries == GappedQueryMode::PreAligned {
+        for record in records {
+            match number_pre_aligned(&record) {
+                Ok(numbered) if write_numbering => write_annotations_with_template(
+                    &numbered.record,
+                    &numbered.numbering,
+                    &[],
+                    args.format,
+                    &args.id_template,
+                    args.coordinate_base,
+                    output(),
+                )
+                .expect("Could not write numbering."),
+                Ok(_numbered) => {}
+                Err(error) => failures.report(&Failure {
+                    record,
+                    error: error.into(),
+                }),
+            }
+        }
+        return finish_numbering(read_error, duplicate_error, sequences, &failures);
+    }
+    let records = records.map(|record| match strip_gaps(&record) {
+        Some(stripped) => {
+            warn!(
+                sequence = record.id(),
+                "Removed the gaps from the sequence, pass --gapped-queries pre-aligned to number it by its columns instead."
+            );
+            stripped
+        }
+        None => record,
+    });
+
+    if args.cdr3_only {
+        records
+            .flat_map(|record| {
+                // The thresholds need an alignment, which the motifs skip.
+                let by_motifs = (!thresholds.is_set())
+                    .then(|| find_cdr3_by_motifs(record.seq(), &scheme))
+                    .flatten();
+                if let Some(cdr3) = by_motifs {
+                    if write_numbering {
+                        write_annotations_with_template(
+                            &record,
+                            &[cdr3],
+                            &["cdr3_by=motifs"],
+                            args.format,
+                            &args.id_template,
+                            args.coordinate_base,
+                            output(),
+                        )
+                        .expect("Could not write CDR3.");
+                    }
+                    return vec![];
+                }
+                debug!(
+                    sequence = record.id(),
+                    "No unambiguous CDR3 motifs or thresholds set, numbering instead."
+                );
+                annotate(
+                    record,
+                    &ref_seqs,
+                    &thresholds,
+                    &scheme,
+                    n_terminal_marker,
+                    &alignment_options,
+                )
+                .into_iter()
+                .filter_map(|result| {
+                    let (vregion_annotation, reference_alignment) = match result {
+                        Ok(annotated) => annotated,
+                        Err(failure) => return Some(failure),
+                    };
+                    if write_numbering {
+                        write_annotations_with_template(
+                            &reference_alignment.query_record,
+                            &vregion_annotation.region_annotations(scheme.cdr3_definition)[5..6],
+                            &["cdr3_by=numbering"],
+                            args.format,
+                            &args.id_template,
+                            args.coordinate_base,
+                            output(),
+                        )
+                        .expect("Could not write CDR3.");
+                    }
+                    None
+                })
+                .collect()
+            })
+            .for_each(|failure| failures.report(&failure));
+        return finish_numbering(read_error, duplicate_error, sequences, &failures);
+    }
+
+    if let Some(GroupBy::Family) = args.group_by {
+        let references = records
+            .flat_map(|record| {
+                annotate(
+                    record,
+                    &ref_seqs,
+                    &thresholds,
+                    &scheme,
+                    n_terminal_marker,
+                    &alignment_options,
+                )
+            })
+            .filter_map(|result| match result {
+                Ok((_vregion_annotation, reference_alignment)) => {
+                    Some(reference_alignment.reference)
+                }
+                Err(failure) => {
+                    failures.report(&failure);
+                    None
+                }
+            });
+        let counts = count_gene_families(references);
+        if write_numbering {
+            for (family, count) in counts {
+                writeln!(out.clone(), "{}\t{}", family, count)?;
+            }
+        }
+        return finish_numbering(read_error, duplicate_error, sequences, &failures);
+    }
+
+    if let Some(threshold) = args.cluster_cdr3 {
+        // Clonotypes are only known once every CDR3 is, so the CDR3s are written at the end.
+        let cdr3s: Vec<_> = records
+            .flat_map(|record| {
+                annotate(
+                    record,
+                    &ref_seqs,
+                    &thresholds,
+                    &scheme,
+                    n_terminal_marker,
+                    &alignment_options,
+                )
+            })
+            .filter_map(|result| match result {
+                Ok((vregion_annotation, reference_alignment)) => Some((
+                    reference_alignment.query_record,
+                    vregion_annotation.region_annotations(scheme.cdr3_definition)[5].clone(),
+                )),
+                Err(failure) => {
+                    failures.report(&failure);
+                    None
+                }
+            })
+            .collect();
+        let clonotypes = cluster_cdr3s(
+            &cdr3s
+                .iter()
+                .map(|(record, cdr3)| &record.seq()[cdr3.start..cdr3.end])
+                .collect::<Vec<_>>(),
+            threshold,
+        );
+        if write_numbering {
+            for ((record, cdr3), clonotype) in cdr3s.iter().zip(clonotypes) {
+                write_annotations_with_template(
+                    record,
+                    std::slice::from_ref(cdr3),
+                    &[&format!("clonotype={}", clonotype)],
+                    args.format,
+                    &args.id_template,
+                    args.coordinate_base,
+                    output(),
+                )?;
+            }
+        }
+        return finish_numbering(read_error, duplicate_error, sequences, &failures);
+    }
+
+    if let Some(columns) = &args.columns {
+        if write_numbering {
+            write_columns_header(columns, args.delimiter, out.clone())?;
+        }
+        records
+            .flat_map(|record| {
+                annotate(
+                    record,
+                    &ref_seqs,
+                    &thresholds,
+                    &scheme,
+                    n_terminal_marker,
+                    &alignment_options,
+                )
+            })
+            .for_each(|result| match result {
+                Ok((vregion_annotation, reference_alignment)) => {
+                    if write_numbering {
+                        write_columns_row(
+                            columns,
+                            args.delimiter,
+                            &reference_alignment,
+                            &vregion_annotation,
+                            out.clone(),
+                        )
+                        .expect("Could not write row.");
+                    }
+                }
+                Err(failure) => failures.report(&failure),
+            });
+        return finish_numbering(read_error, duplicate_error, sequences, &failures);
+    }
+
+    if stats {
+        let mut numbering_stats = NumberingStats::default();
+        records
+            .flat_map(|record| {
+                annotate(
+                    record,
+                    &ref_seqs,
+                    &thresholds,
+                    &scheme,
+                    n_terminal_marker,
+                    &alignment_options,
+                )
+            })
+            .for_each(|result| match result {
+                Ok((vregion_annotation, reference_alignment)) => {
+                    numbering_stats.add(&reference_alignment.reference, &vregion_annotation)
+                }
+                Err(failure) => {
+                    numbering_stats.add_failure();
+                    failures.report(&failure);
+                }
+            });
+        if write_numbering {
+            numbering_stats.write(out.clone())?;
+        }
+        return finish_numbering(read_error, duplicate_error, sequences, &failures);
+    }
+
+    if args.format == OutputFormat::Airr && write_numbering {
+        write_airr_header(out.clone())?;
+    }
+
+    let insertion_format = args.insertion_format.unwrap_or_default();
+    if let Some(threads) = args.threads {
+        let mut engine = NumberingEngine::new(&ref_seqs, scheme);
+        engine.thresholds = thresholds;
+        engine.insertion_format = insertion_format;
+        engine.alignment_mode = args.alignment_mode;
+        engine.id_template = args.id_template.clone();
+        engine.coordinate_base = args.coordinate_base;
+        engine.profile = profile.clone();
+        let numbering_out: Box<dyn Write + Send> = if write_numbering {
+            Box::new(output())
+        } else {
+            Box::new(std::io::sink())
+        };
+        let summary = number_batch(
+            records.map(|record| Query::from(record).with_n_terminal_marker(n_terminal_marker)),
+            &engine,
+            args.format,
+            &BatchOptions {
+                threads,
+                chunk_size: args.chunk_size,
+                capacity: args.channel_capacity,
+            },
+            numbering_out,
+            |failure| {
+                failures.report(&Failure {
+                    record: failure.record,
+                    error: failure.error,
+                })
+            },
+        )?;
+        info!(
+            numbered = summary.numbered,
+            failed = summary.failed,
+            "Numbered sequences."
+        );
+        if let Some(profile) = profile {
+            profile.write(&mut *err)?;
+            writeln!(err)?;
+            profile.write_slowest(err)?;
+        }
+        return finish_numbering(read_error, duplicate_error, sequences, &failures);
+    }
+
+    // With --sort-by, the output of every sequence is kept and written sorted at the end.
+    let mut sorted_output = args.sort_by.map(SortedOutput::new);
+    let unsorted_out = out.clone();
+    let out = match &sorted_output {
+        Some(sorted_output) => SharedWriter::new(SharedBuffer(sorted_output.buffer.clone())),
+        None => out,
+    };
+    let output = || WrappingFastaWriter::new(out.clone(), output_width);
+
+    records
+        .flat_map(|query_seq| {
+            annotate(
+                query_seq,
+                &ref_seqs,
+                &thresholds,
+                &scheme,
+                n_terminal_marker,
+                &alignment_options,
+            )
+        })
+        .filter_map(|result| {
+            let (vregion_annotation, reference_alignment) = match result {
+                Ok(annotated) => annotated,
+                Err(failure) => return Some(failure),
+            };
+            if let Some(sorted_output) = &mut sorted_output {
+                sorted_output.start(reference_alignment.reference.clone());
+            }
+
+            if let Some(id) = &args.show_alignment {
+                let query_id = reference_alignment.query_record.id();
+                if id.as_deref().is_none_or(|id| id == query_id) {
+                    writeln!(
+                        err,
+                        "# {} aligned to {}\n{}",
+                        query_id,
+                        reference_alignment.reference.name,
+                        reference_alignment.pretty(args.alignment_width)
+                    )
+                    .expect("Could not write alignment.");
+                }
+            }
+
+            let completeness = reference_alignment.completeness();
+            if !completeness.is_complete() {
+                warn!(
+                    sequence = reference_alignment.query_record.id(),
+                    flags = completeness.flags().join(","),
+                    "Sequence is truncated."
+                );
+            }
+            if reference_alignment.frame_check().likely_out_of_frame() {
+                warn!(
+                    sequence = reference_alignment.query_record.id(),
+                    identity = reference_alignment.identity(),
+                    "Sequence is likely out of frame and unproductive."
+                );
+            }
+            if args.only_productive && !reference_alignment.is_productive() {
+                debug!(
+                    sequence = reference_alignment.query_record.id(),
+                    "Skipping unproductive sequence."
+                );
+                return None;
+            }
+            let offset_fields = if args.alignment_offsets {
+                reference_alignment
+                    .offsets()
+                    .fields(args.coordinate_base)
+                    .to_vec()
+            } else {
+                vec![]
+            };
+            let chain_field = (args.on_ambiguous_chain == AmbiguousChainPolicy::Both).then(|| {
+                format!(
+                    "chain={}",
+                    reference_alignment
+                        .reference
+                        .locus()
+                        .unwrap_or(UNKNOWN_CHAIN)
+                )
+            });
+            let germline_gaps_field = args.germline_gaps.then(|| {
+                format!(
+                    "germline_gaps={}",
+                    reference_alignment
+                        .reference
+                        .missing_positions()
+                        .iter()
+                        .map(usize::to_string)
+                        .collect::<Vec<_>>()
+                        .join(",")
+                )
+            });
+            let chimera_fields = if args.detect_chimeras {
+                with_thread_aligner(|aligner| {
+                    detect_chimera(&reference_alignment, &ref_seqs, aligner)
+                })
+                .map(|chimera| chimera.fields().to_vec())
+                .unwrap_or_default()
+            } else {
+                vec![]
+            };
+            let graft_fields = if args.detect_cdr_grafts {
+                with_thread_aligner(|aligner| {
+                    detect_cdr_graft(
+                        &reference_alignment,
+                        &vregion_annotation,
+                        &ref_seqs,
+                        aligner,
+                    )
+                    .map(|graft| graft.fields().to_vec())
+                })
+                .unwrap_or_default()
+            } else {
+                vec![]
+            };
+            let reference_fields = if args.format.is_fasta() {
+                reference_alignment.reference_fields().to_vec()
+            } else {
+                vec![]
+            };
+            let mut flags: Vec<&str> = reference_alignment.flags();
+            flags.extend(reference_fields.iter().map(String::as_str));
+            flags.extend(offset_fields.iter().map(String::as_str));
+            flags.extend(chain_field.as_deref());
+            flags.extend(germline_gaps_field.as_deref());
+            if !chimera_fields.is_empty() {
+                flags.push("chimera");
+                flags.extend(chimera_fields.iter().map(String::as_str));
+            }
+            if !graft_fields.is_empty() {
+                flags.push("cdr_graft");
+                flags.extend(graft_fields.iter().map(String::as_str));
+            }
+
+            if args.format == OutputFormat::Airr {
+                if write_numbering {
+                    write_airr_row(&reference_alignment, &vregion_annotation, out.clone())
+                        .expect("Could not write AIRR rearrangement.");
+                }
+                return None;
+            }
+
+            if args.annotate_regions && write_numbering {
+                trace!(
+                    query_seq = reference_alignment.query_record.id(),
+                    "Applying region annotations."
+                );
+                write_annotations_with_template(
+                    &reference_alignment.query_record,
+                    &vregion_annotation.region_annotations(scheme.cdr3_definition),
+                    &flags,
+                    args.format,
+                    &args.id_template,
+                    args.coordinate_base,
+                    output(),
+                )
+                .expect("Could not write annotations.");
+            }
+
+            if args.paratope {
+                if write_numbering {
+                    write_paratope(
+                        &reference_alignment.query_record,
+                        &vregion_annotation.cdr_concatenation(&reference_alignment.query_record),
+                        &flags,
+                        args.format,
+                        output(),
+                    )
+                    .expect("Could not write paratope.");
+                }
+                return None;
+            }
+
+            if args.no_number {
+                return None;
+            }
+
+            trace!("Applying numbering.");
+            let numbering = vregion_annotation
+                .number_regions(&reference_alignment, &scheme)
+                .and_then(|annotations| {
+                    if args.validate {
+                        check_unique_positions(&annotations)?;
+                    }
+                    Ok(annotations)
+                });
+            match numbering {
+                Ok(mut annotations) => {
+                    let deleted_field = args.deleted_positions.then(|| {
+                        let numbered = NumberedSequence::new(
+                            reference_alignment.query_record.clone(),
+                            annotations.clone(),
+                        );
+                        format!("deleted={}", numbered.deleted_positions().join(","))
+                    });
+                    let mut flags = flags;
+                    flags.extend(deleted_field.as_deref());
+                    if write_numbering && args.gapped_regions {
+                        write_gapped_regions(
+                            &reference_alignment.query_record,
+                            &gapped_regions(
+                                &reference_alignment.query_record,
+                                &vregion_annotation.region_annotations(scheme.cdr3_definition),
+                                &annotations,
+                                scheme.cdr3_definition,
+                            ),
+                            &flags,
+                            args.format,
+                            args.coordinate_base,
+                            output(),
+                        )
+                        .expect("Could not write regions.");
+                    } else if write_numbering && args.germline_frame {
+                        write_annotations_with_template(
+                            &reference_alignment.query_record,
+                            &reference_alignment.germline_columns(),
+                            &flags,
+                            args.format,
+                            &args.id_template,
+                            args.coordinate_base,
+                            output(),
+                        )
+                        .expect("Could not write annotations.");
+                    } else if write_numbering && args.run_length {
+                        write_encoded_numbering(
+                            &reference_alignment.query_record,
+                            &reference_alignment.reference.name,
+                            &encode_numbering(
+                                &reference_alignment.query_record,
+                                &annotations,
+                                &reference_alignment.reference,
+                            ),
+                            &flags,
+                            args.format,
+                            output(),
+                        )
+                        .expect("Could not write encoded numbering.");
+                    } else if write_numbering && args.cysteines {
+                        for mut cysteine in
+                            cysteines(&reference_alignment.query_record, &annotations)
+                        {
+                            cysteine.annotation.name =
+                                format_position(&cysteine.annotation.name, insertion_format);
+                            let mut flags = flags.clone();
+                            flags.push(if cysteine.canonical {
+                                "canonical_cysteine"
+                            } else {
+                                "non_canonical_cysteine"
+                            });
+                            write_annotations_with_template(
+                                &reference_alignment.query_record,
+                                &[cysteine.annotation],
+                                &flags,
+                                args.format,
+                                &args.id_template,
+                                args.coordinate_base,
+                                output(),
+                            )
+                            .expect("Could not write cysteines.");
+                        }
+                    } else if write_numbering && args.liabilities {
+                        let numbered = NumberedSequence::new(
+                            reference_alignment.query_record.clone(),
+                            annotations.clone(),
+                        );
+                        for mut liability in liabilities(&numbered) {
+                            liability.annotation.name =
+                                format_position(&liability.annotation.name, insertion_format);
+                            let kind = format!("liability={}", liability.kind.name());
+                            let region = format!("region={}", liability.region);
+                            let mut flags = flags.clone();
+                            flags.extend([kind.as_str(), region.as_str()]);
+                            write_annotations_with_template(
+                                &reference_alignment.query_record,
+                                &[liability.annotation],
+                                &flags,
+                                args.format,
+                                &args.id_template,
+                                args.coordinate_base,
+                                output(),
+                            )
+                            .expect("Could not write liabilities.");
+                        }
+                    } else if write_numbering && args.format == OutputFormat::A2m {
+                        write_a2m(
+                            &reference_alignment.query_record,
+                            &annotations,
+                            &flags,
+                            output(),
+                        )
+                        .expect("Could not write A2M record.");
+                    } else if write_numbering && args.format == OutputFormat::Collier {
+                        write_collier(
+                            &reference_alignment.query_record,
+                            &annotations,
+                            &flags,
+                            insertion_format,
+                            output(),
+                        )
+                        .expect("Could not write collier de perles.");
+                    } else if write_numbering && args.format == OutputFormat::Pair {
+                        write_germline_pair(
+                            &reference_alignment.query_record,
+                            &annotations,
+                            &reference_alignment.reference,
+                            &flags,
+                            output(),
+                        )
+                        .expect("Could not write germline pair.");
+                    } else if write_numbering {
+                        format_positions(&mut annotations, insertion_format);
+                        write_annotations_with_template(
+                            &reference_alignment.query_record,
+                            &annotations,
+                            &flags,
+                            args.format,
+                            &args.id_template,
+                            args.coordinate_base,
+                            output(),
+                        )
+                        .expect("Could not write annotations.");
+                    }
+                    None
+                }
+                Err(error) => Some(Box::new(Failure {
+                    record: reference_alignment.query_record,
+                    error: error.into(),
+                })),
+            }
+        })
+        .for_each(|failure| failures.report(&failure));
+    if let Some(sorted_output) = sorted_output {
+        sorted_output.write(unsorted_out)?;
+    }
+    finish_numbering(read_error, duplicate_error, sequences, &failures)
+}
+
+/// The output of every sequence with its reference, written sorted at the end, see `--sort-by`.
+struct SortedOutput {
+    sort_by: SortBy,
+    /// Where the output of the current sequence is written.
+    buffer: Arc<Mutex<Vec<u8>>>,
+    /// The reference and output of every sequence so far.
+    outputs: Vec<(ReferenceSequence, Vec<u8>)>,
+}
+
+impl SortedOutput {
+    fn new(sort_by: SortBy) -> Self {
+        Self {
+            sort_by,
+            buffer: Arc::default(),
+            outputs: Vec::new(),
+        }
+    }
+
+    /// Start the output of a sequence, ending that of the previous one.
+    fn start(&mut self, reference: ReferenceSequence) {
+        self.end();
+        self.outputs.push((reference, Vec::new()));
+    }
+
+    /// Move what was written to the buffer to the output of the current sequence.
+    fn end(&mut self) {
+        let written =
+            std::mem::take(&mut *self.buffer.lock().expect("Buffer should not be poisoned."));
+        if let Some((_reference, output)) = self.outputs.last_mut() {
+            output.extend(written);
+        }
+    }
+
+    /// Write the output of all sequences, sorted stably by their references.
+    fn write(mut self, mut out: impl Write) -> std::io::Result<()> {
+        self.end();
+        match self.sort_by {
+            SortBy::VGene => self.outputs.sort_by(|a, b| compare_v_genes(&a.0, &b.0)),
+        }
+        for (_reference, output) in self.outputs {
+            out.write_all(&output)?;
+        }
+        Ok(())
+    }
+}
+
+/// A buffer that is written through a [`SharedWriter`], and read by its owner.
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .lock()
+            .expect("Buffer should not be poisoned.")
+            .write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The sequences that could not be numbered.
+struct Failures<'a> {
+    /// Where the failed records are written, if they are collected.
+    writer: Option<fasta::Writer<Box<dyn Write + Send + 'a>>>,
+    count: usize,
+}
+
+impl Failures<'_> {
+    /// Log a sequence that could not be numbered and write it to the failures, if they are collected.
+    fn report(&mut self, failure: &Failure) {
+        error!(
+            sequence = failure.record.id(),
+            error = failure.error.to_string(),
+            "Could not number sequence."
+        );
+        self.count += 1;
+        if let Some(writer) = self.writer.as_mut() {
+            writer
+                .write_record(&failure.to_record())
+                .expect("Could not write failed record.");
+        }
+    }
+}
+
+/// The stats of numbering all sequences, or the error that stopped reading them.
+fn finish_numbering(
+    read_error: Option<std::io::Error>,
+    duplicate_error: Option<InputErr>,
+    sequences: usize,
+    failures: &Failures,
+) -> Result<RunStats, RunError> {
+    if let Some(error) = read_error {
+        return Err(error.into());
+    }
+    if let Some(error) = duplicate_error {
+        return Err(error.into());
+    }
+    Ok(RunStats {
+        sequences,
+        failures: failures.count,
+    })
+}
+
+/// A sequence that could not be numbered.
+struct Failure {
+    record: fasta::Record,
+    error: NumberingError,
+}
+
+impl Failure {
+    /// The original record, with the reason of the failure added to the description.
+    fn to_record(&self) -> fasta::Record {
+        let description = match self.record.desc() {
+            Some(desc) => format!("{} failure: {}", desc, self.error),
+            None => format!("failure: {}", self.error),
+        };
+        fasta::Record::with_attrs(self.record.id(), Some(&description), self.record.seq())
+    }
+}
+
+/// How records are aligned to the references.
+struct AlignmentOptions<'a> {
+    force_reference: Option<&'a str>,
+    mode: AlignmentMode,
+    band_width: Option<usize>,
+    on_ambiguous_chain: AmbiguousChainPolicy,
+    /// Genes that are not functional, which queries are checked against with `on_nonfunctional`.
+    gene_functionality: Option<&'a GeneFunctionality>,
+    on_nonfunctional: NonFunctionalPolicy,
+}
+
+/// Find the references of a query, which are several for a query that matches several chain types with `--on-ambiguous-chain both`.
+fn find_references(
+    query: Query,
+    ref_seqs: &ReferenceIndex,
+    alignment_options: &AlignmentOptions,
+) -> Result<Vec<ReferenceAlignment>, RefSeqErr> {
+    let reference_alignments = find_unchecked_references(query, ref_seqs, alignment_options)?;
+    let Some(gene_functionality) = alignment_options.gene_functionality else {
+        return Ok(reference_alignments);
+    };
+    reference_alignments
+        .into_iter()
+        .map(|reference_alignment| {
+            gene_functionality.check(reference_alignment, alignment_options.on_nonfunctional)
+        })
+        .collect()
+}
+
+/// Like [`find_references`], without checking the functionality of their genes.
+fn find_unchecked_references(
+    query: Query,
+    ref_seqs: &ReferenceIndex,
+    alignment_options: &AlignmentOptions,
+) -> Result<Vec<ReferenceAlignment>, RefSeqErr> {
+    if let Some(name) = alignment_options.force_reference {
+        return align_to_reference(query, ref_seqs, name, alignment_options.mode)
+            .map(|reference_alignment| vec![reference_alignment]);
+    }
+    match alignment_options.band_width {
+        Some(band_width) => find_references_with_aligner(
+            query,
+            ref_seqs,
+            &mut new_banded_aligner(band_width),
+            alignment_options,
+        ),
+        None => with_thread_aligner(|aligner| {
+            find_references_with_aligner(query, ref_seqs, aligner, alignment_options)
+        }),
+    }
+}
+
+fn find_references_with_aligner<A: PairwiseAligner>(
+    query: Query,
+    ref_seqs: &ReferenceIndex,
+    aligner: &mut A,
+    alignment_options: &AlignmentOptions,
+) -> Result<Vec<ReferenceAlignment>, RefSeqErr> {
+    let mode = alignment_options.mode;
+    if alignment_options.on_ambiguous_chain == AmbiguousChainPolicy::Best {
+        return find_best_reference_sequence_with_mode(query, ref_seqs, aligner, mode)
+            .map(|reference_alignment| vec![reference_alignment]);
+    }
+    let tied = find_tied_chain_alignments_with_mode(query, ref_seqs, aligner, mode)?;
+    if alignment_options.on_ambiguous_chain == AmbiguousChainPolicy::Fail && tied.len() > 1 {
+        let chains = tied
+            .iter()
+            .map(|reference_alignment| {
+                reference_alignment
+                    .reference
+                    .locus()
+                    .unwrap_or(UNKNOWN_CHAIN)
+                    .to_string()
+            })
+            .collect();
+        return Err(RefSeqErr::AmbiguousChain(
+            tied[0].query_record.clone(),
+            chains,
+        ));
+    }
+    Ok(tied)
+}
+
+/// Find the references of a record and annotate its regions, once for every reference.
+fn annotate(
+    record: fasta::Record,
+    ref_seqs: &ReferenceIndex,
+    thresholds: &Thresholds,
+    scheme: &NumberingScheme,
+    n_terminal_marker: u8,
+    alignment_options: &AlignmentOptions,
+) -> Vec<Result<(VRegionAnnotation, ReferenceAlignment), Box<Failure>>> {
+    let query = Query::from(record).with_n_terminal_marker(n_terminal_marker);
+    let reference_alignments = match find_references(query, ref_seqs, alignment_options) {
+        Ok(reference_alignments) => reference_alignments,
+        Err(error) => {
+            return vec![Err(Box::new(Failure {
+                record: error.record().clone(),
+                error: error.into(),
+            }))]
+        }
+    };
+    reference_alignments
+        .into_iter()
+        .map(|reference_alignment| annotate_alignment(reference_alignment, thresholds, scheme))
+        .collect()
+}
+
+/// Check an alignment against the thresholds and annotate the regions of its query.
+fn annotate_alignment(
+    reference_alignment: ReferenceAlignment,
+    thresholds: &Thresholds,
+    scheme: &NumberingScheme,
+) -> Result<(VRegionAnnotation, ReferenceAlignment), Box<Failure>> {
+    let reference_alignment = thresholds.check(reference_alignment).map_err(|error| {
+        Box::new(Failure {
+            record: error.record().clone(),
+            error: error.into(),
+        })
+    })?;
+
+    trace!(
+        query_seq = reference_alignment.query_record.id(),
+        alignment = format!("{:?}", reference_alignment.alignment.path()),
+        "Transferring reference alignment."
+    );
+    match reference_alignment.vregion_annotation_for(scheme) {
+        Ok(vregion_annotation) => Ok((vregion_annotation, reference_alignment)),
+        Err(error) => Err(Box::new(Failure {
+            record: reference_alignment.query_record,
+            error,
+        })),
+    }
+}
+
+/// Implement [`ValueEnum`] for option values of the library, with the name and
+/// help of every variant on the command line.
+///
+/// The library types stay free of command line concerns, so these live here.
+macro_rules! value_enum {
+    ($type:ident { $($variant:ident => $name:expr $(, $help:literal)?;)* }) => {
+        impl ValueEnum for $type {
+            fn value_variants<'a>() -> &'a [Self] {
+                &[$($type::$variant),*]
+            }
+
+            fn to_possible_value(&self) -> Option<PossibleValue> {
+                Some(match self {
+                    $($type::$variant => PossibleValue::new($name)$(.help($help))?,)*
+                })
+            }
+        }
+    };
+}
+
+value_enum!(AlignmentMode {
+    Local => "local", "Align the best matching parts, which may leave out divergent ends of the query";
+    Semiglobal => "semiglobal", "Align all of the reference to any part of the query, suited to full-length V-domains";
+    Global => "global", "Align all of the reference to all of the query";
+});
+
+value_enum!(AmbiguousChainPolicy {
+    Best => "best", "Number it with the best reference, of equal scores the one whose name sorts first";
+    Both => "both", "Number it with the best reference of each of the chain types";
+    Fail => "fail", "Fail on it";
+});
+
+value_enum!(Cdr3Definition {
+    Imgt => "imgt", "CDR3-IMGT, positions 105 to 117";
+    Junction => "junction", "IMGT JUNCTION, positions 104 to 118, the CDR3-IMGT with the 2nd-CYS and J-TRP or J-PHE";
+});
+
+value_enum!(Cdr3InsertionPolicy {
+    Imgt => "imgt";
+    Anarci => "anarci";
+});
+
+value_enum!(ImgtRegion {
+    Fr1 => ImgtRegion::Fr1.name();
+    Cdr1 => ImgtRegion::Cdr1.name();
+    Fr2 => ImgtRegion::Fr2.name();
+    Cdr2 => ImgtRegion::Cdr2.name();
+    Fr3 => ImgtRegion::Fr3.name();
+    Cdr3 => ImgtRegion::Cdr3.name(), "The CDR3-IMGT, or the JUNCTION with --cdr3-definition junction";
+    Fr4 => ImgtRegion::Fr4.name();
+});
+
+value_enum!(Column {
+    Id => Column::Id.name(), "Id of the query";
+    Reference => Column::Reference.name(), "Name of the matched reference";
+    VGene => Column::VGene.name(), "V gene of the matched reference";
+    GeneFamily => Column::GeneFamily.name(), "Family of the V gene of the matched reference, such as IGHV1";
+    JGene => Column::JGene.name(), "J gene of the matched reference";
+    Locus => Column::Locus.name(), "Chain type of the matched reference";
+    Score => Column::Score.name(), "Alignment score per aligned column";
+    Identity => Column::Identity.name(), "Fraction of the aligned columns in which the query is identical to the reference";
+    Fr1 => Column::Fr1.name();
+    Cdr1 => Column::Cdr1.name();
+    Fr2 => Column::Fr2.name();
+    Cdr2 => Column::Cdr2.name();
+    Fr3 => Column::Fr3.name();
+    Cdr3 => Column::Cdr3.name(), "The CDR3-IMGT (105-117)";
+    Fr4 => Column::Fr4.name();
+    Junction => Column::Junction.name(), "The IMGT JUNCTION (104-118)";
+    Fr1Length => Column::Fr1Length.name();
+    Cdr1Length => Column::Cdr1Length.name();
+    Fr2Length => Column::Fr2Length.name();
+    Cdr2Length => Column::Cdr2Length.name();
+    Fr3Length => Column::Fr3Length.name();
+    Cdr3Length => Column::Cdr3Length.name();
+    Fr4Length => Column::Fr4Length.name();
+});
+
+value_enum!(GroupBy {
+    Family => "family", "The V gene family of the matched reference, such as IGHV1";
+});
+
+value_enum!(SortBy {
+    VGene => "v_gene", "The V gene of the matched reference, by segment, family and gene";
+});
+
+value_enum!(InputFormat {
+    Fasta => "fasta", "FASTA records";
+    Lines => "lines", "One sequence per line without headers. Empty lines are skipped";
+});
+
+value_enum!(GappedQueryMode {
+    Strip => "strip", "Remove the gaps and align the query like any other";
+    PreAligned => "pre-aligned", "Take the query as aligned to the IMGT positions and number it by its columns";
+});
+
+value_enum!(DuplicateIdPolicy {
+    Rename => "rename", "Add a suffix to the id (_2, _3, ...) that makes it unique";
+    Fail => "fail", "Fail on the first duplicate id";
+    Allow => "allow", "Keep duplicate ids, so the output of these records cannot be told apart";
+});
+
+value_enum!(OutputFormat {
+    Fasta => "fasta", "One FASTA record per annotation. The description names the matched reference";
+    Tsv => "tsv", "One line per annotation with the columns: id, name, start, end, sequence and flags";
+    Airr => "airr", "One line per sequence with the columns of the AIRR rearrangement schema";
+    A2m => "a2m", "One aligned FASTA (A2M) record per sequence in the IMGT column frame";
+    Pair => "pair", "Two aligned FASTA records per sequence, its germline and itself in the same IMGT frame";
+    Collier => "collier", "One line per numbered residue with its region and its strand or loop of the IMGT Collier de Perles";
+});
+
+value_enum!(CoordinateBase {
+    Zero => "0", "Zero based and end exclusive";
+    One => "1", "One based and end inclusive, as in GFF and SAM";
+});
+
+value_enum!(InsertionFormat {
+    Dotted => "dotted", "IMGT style, such as 111.1 and 112.1";
+    Letter => "letter", "Kabat style letter suffixes, such as 111A and 112A";
+});
+
+value_enum!(InsertStates {
+    Reference => "reference", "Only by the #=GC RF line";
+    Lowercase => "lowercase", "By the #=GC RF line, or without one by the residues: columns with only lowercase residues and . are insert columns";
+});
+
+value_enum!(NonFunctionalPolicy {
+    Warn => "warn", "Number it, and log a warning";
+    Reject => "reject", "Fail on it";
+});
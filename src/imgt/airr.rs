@@ -0,0 +1,144 @@
+use std::io::Write;
+
+use super::{annotations::VRegionAnnotation, ReferenceAlignment};
+
+/// The columns of the AIRR rearrangement schema that are written, in order.
+///
+/// All required fields are included. Queries are protein sequences, so the
+/// nucleotide fields (such as `sequence` and `junction`) and the CIGARs are left
+/// empty, as are the D gene and `rev_comp`. The regions are the IMGT regions, so
+/// `cdr3_aa` is the CDR3-IMGT (105-117) whatever the CDR3 definition.
+pub const AIRR_FIELDS: [&str; 24] = [
+    "sequence_id",
+    "sequence",
+    "sequence_aa",
+    "rev_comp",
+    "productive",
+    "complete_vdj",
+    "v_call",
+    "d_call",
+    "j_call",
+    "sequence_alignment",
+    "germline_alignment",
+    "junction",
+    "junction_aa",
+    "junction_aa_length",
+    "v_cigar",
+    "d_cigar",
+    "j_cigar",
+    "fwr1_aa",
+    "cdr1_aa",
+    "fwr2_aa",
+    "cdr2_aa",
+    "fwr3_aa",
+    "cdr3_aa",
+    "fwr4_aa",
+];
+
+/// An AIRR boolean.
+fn airr_bool(value: bool) -> String {
+    if value { "T" } else { "F" }.to_string()
+}
+
+/// The values of the [`AIRR_FIELDS`] for an annotated query.
+///
+/// The V and J calls are taken from the name of the matched reference, see
+/// [`ReferenceSequence::v_call`](super::reference::ReferenceSequence::v_call).
+pub fn airr_row(
+    reference_alignment: &ReferenceAlignment,
+    vregion_annotation: &VRegionAnnotation,
+) -> [String; AIRR_FIELDS.len()] {
+    let record = &reference_alignment.query_record;
+    let reference = &reference_alignment.reference;
+    let junction_aa = vregion_annotation.imgt_junction(record.seq());
+    let regions = vregion_annotation.region_sequences(record);
+    let aa = |residues: &[u8]| String::from_utf8_lossy(residues).into_owned();
+
+    [
+        record.id().to_string(),
+        String::new(),
+        String::from_utf8_lossy(record.seq()).into_owned(),
+        String::new(),
+        airr_bool(!reference_alignment.frame_check().likely_out_of_frame()),
+        airr_bool(reference_alignment.completeness().is_complete()),
+        reference.v_call().unwrap_or_default().to_string(),
+        String::new(),
+        reference.j_call().unwrap_or_default().to_string(),
+        String::new(),
+        String::new(),
+        String::new(),
+        junction_aa.clone().unwrap_or_default(),
+        junction_aa
+            .map(|junction| junction.len().to_string())
+            .unwrap_or_default(),
+        String::new(),
+        String::new(),
+        String::new(),
+        aa(&regions.fr1),
+        aa(&regions.cdr1),
+        aa(&regions.fr2),
+        aa(&regions.cdr2),
+        aa(&regions.fr3),
+        aa(&regions.cdr3),
+        aa(&regions.fr4),
+    ]
+}
+
+/// Write the header line of an AIRR rearrangement TSV.
+pub fn write_airr_header<W: Write>(mut writer: W) -> std::io::Result<()> {
+    writeln!(writer, "{}", AIRR_FIELDS.join("\t"))
+}
+
+/// Write an annotated query as a line of an AIRR rearrangement TSV, see [`airr_row`].
+pub fn write_airr_row<W: Write>(
+    reference_alignment: &ReferenceAlignment,
+    vregion_annotation: &VRegionAnnotation,
+    mut writer: W,
+) -> std::io::Result<()> {
+    writeln!(
+        writer,
+        "{}",
+        airr_row(reference_alignment, vregion_annotation).join("\t")
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::imgt::{
+        find_best_reference_sequence,
+        fixtures::{named_reference, ref_seqs, TEST_SEQUENCE},
+        query::Query,
+    };
+
+    #[test]
+    fn test_airr_row() {
+        let name = "Homo_sapiens_IGHV1-18*01_IGHJ6*01";
+        let ref_seqs = ref_seqs([named_reference(name)]);
+        let reference_alignment =
+            find_best_reference_sequence(Query::new("query", TEST_SEQUENCE.as_bytes()), &ref_seqs)
+                .unwrap();
+        let vregion_annotation = reference_alignment.vregion_annotation().unwrap();
+
+        let row = airr_row(&reference_alignment, &vregion_annotation);
+        let field = |name: &str| row[AIRR_FIELDS.iter().position(|f| *f == name).unwrap()].as_str();
+        assert_eq!(field("sequence_id"), "query");
+        assert_eq!(field("v_call"), "IGHV1-18*01");
+        assert_eq!(field("j_call"), "IGHJ6*01");
+        assert_eq!(field("productive"), "T");
+        assert_eq!(field("cdr3_aa"), "ARMDV");
+        assert_eq!(field("junction_aa"), "CARMDVW");
+        assert_eq!(field("junction_aa_length"), "7");
+        assert_eq!(field("fwr1_aa"), "QVQLVQSGAEVKKPGASVKVSCKAS");
+        assert_eq!(field("fwr4_aa"), "WGQGTTVTVSS");
+
+        let mut written = Vec::new();
+        write_airr_header(&mut written).unwrap();
+        write_airr_row(&reference_alignment, &vregion_annotation, &mut written).unwrap();
+        let lines: Vec<_> = std::str::from_utf8(&written).unwrap().lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines
+            .iter()
+            .all(|line| line.split('\t').count() == AIRR_FIELDS.len()));
+    }
+}
@@ -0,0 +1,350 @@
+use std::{cmp::Ordering, collections::BTreeMap, io::Write};
+
+use bio::io::fasta;
+
+use super::{
+    annotations::{Annotation, VRegionAnnotation},
+    consensus::NumberedSequence,
+    numbering::ImgtRegion,
+    reference::{gene_family, ReferenceSequence},
+};
+
+/// Family of references without a recognisable V gene family, see [`count_gene_families`].
+pub const UNKNOWN_FAMILY: &str = "unknown";
+
+/// Chain type of references without a locus, see [`ReferenceSequence::locus`].
+pub const UNKNOWN_CHAIN: &str = "unknown";
+
+/// How sequences are grouped in a summary instead of being numbered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GroupBy {
+    /// The V gene family of the matched reference, such as `IGHV1`.
+    Family,
+}
+
+/// How the numbering of sequences is ordered in the output.
+///
+/// Sorting needs the output of every sequence, so all of it is kept in memory
+/// until the last sequence is numbered, instead of being written as it goes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortBy {
+    /// The V gene of the matched reference, see [`compare_v_genes`].
+    VGene,
+}
+
+/// Order references by their V gene.
+///
+/// The genes are ordered by locus and segment (such as `IGHV`), then by the number
+/// of their family (see [`gene_family`]), so `IGHV2-5*01` comes before
+/// `IGHV10-1*01`, and then by the gene itself. References without a V gene
+/// come last, by name.
+pub fn compare_v_genes(first: &ReferenceSequence, second: &ReferenceSequence) -> Ordering {
+    fn key(reference: &ReferenceSequence) -> (bool, &str, u32, &str) {
+        let Some(v_call) = reference.v_call() else {
+            return (true, "", 0, &reference.name);
+        };
+        let (segment, family) = gene_family(v_call).map_or(("", 0), |family| {
+            (&family[..4], family[4..].parse().unwrap_or(u32::MAX))
+        });
+        (false, segment, family, v_call)
+    }
+    key(first).cmp(&key(second))
+}
+
+/// IMGT numbers of the cysteines that form the canonical disulfide bond.
+const CANONICAL_CYSTEINES: [&str; 2] = ["23", "104"];
+
+/// A cysteine in a numbered query.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cysteine {
+    pub annotation: Annotation,
+    /// Whether this is the 1st-CYS (23) or 2nd-CYS (104).
+    pub canonical: bool,
+}
+
+/// Find all numbered cysteines of a query.
+///
+/// Cysteines other than 23 and 104 may form additional disulfide bonds, which
+/// matters for developability.
+pub fn cysteines(record: &fasta::Record, numbering: &[Annotation]) -> Vec<Cysteine> {
+    numbering
+        .iter()
+        .filter(|annotation| record.seq().get(annotation.start) == Some(&b'C'))
+        .map(|annotation| Cysteine {
+            annotation: annotation.clone(),
+            canonical: CANONICAL_CYSTEINES.contains(&annotation.name.as_str()),
+        })
+        .collect()
+}
+
+/// Kind of motif that is a liability for the development of an antibody, see [`liabilities`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LiabilityKind {
+    /// N-X-S/T with X not P, a site of N-linked glycosylation.
+    NGlycosylation,
+    /// NG or NS, prone to deamidation of the asparagine.
+    Deamidation,
+}
+
+impl LiabilityKind {
+    /// Name of the liability, as written to the output.
+    pub fn name(self) -> &'static str {
+        match self {
+            LiabilityKind::NGlycosylation => "n_glycosylation",
+            LiabilityKind::Deamidation => "deamidation",
+        }
+    }
+
+    /// Length of the motif, if it starts at the start of a sequence.
+    fn motif_length(self, sequence: &[u8]) -> Option<usize> {
+        match (self, sequence) {
+            (LiabilityKind::NGlycosylation, [b'N', x, b'S' | b'T', ..]) if *x != b'P' => Some(3),
+            (LiabilityKind::Deamidation, [b'N', b'G' | b'S', ..]) => Some(2),
+            _ => None,
+        }
+    }
+}
+
+/// A liability motif in a numbered query.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Liability {
+    pub kind: LiabilityKind,
+    /// The residues of the motif in the query, named after the IMGT number of its asparagine.
+    pub annotation: Annotation,
+    /// The region of the asparagine.
+    pub region: ImgtRegion,
+}
+
+/// Find the motifs of N-linked glycosylation and deamidation in a numbered query.
+///
+/// Motifs are found from every numbered asparagine, so a motif may end in the
+/// residues after the numbering. An NS is both a deamidation motif and the start
+/// of a glycosylation motif, so it can be found twice. The liabilities are in
+/// order of the query.
+pub fn liabilities(sequence: &NumberedSequence) -> Vec<Liability> {
+    let seq = sequence.record.seq();
+    sequence
+        .numbering
+        .iter()
+        .filter(|annotation| seq.get(annotation.start) == Some(&b'N'))
+        .flat_map(|annotation| {
+            [LiabilityKind::NGlycosylation, LiabilityKind::Deamidation]
+                .into_iter()
+                .filter_map(|kind| {
+                    let length = kind.motif_length(&seq[annotation.start..])?;
+                    Some(Liability {
+                        kind,
+                        annotation: Annotation {
+                            start: annotation.start,
+                            end: annotation.start + length,
+                            name: annotation.name.clone(),
+                        },
+                        region: ImgtRegion::of_position(&annotation.name)?,
+                    })
+                })
+        })
+        .collect()
+}
+
+/// Count sequences by the V gene family of the reference they were matched to.
+///
+/// References without a family (see [`ReferenceSequence::gene_family`]) are counted
+/// as [`UNKNOWN_FAMILY`]. The families are in alphabetical order.
+pub fn count_gene_families(
+    references: impl IntoIterator<Item = ReferenceSequence>,
+) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for reference in references {
+        *counts
+            .entry(
+                reference
+                    .gene_family()
+                    .unwrap_or(UNKNOWN_FAMILY)
+                    .to_string(),
+            )
+            .or_default() += 1;
+    }
+    counts
+}
+
+/// Summary of numbering a set of sequences, as written by `numerotator stats`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NumberingStats {
+    pub numbered: usize,
+    pub failed: usize,
+    /// Numbered sequences per chain type of their reference, [`UNKNOWN_CHAIN`] without one.
+    pub chains: BTreeMap<String, usize>,
+    /// Numbered sequences per length of their CDR3-IMGT.
+    pub cdr3_lengths: BTreeMap<usize, usize>,
+}
+
+impl NumberingStats {
+    /// Count a numbered sequence, with the reference it was matched to.
+    pub fn add(&mut self, reference: &ReferenceSequence, vregion_annotation: &VRegionAnnotation) {
+        self.numbered += 1;
+        *self
+            .chains
+            .entry(reference.locus().unwrap_or(UNKNOWN_CHAIN).to_string())
+            .or_default() += 1;
+        let cdr3 = &vregion_annotation.cdr_annotation.cdr3;
+        *self.cdr3_lengths.entry(cdr3.end - cdr3.start).or_default() += 1;
+    }
+
+    /// Count a sequence that could not be numbered.
+    pub fn add_failure(&mut self) {
+        self.failed += 1;
+    }
+
+    /// Write the summary as lines of a statistic and its value, separated by a tab.
+    ///
+    /// The counts per chain type and CDR3 length are named `chain=IGH` and
+    /// `cdr3_length=12`, like the flags of the numbering.
+    pub fn write<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        writeln!(writer, "numbered\t{}", self.numbered)?;
+        writeln!(writer, "failed\t{}", self.failed)?;
+        for (chain, count) in &self.chains {
+            writeln!(writer, "chain={}\t{}", chain, count)?;
+        }
+        for (length, count) in &self.cdr3_lengths {
+            writeln!(writer, "cdr3_length={}\t{}", length, count)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::imgt::{
+        find_best_reference_sequence,
+        fixtures::{named_reference, ref_seqs, test_ref_seqs, TEST_SEQUENCE},
+        numbering::NumberingScheme,
+        query::Query,
+    };
+
+    // The test sequence with a cysteine in the CDR2 (ISAYNGNT -> ISCYNGNT).
+    const CDR2_CYS_SEQUENCE: &str = "QVQLVQSGAEVKKPGASVKVSCKASGYTFTSYGISWVRQAPGQGLEWMGWISCYNGNTNYAQKLQGRVTMTTDTSTSTAYMELRSLRSDDTAVYYCARMDVWGQGTTVTVSS";
+
+    #[test]
+    fn test_non_canonical_cysteine() {
+        let ref_seqs = test_ref_seqs();
+        let reference_alignment = find_best_reference_sequence(
+            Query::new("query", CDR2_CYS_SEQUENCE.as_bytes()),
+            &ref_seqs,
+        )
+        .unwrap();
+        let numbering = reference_alignment
+            .numbered(&NumberingScheme::default())
+            .unwrap();
+
+        let cysteines: Vec<_> = cysteines(&reference_alignment.query_record, &numbering)
+            .into_iter()
+            .map(|cysteine| (cysteine.annotation.name, cysteine.canonical))
+            .collect();
+        assert_eq!(
+            cysteines,
+            [
+                ("23".to_string(), true),
+                ("58".to_string(), false),
+                ("104".to_string(), true)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_liabilities() {
+        let ref_seqs = test_ref_seqs();
+        // A glycosylation motif in the CDR2 (AYNGNT -> AYNGTT).
+        let sequence = TEST_SEQUENCE.replace("AYNGNT", "AYNGTT");
+        let reference_alignment =
+            find_best_reference_sequence(Query::new("query", sequence.as_bytes()), &ref_seqs)
+                .unwrap();
+        let numbering = reference_alignment
+            .numbered(&NumberingScheme::default())
+            .unwrap();
+        let numbered = NumberedSequence::new(reference_alignment.query_record, numbering);
+
+        let found: Vec<_> = liabilities(&numbered)
+            .into_iter()
+            .map(|liability| {
+                (
+                    liability.kind,
+                    liability.annotation.name,
+                    liability.region,
+                    &sequence[liability.annotation.start..liability.annotation.end],
+                )
+            })
+            .collect();
+        // The asparagine of NGT is the start of both motifs, the NY after it of neither.
+        assert_eq!(
+            found,
+            [
+                (
+                    LiabilityKind::NGlycosylation,
+                    "62".to_string(),
+                    ImgtRegion::Cdr2,
+                    "NGT"
+                ),
+                (
+                    LiabilityKind::Deamidation,
+                    "62".to_string(),
+                    ImgtRegion::Cdr2,
+                    "NG"
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn test_numbering_stats() {
+        let name = "Homo_sapiens_IGHV1-18*01_IGHJ6*01";
+        let ref_seqs = ref_seqs([named_reference(name)]);
+        let mut stats = NumberingStats::default();
+        for sequence in [TEST_SEQUENCE, CDR2_CYS_SEQUENCE] {
+            let reference_alignment =
+                find_best_reference_sequence(Query::new("query", sequence.as_bytes()), &ref_seqs)
+                    .unwrap();
+            stats.add(
+                &reference_alignment.reference,
+                &reference_alignment.vregion_annotation().unwrap(),
+            );
+        }
+        stats.add_failure();
+
+        let mut written = Vec::new();
+        stats.write(&mut written).unwrap();
+        assert_eq!(
+            String::from_utf8(written).unwrap(),
+            "numbered\t2\nfailed\t1\nchain=IGH\t2\ncdr3_length=5\t2\n"
+        );
+    }
+
+    #[test]
+    fn test_compare_v_genes() {
+        let reference = |name: &str| named_reference(name);
+        let mut references = [
+            reference("custom"),
+            reference("Homo_sapiens_IGKV1-39*01_IGKJ1*01"),
+            reference("Homo_sapiens_IGHV10-1*01_IGHJ6*01"),
+            reference("Homo_sapiens_IGHV2-5*01_IGHJ6*01"),
+            reference("Homo_sapiens_IGHV1-18*01_IGHJ6*01"),
+            reference("Homo_sapiens_IGHV1-2*02_IGHJ6*01"),
+        ];
+        references.sort_by(compare_v_genes);
+        let names: Vec<_> = references
+            .iter()
+            .map(|reference| reference.name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "Homo_sapiens_IGHV1-18*01_IGHJ6*01",
+                "Homo_sapiens_IGHV1-2*02_IGHJ6*01",
+                "Homo_sapiens_IGHV2-5*01_IGHJ6*01",
+                "Homo_sapiens_IGHV10-1*01_IGHJ6*01",
+                "Homo_sapiens_IGKV1-39*01_IGKJ1*01",
+                "custom"
+            ]
+        );
+    }
+}
@@ -1,7 +1,18 @@
 use bio::io::fasta;
 
+use super::{
+    numbering::{Cdr3Definition, ImgtRegion},
+    output::{annotation_description, CoordinateBase},
+    CDR1_START, CDR2_START, CDR3_START, FR2_START, FR3_START, FR4_END, FR4_START,
+};
+
 /// Annotation of a sequence.
-#[derive(Clone)]
+///
+/// `start` and `end` are always zero based and end exclusive, so that
+/// `&seq[start..end]` are the residues of the annotation. Only when an annotation
+/// is written can the coordinates be converted to another base, see
+/// [`CoordinateBase`].
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Annotation {
     pub start: usize,
     pub end: usize,
@@ -9,21 +20,31 @@ pub struct Annotation {
 }
 
 /// Create a new record for the subsequence that the annotation references in a given record.
-pub fn apply_annotation(record: &fasta::Record, annotation: &Annotation) -> fasta::Record {
-    fasta::Record::with_attrs(
+///
+/// The description has the start and end of the annotation in `coordinate_base`.
+///
+/// Panics when the annotation does not fit in the record, see [`try_apply_annotation`].
+pub fn apply_annotation(
+    record: &fasta::Record,
+    annotation: &Annotation,
+    coordinate_base: CoordinateBase,
+) -> fasta::Record {
+    try_apply_annotation(record, annotation, coordinate_base)
+        .expect("Annotation should be within the annotated record.")
+}
+
+/// Like [`apply_annotation`], but `None` when the annotation does not fit in the record.
+pub fn try_apply_annotation(
+    record: &fasta::Record,
+    annotation: &Annotation,
+    coordinate_base: CoordinateBase,
+) -> Option<fasta::Record> {
+    let seq = record.seq().get(annotation.start..annotation.end)?;
+    Some(fasta::Record::with_attrs(
         format!("{}_{}", annotation.name, record.id()).as_str(),
-        Some(
-            format!(
-                "IMGT Number {} on {}|{}|{}",
-                annotation.name,
-                record.id(),
-                annotation.start,
-                annotation.end
-            )
-            .as_str(),
-        ),
-        &record.seq()[annotation.start..annotation.end],
-    )
+        Some(annotation_description(record, annotation, coordinate_base).as_str()),
+        seq,
+    ))
 }
 
 /// IMGT Framework (FRx-IMGT) annotations of a VREGION sequence.
@@ -62,6 +83,34 @@ impl IntoIterator for CDRAnnotation {
     }
 }
 
+/// The residues of every IMGT region of a query, see [`VRegionAnnotation::region_sequences`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RegionSequences {
+    pub fr1: Vec<u8>,
+    pub cdr1: Vec<u8>,
+    pub fr2: Vec<u8>,
+    pub cdr2: Vec<u8>,
+    pub fr3: Vec<u8>,
+    /// The CDR3-IMGT (105-117).
+    pub cdr3: Vec<u8>,
+    pub fr4: Vec<u8>,
+}
+
+impl RegionSequences {
+    /// The residues of a region.
+    pub fn get(&self, region: ImgtRegion) -> &[u8] {
+        match region {
+            ImgtRegion::Fr1 => &self.fr1,
+            ImgtRegion::Cdr1 => &self.cdr1,
+            ImgtRegion::Fr2 => &self.fr2,
+            ImgtRegion::Cdr2 => &self.cdr2,
+            ImgtRegion::Fr3 => &self.fr3,
+            ImgtRegion::Cdr3 => &self.cdr3,
+            ImgtRegion::Fr4 => &self.fr4,
+        }
+    }
+}
+
 /// VREGION annotation of a sequence.
 #[derive(Clone)]
 pub struct VRegionAnnotation {
@@ -70,15 +119,239 @@ pub struct VRegionAnnotation {
 }
 
 impl VRegionAnnotation {
-    pub fn region_annotations(&self) -> Vec<Annotation> {
+    /// The residues of every region as slices of the sequence, without copying them.
+    ///
+    /// The regions are in the order of [`ImgtRegion::ALL`], with the CDR3-IMGT as
+    /// CDR3. Regions that are not within the sequence (when it is not the one that
+    /// was annotated) are left out. See [`apply_annotation`] for owned records.
+    pub fn region_slices<'a>(&self, seq: &'a [u8]) -> Vec<(ImgtRegion, &'a [u8])> {
+        ImgtRegion::ALL
+            .into_iter()
+            .zip(self.region_annotations(Cdr3Definition::Imgt))
+            .filter_map(|(region, annotation)| {
+                Some((region, seq.get(annotation.start..annotation.end)?))
+            })
+            .collect()
+    }
+
+    /// The residues of every region of the annotated record, as named fields.
+    ///
+    /// Copies the residues, see [`Self::region_slices`] to borrow them instead.
+    /// Regions that are not within the record are empty.
+    pub fn region_sequences(&self, record: &fasta::Record) -> RegionSequences {
+        let mut sequences = RegionSequences::default();
+        for (region, residues) in self.region_slices(record.seq()) {
+            let sequence = match region {
+                ImgtRegion::Fr1 => &mut sequences.fr1,
+                ImgtRegion::Cdr1 => &mut sequences.cdr1,
+                ImgtRegion::Fr2 => &mut sequences.fr2,
+                ImgtRegion::Cdr2 => &mut sequences.cdr2,
+                ImgtRegion::Fr3 => &mut sequences.fr3,
+                ImgtRegion::Cdr3 => &mut sequences.cdr3,
+                ImgtRegion::Fr4 => &mut sequences.fr4,
+            };
+            *sequence = residues.to_vec();
+        }
+        sequences
+    }
+
+    /// The residues of CDR1, CDR2 and CDR3 joined together, roughly the paratope.
+    ///
+    /// An empty CDR2 simply contributes nothing.
+    pub fn cdr_concatenation(&self, record: &fasta::Record) -> Vec<u8> {
+        self.cdr_annotation
+            .clone()
+            .into_iter()
+            .filter_map(|cdr| record.seq().get(cdr.start..cdr.end))
+            .flatten()
+            .copied()
+            .collect()
+    }
+
+    /// Whether the query contains the J-Trp/Phe (118).
+    ///
+    /// Without it, the CDR3 is truncated and FR4 is empty.
+    pub fn has_fr4_anchor(&self) -> bool {
+        let fr4 = &self.framework_annotation.fr4;
+        fr4.start < fr4.end
+    }
+
+    /// The IMGT JUNCTION (104-118) of a sequence, used for clonotyping.
+    ///
+    /// Runs from the second cysteine (the residue before the CDR3) through the
+    /// J-Trp/Phe (the first residue of FR4), both inclusive. `None` for a truncated
+    /// sequence without a J-Trp/Phe (see [`Self::has_fr4_anchor`]) and when the
+    /// junction is not within the sequence, such as one that was not annotated.
+    pub fn imgt_junction(&self, seq: &[u8]) -> Option<String> {
+        if !self.has_fr4_anchor() {
+            return None;
+        }
+        let second_cys = self.cdr_annotation.cdr3.start.checked_sub(1)?;
+        let j_trp_or_phe = self.framework_annotation.fr4.start;
+        seq.get(second_cys..=j_trp_or_phe)
+            .map(|junction| String::from_utf8_lossy(junction).into_owned())
+    }
+
+    /// All region annotations in order.
+    ///
+    /// With the junction definition the CDR3 is replaced by the JUNCTION, which
+    /// takes the last residue of FR3 and the first residue of FR4.
+    pub fn region_annotations(&self, cdr3_definition: Cdr3Definition) -> Vec<Annotation> {
+        let mut fr3 = self.framework_annotation.fr3.clone();
+        let mut cdr3 = self.cdr_annotation.cdr3.clone();
+        let mut fr4 = self.framework_annotation.fr4.clone();
+        if cdr3_definition == Cdr3Definition::Junction {
+            fr3.end -= 1;
+            if self.has_fr4_anchor() {
+                fr4.start += 1;
+            }
+            cdr3 = Annotation {
+                start: fr3.end,
+                end: fr4.start,
+                name: "JUNCTION".to_string(),
+            };
+        }
+
         vec![
             self.framework_annotation.fr1.clone(),
             self.cdr_annotation.cdr1.clone(),
             self.framework_annotation.fr2.clone(),
             self.cdr_annotation.cdr2.clone(),
-            self.framework_annotation.fr3.clone(),
-            self.cdr_annotation.cdr3.clone(),
-            self.framework_annotation.fr4.clone(),
+            fr3,
+            cdr3,
+            fr4,
         ]
     }
 }
+
+/// The IMGT positions (without insertions) of a region as named by [`VRegionAnnotation::region_annotations`].
+pub fn region_positions(
+    name: &str,
+    cdr3_definition: Cdr3Definition,
+) -> Option<std::ops::RangeInclusive<usize>> {
+    let junction = cdr3_definition == Cdr3Definition::Junction;
+    Some(match name {
+        "FR1-IMGT" => 1..=CDR1_START - 1,
+        "CDR1-IMGT" => CDR1_START..=FR2_START - 1,
+        "FR2-IMGT" => FR2_START..=CDR2_START - 1,
+        "CDR2-IMGT" => CDR2_START..=FR3_START - 1,
+        "FR3-IMGT" if junction => FR3_START..=CDR3_START - 2,
+        "FR3-IMGT" => FR3_START..=CDR3_START - 1,
+        "CDR3-IMGT" => CDR3_START..=FR4_START - 1,
+        "JUNCTION" => CDR3_START - 1..=FR4_START,
+        "FR4-IMGT" if junction => FR4_START + 1..=FR4_END,
+        "FR4-IMGT" => FR4_START..=FR4_END,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn annotation(start: usize, end: usize, name: &str) -> Annotation {
+        Annotation {
+            start,
+            end,
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_apply_annotation() {
+        let record = fasta::Record::with_attrs("query", None, b"QVQLVQSGAE");
+        let cdr = annotation(2, 5, "CDR1-IMGT");
+        let applied = apply_annotation(&record, &cdr, CoordinateBase::Zero);
+        assert_eq!(applied.id(), "CDR1-IMGT_query");
+        assert_eq!(applied.desc(), Some("IMGT Number CDR1-IMGT on query|2|5"));
+        assert_eq!(applied.seq(), b"QLV");
+        // One based and end inclusive, the third to fifth residue are still QLV.
+        let one_based = apply_annotation(&record, &cdr, CoordinateBase::One);
+        assert_eq!(one_based.desc(), Some("IMGT Number CDR1-IMGT on query|3|5"));
+        assert_eq!(&record.seq()[3 - 1..5], one_based.seq());
+        assert!(
+            try_apply_annotation(&record, &annotation(8, 11, "x"), CoordinateBase::One).is_none()
+        );
+    }
+
+    #[test]
+    fn test_cdr_concatenation_with_empty_cdr2() {
+        let vregion_annotation = VRegionAnnotation {
+            framework_annotation: FrameworkAnnotation {
+                fr1: annotation(0, 2, "FR1-IMGT"),
+                fr2: annotation(4, 6, "FR2-IMGT"),
+                fr3: annotation(6, 8, "FR3-IMGT"),
+                fr4: annotation(11, 12, "FR4-IMGT"),
+            },
+            cdr_annotation: CDRAnnotation {
+                cdr1: annotation(2, 4, "CDR1-IMGT"),
+                cdr2: annotation(6, 6, "CDR2-IMGT"),
+                cdr3: annotation(8, 11, "CDR3-IMGT"),
+            },
+        };
+        let record = fasta::Record::with_attrs("test", None, b"ffAAffffBBBf");
+        assert_eq!(vregion_annotation.cdr_concatenation(&record), b"AABBB");
+    }
+
+    #[test]
+    fn test_region_slices() {
+        let vregion_annotation = VRegionAnnotation {
+            framework_annotation: FrameworkAnnotation {
+                fr1: annotation(0, 2, "FR1-IMGT"),
+                fr2: annotation(4, 6, "FR2-IMGT"),
+                fr3: annotation(6, 8, "FR3-IMGT"),
+                fr4: annotation(11, 12, "FR4-IMGT"),
+            },
+            cdr_annotation: CDRAnnotation {
+                cdr1: annotation(2, 4, "CDR1-IMGT"),
+                cdr2: annotation(6, 6, "CDR2-IMGT"),
+                cdr3: annotation(8, 11, "CDR3-IMGT"),
+            },
+        };
+        let seq = b"ffAAffffBBBf".as_slice();
+        let slices = vregion_annotation.region_slices(seq);
+        assert_eq!(
+            slices.iter().map(|(region, _)| *region).collect::<Vec<_>>(),
+            ImgtRegion::ALL
+        );
+        let (_, cdr3) = slices[5];
+        assert_eq!(cdr3, b"BBB");
+        // The slice borrows the sequence instead of copying it.
+        assert!(std::ptr::eq(cdr3.as_ptr(), seq[8..].as_ptr()));
+
+        let truncated = vregion_annotation.region_slices(&seq[..10]);
+        assert_eq!(truncated.len(), 5);
+    }
+
+    #[test]
+    fn test_region_sequences() {
+        let vregion_annotation = VRegionAnnotation {
+            framework_annotation: FrameworkAnnotation {
+                fr1: annotation(0, 2, "FR1-IMGT"),
+                fr2: annotation(4, 6, "FR2-IMGT"),
+                fr3: annotation(6, 8, "FR3-IMGT"),
+                fr4: annotation(11, 12, "FR4-IMGT"),
+            },
+            cdr_annotation: CDRAnnotation {
+                cdr1: annotation(2, 4, "CDR1-IMGT"),
+                cdr2: annotation(6, 6, "CDR2-IMGT"),
+                cdr3: annotation(8, 11, "CDR3-IMGT"),
+            },
+        };
+        let record = fasta::Record::with_attrs("test", None, b"abCDefghIJKl");
+        let sequences = vregion_annotation.region_sequences(&record);
+        assert_eq!(
+            sequences,
+            RegionSequences {
+                fr1: b"ab".to_vec(),
+                cdr1: b"CD".to_vec(),
+                fr2: b"ef".to_vec(),
+                cdr2: vec![],
+                fr3: b"gh".to_vec(),
+                cdr3: b"IJK".to_vec(),
+                fr4: b"l".to_vec(),
+            }
+        );
+        assert_eq!(sequences.get(ImgtRegion::Cdr3), b"IJK");
+    }
+}
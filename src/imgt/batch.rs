@@ -0,0 +1,149 @@
+use std::{io::Write, sync::mpsc::sync_channel};
+
+use bio::io::fasta;
+use rayon::prelude::*;
+use thiserror::Error;
+
+use super::{
+    engine::{number_record_into, NumberingEngine},
+    output::OutputFormat,
+    query::Query,
+    NumberingError,
+};
+
+/// Number of records that are numbered together by default.
+pub const DEFAULT_CHUNK_SIZE: usize = 256;
+
+/// Number of chunks that can wait to be numbered, or to be written, by default.
+pub const DEFAULT_CAPACITY: usize = 4;
+
+/// How a batch is split over threads.
+///
+/// At most about `(2 * capacity + threads) * chunk_size` records are in memory at once,
+/// however many records the batch has.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BatchOptions {
+    /// Number of threads that number records, all cores when 0.
+    pub threads: usize,
+    /// Number of records that are numbered together.
+    pub chunk_size: usize,
+    /// Number of chunks that can wait to be numbered, and to be written.
+    pub capacity: usize,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        Self {
+            threads: 0,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            capacity: DEFAULT_CAPACITY,
+        }
+    }
+}
+
+/// A record of a batch that could not be numbered.
+#[derive(Debug)]
+pub struct BatchFailure {
+    pub record: fasta::Record,
+    pub error: NumberingError,
+}
+
+/// How many records of a batch were numbered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BatchSummary {
+    pub numbered: usize,
+    pub failed: usize,
+}
+
+/// Error thrown when a batch cannot be processed at all.
+#[derive(Debug, Error)]
+pub enum BatchErr {
+    #[error("Could not write output.")]
+    Io(#[from] std::io::Error),
+
+    #[error("Could not start the numbering threads.")]
+    ThreadPool(#[from] rayon::ThreadPoolBuildError),
+}
+
+/// Number many records in parallel, writing the numbering in input order.
+///
+/// A reader thread collects the records into chunks and passes them over a bounded
+/// channel to a thread pool, which numbers every chunk with
+/// [`number_into`](super::engine::number_into). A writer thread writes the
+/// numbered chunks in order and passes failures to `on_failure`.
+/// Every thread gets a clone of `engine`, so they use the same settings.
+pub fn number_batch<I, W, F>(
+    records: I,
+    engine: &NumberingEngine,
+    format: OutputFormat,
+    options: &BatchOptions,
+    mut out: W,
+    mut on_failure: F,
+) -> Result<BatchSummary, BatchErr>
+where
+    I: IntoIterator<Item = Query>,
+    I::IntoIter: Send,
+    W: Write + Send,
+    F: FnMut(BatchFailure) + Send,
+{
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.threads)
+        .build()?;
+    let chunk_size = options.chunk_size.max(1);
+    let (chunk_sender, chunk_receiver) = sync_channel::<Vec<Query>>(options.capacity);
+    let (result_sender, result_receiver) =
+        sync_channel::<Vec<Result<Vec<u8>, Box<BatchFailure>>>>(options.capacity);
+    let mut records = records.into_iter();
+
+    std::thread::scope(|scope| {
+        scope.spawn(move || loop {
+            let chunk: Vec<_> = records.by_ref().take(chunk_size).collect();
+            // Stop at the end of the records, or when numbering stopped.
+            if chunk.is_empty() || chunk_sender.send(chunk).is_err() {
+                break;
+            }
+        });
+
+        let writer = scope.spawn(move || -> std::io::Result<BatchSummary> {
+            let mut summary = BatchSummary::default();
+            for results in result_receiver {
+                for result in results {
+                    match result {
+                        Ok(output) => {
+                            out.write_all(&output)?;
+                            summary.numbered += 1;
+                        }
+                        Err(failure) => {
+                            on_failure(*failure);
+                            summary.failed += 1;
+                        }
+                    }
+                }
+            }
+            out.flush()?;
+            Ok(summary)
+        });
+
+        // Both channel ends are moved in, so they are dropped when numbering stops.
+        pool.install(move || {
+            for chunk in chunk_receiver {
+                let results = chunk
+                    .into_par_iter()
+                    .map_init(
+                        || engine.clone(),
+                        |engine, query| {
+                            let mut output = Vec::new();
+                            number_record_into(query, engine, &mut output, format).map(|_| output)
+                        },
+                    )
+                    .collect();
+                // The writer stops early when it cannot write.
+                if result_sender.send(results).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(writer.join().expect("Writer thread should not panic.")?)
+    })
+}
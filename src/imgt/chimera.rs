@@ -0,0 +1,277 @@
+use super::{
+    annotations::VRegionAnnotation,
+    find_best_reference_sequence_with_mode,
+    numbering::ImgtRegion,
+    query::Query,
+    reference::{ReferenceIndex, ReferenceSequence},
+    AlignmentMode, PairwiseAligner, ReferenceAlignment,
+};
+
+/// Minimal gain in alignment score for a query to be a chimera, see [`detect_chimera`].
+///
+/// With the scoring of [`super::new_aligner`] every substitution costs 2, so this
+/// is about five substitutions that realigning the halves explains. Somatic
+/// mutations rarely favour another germline by as much over a whole half.
+pub const MIN_CHIMERA_SCORE_GAIN: i32 = 10;
+
+/// A query of which the halves align best to references of different V genes.
+///
+/// Artifacts of PCR (template switching) and of assembly can join the start of one
+/// V-region to the end of another.
+pub struct Chimera {
+    /// Best alignment of the N-terminal half of the aligned part of the query.
+    pub n_terminal: ReferenceAlignment,
+    /// Best alignment of the C-terminal half of the aligned part of the query.
+    pub c_terminal: ReferenceAlignment,
+    /// How much higher the combined score of the halves is than when both are
+    /// aligned to the reference of the whole query.
+    pub score_gain: i32,
+}
+
+impl Chimera {
+    /// The germlines of both halves, as written to the output.
+    pub fn fields(&self) -> [String; 2] {
+        [
+            format!("chimera_n={}", v_gene_call(&self.n_terminal.reference)),
+            format!("chimera_c={}", v_gene_call(&self.c_terminal.reference)),
+        ]
+    }
+}
+
+/// The V gene (with allele) of a reference, or its name when it has none.
+fn v_gene_call(reference: &ReferenceSequence) -> &str {
+    reference.v_call().unwrap_or(&reference.name)
+}
+
+/// The V gene (without allele) of a reference, or its name when it has none.
+fn v_gene(reference: &ReferenceSequence) -> &str {
+    let call = v_gene_call(reference);
+    call.split_once('*').map_or(call, |(gene, _allele)| gene)
+}
+
+/// Check whether a query is a chimera of two germlines.
+///
+/// The aligned part of the query (see [`ReferenceAlignment::offsets`]) is split
+/// in two, and each half is aligned to all references on its own. The halves are
+/// partial V-regions, so they are always aligned locally. When they align best to
+/// different V genes, with a combined score at least [`MIN_CHIMERA_SCORE_GAIN`]
+/// higher than that of both halves aligned to the reference of the whole query,
+/// the query is a chimera. Where the reference of the whole query is as good as
+/// the best for a half, it is taken for that half.
+pub fn detect_chimera<A: PairwiseAligner>(
+    reference_alignment: &ReferenceAlignment,
+    ref_seqs: &ReferenceIndex,
+    aligner: &mut A,
+) -> Option<Chimera> {
+    let record = &reference_alignment.query_record;
+    let aligned = reference_alignment.offsets().query;
+    let middle = aligned.start + aligned.len() / 2;
+    let mut best_half = |range: std::ops::Range<usize>| {
+        let half = Query::new(record.id(), &record.seq()[range]);
+        let own = ReferenceAlignment {
+            reference: reference_alignment.reference.clone(),
+            alignment: AlignmentMode::Local.align(
+                aligner,
+                &reference_alignment.reference.get_sequence(),
+                &half.alignment_seq(),
+            ),
+            query_record: half.record().clone(),
+            modified_n_terminus: half.has_modified_n_terminus(),
+        };
+        let best =
+            find_best_reference_sequence_with_mode(half, ref_seqs, aligner, AlignmentMode::Local)
+                .ok()?;
+        let gain = best.alignment.score - own.alignment.score;
+        Some(if gain > 0 { (best, gain) } else { (own, 0) })
+    };
+    let (n_terminal, n_gain) = best_half(aligned.start..middle)?;
+    let (c_terminal, c_gain) = best_half(middle..aligned.end)?;
+
+    let score_gain = n_gain + c_gain;
+    (v_gene(&n_terminal.reference) != v_gene(&c_terminal.reference)
+        && score_gain >= MIN_CHIMERA_SCORE_GAIN)
+        .then_some(Chimera {
+            n_terminal,
+            c_terminal,
+            score_gain,
+        })
+}
+
+/// Minimal gain in alignment score of the CDRs for a query to be a CDR graft, see [`detect_cdr_graft`].
+///
+/// The CDR1 and CDR2 together are only about 16 residues, so this is lower than
+/// [`MIN_CHIMERA_SCORE_GAIN`]: three substitutions that the other germline explains.
+pub const MIN_CDR_GRAFT_SCORE_GAIN: i32 = 6;
+
+/// Regions of which the residues come from the V gene, other than the CDRs.
+const GRAFT_FRAMEWORKS: [ImgtRegion; 3] = [ImgtRegion::Fr1, ImgtRegion::Fr2, ImgtRegion::Fr3];
+
+/// CDRs that come from the V gene. The CDR3 is formed by the joining of the genes.
+const GRAFT_CDRS: [ImgtRegion; 2] = [ImgtRegion::Cdr1, ImgtRegion::Cdr2];
+
+/// A query of which the CDRs align best to a V gene other than its frameworks.
+///
+/// Humanized antibodies are engineered by grafting the CDRs of one germline (often
+/// of another species) onto the frameworks of another, so the reference of the
+/// whole query only describes part of it.
+pub struct CdrGraft<'a> {
+    /// The reference of which FR1 to FR3 best match those of the query.
+    pub framework: &'a ReferenceSequence,
+    /// The reference of which the CDR1 and CDR2 best match those of the query.
+    pub cdrs: &'a ReferenceSequence,
+    /// How much higher the score of the CDRs is with [`Self::cdrs`] than with [`Self::framework`].
+    pub score_gain: i32,
+}
+
+impl CdrGraft<'_> {
+    /// The germlines of the frameworks and the CDRs, as written to the output.
+    pub fn fields(&self) -> [String; 2] {
+        [
+            format!("graft_fr={}", v_gene_call(self.framework)),
+            format!("graft_cdr={}", v_gene_call(self.cdrs)),
+        ]
+    }
+}
+
+/// Check whether the CDRs of a query come from another germline than its frameworks.
+///
+/// Every framework and CDR of the query is aligned (locally) to the same region
+/// of every reference, and the scores are summed per reference over the frameworks
+/// (FR1 to FR3) and over the CDRs (CDR1 and CDR2). When the best references for
+/// both have different V genes, and the CDRs score at least
+/// [`MIN_CDR_GRAFT_SCORE_GAIN`] higher with theirs than with the framework
+/// reference, the query is a CDR graft. Equal scores go to the first reference by
+/// name. This aligns every region to every reference, so it is several times
+/// slower than finding the reference of the query.
+pub fn detect_cdr_graft<'a, A: PairwiseAligner>(
+    reference_alignment: &ReferenceAlignment,
+    vregion_annotation: &VRegionAnnotation,
+    ref_seqs: &'a ReferenceIndex,
+    aligner: &mut A,
+) -> Option<CdrGraft<'a>> {
+    let query_regions = vregion_annotation.region_slices(reference_alignment.query_record.seq());
+    let mut scores: Vec<_> = ref_seqs
+        .values()
+        .map(|reference| {
+            let sequence = reference.get_sequence();
+            let reference_regions = reference.get_vregion_annotation().region_slices(&sequence);
+            let mut score = |regions: &[ImgtRegion]| -> i32 {
+                query_regions
+                    .iter()
+                    .filter(|(region, residues)| regions.contains(region) && !residues.is_empty())
+                    .filter_map(|(region, residues)| {
+                        let (_, germline) =
+                            reference_regions
+                                .iter()
+                                .find(|(reference_region, germline)| {
+                                    reference_region == region && !germline.is_empty()
+                                })?;
+                        Some(aligner.local(germline, residues).score)
+                    })
+                    .sum()
+            };
+            (reference, score(&GRAFT_FRAMEWORKS), score(&GRAFT_CDRS))
+        })
+        .collect();
+    scores.sort_by(|a, b| a.0.name.cmp(&b.0.name));
+    // On equal scores `max_by_key` takes the last, so reverse to take the first reference.
+    let (framework, _, framework_cdr_score) = *scores
+        .iter()
+        .rev()
+        .max_by_key(|(_, framework_score, _)| *framework_score)?;
+    let (cdrs, _, cdr_score) = *scores
+        .iter()
+        .rev()
+        .max_by_key(|(_, _, cdr_score)| *cdr_score)?;
+
+    let score_gain = cdr_score - framework_cdr_score;
+    (v_gene(framework) != v_gene(cdrs) && score_gain >= MIN_CDR_GRAFT_SCORE_GAIN).then_some(
+        CdrGraft {
+            framework,
+            cdrs,
+            score_gain,
+        },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::imgt::{
+        find_best_reference_sequence,
+        fixtures::{diverged, named_reference, ref_seqs, TEST_ALIGNMENT_STR, TEST_SEQUENCE},
+        new_aligner,
+    };
+
+    #[test]
+    fn test_detect_chimera() {
+        let first_name = "Homo_sapiens_IGHV1-18*01_IGHJ6*01";
+        let second_name = "Homo_sapiens_IGHV3-23*01_IGHJ6*01";
+        let second_alignment_str = diverged(TEST_ALIGNMENT_STR);
+        let ref_seqs = ref_seqs([
+            named_reference(first_name),
+            ReferenceSequence::new(second_name, second_alignment_str.as_bytes()).unwrap(),
+        ]);
+        let second_sequence = second_alignment_str.replace('-', "");
+        // The start of the first germline joined to the end of the second.
+        let chimeric = format!("{}{}", &TEST_SEQUENCE[..56], &second_sequence[56..]);
+
+        let detect = |sequence: &str| {
+            let reference_alignment =
+                find_best_reference_sequence(Query::new("query", sequence.as_bytes()), &ref_seqs)
+                    .unwrap();
+            detect_chimera(&reference_alignment, &ref_seqs, &mut new_aligner())
+        };
+        let chimera = detect(&chimeric).expect("The joined sequence is a chimera.");
+        assert!(chimera.score_gain >= MIN_CHIMERA_SCORE_GAIN);
+        assert_eq!(
+            chimera.fields(),
+            ["chimera_n=IGHV1-18*01", "chimera_c=IGHV3-23*01"]
+        );
+        assert!(detect(TEST_SEQUENCE).is_none());
+        assert!(detect(&second_sequence).is_none());
+    }
+
+    #[test]
+    fn test_detect_cdr_graft() {
+        let first_name = "Homo_sapiens_IGHV1-18*01_IGHJ6*01";
+        let second_name = "Homo_sapiens_IGHV3-23*01_IGHJ6*01";
+        let second_alignment_str = diverged(TEST_ALIGNMENT_STR);
+        let second = ReferenceSequence::new(second_name, second_alignment_str.as_bytes()).unwrap();
+        let second_sequence = second.get_sequence();
+        let second_regions = second
+            .get_vregion_annotation()
+            .region_slices(&second_sequence);
+        let ref_seqs = ref_seqs([named_reference(first_name), second.clone()]);
+        let cdr = |region: ImgtRegion| {
+            let (_, residues) = second_regions
+                .iter()
+                .find(|(second_region, _)| *second_region == region)
+                .unwrap();
+            String::from_utf8(residues.to_vec()).unwrap()
+        };
+        // The frameworks of the first germline with the CDR1 and CDR2 of the second.
+        let grafted = TEST_SEQUENCE
+            .replace("GYTFTSYG", &cdr(ImgtRegion::Cdr1))
+            .replace("ISAYNGNT", &cdr(ImgtRegion::Cdr2));
+        assert_ne!(grafted, TEST_SEQUENCE);
+
+        let detect = |sequence: &str| {
+            let reference_alignment =
+                find_best_reference_sequence(Query::new("query", sequence.as_bytes()), &ref_seqs)
+                    .unwrap();
+            let vregion_annotation = reference_alignment.vregion_annotation().unwrap();
+            detect_cdr_graft(
+                &reference_alignment,
+                &vregion_annotation,
+                &ref_seqs,
+                &mut new_aligner(),
+            )
+            .map(|graft| (graft.fields(), graft.score_gain))
+        };
+        let (fields, score_gain) = detect(&grafted).expect("The CDRs are of the second germline.");
+        assert_eq!(fields, ["graft_fr=IGHV1-18*01", "graft_cdr=IGHV3-23*01"]);
+        assert!(score_gain >= MIN_CDR_GRAFT_SCORE_GAIN);
+        assert!(detect(TEST_SEQUENCE).is_none());
+    }
+}
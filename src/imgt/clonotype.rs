@@ -0,0 +1,73 @@
+use bio::alignment::distance::{hamming, levenshtein};
+
+/// Distance between two CDR3s: the Hamming distance for CDR3s of the same length,
+/// otherwise the Levenshtein distance.
+pub fn cdr3_distance(first: &[u8], second: &[u8]) -> u64 {
+    if first.len() == second.len() {
+        hamming(first, second)
+    } else {
+        levenshtein(first, second).into()
+    }
+}
+
+/// Group CDR3s into clonotypes, and give the clonotype of every CDR3.
+///
+/// CDR3s within `threshold` of each other (see [`cdr3_distance`]) are in the same
+/// clonotype, and so are CDR3s linked by a chain of such CDR3s (single linkage).
+/// Clonotypes are numbered from 0 in order of their first CDR3. This compares
+/// every pair of CDR3s, so it is quadratic in their number.
+pub fn cluster_cdr3s<T: AsRef<[u8]>>(cdr3s: &[T], threshold: u64) -> Vec<usize> {
+    // Union-find of the CDR3s, of which the root is the first CDR3 of the clonotype.
+    let mut parents: Vec<usize> = (0..cdr3s.len()).collect();
+    fn root(parents: &mut [usize], mut i: usize) -> usize {
+        while parents[i] != i {
+            parents[i] = parents[parents[i]];
+            i = parents[i];
+        }
+        i
+    }
+    for i in 0..cdr3s.len() {
+        for j in 0..i {
+            if cdr3_distance(cdr3s[i].as_ref(), cdr3s[j].as_ref()) <= threshold {
+                let (root_i, root_j) = (root(&mut parents, i), root(&mut parents, j));
+                parents[root_i.max(root_j)] = root_i.min(root_j);
+            }
+        }
+    }
+
+    let mut clonotypes = vec![0; cdr3s.len()];
+    let mut n_clonotypes = 0;
+    for i in 0..cdr3s.len() {
+        let first = root(&mut parents, i);
+        clonotypes[i] = if first == i {
+            n_clonotypes += 1;
+            n_clonotypes - 1
+        } else {
+            clonotypes[first]
+        };
+    }
+    clonotypes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cdr3_distance() {
+        assert_eq!(cdr3_distance(b"ARMDV", b"ARMDV"), 0);
+        assert_eq!(cdr3_distance(b"ARMDV", b"AKMDI"), 2);
+        // A deletion is one edit, instead of a substitution of every later residue.
+        assert_eq!(cdr3_distance(b"ARGMDV", b"ARMDV"), 1);
+    }
+
+    #[test]
+    fn test_cluster_cdr3s() {
+        let cdr3s = ["ARMDV", "ARGGYMDV", "AKMDV", "ARGGYFDV", "TTTTT", "AKMDI"];
+        assert_eq!(cluster_cdr3s(&cdr3s, 0), [0, 1, 2, 3, 4, 5]);
+        // AKMDI joins ARMDV through AKMDV, although it differs from it in two residues.
+        assert_eq!(cluster_cdr3s(&cdr3s, 1), [0, 1, 0, 1, 2, 0]);
+        assert_eq!(cluster_cdr3s(&cdr3s, 10), [0; 6]);
+        assert!(cluster_cdr3s::<&str>(&[], 1).is_empty());
+    }
+}
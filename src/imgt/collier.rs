@@ -0,0 +1,189 @@
+use std::io::Write;
+
+use bio::io::fasta;
+
+use super::{
+    annotations::Annotation,
+    numbering::ImgtRegion,
+    output::{format_position, InsertionFormat},
+};
+
+/// A strand or loop of the V domain, as laid out in the IMGT Collier de Perles.
+///
+/// The strands A to G form the two beta sheets of the domain, and the loops
+/// between B and C, C' and C'' and F and G are the CDRs. IMGT lets strands share
+/// the position of the turn between them (such as 15 of A and B), but here every
+/// position belongs to a single strand.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strand {
+    A,
+    B,
+    /// The BC loop, the CDR1-IMGT.
+    BcLoop,
+    C,
+    CPrime,
+    /// The C'C'' loop, the CDR2-IMGT.
+    CPrimeCDoublePrimeLoop,
+    CDoublePrime,
+    D,
+    E,
+    F,
+    /// The FG loop, the CDR3-IMGT.
+    FgLoop,
+    G,
+}
+
+impl Strand {
+    /// The strand or loop of an IMGT number (with or without insertion code, such
+    /// as `111.1`). `None` for names that are not IMGT numbers.
+    pub fn of_position(name: &str) -> Option<Strand> {
+        let position: usize = name.split('.').next()?.parse().ok()?;
+        Some(match position {
+            1..=15 => Strand::A,
+            16..=26 => Strand::B,
+            27..=38 => Strand::BcLoop,
+            39..=46 => Strand::C,
+            47..=55 => Strand::CPrime,
+            56..=65 => Strand::CPrimeCDoublePrimeLoop,
+            66..=74 => Strand::CDoublePrime,
+            75..=84 => Strand::D,
+            85..=96 => Strand::E,
+            97..=104 => Strand::F,
+            105..=117 => Strand::FgLoop,
+            118..=128 => Strand::G,
+            _ => return None,
+        })
+    }
+
+    /// The name of the strand as in the Collier de Perles, such as `C'` or `FG`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Strand::A => "A",
+            Strand::B => "B",
+            Strand::BcLoop => "BC",
+            Strand::C => "C",
+            Strand::CPrime => "C'",
+            Strand::CPrimeCDoublePrimeLoop => "C'C''",
+            Strand::CDoublePrime => "C''",
+            Strand::D => "D",
+            Strand::E => "E",
+            Strand::F => "F",
+            Strand::FgLoop => "FG",
+            Strand::G => "G",
+        }
+    }
+
+    pub fn is_loop(self) -> bool {
+        matches!(
+            self,
+            Strand::BcLoop | Strand::CPrimeCDoublePrimeLoop | Strand::FgLoop
+        )
+    }
+}
+
+/// Write every numbered residue of a record with its region and strand, see [`Strand`].
+///
+/// Every residue is a line with the columns: id, IMGT number, residue, region
+/// (see [`ImgtRegion::of_position`]), strand or loop and flags. Expects IMGT numbers
+/// with dotted insertions, which are written in `insertion_format`. Residues that
+/// are not numbered are left out.
+pub fn write_collier<W: Write>(
+    record: &fasta::Record,
+    numbering: &[Annotation],
+    flags: &[&str],
+    insertion_format: InsertionFormat,
+    mut writer: W,
+) -> std::io::Result<()> {
+    let flags = flags.join(",");
+    for annotation in numbering {
+        let (Some(residue), Some(region), Some(strand)) = (
+            record.seq().get(annotation.start),
+            ImgtRegion::of_position(&annotation.name),
+            Strand::of_position(&annotation.name),
+        ) else {
+            continue;
+        };
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            record.id(),
+            format_position(&annotation.name, insertion_format),
+            *residue as char,
+            region,
+            strand.name(),
+            flags
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_strand_of_position() {
+        assert_eq!(Strand::of_position("1"), Some(Strand::A));
+        assert_eq!(Strand::of_position("15"), Some(Strand::A));
+        assert_eq!(Strand::of_position("23"), Some(Strand::B));
+        assert_eq!(Strand::of_position("32.1"), Some(Strand::BcLoop));
+        assert_eq!(Strand::of_position("41"), Some(Strand::C));
+        assert_eq!(Strand::of_position("55"), Some(Strand::CPrime));
+        assert_eq!(
+            Strand::of_position("60"),
+            Some(Strand::CPrimeCDoublePrimeLoop)
+        );
+        assert_eq!(Strand::of_position("66"), Some(Strand::CDoublePrime));
+        assert_eq!(Strand::of_position("89"), Some(Strand::E));
+        assert_eq!(Strand::of_position("104"), Some(Strand::F));
+        assert_eq!(Strand::of_position("112.1"), Some(Strand::FgLoop));
+        assert_eq!(Strand::of_position("118"), Some(Strand::G));
+        assert_eq!(Strand::of_position("0"), None);
+        assert_eq!(Strand::of_position("129"), None);
+        assert_eq!(Strand::of_position("CDR1-IMGT"), None);
+
+        // The loops are the CDRs.
+        for position in 1..=128 {
+            let name = position.to_string();
+            assert_eq!(
+                Strand::of_position(&name).unwrap().is_loop(),
+                ImgtRegion::of_position(&name).unwrap().is_cdr()
+            );
+        }
+    }
+
+    #[test]
+    fn test_write_collier() {
+        let record = fasta::Record::with_attrs("query", None, b"CAW");
+        let numbering = [
+            Annotation {
+                name: "23".to_string(),
+                start: 0,
+                end: 1,
+            },
+            Annotation {
+                name: "111.1".to_string(),
+                start: 1,
+                end: 2,
+            },
+            Annotation {
+                name: "118".to_string(),
+                start: 2,
+                end: 3,
+            },
+        ];
+        let mut written = Vec::new();
+        write_collier(
+            &record,
+            &numbering,
+            &["missing_fr1"],
+            InsertionFormat::Letter,
+            &mut written,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(written).unwrap(),
+            "query\t23\tC\tFR1\tB\tmissing_fr1\nquery\t111A\tA\tCDR3\tFG\tmissing_fr1\nquery\t118\tW\tFR4\tG\tmissing_fr1\n"
+        );
+    }
+}
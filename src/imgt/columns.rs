@@ -0,0 +1,228 @@
+use std::io::Write;
+
+use super::{annotations::VRegionAnnotation, numbering::ImgtRegion, ReferenceAlignment};
+
+/// A field of a numbered query, to select the columns of a table with one row per query.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Column {
+    /// Id of the query.
+    Id,
+    /// Name of the matched reference.
+    Reference,
+    /// V gene of the matched reference, see [`ReferenceSequence::v_call`](super::reference::ReferenceSequence::v_call).
+    VGene,
+    /// Family of the V gene of the matched reference, see [`ReferenceSequence::gene_family`](super::reference::ReferenceSequence::gene_family).
+    GeneFamily,
+    /// J gene of the matched reference.
+    JGene,
+    /// Chain type of the matched reference.
+    Locus,
+    /// See [`ReferenceAlignment::normalized_score`].
+    Score,
+    /// See [`ReferenceAlignment::identity`].
+    Identity,
+    Fr1,
+    Cdr1,
+    Fr2,
+    Cdr2,
+    Fr3,
+    /// The CDR3-IMGT (105-117).
+    Cdr3,
+    Fr4,
+    /// The IMGT JUNCTION (104-118).
+    Junction,
+    Fr1Length,
+    Cdr1Length,
+    Fr2Length,
+    Cdr2Length,
+    Fr3Length,
+    Cdr3Length,
+    Fr4Length,
+}
+
+impl std::fmt::Display for Column {
+    /// Name of the column in the header, as on the command line.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl Column {
+    /// All columns, in the order of their variants.
+    pub const ALL: [Column; 23] = [
+        Column::Id,
+        Column::Reference,
+        Column::VGene,
+        Column::GeneFamily,
+        Column::JGene,
+        Column::Locus,
+        Column::Score,
+        Column::Identity,
+        Column::Fr1,
+        Column::Cdr1,
+        Column::Fr2,
+        Column::Cdr2,
+        Column::Fr3,
+        Column::Cdr3,
+        Column::Fr4,
+        Column::Junction,
+        Column::Fr1Length,
+        Column::Cdr1Length,
+        Column::Fr2Length,
+        Column::Cdr2Length,
+        Column::Fr3Length,
+        Column::Cdr3Length,
+        Column::Fr4Length,
+    ];
+
+    /// Name of the column, such as `cdr3_length`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Column::Id => "id",
+            Column::Reference => "reference",
+            Column::VGene => "v_gene",
+            Column::GeneFamily => "gene_family",
+            Column::JGene => "j_gene",
+            Column::Locus => "locus",
+            Column::Score => "score",
+            Column::Identity => "identity",
+            Column::Fr1 => "fr1",
+            Column::Cdr1 => "cdr1",
+            Column::Fr2 => "fr2",
+            Column::Cdr2 => "cdr2",
+            Column::Fr3 => "fr3",
+            Column::Cdr3 => "cdr3",
+            Column::Fr4 => "fr4",
+            Column::Junction => "junction",
+            Column::Fr1Length => "fr1_length",
+            Column::Cdr1Length => "cdr1_length",
+            Column::Fr2Length => "fr2_length",
+            Column::Cdr2Length => "cdr2_length",
+            Column::Fr3Length => "fr3_length",
+            Column::Cdr3Length => "cdr3_length",
+            Column::Fr4Length => "fr4_length",
+        }
+    }
+
+    /// The value of the column for an annotated query.
+    ///
+    /// Fields that the query does not have (such as the V gene of a reference
+    /// without one, or the junction of a query without J-Trp/Phe) are empty.
+    /// Scores are written with three decimals.
+    pub fn value(
+        self,
+        reference_alignment: &ReferenceAlignment,
+        vregion_annotation: &VRegionAnnotation,
+    ) -> String {
+        let record = &reference_alignment.query_record;
+        let reference = &reference_alignment.reference;
+        let residues = |region: ImgtRegion| {
+            String::from_utf8_lossy(vregion_annotation.region_sequences(record).get(region))
+                .into_owned()
+        };
+        let length = |region: ImgtRegion| {
+            vregion_annotation
+                .region_sequences(record)
+                .get(region)
+                .len()
+                .to_string()
+        };
+        match self {
+            Column::Id => record.id().to_string(),
+            Column::Reference => reference.name.clone(),
+            Column::VGene => reference.v_call().unwrap_or_default().to_string(),
+            Column::GeneFamily => reference.gene_family().unwrap_or_default().to_string(),
+            Column::JGene => reference.j_call().unwrap_or_default().to_string(),
+            Column::Locus => reference.locus().unwrap_or_default().to_string(),
+            Column::Score => format!("{:.3}", reference_alignment.normalized_score()),
+            Column::Identity => format!("{:.3}", reference_alignment.identity()),
+            Column::Fr1 => residues(ImgtRegion::Fr1),
+            Column::Cdr1 => residues(ImgtRegion::Cdr1),
+            Column::Fr2 => residues(ImgtRegion::Fr2),
+            Column::Cdr2 => residues(ImgtRegion::Cdr2),
+            Column::Fr3 => residues(ImgtRegion::Fr3),
+            Column::Cdr3 => residues(ImgtRegion::Cdr3),
+            Column::Fr4 => residues(ImgtRegion::Fr4),
+            Column::Junction => vregion_annotation
+                .imgt_junction(record.seq())
+                .unwrap_or_default(),
+            Column::Fr1Length => length(ImgtRegion::Fr1),
+            Column::Cdr1Length => length(ImgtRegion::Cdr1),
+            Column::Fr2Length => length(ImgtRegion::Fr2),
+            Column::Cdr2Length => length(ImgtRegion::Cdr2),
+            Column::Fr3Length => length(ImgtRegion::Fr3),
+            Column::Cdr3Length => length(ImgtRegion::Cdr3),
+            Column::Fr4Length => length(ImgtRegion::Fr4),
+        }
+    }
+}
+
+/// Write the header line of a table with the given columns.
+pub fn write_columns_header<W: Write>(
+    columns: &[Column],
+    delimiter: char,
+    mut writer: W,
+) -> std::io::Result<()> {
+    let names: Vec<_> = columns.iter().map(|column| column.to_string()).collect();
+    writeln!(writer, "{}", names.join(&delimiter.to_string()))
+}
+
+/// Write an annotated query as a line of a table with the given columns, see [`Column::value`].
+pub fn write_columns_row<W: Write>(
+    columns: &[Column],
+    delimiter: char,
+    reference_alignment: &ReferenceAlignment,
+    vregion_annotation: &VRegionAnnotation,
+    mut writer: W,
+) -> std::io::Result<()> {
+    let values: Vec<_> = columns
+        .iter()
+        .map(|column| column.value(reference_alignment, vregion_annotation))
+        .collect();
+    writeln!(writer, "{}", values.join(&delimiter.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::imgt::{
+        find_best_reference_sequence,
+        fixtures::{named_reference, ref_seqs, TEST_SEQUENCE},
+        query::Query,
+    };
+
+    #[test]
+    fn test_columns() {
+        let name = "Homo_sapiens_IGHV1-18*01_IGHJ6*01";
+        let ref_seqs = ref_seqs([named_reference(name)]);
+        let reference_alignment =
+            find_best_reference_sequence(Query::new("query", TEST_SEQUENCE.as_bytes()), &ref_seqs)
+                .unwrap();
+        let vregion_annotation = reference_alignment.vregion_annotation().unwrap();
+
+        let columns = [
+            Column::Id,
+            Column::VGene,
+            Column::GeneFamily,
+            Column::Cdr3,
+            Column::Identity,
+            Column::Cdr2Length,
+            Column::Junction,
+        ];
+        let mut written = Vec::new();
+        write_columns_header(&columns, ',', &mut written).unwrap();
+        write_columns_row(
+            &columns,
+            ',',
+            &reference_alignment,
+            &vregion_annotation,
+            &mut written,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(written).unwrap(),
+            "id,v_gene,gene_family,cdr3,identity,cdr2_length,junction\nquery,IGHV1-18*01,IGHV1,ARMDV,1.000,8,CARMDVW\n"
+        );
+        assert_eq!(Column::JGene.to_string(), "j_gene");
+    }
+}
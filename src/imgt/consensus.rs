@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+
+use bio::io::fasta;
+
+use super::annotations::Annotation;
+
+/// A query together with its (IMGT) numbering.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NumberedSequence {
+    pub record: fasta::Record,
+    pub numbering: Vec<Annotation>,
+}
+
+impl NumberedSequence {
+    pub fn new(record: fasta::Record, numbering: Vec<Annotation>) -> Self {
+        Self { record, numbering }
+    }
+
+    /// The number and residue of every numbered residue, in order.
+    pub fn residues(&self) -> impl Iterator<Item = (&str, u8)> + '_ {
+        self.numbering.iter().filter_map(|annotation| {
+            self.record
+                .seq()
+                .get(annotation.start)
+                .map(|residue| (annotation.name.as_str(), *residue))
+        })
+    }
+
+    /// The IMGT positions between the first and last numbered position that have no residue.
+    ///
+    /// Only positions without insertions are considered, so for example a deletion
+    /// of 10 gives `["10"]`. The unused positions in the middle of a short CDR are
+    /// included as well, as they have no residue either.
+    pub fn deleted_positions(&self) -> Vec<String> {
+        let numbered: Vec<usize> = self
+            .numbering
+            .iter()
+            .filter_map(|annotation| annotation.name.parse().ok())
+            .collect();
+        let (Some(first), Some(last)) = (numbered.iter().min(), numbered.iter().max()) else {
+            return vec![];
+        };
+        (*first..=*last)
+            .filter(|position| !numbered.contains(position))
+            .map(|position| position.to_string())
+            .collect()
+    }
+}
+
+/// Sort key of an IMGT number, such that numbers sort in sequence order.
+///
+/// Insertions follow their position (`111`, `111.1`, `111.2`), except for those
+/// on 112, which precede it in reverse order (`112.2`, `112.1`, `112`). Names that
+/// are not IMGT numbers sort last.
+pub fn imgt_position_key(name: &str) -> (usize, isize) {
+    let (position, insertion) = match name.split_once('.') {
+        Some((position, insertion)) => (position, insertion.parse::<isize>().ok()),
+        None => (name, Some(0)),
+    };
+    match (position.parse(), insertion) {
+        (Ok(112), Some(insertion)) => (112, -insertion),
+        (Ok(position), Some(insertion)) => (position, insertion),
+        _ => (usize::MAX, 0),
+    }
+}
+
+/// How often each residue occurs at a number.
+type ResidueCounts = BTreeMap<u8, usize>;
+
+/// The consensus numbering of related sequences, such as a clonal family.
+///
+/// The consensus has every number of any of the sequences, in IMGT order, with
+/// the most frequent residue at that number. Ties go to the alphabetically first
+/// residue, so the consensus does not depend on the order of the sequences.
+pub fn consensus_numbering(sequences: &[NumberedSequence]) -> NumberedSequence {
+    let mut counts: BTreeMap<((usize, isize), &str), ResidueCounts> = BTreeMap::new();
+    for sequence in sequences {
+        for (name, residue) in sequence.residues() {
+            *counts
+                .entry((imgt_position_key(name), name))
+                .or_default()
+                .entry(residue)
+                .or_default() += 1;
+        }
+    }
+
+    let (names, residues): (Vec<_>, Vec<_>) = counts
+        .into_iter()
+        .map(|((_, name), residue_counts)| {
+            let (residue, _) = residue_counts
+                .into_iter()
+                // On equal counts `max_by_key` takes the last, so reverse to take the first residue.
+                .rev()
+                .max_by_key(|(_, count)| *count)
+                .expect("Every number has at least one residue.");
+            (name.to_string(), residue)
+        })
+        .unzip();
+    let numbering = names
+        .into_iter()
+        .enumerate()
+        .map(|(i, name)| Annotation {
+            start: i,
+            end: i + 1,
+            name,
+        })
+        .collect();
+
+    NumberedSequence::new(
+        fasta::Record::with_attrs(
+            "consensus",
+            Some(format!("Consensus numbering of {} sequences", sequences.len()).as_str()),
+            &residues,
+        ),
+        numbering,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::imgt::{
+        find_best_reference_sequence,
+        fixtures::{test_ref_seqs, TEST_SEQUENCE},
+        numbering::NumberingScheme,
+        query::Query,
+    };
+
+    // The test sequence with a substitution in the CDR2 (ISAYNGNT -> ISCYNGNT).
+    const CDR2_MUTATED_SEQUENCE: &str = "QVQLVQSGAEVKKPGASVKVSCKASGYTFTSYGISWVRQAPGQGLEWMGWISCYNGNTNYAQKLQGRVTMTTDTSTSTAYMELRSLRSDDTAVYYCARMDVWGQGTTVTVSS";
+    // The test sequence with a longer CDR3 (ARMDV -> ARGGYMDV).
+    const CDR3_LONGER_SEQUENCE: &str = "QVQLVQSGAEVKKPGASVKVSCKASGYTFTSYGISWVRQAPGQGLEWMGWISAYNGNTNYAQKLQGRVTMTTDTSTSTAYMELRSLRSDDTAVYYCARGGYMDVWGQGTTVTVSS";
+
+    #[test]
+    fn test_imgt_position_key() {
+        let mut names = ["112", "111.1", "112.1", "111", "113", "112.2", "110"];
+        names.sort_by_key(|name| imgt_position_key(name));
+        assert_eq!(
+            names,
+            ["110", "111", "111.1", "112.2", "112.1", "112", "113"]
+        );
+    }
+
+    #[test]
+    fn test_deleted_positions() {
+        let ref_seqs = test_ref_seqs();
+        // Without its first residues, so positions before the start of the query are not deleted.
+        let reference_alignment = find_best_reference_sequence(
+            Query::new("query", &TEST_SEQUENCE.as_bytes()[3..]),
+            &ref_seqs,
+        )
+        .unwrap();
+        let numbering = reference_alignment
+            .numbered(&NumberingScheme::default())
+            .unwrap();
+        let numbered = NumberedSequence::new(reference_alignment.query_record, numbering);
+
+        let deleted = numbered.deleted_positions();
+        // The gaps of the germline in FR1, CDR1, CDR2, FR3 and the short CDR3.
+        assert_eq!(deleted[0], "10");
+        for position in ["31", "32", "33", "34", "60", "61", "73", "108", "115"] {
+            assert!(deleted.contains(&position.to_string()), "{}", position);
+        }
+        assert!(!deleted.contains(&"1".to_string()));
+        assert!(!deleted.contains(&"104".to_string()));
+    }
+
+    #[test]
+    fn test_consensus_numbering() {
+        let ref_seqs = test_ref_seqs();
+        let numbered = |id: &str, sequence: &str| {
+            let reference_alignment =
+                find_best_reference_sequence(Query::new(id, sequence.as_bytes()), &ref_seqs)
+                    .unwrap();
+            let numbering = reference_alignment
+                .numbered(&NumberingScheme::default())
+                .unwrap();
+            NumberedSequence::new(reference_alignment.query_record, numbering)
+        };
+        let clone = [
+            numbered("1", TEST_SEQUENCE),
+            numbered("2", CDR2_MUTATED_SEQUENCE),
+            numbered("3", CDR3_LONGER_SEQUENCE),
+        ];
+
+        let consensus = consensus_numbering(&clone);
+        let residues: HashMap<_, _> = consensus.residues().collect();
+        // Two out of three sequences have the germline alanine at 58.
+        assert_eq!(residues["58"], b'A');
+        // The numbers of the longer CDR3 are all in the consensus.
+        assert!(clone[2]
+            .residues()
+            .all(|(name, _)| residues.contains_key(name)));
+        assert_eq!(consensus.numbering.len(), clone[2].numbering.len());
+        assert!(consensus
+            .numbering
+            .windows(2)
+            .all(|pair| imgt_position_key(&pair[0].name) < imgt_position_key(&pair[1].name)));
+    }
+}
@@ -1,14 +1,23 @@
 use bio::alignment::{Alignment, AlignmentOperation};
+use itertools::Itertools;
 use thiserror::Error;
+use tracing::debug;
+
+use super::{
+    annotations::Annotation, input::is_gap, junction::is_j_motif, query::standard_residue,
+};
 
 /// Container for the positions of a sequence that correspond with IMGT conserved residues in the VREGION.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct ConservedResidues {
     pub first_cys: usize,
     pub conserved_trp: usize,
     pub hydrophobic_89: usize,
     pub second_cys: usize,
     pub j_trp_or_phe: usize,
+    /// How far the hydrophobic 89 was moved from the residue aligned to it, see
+    /// [`ConservedResidues::transfer`]. Always 0 for references.
+    pub hydrophobic_89_shift: isize,
 }
 
 /// Count the number of gaps in a sequence before a given index.
@@ -17,7 +26,7 @@ pub struct ConservedResidues {
 pub fn count_gaps_in_sequence_before_index(sequence: &[u8], index: usize) -> usize {
     // TODO: Accept multiple gap characters.
     sequence
-        .into_iter()
+        .iter()
         .take(index)
         .filter(|char| **char == b'-')
         .count()
@@ -45,44 +54,408 @@ fn find_corresponding_position_in_alignment(alignment: &Alignment, pos: usize) -
         .map(|(_, y, _)| y)
 }
 
+/// IMGT positions of the conserved residues, in the order of the fields of `ConservedResidues`.
+pub const CONSERVED_POSITIONS: [usize; 5] = [23, 41, 89, 104, 118];
+
+/// Residues expected at the conserved positions, in the order of the fields of `ConservedResidues`.
+const CONSERVED_RESIDUE_IDENTITIES: [&[u8]; 5] = [b"C", b"W", b"AILMFWYV", b"C", b"FW"];
+
+/// The residues a reference alignment may have at each of the conserved positions.
+///
+/// Defaults to the IMGT definition: cysteine at 23, tryptophan at 41, a hydrophobic
+/// residue at 89, cysteine at 104 and phenylalanine or tryptophan at 118. A custom
+/// spec can accept divergent references, for example of other species.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConservedResidueSpec {
+    pub first_cys: Vec<u8>,
+    pub conserved_trp: Vec<u8>,
+    pub hydrophobic_89: Vec<u8>,
+    pub second_cys: Vec<u8>,
+    pub j_trp_or_phe: Vec<u8>,
+    /// How many columns an anchor may be away from its IMGT position, see [`Self::find_anchor`].
+    ///
+    /// 0 (the default) requires every anchor at exactly its IMGT position.
+    pub anchor_window: usize,
+}
+
+impl Default for ConservedResidueSpec {
+    fn default() -> Self {
+        let [first_cys, conserved_trp, hydrophobic_89, second_cys, j_trp_or_phe] =
+            CONSERVED_RESIDUE_IDENTITIES.map(<[u8]>::to_vec);
+        Self {
+            first_cys,
+            conserved_trp,
+            hydrophobic_89,
+            second_cys,
+            j_trp_or_phe,
+            anchor_window: 0,
+        }
+    }
+}
+
+impl ConservedResidueSpec {
+    fn to_array(&self) -> [&[u8]; 5] {
+        [
+            &self.first_cys,
+            &self.conserved_trp,
+            &self.hydrophobic_89,
+            &self.second_cys,
+            &self.j_trp_or_phe,
+        ]
+    }
+
+    /// The residues allowed at an IMGT position, `None` if the position is not conserved.
+    pub fn allowed_residues(&self, imgt_position: usize) -> Option<&[u8]> {
+        CONSERVED_POSITIONS
+            .iter()
+            .zip(self.to_array())
+            .find(|(position, _residues)| **position == imgt_position)
+            .map(|(_position, residues)| residues)
+    }
+
+    /// Find the (one based) column of the alignment that holds the anchor of an IMGT position.
+    ///
+    /// Curated alignments with unusual gaps can have an anchor a column or so from
+    /// its IMGT position. The allowed residue closest to the IMGT position, within
+    /// [`Self::anchor_window`] columns, is taken as the anchor. On equal distance
+    /// the earlier column wins.
+    pub fn find_anchor(&self, alignment: &[u8], imgt_position: usize) -> Option<usize> {
+        let residues = self.allowed_residues(imgt_position)?;
+        (0..=self.anchor_window)
+            .flat_map(|offset| {
+                [
+                    imgt_position.checked_sub(offset),
+                    Some(imgt_position + offset),
+                ]
+            })
+            .flatten()
+            .find(|column| {
+                column
+                    .checked_sub(1)
+                    .and_then(|index| alignment.get(index))
+                    .is_some_and(|residue| residues.contains(residue))
+            })
+    }
+
+    /// Move every anchor of an IMGT-gapped alignment onto the column of its IMGT position.
+    ///
+    /// An anchor found off its IMGT position (see [`Self::find_anchor`]) would be
+    /// numbered by its column, so a conserved Trp in column 42 would get 42. Only
+    /// gaps may lie between the anchor and its IMGT column. These move to the other
+    /// side of the anchor, so all other residues keep their column. `None` when a
+    /// residue lies between them or an anchor is missing.
+    pub fn align_anchors(&self, alignment: &[u8]) -> Option<Vec<u8>> {
+        let mut aligned = alignment.to_vec();
+        for position in CONSERVED_POSITIONS {
+            let column = self.find_anchor(alignment, position)?;
+            // Zero based, from the first to the last of the two columns.
+            let columns = &mut aligned[column.min(position) - 1..column.max(position)];
+            if columns.iter().filter(|residue| !is_gap(**residue)).count() != 1 {
+                return None;
+            }
+            if column > position {
+                columns.rotate_right(1);
+            } else {
+                columns.rotate_left(1);
+            }
+        }
+        Some(aligned)
+    }
+
+    /// Find the conserved residues of an IMGT-gapped alignment, if it has allowed residues at all of them.
+    ///
+    /// The anchors may be off their IMGT position by up to [`Self::anchor_window`]
+    /// columns, but must stay in order.
+    pub fn validate(&self, alignment: &[u8]) -> Option<ConservedResidues> {
+        if alignment.len() < CONSERVED_POSITIONS[4] {
+            return None;
+        }
+        let columns = CONSERVED_POSITIONS
+            .iter()
+            .map(|position| self.find_anchor(alignment, *position))
+            .collect::<Option<Vec<_>>>()?;
+        if !columns.windows(2).all(|pair| pair[0] < pair[1]) {
+            return None;
+        }
+        Some(ConservedResidues::from_array(
+            <[usize; 5]>::try_from(columns)
+                .expect("There are five conserved positions.")
+                .map(|column| column - count_gaps_in_sequence_before_index(alignment, column)),
+        ))
+    }
+}
+
+/// How far from the aligned position alternative candidates for a conserved residue are searched.
+const CANDIDATE_WINDOW: usize = 2;
+
+/// How far from the aligned position alternatives for the hydrophobic 89 are searched.
+///
+/// Indels in FR3 can shift the hydrophobic residue by one, but further away another
+/// hydrophobic residue is more likely than a shifted 89.
+const HYDROPHOBIC_89_WINDOW: usize = 1;
+
 impl ConservedResidues {
-    /// Confirm that an alignment sequence is a valid IMGT reference sequence.
+    fn to_array(&self) -> [usize; 5] {
+        [
+            self.first_cys,
+            self.conserved_trp,
+            self.hydrophobic_89,
+            self.second_cys,
+            self.j_trp_or_phe,
+        ]
+    }
+
+    fn from_array(positions: [usize; 5]) -> Self {
+        let [first_cys, conserved_trp, hydrophobic_89, second_cys, j_trp_or_phe] = positions;
+        Self {
+            first_cys,
+            conserved_trp,
+            hydrophobic_89,
+            second_cys,
+            j_trp_or_phe,
+            hydrophobic_89_shift: 0,
+        }
+    }
+
+    /// The IMGT numbers that a numbering of the query gives the conserved residues.
+    ///
+    /// In the order of the fields, so for a correct numbering this is
+    /// `23`, `41`, `89`, `104` and `118`. `None` for a residue that is not numbered.
+    pub fn imgt_labels<'a>(&self, numbering: &'a [Annotation]) -> [Option<&'a str>; 5] {
+        self.to_array().map(|position| {
+            numbering
+                .iter()
+                // Conserved residues are one based, annotations are zero based.
+                .find(|annotation| annotation.start == position - 1)
+                .map(|annotation| annotation.name.as_str())
+        })
+    }
+
+    /// Count the conserved positions (one based) that do not hold a residue of the spec in a sequence.
+    fn count_unexpected_residues(
+        positions: &[usize; 5],
+        spec: &ConservedResidueSpec,
+        sequence: &[u8],
+    ) -> usize {
+        positions
+            .iter()
+            .zip(spec.to_array())
+            .filter(|(position, identities)| !Self::is_expected(**position, identities, sequence))
+            .count()
+    }
+
+    /// Whether a position (one based) of a sequence holds one of the expected residues.
     ///
-    /// The alignment sequence should be of the shap "ABC-DE", similar to what you
-    /// would find in a single line of a stockholm file.
+    /// A non-standard residue counts as its [`standard_residue`], so a selenocysteine is a cysteine.
+    fn is_expected(position: usize, identities: &[u8], sequence: &[u8]) -> bool {
+        sequence
+            .get(position - 1)
+            .is_some_and(|residue| identities.contains(&standard_residue(*residue)))
+    }
+
+    /// Whether positions are in order and leave room for the shortest CDR3.
+    fn has_consistent_layout(positions: &[usize; 5]) -> bool {
+        positions.windows(2).all(|pair| pair[0] < pair[1]) && positions[4] - positions[3] > 5
+    }
+
+    /// How much the distance between the hydrophobic 89 and 2nd-CYS deviates from the reference.
+    ///
+    /// These only have framework residues between them, so their spacing should be conserved.
+    fn spacing_deviation(&self, positions: &[usize; 5]) -> usize {
+        (positions[3] - positions[2]).abs_diff(self.second_cys - self.hydrophobic_89)
+    }
+
     /// Identify the conserved residues of a new sequence through the conserved residues of a reference sequence and an alignment between the two.
+    ///
+    /// The expected residues are those of `spec`, the spec that the reference was
+    /// checked with (see [`ReferenceSequence::get_spec`](super::reference::ReferenceSequence::get_spec)).
+    /// The positions aligned to the conserved residues of the reference are used,
+    /// unless they do not hold the expected residues, are out of order or are not
+    /// spaced like the reference. In that case nearby residues of the expected
+    /// kind are tried as well, and the most consistent set of positions is chosen:
+    /// first the fewest unexpected residues, then the spacing closest to the
+    /// reference and finally the smallest shift from the aligned positions.
+    ///
+    /// The hydrophobic 89 is an exception: an aligned hydrophobic residue is always
+    /// kept, otherwise only the residues directly next to it are tried. A move is
+    /// recorded in [`Self::hydrophobic_89_shift`].
     pub fn transfer(
         &self,
         alignment: &Alignment,
-        _destination: &[u8],
+        destination: &[u8],
+        spec: &ConservedResidueSpec,
+    ) -> Result<Self, TransferErr> {
+        self.transfer_with(alignment, destination, spec, false)
+    }
+
+    /// Like [`Self::transfer`], but tolerates a query that ends before the J-Trp/Phe (118).
+    ///
+    /// When only the J-Trp/Phe is not in the alignment, it is placed just past the
+    /// end of the destination, so `j_trp_or_phe > destination.len()`.
+    pub fn transfer_allowing_missing_j_trp_or_phe(
+        &self,
+        alignment: &Alignment,
+        destination: &[u8],
+        spec: &ConservedResidueSpec,
+    ) -> Result<Self, TransferErr> {
+        self.transfer_with(alignment, destination, spec, true)
+    }
+
+    /// The IMGT positions of the conserved residues of a sequence that do not hold a
+    /// canonical residue (one of `spec`), with the residue they hold instead.
+    ///
+    /// [`Self::transfer`] keeps the aligned residue when no nearby residue is canonical,
+    /// so a transferred 2nd-CYS can be a serine. A J-Trp/Phe past the end of the
+    /// sequence (see [`Self::transfer_allowing_missing_j_trp_or_phe`]) is not checked.
+    pub fn non_canonical_anchors(
+        &self,
+        sequence: &[u8],
+        spec: &ConservedResidueSpec,
+    ) -> Vec<(usize, u8)> {
+        CONSERVED_POSITIONS
+            .into_iter()
+            .zip(self.to_array())
+            .zip(spec.to_array())
+            .filter(|((_imgt_position, position), identities)| {
+                *position <= sequence.len() && !Self::is_expected(*position, identities, sequence)
+            })
+            .map(|((imgt_position, position), _identities)| (imgt_position, sequence[position - 1]))
+            .collect()
+    }
+
+    /// Fail on the first conserved residue that is not canonical, see [`Self::non_canonical_anchors`].
+    pub fn check_canonical(
+        &self,
+        sequence: &[u8],
+        spec: &ConservedResidueSpec,
+    ) -> Result<(), TransferErr> {
+        match self.non_canonical_anchors(sequence, spec).first() {
+            Some(&(imgt_position, residue)) => {
+                Err(TransferErr::NonCanonicalAnchor(imgt_position, residue))
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn transfer_with(
+        &self,
+        alignment: &Alignment,
+        destination: &[u8],
+        spec: &ConservedResidueSpec,
+        allow_missing_j_trp_or_phe: bool,
     ) -> Result<Self, TransferErr> {
-        // TODO: Ensure that on the destination string, the conserved aas are still there!
+        let mut aligned = self
+            .to_array()
+            .map(|position| find_corresponding_position_in_alignment(alignment, position));
+        if let [Some(_), Some(_), Some(_), Some(second_cys), None] = aligned {
+            aligned[4] = find_j_trp_or_phe_by_motif(destination, second_cys);
+        }
+        if allow_missing_j_trp_or_phe && aligned[..4].iter().all(Option::is_some) {
+            aligned[4] = aligned[4].or(Some(destination.len() + 1));
+        }
+        if aligned.contains(&None) {
+            return Err(TransferErr::ConservedPositionNotInAlignment);
+        }
+        let aligned = aligned.map(|position| position.unwrap());
+
+        if Self::count_unexpected_residues(&aligned, spec, destination) == 0
+            && Self::has_consistent_layout(&aligned)
+            && self.spacing_deviation(&aligned) == 0
+        {
+            return Ok(Self::from_array(aligned));
+        }
+
+        let candidates =
+            aligned
+                .iter()
+                .zip(spec.to_array())
+                .enumerate()
+                .map(|(i, (&position, identities))| {
+                    let window = match i {
+                        // An aligned hydrophobic 89 is kept.
+                        2 if Self::is_expected(position, identities, destination) => 0,
+                        2 => HYDROPHOBIC_89_WINDOW,
+                        _ => CANDIDATE_WINDOW,
+                    };
+                    let window = position.saturating_sub(window).max(1)
+                        ..=(position + window).min(destination.len());
+                    // A position past the end of the destination (a missing J-Trp/Phe) has no alternatives.
+                    std::iter::once(position)
+                        .chain(window.filter(move |candidate| {
+                            *candidate != position
+                                && position <= destination.len()
+                                && Self::is_expected(*candidate, identities, destination)
+                        }))
+                        .collect::<Vec<_>>()
+                });
+
+        let best = candidates
+            .multi_cartesian_product()
+            .map(|positions| -> [usize; 5] { positions.try_into().unwrap() })
+            .filter(Self::has_consistent_layout)
+            .min_by_key(|positions| {
+                (
+                    Self::count_unexpected_residues(positions, spec, destination),
+                    self.spacing_deviation(positions),
+                    positions
+                        .iter()
+                        .zip(aligned)
+                        .map(|(position, aligned)| position.abs_diff(aligned))
+                        .sum::<usize>(),
+                )
+            })
+            .unwrap_or(aligned);
+
+        let shift = best[2] as isize - aligned[2] as isize;
+        if shift != 0 {
+            debug!(shift, "Moved the hydrophobic 89 from its aligned residue.");
+        }
         Ok(Self {
-            first_cys: find_corresponding_position_in_alignment(alignment, self.first_cys)
-                .ok_or(TransferErr::ConservedPositionNotInAlignment)?,
-            conserved_trp: find_corresponding_position_in_alignment(alignment, self.conserved_trp)
-                .ok_or(TransferErr::ConservedPositionNotInAlignment)?,
-            hydrophobic_89: find_corresponding_position_in_alignment(
-                alignment,
-                self.hydrophobic_89,
-            )
-            .ok_or(TransferErr::ConservedPositionNotInAlignment)?,
-            second_cys: find_corresponding_position_in_alignment(alignment, self.second_cys)
-                .ok_or(TransferErr::ConservedPositionNotInAlignment)?,
-            j_trp_or_phe: find_corresponding_position_in_alignment(alignment, self.j_trp_or_phe)
-                .ok_or(TransferErr::ConservedPositionNotInAlignment)?,
+            hydrophobic_89_shift: shift,
+            ..Self::from_array(best)
         })
     }
 }
 
+/// Find the J-Trp/Phe (one based) of a sequence by the `[WF]G.G` motif after the 2nd-CYS (one based).
+///
+/// A local alignment can end before the J region when the CDR3 of the query is much
+/// longer than the one of the reference, as the gaps cost more than the J region
+/// scores. Only the first motif that leaves room for the shortest CDR3 is used.
+fn find_j_trp_or_phe_by_motif(sequence: &[u8], second_cys: usize) -> Option<usize> {
+    // The zero based index of the first J-Trp/Phe that passes `has_consistent_layout`.
+    let start = second_cys + 5;
+    let offset = sequence.get(start..)?.windows(4).position(is_j_motif)?;
+    // One based, so one past the zero based index.
+    Some(start + offset + 1)
+}
+
 /// Errors for when transfering conserved residues from one sequence to another.
 #[derive(Debug, Error)]
 pub enum TransferErr {
     #[error("Conserved residue not in alignment.")]
     ConservedPositionNotInAlignment,
+
+    #[error("Conserved residue {0} is {}, not a canonical residue.", char::from(*.1))]
+    NonCanonicalAnchor(usize, u8),
+}
+
+/// Errors for when finding the conserved residues in an alignment sequence.
+#[derive(Debug, Error)]
+pub enum ConservedResidueErr {
+    #[error(
+        "Alignment of length {0} is too short to contain position {}.",
+        CONSERVED_POSITIONS[4]
+    )]
+    AlignmentTooShort(usize),
+
+    #[error("Alignment has a gap at conserved position {0}.")]
+    GapAtConservedPosition(usize),
 }
 
-impl From<&[u8]> for ConservedResidues {
+impl TryFrom<&[u8]> for ConservedResidues {
+    type Error = ConservedResidueErr;
+
     /// Find the conserved residues in a sequence by the alignment.
     ///
     /// Expects that the following relations will be fulfilled:
@@ -92,29 +465,300 @@ impl From<&[u8]> for ConservedResidues {
     /// - hydrophobic amino acid at position 89
     /// - cysteine at position 104
     /// - phenylalanine or tryptophan at position 118
-    fn from(alignment: &[u8]) -> Self {
-        Self {
-            first_cys: 23 - count_gaps_in_sequence_before_index(alignment, 23),
-            conserved_trp: 41 - count_gaps_in_sequence_before_index(alignment, 41),
-            hydrophobic_89: 89 - count_gaps_in_sequence_before_index(alignment, 89),
-            second_cys: 104 - count_gaps_in_sequence_before_index(alignment, 104),
-            j_trp_or_phe: 118 - count_gaps_in_sequence_before_index(alignment, 118),
+    ///
+    /// Only the presence of residues at these positions is checked here, not their identity.
+    /// The anchors must be at exactly these positions, see [`ConservedResidueSpec::validate`]
+    /// to allow for some drift.
+    fn try_from(alignment: &[u8]) -> Result<Self, Self::Error> {
+        if alignment.len() < CONSERVED_POSITIONS[4] {
+            return Err(ConservedResidueErr::AlignmentTooShort(alignment.len()));
         }
+
+        let position = |imgt_position: usize| {
+            if alignment[imgt_position - 1] == b'-' {
+                return Err(ConservedResidueErr::GapAtConservedPosition(imgt_position));
+            }
+            Ok(imgt_position - count_gaps_in_sequence_before_index(alignment, imgt_position))
+        };
+
+        let [first_cys, conserved_trp, hydrophobic_89, second_cys, j_trp_or_phe] =
+            CONSERVED_POSITIONS.map(position);
+        Ok(Self {
+            first_cys: first_cys?,
+            conserved_trp: conserved_trp?,
+            hydrophobic_89: hydrophobic_89?,
+            second_cys: second_cys?,
+            j_trp_or_phe: j_trp_or_phe?,
+            hydrophobic_89_shift: 0,
+        })
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    const TEST_ALIGNMENT_STR: &str = "QVQLVQSGA-EVKKPGASVKVSCKASGYTF----TSYGISWVRQAPGQGLEWMGWISAY--NGNTNYAQKLQ-GRVTMTTDTSTSTAYMELRSLRSDDTAVYYCAR--------MDVWGQGTTVTVSS";
+    use crate::imgt::{
+        fixtures::{ref_seqs, test_ref_seqs, test_reference, TEST_ALIGNMENT_STR, TEST_SEQUENCE},
+        new_aligner,
+        reference::ReferenceSequence,
+    };
 
     #[test]
     fn test_conserved_amino_acids_from_str() {
-        let conserved_aas = ConservedResidues::from(TEST_ALIGNMENT_STR.as_bytes());
+        let conserved_aas = ConservedResidues::try_from(TEST_ALIGNMENT_STR.as_bytes()).unwrap();
         assert_eq!(conserved_aas.first_cys, 22);
         assert_eq!(conserved_aas.conserved_trp, 36);
         assert_eq!(conserved_aas.hydrophobic_89, 81);
         assert_eq!(conserved_aas.second_cys, 96);
         assert_eq!(conserved_aas.j_trp_or_phe, 102);
     }
+
+    #[test]
+    fn test_custom_spec() {
+        // A serine instead of the 2nd-CYS.
+        let mut alignment = TEST_ALIGNMENT_STR.as_bytes().to_vec();
+        alignment[103] = b'S';
+        let imgt = ConservedResidueSpec::default();
+        assert!(imgt.validate(TEST_ALIGNMENT_STR.as_bytes()).is_some());
+        assert!(imgt.validate(&alignment).is_none());
+
+        let spec = ConservedResidueSpec {
+            second_cys: b"CS".to_vec(),
+            ..Default::default()
+        };
+        assert_eq!(spec.allowed_residues(104), Some(&b"CS"[..]));
+        assert_eq!(spec.allowed_residues(105), None);
+        assert_eq!(
+            spec.validate(&alignment),
+            ConservedResidues::try_from(TEST_ALIGNMENT_STR.as_bytes()).ok()
+        );
+    }
+
+    #[test]
+    fn test_hydrophobic_89_spec() {
+        // A threonine instead of the hydrophobic 89 (TAYMELR -> TAYTELR).
+        let mut alignment = TEST_ALIGNMENT_STR.as_bytes().to_vec();
+        alignment[88] = b'T';
+        assert!(ConservedResidueSpec::default()
+            .validate(&alignment)
+            .is_none());
+        assert!(crate::imgt::reference::is_valid_alignment(&alignment).is_none());
+
+        let expanded = ConservedResidueSpec {
+            hydrophobic_89: b"AILMFWYVT".to_vec(),
+            ..Default::default()
+        };
+        assert_eq!(
+            expanded.validate(&alignment),
+            ConservedResidues::try_from(TEST_ALIGNMENT_STR.as_bytes()).ok()
+        );
+        // The cysteines are still checked as strictly.
+        alignment[103] = b'S';
+        assert!(expanded.validate(&alignment).is_none());
+    }
+
+    #[test]
+    fn test_anchor_window() {
+        // Move the conserved Trp to 42 (taking a gap from the CDR2) and the 2nd-CYS to
+        // 105 (taking a gap from the CDR3), so the residues keep their sequence position.
+        let drifted = TEST_ALIGNMENT_STR
+            .replace("ISWVRQ", "IS-WVRQ")
+            .replace("SAY--NG", "SAY-NG")
+            .replace("YYCAR--------", "YY-CAR-------");
+        assert_eq!(drifted.len(), TEST_ALIGNMENT_STR.len());
+        assert_eq!(&drifted.as_bytes()[103..105], b"-C");
+
+        let exact = ConservedResidueSpec::default();
+        assert!(exact.validate(drifted.as_bytes()).is_none());
+
+        let window = ConservedResidueSpec {
+            anchor_window: 1,
+            ..Default::default()
+        };
+        assert_eq!(window.find_anchor(drifted.as_bytes(), 41), Some(42));
+        assert_eq!(window.find_anchor(drifted.as_bytes(), 104), Some(105));
+        // The hydrophobic 89 did not move, even though 88 is hydrophobic as well.
+        assert_eq!(window.find_anchor(drifted.as_bytes(), 89), Some(89));
+        assert_eq!(
+            window.validate(drifted.as_bytes()),
+            ConservedResidues::try_from(TEST_ALIGNMENT_STR.as_bytes()).ok()
+        );
+
+        // The gaps move to the other side of the anchors, so the anchors are in their IMGT column.
+        let aligned = String::from_utf8(window.align_anchors(drifted.as_bytes()).unwrap()).unwrap();
+        assert_eq!(
+            aligned,
+            TEST_ALIGNMENT_STR
+                .replace("ISWVRQ", "ISW-VRQ")
+                .replace("SAY--NG", "SAY-NG")
+                .replace("YYCAR--------", "YYC-AR-------")
+        );
+        assert_eq!(
+            exact.align_anchors(aligned.as_bytes()).unwrap(),
+            aligned.as_bytes()
+        );
+        // A residue between the anchor and its IMGT column cannot be moved.
+        let shifted = format!("-{}", &drifted[..drifted.len() - 1]);
+        assert!(window.align_anchors(shifted.as_bytes()).is_none());
+
+        // A query is numbered with the IMGT positions of the anchors, not with their drifted columns.
+        let reference =
+            ReferenceSequence::with_spec("drifted", drifted.as_bytes(), &window).unwrap();
+        let ref_seqs = ref_seqs([reference]);
+        let reference_alignment = crate::imgt::find_best_reference_sequence(
+            crate::imgt::query::Query::new("query", TEST_SEQUENCE.as_bytes()),
+            &ref_seqs,
+        )
+        .unwrap();
+        let numbering = reference_alignment.numbered(&Default::default()).unwrap();
+        assert_eq!(
+            transfer_to(TEST_SEQUENCE.as_bytes()).imgt_labels(&numbering),
+            [Some("23"), Some("41"), Some("89"), Some("104"), Some("118")]
+        );
+    }
+
+    #[test]
+    fn test_conserved_amino_acids_from_short_str() {
+        assert!(matches!(
+            ConservedResidues::try_from(&TEST_ALIGNMENT_STR.as_bytes()[..100]),
+            Err(ConservedResidueErr::AlignmentTooShort(100))
+        ));
+        assert_eq!(
+            ConservedResidues::try_from(&TEST_ALIGNMENT_STR.as_bytes()[..117])
+                .err()
+                .unwrap()
+                .to_string(),
+            "Alignment of length 117 is too short to contain position 118."
+        );
+        assert!(ConservedResidues::try_from(&TEST_ALIGNMENT_STR.as_bytes()[..118]).is_ok());
+    }
+
+    #[test]
+    fn test_conserved_amino_acids_with_gap_at_anchor() {
+        let mut alignment = TEST_ALIGNMENT_STR.as_bytes().to_vec();
+        alignment[40] = b'-';
+        assert!(matches!(
+            ConservedResidues::try_from(&alignment[..]),
+            Err(ConservedResidueErr::GapAtConservedPosition(41))
+        ));
+    }
+
+    /// Transfer the conserved residues of the test alignment to a query.
+    fn transfer_to(query: &[u8]) -> ConservedResidues {
+        let reference = test_reference();
+        let alignment = new_aligner().local(&reference.get_sequence(), query);
+        reference
+            .get_conserved_residues()
+            .transfer(&alignment, query, reference.get_spec())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_imgt_labels() {
+        let ref_seqs = test_ref_seqs();
+        let reference_alignment = crate::imgt::find_best_reference_sequence(
+            crate::imgt::query::Query::new("query", TEST_SEQUENCE.as_bytes()),
+            &ref_seqs,
+        )
+        .unwrap();
+        let numbering = reference_alignment
+            .vregion_annotation()
+            .unwrap()
+            .number_regions(&reference_alignment, &Default::default())
+            .unwrap();
+        let conserved_residues = transfer_to(TEST_SEQUENCE.as_bytes());
+
+        assert_eq!(
+            conserved_residues.imgt_labels(&numbering),
+            [Some("23"), Some("41"), Some("89"), Some("104"), Some("118")]
+        );
+    }
+
+    #[test]
+    fn test_transfer_with_extra_cysteine_near_second_cys() {
+        let sequence = "QVQLVQSGAEVKKPGASVKVSCKASGYTFTSYGISWVRQAPGQGLEWMGWISAYNGNTNYAQKLQGRVTMTTDTSTSTAYMELRSLRSDDTAVYYCCARMDVWGQGTTVTVSS";
+        let conserved_residues = transfer_to(sequence.as_bytes());
+
+        // The first cysteine is spaced from the hydrophobic 89 like in the reference.
+        assert_eq!(conserved_residues.second_cys, 96);
+        assert_eq!(
+            conserved_residues.second_cys - conserved_residues.hydrophobic_89,
+            15
+        );
+    }
+
+    #[test]
+    fn test_transfer_with_reference_spec() {
+        // A serine instead of the 2nd-CYS, and a cysteine right after it in the query.
+        let mut alignment = TEST_ALIGNMENT_STR.as_bytes().to_vec();
+        alignment[103] = b'S';
+        let spec = ConservedResidueSpec {
+            second_cys: b"CS".to_vec(),
+            ..Default::default()
+        };
+        let reference = ReferenceSequence::with_spec("test", &alignment, &spec).unwrap();
+        let query = String::from_utf8(reference.get_sequence())
+            .unwrap()
+            .replace("YYSARMDV", "YYSCARGMDV");
+        let alignment = new_aligner().local(&reference.get_sequence(), query.as_bytes());
+        let transfer = |spec: &ConservedResidueSpec| {
+            reference
+                .get_conserved_residues()
+                .transfer(&alignment, query.as_bytes(), spec)
+                .unwrap()
+        };
+
+        // The serine is expected by the spec of the reference, so it stays the 2nd-CYS.
+        let conserved_residues = transfer(reference.get_spec());
+        assert_eq!(conserved_residues.second_cys, 96);
+        assert!(conserved_residues
+            .non_canonical_anchors(query.as_bytes(), reference.get_spec())
+            .is_empty());
+
+        // The IMGT spec only expects a cysteine, so the next residue is taken.
+        let imgt = ConservedResidueSpec::default();
+        assert_eq!(transfer(&imgt).second_cys, 97);
+        assert_eq!(
+            conserved_residues.non_canonical_anchors(query.as_bytes(), &imgt),
+            [(104, b'S')]
+        );
+    }
+
+    #[test]
+    fn test_transfer_j_trp_or_phe_past_local_alignment() {
+        // The CDR3 of trastuzumab is so much longer than the one of the reference that
+        // the local alignment ends before the J region.
+        let sequence = TEST_SEQUENCE.replace("CARMDVW", "CSRWGGDGFYAMDYW");
+        let reference = test_reference();
+        let alignment = new_aligner().local(&reference.get_sequence(), sequence.as_bytes());
+        assert!(alignment.yend < sequence.find("WGQG").unwrap());
+
+        let conserved_residues = transfer_to(sequence.as_bytes());
+        assert_eq!(conserved_residues.second_cys, 96);
+        assert_eq!(
+            conserved_residues.j_trp_or_phe,
+            sequence.find("WGQG").unwrap() + 1
+        );
+    }
+
+    #[test]
+    fn test_transfer_hydrophobic_89_window() {
+        assert_eq!(
+            transfer_to(TEST_SEQUENCE.as_bytes()).hydrophobic_89_shift,
+            0
+        );
+
+        // YME -> YEM puts the glutamate on 89, next to two hydrophobic residues.
+        let swapped = TEST_SEQUENCE.replace("AYMEL", "AYEML");
+        let conserved_residues = transfer_to(swapped.as_bytes());
+        assert_eq!(conserved_residues.hydrophobic_89_shift.abs(), 1);
+        assert!(b"YM".contains(&swapped.as_bytes()[conserved_residues.hydrophobic_89 - 1]));
+        assert_eq!(conserved_residues.second_cys, 96);
+
+        // The closest hydrophobic residue (the leucine) is two away, too far to be a shifted 89.
+        let substituted = TEST_SEQUENCE.replace("AYMEL", "SKSEL");
+        let conserved_residues = transfer_to(substituted.as_bytes());
+        assert_eq!(conserved_residues.hydrophobic_89, 81);
+        assert_eq!(conserved_residues.hydrophobic_89_shift, 0);
+    }
 }
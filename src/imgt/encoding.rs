@@ -0,0 +1,247 @@
+use std::fmt::Display;
+
+use bio::io::fasta;
+use thiserror::Error;
+
+use super::{annotations::Annotation, reference::ReferenceSequence};
+
+/// A run of an [`EncodedNumbering`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NumberingRun {
+    /// Consecutive residues identical to the germline, numbered `label`, `label + 1`, ...
+    Germline { label: usize, length: usize },
+    /// A residue that differs from the germline or is an insertion, with its number.
+    Residue { name: String, residue: u8 },
+    /// Residues of the query that are not numbered.
+    Skip(usize),
+}
+
+/// Compact form of the numbering of a query, relative to its germline.
+///
+/// Stretches that match the germline are stored as runs, so a mostly germline
+/// query takes a handful of runs instead of a number per residue. Written as
+/// `@start` followed by the runs separated by commas: `label+length` for germline
+/// runs, `name=residue` for other residues and `~count` for unnumbered residues,
+/// for example `@0,1+9,11+40,52=A,53+51`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EncodedNumbering {
+    /// The (zero based) position in the query of the first numbered residue.
+    pub start: usize,
+    pub runs: Vec<NumberingRun>,
+}
+
+/// Error for encoded numberings that cannot be read or do not fit their germline.
+#[derive(Debug, Error)]
+pub enum EncodingErr {
+    #[error("Could not read encoded numbering at '{0}'.")]
+    InvalidRun(String),
+
+    #[error("Germline run {0}+{1} does not fit the reference.")]
+    NotInGermline(usize, usize),
+}
+
+/// The residue of the germline at an IMGT position.
+fn germline_residue(reference: &ReferenceSequence, label: usize) -> Option<u8> {
+    reference
+        .get_alignment()
+        .get(label.checked_sub(1)?)
+        .copied()
+        .filter(|residue| *residue != b'-')
+}
+
+/// Encode the numbering of a query relative to the germline it was numbered with.
+pub fn encode_numbering(
+    record: &fasta::Record,
+    numbering: &[Annotation],
+    reference: &ReferenceSequence,
+) -> EncodedNumbering {
+    let start = numbering.first().map_or(0, |annotation| annotation.start);
+    let mut position = start;
+    let mut runs: Vec<NumberingRun> = Vec::new();
+    for annotation in numbering {
+        if annotation.start > position {
+            runs.push(NumberingRun::Skip(annotation.start - position));
+        }
+        position = annotation.end;
+        let residue = record.seq()[annotation.start];
+        let germline_label = annotation
+            .name
+            .parse()
+            .ok()
+            .filter(|label| germline_residue(reference, *label) == Some(residue));
+
+        match (germline_label, runs.last_mut()) {
+            (
+                Some(label),
+                Some(NumberingRun::Germline {
+                    label: first,
+                    length,
+                }),
+            ) if *first + *length == label => *length += 1,
+            (Some(label), _) => runs.push(NumberingRun::Germline { label, length: 1 }),
+            (None, _) => runs.push(NumberingRun::Residue {
+                name: annotation.name.clone(),
+                residue,
+            }),
+        }
+    }
+    EncodedNumbering { start, runs }
+}
+
+/// Restore the numbering, and the numbered residues, from an encoded numbering.
+pub fn decode_numbering(
+    encoded: &EncodedNumbering,
+    reference: &ReferenceSequence,
+) -> Result<Vec<(Annotation, u8)>, EncodingErr> {
+    let mut position = encoded.start;
+    let mut numbered = Vec::new();
+    for run in &encoded.runs {
+        let residues = match run {
+            NumberingRun::Germline { label, length } => (*label..label + length)
+                .map(|label| {
+                    germline_residue(reference, label)
+                        .map(|residue| (label.to_string(), residue))
+                        .ok_or(EncodingErr::NotInGermline(label, *length))
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            NumberingRun::Residue { name, residue } => vec![(name.clone(), *residue)],
+            NumberingRun::Skip(count) => {
+                position += count;
+                continue;
+            }
+        };
+        for (name, residue) in residues {
+            numbered.push((
+                Annotation {
+                    start: position,
+                    end: position + 1,
+                    name,
+                },
+                residue,
+            ));
+            position += 1;
+        }
+    }
+    Ok(numbered)
+}
+
+impl Display for NumberingRun {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NumberingRun::Germline { label, length } => write!(f, "{}+{}", label, length),
+            NumberingRun::Residue { name, residue } => write!(f, "{}={}", name, *residue as char),
+            NumberingRun::Skip(count) => write!(f, "~{}", count),
+        }
+    }
+}
+
+impl Display for EncodedNumbering {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "@{}", self.start)?;
+        self.runs.iter().try_for_each(|run| write!(f, ",{}", run))
+    }
+}
+
+impl std::str::FromStr for NumberingRun {
+    type Err = EncodingErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || EncodingErr::InvalidRun(s.to_string());
+        if let Some(count) = s.strip_prefix('~') {
+            return count.parse().map(NumberingRun::Skip).map_err(|_| invalid());
+        }
+        if let Some((label, length)) = s.split_once('+') {
+            return Ok(NumberingRun::Germline {
+                label: label.parse().map_err(|_| invalid())?,
+                length: length.parse().map_err(|_| invalid())?,
+            });
+        }
+        match s.split_once('=') {
+            Some((name, residue)) if residue.len() == 1 && !name.is_empty() => {
+                Ok(NumberingRun::Residue {
+                    name: name.to_string(),
+                    residue: residue.as_bytes()[0],
+                })
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl std::str::FromStr for EncodedNumbering {
+    type Err = EncodingErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(',');
+        let start = parts
+            .next()
+            .and_then(|start| start.strip_prefix('@'))
+            .and_then(|start| start.parse().ok())
+            .ok_or_else(|| EncodingErr::InvalidRun(s.to_string()))?;
+        Ok(Self {
+            start,
+            runs: parts.map(str::parse).collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::imgt::{
+        find_best_reference_sequence,
+        fixtures::{ref_seqs, test_reference},
+        numbering::NumberingScheme,
+        query::Query,
+    };
+
+    // The test sequence with a substitution in the CDR2 (ISAYNGNT -> ISCYNGNT) and a longer CDR3.
+    const MUTATED_SEQUENCE: &str = "QVQLVQSGAEVKKPGASVKVSCKASGYTFTSYGISWVRQAPGQGLEWMGWISCYNGNTNYAQKLQGRVTMTTDTSTSTAYMELRSLRSDDTAVYYCARGGYMDVWGQGTTVTVSS";
+
+    #[test]
+    fn test_encoding_round_trip() {
+        let reference = test_reference();
+        let ref_seqs = ref_seqs([reference.clone()]);
+        let reference_alignment = find_best_reference_sequence(
+            Query::new("query", MUTATED_SEQUENCE.as_bytes()),
+            &ref_seqs,
+        )
+        .unwrap();
+        let record = &reference_alignment.query_record;
+        let numbering = reference_alignment
+            .numbered(&NumberingScheme::default())
+            .unwrap();
+
+        let encoded = encode_numbering(record, &numbering, &reference);
+        let written = encoded.to_string();
+        assert_eq!(written.parse::<EncodedNumbering>().unwrap(), encoded);
+        assert!(written.contains("58=C"));
+
+        let decoded = decode_numbering(&encoded, &reference).unwrap();
+        assert_eq!(
+            decoded,
+            numbering
+                .iter()
+                .map(|annotation| (annotation.clone(), record.seq()[annotation.start]))
+                .collect::<Vec<_>>()
+        );
+
+        let naive: Vec<_> = decoded
+            .iter()
+            .map(|(annotation, residue)| format!("{}={}", annotation.name, *residue as char))
+            .collect();
+        assert!(written.len() < naive.join(",").len() / 4);
+    }
+
+    #[test]
+    fn test_invalid_encoding() {
+        assert!("1+9".parse::<EncodedNumbering>().is_err());
+        assert!("@0,1+x".parse::<EncodedNumbering>().is_err());
+        let reference = test_reference();
+        // Position 10 is a gap in the germline.
+        assert!(matches!(
+            decode_numbering(&"@0,9+2".parse().unwrap(), &reference),
+            Err(EncodingErr::NotInGermline(10, 2))
+        ));
+    }
+}
@@ -0,0 +1,219 @@
+use std::{io::Write, sync::Arc, time::Instant};
+
+use bio::alignment::pairwise::Aligner;
+
+use super::{
+    annotations::Annotation,
+    batch::BatchFailure,
+    collier::write_collier,
+    find_best_reference_sequence_with_mode, new_aligner_for,
+    numbering::NumberingScheme,
+    output::{
+        format_positions, write_a2m, write_annotation, write_germline_pair, CoordinateBase,
+        IdTemplate, InsertionFormat, OutputFormat,
+    },
+    profile::{time_stage, Profile, Stage},
+    query::Query,
+    reference::ReferenceIndex,
+    AlignmentMode, MatchFn, NumberingError, RefSeqErr, Thresholds,
+};
+
+/// Numbers many sequences, reusing its buffers between sequences.
+///
+/// Use together with [`number_into`] for throughput sensitive numbering.
+pub struct NumberingEngine<'a> {
+    ref_seqs: &'a ReferenceIndex,
+    pub scheme: NumberingScheme,
+    pub thresholds: Thresholds,
+    pub insertion_format: InsertionFormat,
+    pub alignment_mode: AlignmentMode,
+    pub id_template: IdTemplate,
+    pub coordinate_base: CoordinateBase,
+    /// Where the time spent in every stage of [`number_into`] is added, when set.
+    pub profile: Option<Arc<Profile>>,
+    aligner: Aligner<MatchFn>,
+    annotations: Vec<Annotation>,
+    output: Vec<u8>,
+}
+
+impl<'a> NumberingEngine<'a> {
+    pub fn new(ref_seqs: &'a ReferenceIndex, scheme: NumberingScheme) -> Self {
+        Self {
+            ref_seqs,
+            scheme,
+            thresholds: Thresholds::default(),
+            insertion_format: InsertionFormat::default(),
+            alignment_mode: AlignmentMode::default(),
+            id_template: IdTemplate::default(),
+            coordinate_base: CoordinateBase::default(),
+            profile: None,
+            aligner: new_aligner_for(ref_seqs),
+            annotations: Vec::new(),
+            output: Vec::new(),
+        }
+    }
+}
+
+impl Clone for NumberingEngine<'_> {
+    /// A new engine with the same settings, with its own aligner and buffers.
+    ///
+    /// The clone adds to the same profile.
+    fn clone(&self) -> Self {
+        Self {
+            thresholds: self.thresholds,
+            insertion_format: self.insertion_format,
+            alignment_mode: self.alignment_mode,
+            id_template: self.id_template.clone(),
+            coordinate_base: self.coordinate_base,
+            profile: self.profile.clone(),
+            ..Self::new(self.ref_seqs, self.scheme.clone())
+        }
+    }
+}
+
+/// Number a record and write the numbering directly to a writer.
+///
+/// Produces the same output as numbering the record and passing the annotations to
+/// [`super::output::write_annotations`] (with the
+/// [reference fields](super::ReferenceAlignment::reference_fields) as flags of FASTA
+/// records), but does not create a record per annotation
+/// and reuses the aligner and buffers of the engine. The output of a record is
+/// written with a single call, so nothing is written if numbering fails. With
+/// [`OutputFormat::A2m`] the whole record is written as a single A2M record instead,
+/// with [`OutputFormat::Pair`] as its germline and itself, and with
+/// [`OutputFormat::Collier`] as a line per residue.
+///
+/// With a profile, the time the record takes is also added with
+/// [`Profile::add_sequence`], whether numbering it succeeds or not.
+pub fn number_into<W: Write>(
+    record: impl Into<Query>,
+    engine: &mut NumberingEngine,
+    out: &mut W,
+    format: OutputFormat,
+) -> Result<(), NumberingError> {
+    number_record_into(record, engine, out, format).map_err(|failure| failure.error)
+}
+
+/// Like [`number_into`], but a failure has the record of the query, so that it
+/// does not have to be kept to report the failure.
+pub(crate) fn number_record_into<W: Write>(
+    record: impl Into<Query>,
+    engine: &mut NumberingEngine,
+    out: &mut W,
+    format: OutputFormat,
+) -> Result<(), Box<BatchFailure>> {
+    let record = record.into();
+    let Some(profile) = engine.profile.clone() else {
+        return number_query_into(record, engine, out, format);
+    };
+    let start = Instant::now();
+    let id = record.id().to_string();
+    let result = number_query_into(record, engine, out, format);
+    profile.add_sequence(&id, start.elapsed());
+    result
+}
+
+/// Number a query as in [`number_record_into`], without timing it as a whole.
+fn number_query_into<W: Write>(
+    record: Query,
+    engine: &mut NumberingEngine,
+    out: &mut W,
+    format: OutputFormat,
+) -> Result<(), Box<BatchFailure>> {
+    let profile = engine.profile.clone();
+    let profile = profile.as_deref();
+    let reference_alignment = time_stage(profile, Stage::Alignment, || {
+        find_best_reference_sequence_with_mode(
+            record,
+            engine.ref_seqs,
+            &mut engine.aligner,
+            engine.alignment_mode,
+        )
+    })
+    .map_err(not_aligned)?;
+    let reference_alignment = engine
+        .thresholds
+        .check(reference_alignment)
+        .map_err(not_aligned)?;
+    // Failures are rare, so only then is the record copied.
+    let not_numbered = |error: NumberingError| {
+        Box::new(BatchFailure {
+            record: reference_alignment.query_record.clone(),
+            error,
+        })
+    };
+
+    time_stage(
+        profile,
+        Stage::Numbering,
+        || -> Result<(), NumberingError> {
+            let vregion_annotation = reference_alignment.vregion_annotation_for(&engine.scheme)?;
+            engine.annotations.clear();
+            vregion_annotation.number_regions_into(
+                &reference_alignment,
+                &engine.scheme,
+                &mut engine.annotations,
+            )?;
+            Ok(())
+        },
+    )
+    .map_err(not_numbered)?;
+
+    time_stage(profile, Stage::Output, || -> Result<(), NumberingError> {
+        let reference_fields = if format.is_fasta() {
+            reference_alignment.reference_fields().to_vec()
+        } else {
+            vec![]
+        };
+        let mut flags = reference_alignment.flags();
+        flags.extend(reference_fields.iter().map(String::as_str));
+        engine.output.clear();
+        match format {
+            OutputFormat::A2m => write_a2m(
+                &reference_alignment.query_record,
+                &engine.annotations,
+                &flags,
+                &mut engine.output,
+            )?,
+            OutputFormat::Pair => write_germline_pair(
+                &reference_alignment.query_record,
+                &engine.annotations,
+                &reference_alignment.reference,
+                &flags,
+                &mut engine.output,
+            )?,
+            OutputFormat::Collier => write_collier(
+                &reference_alignment.query_record,
+                &engine.annotations,
+                &flags,
+                engine.insertion_format,
+                &mut engine.output,
+            )?,
+            _ => {
+                format_positions(&mut engine.annotations, engine.insertion_format);
+                for annotation in &engine.annotations {
+                    write_annotation(
+                        &mut engine.output,
+                        &reference_alignment.query_record,
+                        annotation,
+                        &flags,
+                        format,
+                        &engine.id_template,
+                        engine.coordinate_base,
+                    )?;
+                }
+            }
+        }
+        out.write_all(&engine.output)?;
+        Ok(())
+    })
+    .map_err(not_numbered)
+}
+
+/// A failure to align a query, which has the record of the query.
+fn not_aligned(error: RefSeqErr) -> Box<BatchFailure> {
+    Box::new(BatchFailure {
+        record: error.record().clone(),
+        error: error.into(),
+    })
+}
@@ -0,0 +1,49 @@
+//! Fixtures shared by the unit and integration tests: a human IGHV1-18 germline,
+//! as IMGT-gapped reference and as query, and indexes of references.
+//!
+//! Only refers to its parent through `super::reference`, so the integration tests
+//! can include it as well (see `tests/common/mod.rs`).
+use super::reference::{ReferenceIndex, ReferenceSequence};
+
+/// The IMGT-gapped alignment of the test reference.
+pub const TEST_ALIGNMENT_STR: &str = "QVQLVQSGA-EVKKPGASVKVSCKASGYTF----TSYGISWVRQAPGQGLEWMGWISAY--NGNTNYAQKLQ-GRVTMTTDTSTSTAYMELRSLRSDDTAVYYCAR--------MDVWGQGTTVTVSS";
+/// The residues of [`TEST_ALIGNMENT_STR`], which number without insertions against it.
+pub const TEST_SEQUENCE: &str = "QVQLVQSGAEVKKPGASVKVSCKASGYTFTSYGISWVRQAPGQGLEWMGWISAYNGNTNYAQKLQGRVTMTTDTSTSTAYMELRSLRSDDTAVYYCARMDVWGQGTTVTVSS";
+
+/// A second germline: an alignment with every third residue substituted, keeping the
+/// gaps and the conserved cysteines and tryptophans.
+pub fn diverged(alignment: &str) -> String {
+    alignment
+        .chars()
+        .enumerate()
+        .map(|(i, residue)| match residue {
+            '-' | 'C' | 'W' => residue,
+            _ if i % 3 != 0 => residue,
+            'A' => 'S',
+            _ => 'A',
+        })
+        .collect()
+}
+
+/// The test reference under a name, such as `Homo_sapiens_IGHV1-18*01` to have a V gene.
+pub fn named_reference(name: &str) -> ReferenceSequence {
+    ReferenceSequence::new(name, TEST_ALIGNMENT_STR.as_bytes()).unwrap()
+}
+
+/// The test reference, named `test`.
+pub fn test_reference() -> ReferenceSequence {
+    named_reference("test")
+}
+
+/// An index of references by their name.
+pub fn ref_seqs(references: impl IntoIterator<Item = ReferenceSequence>) -> ReferenceIndex {
+    references
+        .into_iter()
+        .map(|reference| (reference.name.clone(), reference))
+        .collect()
+}
+
+/// An index of only the [`test_reference`].
+pub fn test_ref_seqs() -> ReferenceIndex {
+    ref_seqs([test_reference()])
+}
@@ -0,0 +1,211 @@
+use std::{
+    collections::HashSet,
+    io::{BufRead, BufReader, Read},
+};
+
+use bio::io::fasta;
+use thiserror::Error;
+use tracing::warn;
+
+/// Format in which sequences are read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InputFormat {
+    /// FASTA records.
+    #[default]
+    Fasta,
+    /// One sequence per line without headers. Empty lines are skipped.
+    Lines,
+}
+
+/// How queries with gaps, such as the rows of a multiple sequence alignment, are numbered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GappedQueryMode {
+    /// Remove the gaps and align the query like any other.
+    #[default]
+    Strip,
+    /// Take the query as aligned to the IMGT positions, so its first column is
+    /// position 1, and number it by its columns without aligning it.
+    PreAligned,
+}
+
+/// Whether a residue of a query is a gap (`-` or `.`).
+pub fn is_gap(residue: u8) -> bool {
+    matches!(residue, b'-' | b'.')
+}
+
+/// The record with the gaps removed from its sequence, or `None` when it has no gaps.
+pub fn strip_gaps(record: &fasta::Record) -> Option<fasta::Record> {
+    if !record.seq().iter().copied().any(is_gap) {
+        return None;
+    }
+    let seq: Vec<u8> = record
+        .seq()
+        .iter()
+        .copied()
+        .filter(|&residue| !is_gap(residue))
+        .collect();
+    Some(fasta::Record::with_attrs(record.id(), record.desc(), &seq))
+}
+
+/// Create a record for a sequence without a header, identified by its (zero based)
+/// number in the source, like the sequences given on the command line.
+pub fn numbered_record(number: usize, source: &str, seq: &str) -> fasta::Record {
+    fasta::Record::with_attrs(
+        // Can clash with the ids of other records, see `DuplicateIdPolicy`.
+        number.to_string().as_str(),
+        Some(format!("sequence {} from {}", number, source).as_str()),
+        seq.as_bytes(),
+    )
+}
+
+/// The UTF-8 byte order mark that some editors write at the start of a file.
+const BYTE_ORDER_MARK: &[u8] = b"\xEF\xBB\xBF";
+
+/// Reader that drops a leading byte order mark and all carriage returns.
+///
+/// Files written on Windows end their lines with `\r\n`. Without this the `\r`
+/// (and the byte order mark) would end up in the sequences, which shifts residues.
+pub struct NormalizedReader<R> {
+    inner: BufReader<R>,
+    started: bool,
+}
+
+impl<R: Read> NormalizedReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            inner: BufReader::new(reader),
+            started: false,
+        }
+    }
+}
+
+impl<R: Read> Read for NormalizedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if !self.started {
+            self.started = true;
+            if self.inner.fill_buf()?.starts_with(BYTE_ORDER_MARK) {
+                self.inner.consume(BYTE_ORDER_MARK.len());
+            }
+        }
+        loop {
+            let read = self.inner.read(buf)?;
+            if read == 0 {
+                return Ok(0);
+            }
+            let mut kept = 0;
+            for i in 0..read {
+                if buf[i] != b'\r' {
+                    buf[kept] = buf[i];
+                    kept += 1;
+                }
+            }
+            // A read of only carriage returns is not the end of the input.
+            if kept > 0 {
+                return Ok(kept);
+            }
+        }
+    }
+}
+
+/// Read the records of a sequences file in the given format.
+///
+/// Sequences read as [`InputFormat::Lines`] get their (zero based) line number as id,
+/// see [`numbered_record`]. The input is read through a [`NormalizedReader`].
+pub fn read_records<R: Read + Send + 'static>(
+    reader: R,
+    format: InputFormat,
+) -> Box<dyn Iterator<Item = std::io::Result<fasta::Record>> + Send> {
+    let reader = NormalizedReader::new(reader);
+    match format {
+        InputFormat::Fasta => Box::new(fasta::Reader::new(reader).records()),
+        InputFormat::Lines => Box::new(BufReader::new(reader).lines().enumerate().filter_map(
+            |(i, line)| match line {
+                Ok(line) if line.trim().is_empty() => None,
+                Ok(line) => Some(Ok(numbered_record(i, "the sequences file", line.trim()))),
+                Err(error) => Some(Err(error)),
+            },
+        )),
+    }
+}
+
+/// What to do with a record whose id occurred before, see [`check_duplicate_ids`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicateIdPolicy {
+    /// Add a suffix to the id (`_2`, `_3`, ...) that makes it unique.
+    #[default]
+    Rename,
+    /// Fail on the first duplicate id.
+    Fail,
+    /// Keep duplicate ids, so the output of these records cannot be told apart.
+    Allow,
+}
+
+/// Error thrown when reading the records to number.
+#[derive(Debug, Error)]
+pub enum InputErr {
+    #[error("Record id '{0}' occurs more than once.")]
+    DuplicateId(String),
+}
+
+/// Apply a [`DuplicateIdPolicy`] to records, in order.
+///
+/// The output of a record is identified by its id, so duplicate ids make it
+/// ambiguous. Renamed records get the first free suffix, so a renamed id does not
+/// clash with later ids either. All ids are kept in memory, except with
+/// [`DuplicateIdPolicy::Allow`].
+pub fn check_duplicate_ids(
+    records: impl Iterator<Item = fasta::Record>,
+    policy: DuplicateIdPolicy,
+) -> impl Iterator<Item = Result<fasta::Record, InputErr>> {
+    let mut seen = HashSet::new();
+    records.map(move |record| {
+        if policy == DuplicateIdPolicy::Allow || seen.insert(record.id().to_string()) {
+            return Ok(record);
+        }
+        if policy == DuplicateIdPolicy::Fail {
+            return Err(InputErr::DuplicateId(record.id().to_string()));
+        }
+        let renamed = (2..)
+            .map(|suffix| format!("{}_{}", record.id(), suffix))
+            .find(|id| !seen.contains(id))
+            .expect("There is always a free suffix.");
+        warn!(sequence = record.id(), renamed, "Renamed duplicate id.");
+        seen.insert(renamed.clone());
+        Ok(fasta::Record::with_attrs(
+            &renamed,
+            record.desc(),
+            record.seq(),
+        ))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check_duplicate_ids() {
+        let records = || {
+            ["a", "b", "a", "a_2", "a"]
+                .into_iter()
+                .map(|id| fasta::Record::with_attrs(id, None, b"QVQ"))
+        };
+        let ids = |policy: DuplicateIdPolicy| -> Result<Vec<String>, InputErr> {
+            check_duplicate_ids(records(), policy)
+                .map(|record| record.map(|record| record.id().to_string()))
+                .collect()
+        };
+        assert_eq!(
+            ids(DuplicateIdPolicy::Rename).unwrap(),
+            ["a", "b", "a_2", "a_2_2", "a_3"]
+        );
+        assert_eq!(
+            ids(DuplicateIdPolicy::Allow).unwrap(),
+            ["a", "b", "a", "a_2", "a"]
+        );
+        assert!(matches!(
+            ids(DuplicateIdPolicy::Fail),
+            Err(InputErr::DuplicateId(id)) if id == "a"
+        ));
+    }
+}
@@ -0,0 +1,94 @@
+use itertools::Itertools;
+
+use super::{
+    annotations::Annotation,
+    numbering::{Cdr3Definition, NumberingScheme},
+};
+
+/// Whether residues 102 to 104 are `Y[YFH]C`, ending on the 2nd-CYS.
+fn is_second_cys_motif(residues: &[u8]) -> bool {
+    matches!(residues, [b'Y', b'Y' | b'F' | b'H', b'C'])
+}
+
+/// Whether residues 118 to 121 are `[WF]G.G`, starting with the J-Trp/Phe.
+pub(super) fn is_j_motif(residues: &[u8]) -> bool {
+    matches!(residues, [b'W' | b'F', b'G', _, b'G'])
+}
+
+/// Find the CDR3 of a query by the motifs around its anchors, without aligning it.
+///
+/// The 2nd-CYS (104) is the `C` of `Y[YFH]C` and the J-Trp/Phe (118) the first
+/// residue of `[WF]G.G`. This is much faster than numbering, but only as reliable
+/// as the motifs: `None` when either motif is missing or occurs more than once, or
+/// when the length of the CDR3-IMGT between them is not in
+/// [`NumberingScheme::cdr3_lengths`]. Number the query in that case. The CDR3 is named and bounded like the one of
+/// [`VRegionAnnotation::region_annotations`](super::annotations::VRegionAnnotation::region_annotations).
+pub fn find_cdr3_by_motifs(seq: &[u8], scheme: &NumberingScheme) -> Option<Annotation> {
+    let second_cys = seq
+        .windows(3)
+        .positions(is_second_cys_motif)
+        .exactly_one()
+        .ok()?
+        + 2;
+    let j_trp_or_phe = seq.windows(4).positions(is_j_motif).exactly_one().ok()?;
+    let cdr3_length = j_trp_or_phe.checked_sub(second_cys + 1)?;
+    if !scheme.cdr3_lengths().contains(&cdr3_length) {
+        return None;
+    }
+
+    Some(match scheme.cdr3_definition {
+        Cdr3Definition::Imgt => Annotation {
+            start: second_cys + 1,
+            end: j_trp_or_phe,
+            name: "CDR3-IMGT".to_string(),
+        },
+        Cdr3Definition::Junction => Annotation {
+            start: second_cys,
+            end: j_trp_or_phe + 1,
+            name: "JUNCTION".to_string(),
+        },
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::imgt::{
+        find_best_reference_sequence,
+        fixtures::{test_ref_seqs, TEST_SEQUENCE},
+        query::Query,
+    };
+
+    #[test]
+    fn test_cdr3_by_motifs_matches_numbering() {
+        let ref_seqs = test_ref_seqs();
+        let reference_alignment =
+            find_best_reference_sequence(Query::new("query", TEST_SEQUENCE.as_bytes()), &ref_seqs)
+                .unwrap();
+        let vregion_annotation = reference_alignment.vregion_annotation().unwrap();
+
+        for cdr3_definition in [Cdr3Definition::Imgt, Cdr3Definition::Junction] {
+            let scheme = NumberingScheme {
+                cdr3_definition,
+                ..Default::default()
+            };
+            assert_eq!(
+                find_cdr3_by_motifs(TEST_SEQUENCE.as_bytes(), &scheme),
+                Some(vregion_annotation.region_annotations(cdr3_definition)[5].clone())
+            );
+        }
+    }
+
+    #[test]
+    fn test_ambiguous_motifs() {
+        let scheme = NumberingScheme::default();
+        // A second Y[YFH]C motif in FR3.
+        let two_cys_motifs = TEST_SEQUENCE.replace("AYMEL", "AYYCL");
+        assert!(find_cdr3_by_motifs(two_cys_motifs.as_bytes(), &scheme).is_none());
+        // No J motif.
+        assert!(find_cdr3_by_motifs(&TEST_SEQUENCE.as_bytes()[..100], &scheme).is_none());
+        // A CDR3-IMGT of three residues.
+        let short_cdr3 = TEST_SEQUENCE.replace("ARMDV", "ARV");
+        assert!(find_cdr3_by_motifs(short_cdr3.as_bytes(), &scheme).is_none());
+    }
+}
@@ -1,15 +1,45 @@
-use std::collections::HashMap;
+use std::{cell::RefCell, cmp::Reverse, collections::BTreeMap};
 
 use thiserror::Error;
 use tracing::trace;
 
-use bio::{alignment::Alignment, io::fasta};
+use bio::{
+    alignment::{
+        pairwise::{banded, Aligner},
+        Alignment, AlignmentOperation,
+    },
+    io::fasta,
+};
 
-use self::{conserved_residues::ConservedResidues, reference::ReferenceSequence};
+use self::{
+    annotations::{Annotation, VRegionAnnotation},
+    conserved_residues::{ConservedResidues, TransferErr},
+    numbering::NumberingScheme,
+    output::CoordinateBase,
+    query::Query,
+    reference::{functionality::Functionality, ReferenceIndex, ReferenceSequence},
+};
 
+pub mod airr;
+pub mod analysis;
 pub mod annotations;
+pub mod batch;
+pub mod chimera;
+pub mod clonotype;
+pub mod collier;
+pub mod columns;
+pub mod consensus;
 pub mod conserved_residues;
+pub mod encoding;
+pub mod engine;
+#[cfg(test)]
+pub(crate) mod fixtures;
+pub mod input;
+pub mod junction;
 pub mod numbering;
+pub mod output;
+pub mod profile;
+pub mod query;
 pub mod reference;
 pub mod regions;
 
@@ -32,7 +62,7 @@ const FR4_END: usize = 128;
 const FR1: std::ops::Range<usize> = FR1_START..CDR1_START;
 const FR2: std::ops::Range<usize> = FR2_START..CDR2_START;
 const FR3: std::ops::Range<usize> = FR3_START..CDR3_START;
-const FR4: std::ops::Range<usize> = FR4_START..FR4_END;
+const FR4: std::ops::Range<usize> = FR4_START..FR4_END + 1;
 
 /// Error for when
 #[derive(Debug, Error)]
@@ -43,12 +73,30 @@ pub enum IMGTError {
     #[error("Unexpected length ({1}) for region '{0}'.")]
     RegionTooLong(String, usize),
 
+    #[error("Region '{0}' too short, got {1} residues.")]
+    RegionTooShort(String, usize),
+
     #[error("CDR3 region too short. Expected at least 5, got {0}")]
     CDR3TooShort(usize),
 
+    #[error("CDR3 region of {0} residues is longer than the maximum of {1}, likely an alignment artifact.")]
+    CDR3TooLong(usize, usize),
+
     #[error("Region '{0}' and '{0}' overlapped.")]
     OverlappingRegions(String, String),
 
+    #[error("Region '{0}' does not fit in the sequence.")]
+    RegionOutOfRange(String),
+
+    #[error("Position '{0}' was numbered more than once.")]
+    DuplicatePosition(String),
+
+    #[error("Pre-aligned sequence of {0} columns is longer than the {FR4_END} IMGT positions.")]
+    PreAlignedTooLong(usize),
+
+    #[error("Position '{0}' has no column in the alignment of a reference.")]
+    InsertionInReference(String),
+
     #[error("Bad alignment string.")]
     BadBytesInAlignment(#[from] std::str::Utf8Error),
 }
@@ -58,6 +106,51 @@ pub enum IMGTError {
 pub enum RefSeqErr {
     #[error("Could not find reference record for record {0}")]
     NoReferenceSequenceFound(fasta::Record),
+
+    #[error("Normalized alignment score {1:.3} of record {} is below the minimum of {2}", .0.id())]
+    ScoreTooLow(fasta::Record, f64, f64),
+
+    #[error("Identity {1:.3} of record {} is below the minimum of {2}", .0.id())]
+    IdentityTooLow(fasta::Record, f64, f64),
+
+    #[error("No reference sequence named '{1}' to align record {} to", .0.id())]
+    UnknownReference(fasta::Record, String),
+
+    #[error("Record {} matches the chain types {} equally well", .0.id(), .1.join(", "))]
+    AmbiguousChain(fasta::Record, Vec<String>),
+
+    #[error("Record {} is assigned to {1}, which is a {2}", .0.id())]
+    NonFunctionalGene(fasta::Record, String, Functionality),
+}
+
+impl RefSeqErr {
+    /// The record for which no reference sequence was found.
+    pub fn record(&self) -> &fasta::Record {
+        match self {
+            RefSeqErr::NoReferenceSequenceFound(record)
+            | RefSeqErr::ScoreTooLow(record, ..)
+            | RefSeqErr::IdentityTooLow(record, ..)
+            | RefSeqErr::UnknownReference(record, ..)
+            | RefSeqErr::AmbiguousChain(record, ..)
+            | RefSeqErr::NonFunctionalGene(record, ..) => record,
+        }
+    }
+}
+
+/// Error for any of the steps of numbering a sequence.
+#[derive(Debug, Error)]
+pub enum NumberingError {
+    #[error(transparent)]
+    Reference(#[from] RefSeqErr),
+
+    #[error(transparent)]
+    Transfer(#[from] TransferErr),
+
+    #[error(transparent)]
+    Annotation(#[from] IMGTError),
+
+    #[error("Could not write output.")]
+    Io(#[from] std::io::Error),
 }
 
 /// Captures an alignment of a query sequence to reference sequence.
@@ -69,41 +162,1448 @@ pub struct ReferenceAlignment {
     pub reference: ReferenceSequence,
     pub query_record: fasta::Record,
     pub alignment: Alignment,
+    /// The first residue of the query is modified, see [`Query::has_modified_n_terminus`].
+    pub modified_n_terminus: bool,
+}
+
+/// Which ends of the V-region are missing from a query.
+///
+/// Partial reads often lack the start of FR1 or the end of FR4. These are
+/// still numbered, but the numbering only covers the aligned part.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Completeness {
+    /// The alignment does not cover the first residue of the reference.
+    pub missing_fr1: bool,
+    /// The alignment does not cover the last residue of the reference.
+    pub missing_fr4: bool,
+}
+
+impl Completeness {
+    pub fn is_complete(&self) -> bool {
+        !(self.missing_fr1 || self.missing_fr4)
+    }
+
+    /// Names of the flags that are set.
+    pub fn flags(&self) -> Vec<&'static str> {
+        [
+            (self.missing_fr1, "missing_fr1"),
+            (self.missing_fr4, "missing_fr4"),
+        ]
+        .into_iter()
+        .filter_map(|(is_set, name)| is_set.then_some(name))
+        .collect()
+    }
+}
+
+/// The ranges of the reference and the query covered by an alignment.
+///
+/// Both are zero based and half open, in residues of the ungapped sequences.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AlignmentOffsets {
+    pub reference: std::ops::Range<usize>,
+    pub query: std::ops::Range<usize>,
+}
+
+impl AlignmentOffsets {
+    /// Labeled offsets in `coordinate_base`, as written to the output.
+    pub fn fields(&self, coordinate_base: CoordinateBase) -> [String; 4] {
+        let (reference_start, reference_end) =
+            coordinate_base.range(self.reference.start, self.reference.end);
+        let (query_start, query_end) = coordinate_base.range(self.query.start, self.query.end);
+        [
+            format!("reference_start={}", reference_start),
+            format!("reference_end={}", reference_end),
+            format!("query_start={}", query_start),
+            format!("query_end={}", query_end),
+        ]
+    }
+}
+
+/// Minimal identity to the reference below which a query is considered out of frame.
+///
+/// Even heavily mutated in-frame V-regions keep well over half of their germline
+/// residues, while a translation in the wrong frame is essentially unrelated.
+pub const MIN_IN_FRAME_IDENTITY: f64 = 0.5;
+
+/// Signs that a query was translated in the wrong frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameCheck {
+    /// The aligned part of the query contains a stop codon (`*`).
+    pub stop_codon: bool,
+    /// The identity to the reference is below [`MIN_IN_FRAME_IDENTITY`].
+    pub low_identity: bool,
+}
+
+impl FrameCheck {
+    /// Whether the query is likely out of frame, and therefore unproductive.
+    pub fn likely_out_of_frame(&self) -> bool {
+        self.stop_codon || self.low_identity
+    }
+
+    /// Names of the flags that are set.
+    pub fn flags(&self) -> Vec<&'static str> {
+        if self.likely_out_of_frame() {
+            vec!["likely_out_of_frame"]
+        } else {
+            vec![]
+        }
+    }
+}
+
+impl ReferenceAlignment {
+    /// Transfer the conserved residues of the reference to the query and annotate its regions.
+    pub fn vregion_annotation(&self) -> Result<VRegionAnnotation, NumberingError> {
+        let conserved_residues = self.reference.get_conserved_residues().transfer(
+            &self.alignment,
+            self.query_record.seq(),
+            self.reference.get_spec(),
+        )?;
+        self.vregion_annotation_from(&conserved_residues)
+    }
+
+    /// Like [`Self::vregion_annotation`], but tolerates a query that ends before the J-Trp/Phe (118).
+    ///
+    /// FR4 is then empty and the CDR3 runs to the end of the aligned query.
+    pub fn partial_vregion_annotation(&self) -> Result<VRegionAnnotation, NumberingError> {
+        let conserved_residues = self
+            .reference
+            .get_conserved_residues()
+            .transfer_allowing_missing_j_trp_or_phe(
+                &self.alignment,
+                self.query_record.seq(),
+                self.reference.get_spec(),
+            )?;
+        self.vregion_annotation_from(&conserved_residues)
+    }
+
+    fn vregion_annotation_from(
+        &self,
+        conserved_residues: &ConservedResidues,
+    ) -> Result<VRegionAnnotation, NumberingError> {
+        trace!(
+            query_seq = self.query_record.id(),
+            "Creating VREGION annotation."
+        );
+        Ok(VRegionAnnotation::try_from(
+            conserved_residues,
+            &self.alignment,
+            &self.reference,
+        )?)
+    }
+
+    /// Annotate the regions as the scheme asks, see [`NumberingScheme::allow_missing_fr4`],
+    /// [`NumberingScheme::strict_anchors`] and [`NumberingScheme::extend_truncated_fr1`].
+    pub fn vregion_annotation_for(
+        &self,
+        scheme: &NumberingScheme,
+    ) -> Result<VRegionAnnotation, NumberingError> {
+        let reference_residues = self.reference.get_conserved_residues();
+        let conserved_residues = if scheme.allow_missing_fr4 {
+            reference_residues.transfer_allowing_missing_j_trp_or_phe(
+                &self.alignment,
+                self.query_record.seq(),
+                self.reference.get_spec(),
+            )?
+        } else {
+            reference_residues.transfer(
+                &self.alignment,
+                self.query_record.seq(),
+                self.reference.get_spec(),
+            )?
+        };
+        if scheme.strict_anchors {
+            conserved_residues
+                .check_canonical(self.query_record.seq(), self.reference.get_spec())?;
+        }
+        let mut vregion_annotation = self.vregion_annotation_from(&conserved_residues)?;
+        if scheme.extend_truncated_fr1 {
+            vregion_annotation.framework_annotation.fr1.start -= self.unaligned_fr1_residues();
+        }
+        Ok(vregion_annotation)
+    }
+
+    /// Number the query: transfer the conserved residues, annotate the regions and number them.
+    ///
+    /// This is [`Self::vregion_annotation_for`] followed by [`VRegionAnnotation::number_regions`].
+    pub fn numbered(&self, scheme: &NumberingScheme) -> Result<Vec<Annotation>, NumberingError> {
+        Ok(self
+            .vregion_annotation_for(scheme)?
+            .number_regions(self, scheme)?)
+    }
+
+    /// The reference (top) and query (bottom) as they are aligned, with `|` between
+    /// identical residues and `\\` between substitutions, wrapped after `width`
+    /// columns, see [`Alignment::pretty`].
+    pub fn pretty(&self, width: usize) -> String {
+        self.alignment.pretty(
+            &self.reference.get_sequence(),
+            self.query_record.seq(),
+            width,
+        )
+    }
+
+    /// The name of the matched reference and the alignment score, as written to the
+    /// description of FASTA records so the germline assignment is kept.
+    pub fn reference_fields(&self) -> [String; 2] {
+        [
+            format!("reference={}", self.reference.name),
+            format!("score={}", self.alignment.score),
+        ]
+    }
+
+    /// The first and last pair of aligned residues, as (one based) positions of the path.
+    ///
+    /// Unlike `xstart` and `xend` these do not depend on the [`AlignmentMode`]: a
+    /// global alignment covers all of the reference, but gaps at its ends are not aligned.
+    fn aligned_ends(&self) -> Option<((usize, usize), (usize, usize))> {
+        let path = self.alignment.path();
+        let mut aligned = path.iter().filter(|(_x, _y, op)| {
+            matches!(op, AlignmentOperation::Match | AlignmentOperation::Subst)
+        });
+        let first = aligned.next()?;
+        let last = aligned.next_back().unwrap_or(first);
+        Some(((first.0, first.1), (last.0, last.1)))
+    }
+
+    /// Check whether the alignment covers the first and last framework positions of the reference.
+    pub fn completeness(&self) -> Completeness {
+        match self.aligned_ends() {
+            Some(((first_x, _), (last_x, _))) => Completeness {
+                missing_fr1: first_x > 1,
+                missing_fr4: last_x < self.alignment.xlen,
+            },
+            None => Completeness {
+                missing_fr1: true,
+                missing_fr4: true,
+            },
+        }
+    }
+
+    /// The IMGT positions of the reference residues before the first aligned residue.
+    ///
+    /// A local alignment need not start at the first residue of the reference, for
+    /// example for partial reads or a divergent start. These positions are not
+    /// numbered, and FR1 starts at the first aligned residue (see [`Completeness::missing_fr1`]),
+    /// unless [`NumberingScheme::extend_truncated_fr1`] gives them to the residues before it.
+    pub fn missing_fr1_positions(&self) -> Vec<usize> {
+        let first_x = match self.aligned_ends() {
+            Some(((first_x, _), _)) => first_x,
+            None => return vec![],
+        };
+        (FR1_START..CDR1_START)
+            .filter(|position| {
+                self.reference
+                    .get_sequence_position(*position)
+                    .is_some_and(|x| x < first_x)
+            })
+            .collect()
+    }
+
+    /// How many query residues before the first aligned residue can be numbered by
+    /// the [missing FR1 positions](Self::missing_fr1_positions).
+    ///
+    /// These residues did not align, so their positions follow from the offset of
+    /// the first aligned residue: the residue right before it takes the last missing
+    /// position, and so on, as far as there are both residues and positions.
+    pub fn unaligned_fr1_residues(&self) -> usize {
+        match self.aligned_ends() {
+            Some(((_, first_y), _)) => (first_y - 1).min(self.missing_fr1_positions().len()),
+            None => 0,
+        }
+    }
+
+    /// Number the query by the columns of the curated alignment of its reference.
+    ///
+    /// All queries assigned to the same reference line up column for column. Unlike
+    /// the IMGT numbering, query residues that are not aligned to a reference residue
+    /// are insertions after the preceding column (`52.1`, `52.2`, ...), also in the
+    /// CDRs. Residues before the first aligned column are not numbered.
+    pub fn germline_columns(&self) -> Vec<Annotation> {
+        let mut annotations = Vec::new();
+        let mut previous_column = None;
+        let mut insertion = 0;
+        for (x, y, operation) in self.alignment.path() {
+            let name = match operation {
+                AlignmentOperation::Match | AlignmentOperation::Subst => {
+                    let column = self.reference.get_alignment_column(x);
+                    previous_column = column;
+                    insertion = 0;
+                    column.map(|column| column.to_string())
+                }
+                AlignmentOperation::Del => {
+                    insertion += 1;
+                    previous_column.map(|column| format!("{column}.{insertion}"))
+                }
+                _ => None,
+            };
+            if let Some(name) = name {
+                annotations.push(Annotation {
+                    start: y - 1,
+                    end: y,
+                    name,
+                });
+            }
+        }
+        annotations
+    }
+
+    /// The ranges of the reference and the query that are aligned.
+    pub fn offsets(&self) -> AlignmentOffsets {
+        match self.aligned_ends() {
+            Some(((first_x, first_y), (last_x, last_y))) => AlignmentOffsets {
+                reference: first_x - 1..last_x,
+                query: first_y - 1..last_y,
+            },
+            None => AlignmentOffsets {
+                reference: self.alignment.xstart..self.alignment.xstart,
+                query: self.alignment.ystart..self.alignment.ystart,
+            },
+        }
+    }
+
+    /// Alignment score per aligned column, which does not depend on the length of the query.
+    ///
+    /// This is `score / columns`, where the columns are all matches, substitutions,
+    /// insertions and deletions of the alignment. With the scoring of [`new_aligner`]
+    /// it is at most 1, for an identical sequence. The raw score is `alignment.score`.
+    pub fn normalized_score(&self) -> f64 {
+        let columns = self.alignment.operations.len();
+        if columns == 0 {
+            return 0.0;
+        }
+        self.alignment.score as f64 / columns as f64
+    }
+
+    /// Fraction of the aligned columns in which the query is identical to the reference.
+    ///
+    /// Gaps before the first and after the last pair of aligned residues, which a
+    /// global alignment of a partial query has, do not count.
+    pub fn identity(&self) -> f64 {
+        let operations = &self.alignment.operations;
+        let Some(first) = operations.iter().position(|operation| {
+            matches!(
+                operation,
+                AlignmentOperation::Match | AlignmentOperation::Subst
+            )
+        }) else {
+            return 0.0;
+        };
+        let last = operations
+            .iter()
+            .rposition(|operation| {
+                matches!(
+                    operation,
+                    AlignmentOperation::Match | AlignmentOperation::Subst
+                )
+            })
+            .unwrap_or(first);
+        let columns = &operations[first..=last];
+        let matches = columns
+            .iter()
+            .filter(|operation| **operation == AlignmentOperation::Match)
+            .count();
+        matches as f64 / columns.len() as f64
+    }
+
+    /// Check whether the query looks like a translation in the wrong frame.
+    pub fn frame_check(&self) -> FrameCheck {
+        FrameCheck {
+            stop_codon: self.query_record.seq()[self.offsets().query].contains(&b'*'),
+            low_identity: self.identity() < MIN_IN_FRAME_IDENTITY,
+        }
+    }
+
+    /// Whether the query is a productive, complete V-domain.
+    ///
+    /// That is, all of:
+    /// - it has no stop codon and is not likely out of frame, see [`FrameCheck`],
+    /// - the conserved residues of the reference transfer to it, see [`Self::vregion_annotation`],
+    /// - it covers FR1 through FR4 completely, see [`Completeness`].
+    pub fn is_productive(&self) -> bool {
+        !self.frame_check().likely_out_of_frame()
+            && self.completeness().is_complete()
+            && self.vregion_annotation().is_ok()
+    }
+
+    /// Names of all flags that are set for the query, see [`Completeness`] and [`FrameCheck`],
+    /// and `modified_n_terminus` for a modified first residue.
+    pub fn flags(&self) -> Vec<&'static str> {
+        let mut flags = self.completeness().flags();
+        flags.extend(self.frame_check().flags());
+        if self.modified_n_terminus {
+            flags.push("modified_n_terminus");
+        }
+        flags
+    }
+}
+
+/// Minimal quality of the best alignment for a query to be numbered.
+///
+/// When both are set, the alignment has to pass both.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Thresholds {
+    /// Minimal normalized alignment score, see [`ReferenceAlignment::normalized_score`].
+    pub min_score: Option<f64>,
+    /// Minimal identity to the reference, see [`ReferenceAlignment::identity`].
+    pub min_identity: Option<f64>,
+}
+
+impl Thresholds {
+    /// Whether any threshold is set, so that queries have to be aligned to be checked.
+    pub fn is_set(&self) -> bool {
+        self.min_score.is_some() || self.min_identity.is_some()
+    }
+
+    /// Reject a reference alignment that does not meet the thresholds.
+    pub fn check(
+        &self,
+        reference_alignment: ReferenceAlignment,
+    ) -> Result<ReferenceAlignment, RefSeqErr> {
+        let score = reference_alignment.normalized_score();
+        if let Some(min_score) = self.min_score.filter(|min_score| score < *min_score) {
+            return Err(RefSeqErr::ScoreTooLow(
+                reference_alignment.query_record,
+                score,
+                min_score,
+            ));
+        }
+        let identity = reference_alignment.identity();
+        if let Some(min_identity) = self
+            .min_identity
+            .filter(|min_identity| identity < *min_identity)
+        {
+            return Err(RefSeqErr::IdentityTooLow(
+                reference_alignment.query_record,
+                identity,
+                min_identity,
+            ));
+        }
+        Ok(reference_alignment)
+    }
+}
+
+/// Scoring function used to align queries to the reference sequences.
+pub type MatchFn = fn(u8, u8) -> i32;
+
+/// Create the aligner used to find reference sequences.
+pub fn new_aligner() -> Aligner<MatchFn> {
+    // TODO: Optimize settings.
+    // Settings taken from rust bio example. Fully unoptimized.
+    Aligner::new(-5, -1, |a, b| if a == b { 1i32 } else { -1i32 })
+}
+
+/// Create an aligner with its matrices sized for the longest of the references.
+///
+/// Queries are about as long as their references, so aligning a query to any
+/// of them does not have to grow the matrices.
+pub fn new_aligner_for(ref_seqs: &ReferenceIndex) -> Aligner<MatchFn> {
+    let length = ref_seqs
+        .values()
+        .map(|reference| reference.get_sequence().len())
+        .max()
+        .unwrap_or_default();
+    Aligner::with_capacity(
+        length,
+        length,
+        -5,
+        -1,
+        |a, b| {
+            if a == b {
+                1i32
+            } else {
+                -1i32
+            }
+        },
+    )
+}
+
+thread_local! {
+    /// The aligner of [`with_thread_aligner`].
+    static THREAD_ALIGNER: RefCell<Aligner<MatchFn>> = RefCell::new(new_aligner());
+}
+
+/// Call `f` with the aligner of the current thread.
+///
+/// An aligner allocates its matrices when it is created and reuses them for every
+/// alignment after, so reusing one aligner for all queries aligned on a thread
+/// avoids allocating for every query. Panics when called again from within `f`.
+pub fn with_thread_aligner<T>(f: impl FnOnce(&mut Aligner<MatchFn>) -> T) -> T {
+    THREAD_ALIGNER.with_borrow_mut(f)
+}
+
+/// Length of the exact matches that seed the band of a [`new_banded_aligner`].
+pub const BANDED_KMER_LENGTH: usize = 3;
+
+/// Create a banded aligner, which only aligns within `band_width` of exact matches.
+///
+/// Much faster than [`new_aligner`] for long queries (such as a V-domain with a
+/// constant region attached), since the query is so similar to its reference
+/// that the best alignment stays close to the diagonal. It is not guaranteed to
+/// find the same alignment, see `examples/banded_alignment.rs` for a comparison.
+pub fn new_banded_aligner(band_width: usize) -> banded::Aligner<MatchFn> {
+    banded::Aligner::new(
+        -5,
+        -1,
+        |a, b| if a == b { 1i32 } else { -1i32 },
+        BANDED_KMER_LENGTH,
+        band_width,
+    )
+}
+
+/// An aligner of queries to reference sequences, see [`AlignmentMode::align`].
+pub trait PairwiseAligner {
+    fn local(&mut self, reference: &[u8], query: &[u8]) -> Alignment;
+    fn semiglobal(&mut self, reference: &[u8], query: &[u8]) -> Alignment;
+    fn global(&mut self, reference: &[u8], query: &[u8]) -> Alignment;
+}
+
+impl PairwiseAligner for Aligner<MatchFn> {
+    fn local(&mut self, reference: &[u8], query: &[u8]) -> Alignment {
+        Aligner::local(self, reference, query)
+    }
+    fn semiglobal(&mut self, reference: &[u8], query: &[u8]) -> Alignment {
+        Aligner::semiglobal(self, reference, query)
+    }
+    fn global(&mut self, reference: &[u8], query: &[u8]) -> Alignment {
+        Aligner::global(self, reference, query)
+    }
+}
+
+/// Whether a query is too short for [`new_banded_aligner`], which then aligns it in full.
+///
+/// The banded aligner starts from the k-mers that the query shares with the
+/// reference, and tries to allocate gigabytes for an empty query.
+fn is_too_short_for_band(query: &[u8]) -> bool {
+    query.len() < BANDED_KMER_LENGTH
+}
+
+impl PairwiseAligner for banded::Aligner<MatchFn> {
+    fn local(&mut self, reference: &[u8], query: &[u8]) -> Alignment {
+        if is_too_short_for_band(query) {
+            return new_aligner().local(reference, query);
+        }
+        banded::Aligner::local(self, reference, query)
+    }
+    fn semiglobal(&mut self, reference: &[u8], query: &[u8]) -> Alignment {
+        if is_too_short_for_band(query) {
+            return new_aligner().semiglobal(reference, query);
+        }
+        banded::Aligner::semiglobal(self, reference, query)
+    }
+    fn global(&mut self, reference: &[u8], query: &[u8]) -> Alignment {
+        if is_too_short_for_band(query) {
+            return new_aligner().global(reference, query);
+        }
+        banded::Aligner::global(self, reference, query)
+    }
+}
+
+/// How queries are aligned to the reference sequences.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AlignmentMode {
+    /// Align the best matching parts, which may leave out divergent ends of the query.
+    #[default]
+    Local,
+    /// Align all of the reference to any part of the query, suited to full-length V-domains.
+    Semiglobal,
+    /// Align all of the reference to all of the query.
+    Global,
+}
+
+impl AlignmentMode {
+    /// Align a query to a reference sequence in this mode.
+    pub fn align<A: PairwiseAligner>(
+        self,
+        aligner: &mut A,
+        reference: &[u8],
+        query: &[u8],
+    ) -> Alignment {
+        match self {
+            AlignmentMode::Local => aligner.local(reference, query),
+            AlignmentMode::Semiglobal => aligner.semiglobal(reference, query),
+            AlignmentMode::Global => aligner.global(reference, query),
+        }
+    }
 }
 
 /// Find the record that produces the best alignment.
 pub fn find_best_reference_sequence(
-    record: fasta::Record,
-    ref_seqs: &HashMap<&str, ReferenceSequence>,
+    record: impl Into<Query>,
+    ref_seqs: &ReferenceIndex,
 ) -> Result<ReferenceAlignment, RefSeqErr> {
-    trace!(query_seq = record.id(), "Finding reference sequence.");
-    // TODO: Optimize settings.
-    // Settings taken from rust bio example. Fully unoptimized.
-    let mut aligner =
-        bio::alignment::pairwise::Aligner::new(-5, -1, |a, b| if a == b { 1i32 } else { -1i32 });
+    with_thread_aligner(|aligner| {
+        find_best_reference_sequence_with_aligner(record, ref_seqs, aligner)
+    })
+}
+
+/// Find the record that produces the best alignment, reusing an existing aligner.
+pub fn find_best_reference_sequence_with_aligner(
+    record: impl Into<Query>,
+    ref_seqs: &ReferenceIndex,
+    aligner: &mut Aligner<MatchFn>,
+) -> Result<ReferenceAlignment, RefSeqErr> {
+    find_best_reference_sequence_with_mode(record, ref_seqs, aligner, AlignmentMode::Local)
+}
+
+/// Find the record that produces the best alignment in the given mode, reusing an existing aligner.
+///
+/// The aligner is either a full [`new_aligner`] or a [`new_banded_aligner`]. Of
+/// references with the same score, the one whose name sorts first is taken, so a
+/// query is numbered the same way whatever the order of the [`ReferenceIndex`].
+pub fn find_best_reference_sequence_with_mode<A: PairwiseAligner>(
+    record: impl Into<Query>,
+    ref_seqs: &ReferenceIndex,
+    aligner: &mut A,
+    mode: AlignmentMode,
+) -> Result<ReferenceAlignment, RefSeqErr> {
+    let query: Query = record.into();
+    trace!(query_seq = query.id(), "Finding reference sequence.");
+    let sequence = query.alignment_seq();
 
     // TODO: Optimize this to go by alignment block!
-    ref_seqs
+    let best = ref_seqs
         .values()
         .map(|reference_sequence| {
             (
                 reference_sequence,
-                aligner.local(&reference_sequence.get_sequence(), record.seq()),
+                mode.align(aligner, &reference_sequence.get_sequence(), &sequence),
             )
         })
-        .max_by_key(|(_reference, alignment)| alignment.score)
-        .map(|(reference, alignment)| {
-            trace!(
-                score = alignment.score,
-                reference = reference.name,
-                "Found alignment."
+        .max_by_key(|(reference, alignment)| (alignment.score, Reverse(&reference.name)));
+    let Some((reference, alignment)) = best else {
+        return Err(RefSeqErr::NoReferenceSequenceFound(query.into()));
+    };
+    trace!(
+        score = alignment.score,
+        reference = reference.name,
+        "Found alignment."
+    );
+    Ok(ReferenceAlignment {
+        // Cloning here should not be a huge problem, since we only clone once per query sequence.
+        reference: reference.clone(),
+        alignment,
+        modified_n_terminus: query.has_modified_n_terminus(),
+        query_record: query.into(),
+    })
+}
+
+/// What to do with a query that matches several chain types equally well, see [`find_tied_chain_alignments_with_mode`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AmbiguousChainPolicy {
+    /// Number it with the best reference, see [`find_best_reference_sequence_with_mode`].
+    #[default]
+    Best,
+    /// Number it with the best reference of each of the chain types.
+    Both,
+    /// Fail with [`RefSeqErr::AmbiguousChain`].
+    Fail,
+}
+
+/// Find the best reference of every chain type that the query matches best, see [`ReferenceSequence::locus`].
+///
+/// Usually this is the single best reference, but a query that matches several
+/// chain types equally well (such as an scFv) gets the best reference of each of
+/// them, ordered by locus. References without a locus count as one chain type.
+/// Ties within a chain type are broken by name, as in [`find_best_reference_sequence_with_mode`].
+pub fn find_tied_chain_alignments_with_mode<A: PairwiseAligner>(
+    record: impl Into<Query>,
+    ref_seqs: &ReferenceIndex,
+    aligner: &mut A,
+    mode: AlignmentMode,
+) -> Result<Vec<ReferenceAlignment>, RefSeqErr> {
+    let query: Query = record.into();
+    let sequence = query.alignment_seq();
+
+    let mut best_per_locus: BTreeMap<Option<&str>, (&ReferenceSequence, Alignment)> =
+        BTreeMap::new();
+    for reference_sequence in ref_seqs.values() {
+        let alignment = mode.align(aligner, &reference_sequence.get_sequence(), &sequence);
+        let locus = reference_sequence.locus();
+        if best_per_locus
+            .get(&locus)
+            .is_none_or(|(best_reference, best)| {
+                (best.score, Reverse(&best_reference.name))
+                    < (alignment.score, Reverse(&reference_sequence.name))
+            })
+        {
+            best_per_locus.insert(locus, (reference_sequence, alignment));
+        }
+    }
+    let Some(top_score) = best_per_locus
+        .values()
+        .map(|(_reference, alignment)| alignment.score)
+        .max()
+    else {
+        return Err(RefSeqErr::NoReferenceSequenceFound(query.into()));
+    };
+    Ok(best_per_locus
+        .into_values()
+        .filter(|(_reference, alignment)| alignment.score == top_score)
+        .map(|(reference, alignment)| ReferenceAlignment {
+            reference: reference.clone(),
+            alignment,
+            query_record: query.record().clone(),
+            modified_n_terminus: query.has_modified_n_terminus(),
+        })
+        .collect())
+}
+
+/// Align a query to the reference with the given name, instead of the best reference.
+pub fn align_to_reference(
+    record: impl Into<Query>,
+    ref_seqs: &ReferenceIndex,
+    name: &str,
+    mode: AlignmentMode,
+) -> Result<ReferenceAlignment, RefSeqErr> {
+    let query: Query = record.into();
+    let Some(reference) = ref_seqs.get(name) else {
+        return Err(RefSeqErr::UnknownReference(query.into(), name.to_string()));
+    };
+    let alignment = with_thread_aligner(|aligner| {
+        mode.align(aligner, &reference.get_sequence(), &query.alignment_seq())
+    });
+    trace!(
+        score = alignment.score,
+        reference = reference.name,
+        "Aligned to chosen reference."
+    );
+    Ok(ReferenceAlignment {
+        reference: reference.clone(),
+        alignment,
+        modified_n_terminus: query.has_modified_n_terminus(),
+        query_record: query.into(),
+    })
+}
+
+/// Number a query against a set of references.
+///
+/// Does not panic on any input, including sequences that are not V-regions or
+/// contain bytes that are not amino acids, so it can process untrusted input.
+pub fn try_number(
+    query: impl Into<Query>,
+    ref_seqs: &ReferenceIndex,
+    scheme: &NumberingScheme,
+) -> Result<Vec<Annotation>, NumberingError> {
+    find_best_reference_sequence(query, ref_seqs)?.numbered(scheme)
+}
+
+/// Number a sequence with the default [`NumberingScheme`], as pairs of IMGT number and residue.
+///
+/// The simplest way to number a single sequence, see [`try_number`] for the annotations themselves.
+pub fn number_sequence(
+    record: &fasta::Record,
+    ref_seqs: &ReferenceIndex,
+) -> Result<Vec<(String, u8)>, NumberingError> {
+    Ok(
+        try_number(record.clone(), ref_seqs, &NumberingScheme::default())?
+            .into_iter()
+            .map(|annotation| {
+                let residue = record.seq()[annotation.start];
+                (annotation.name, residue)
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::imgt::fixtures::{
+        named_reference, ref_seqs, test_ref_seqs, test_reference, TEST_ALIGNMENT_STR, TEST_SEQUENCE,
+    };
+
+    fn align(sequence: &str) -> ReferenceAlignment {
+        let ref_seqs = test_ref_seqs();
+        find_best_reference_sequence(
+            fasta::Record::with_attrs("query", None, sequence.as_bytes()),
+            &ref_seqs,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_pretty() {
+        let pretty = align(TEST_SEQUENCE).pretty(50);
+        let lines: Vec<_> = pretty.lines().collect();
+        // The query is the germline, so every pair is a match.
+        assert_eq!(lines[0], &TEST_SEQUENCE[..50]);
+        assert_eq!(lines[1], "|".repeat(50));
+        assert_eq!(lines[2], &TEST_SEQUENCE[..50]);
+        assert!(pretty.contains(&TEST_SEQUENCE[100..]));
+
+        let mutated = TEST_SEQUENCE.replacen("QVQLV", "QVELV", 1);
+        let pretty = align(&mutated).pretty(50);
+        assert!(pretty.lines().nth(1).unwrap().starts_with("||\\||"));
+    }
+
+    #[test]
+    fn test_gapped_query() {
+        let ref_seqs = test_ref_seqs();
+        let gapped = fasta::Record::with_attrs("query", None, TEST_ALIGNMENT_STR.as_bytes());
+        let stripped = input::strip_gaps(&gapped).unwrap();
+        assert_eq!(stripped.seq(), TEST_SEQUENCE.as_bytes());
+        assert_eq!(
+            number_sequence(&stripped, &ref_seqs).unwrap(),
+            number_sequence(
+                &fasta::Record::with_attrs("query", None, TEST_SEQUENCE.as_bytes()),
+                &ref_seqs
+            )
+            .unwrap()
+        );
+        assert!(input::strip_gaps(&stripped).is_none());
+
+        let pre_aligned = numbering::number_pre_aligned(&gapped).unwrap();
+        assert_eq!(pre_aligned.record.seq(), TEST_SEQUENCE.as_bytes());
+        let n = pre_aligned
+            .numbering
+            .iter()
+            .find(|annotation| annotation.start == 54)
+            .unwrap();
+        assert_eq!(n.name, "62");
+        assert_eq!(pre_aligned.numbering.last().unwrap().name, "128");
+
+        let too_long =
+            fasta::Record::with_attrs("query", None, format!("{TEST_ALIGNMENT_STR}S").as_bytes());
+        assert!(matches!(
+            numbering::number_pre_aligned(&too_long),
+            Err(IMGTError::PreAlignedTooLong(129))
+        ));
+    }
+
+    #[test]
+    fn test_query_from_id_and_sequence() {
+        let ref_seqs = test_ref_seqs();
+        let reference_alignment =
+            find_best_reference_sequence(Query::new("query", TEST_SEQUENCE.as_bytes()), &ref_seqs)
+                .unwrap();
+        assert_eq!(reference_alignment.query_record.id(), "query");
+        assert_eq!(
+            reference_alignment.alignment,
+            align(TEST_SEQUENCE).alignment
+        );
+    }
+
+    #[test]
+    fn test_number_sequence() {
+        let ref_seqs = test_ref_seqs();
+        let record = fasta::Record::with_attrs("query", None, TEST_SEQUENCE.as_bytes());
+        let numbered = number_sequence(&record, &ref_seqs).unwrap();
+
+        assert_eq!(numbered.len(), TEST_SEQUENCE.len());
+        assert!(numbered.contains(&("104".to_string(), b'C')));
+        assert!(numbered.contains(&("118".to_string(), b'W')));
+    }
+
+    #[test]
+    fn test_align_to_reference() {
+        // Same reference, but with a different CDR3 and J.
+        let other_alignment = TEST_ALIGNMENT_STR.replace("MDVWGQGTTVTVSS", "FDYWGQGTLVTVSS");
+        let ref_seqs = ref_seqs([
+            test_reference(),
+            ReferenceSequence::new("other", other_alignment.as_bytes()).unwrap(),
+        ]);
+        let query = Query::new("query", TEST_SEQUENCE.as_bytes());
+
+        let best = find_best_reference_sequence(query.clone(), &ref_seqs).unwrap();
+        assert_eq!(best.reference.name, "test");
+        let forced =
+            align_to_reference(query.clone(), &ref_seqs, "other", AlignmentMode::Local).unwrap();
+        assert_eq!(forced.reference.name, "other");
+        assert!(forced.alignment.score < best.alignment.score);
+        assert!(matches!(
+            align_to_reference(query, &ref_seqs, "missing", AlignmentMode::Local),
+            Err(RefSeqErr::UnknownReference(_, name)) if name == "missing"
+        ));
+    }
+
+    #[test]
+    fn test_imgt_junction_is_cdr3_with_flanks() {
+        let vregion_annotation = align(TEST_SEQUENCE).vregion_annotation().unwrap();
+        let cdr3 = &vregion_annotation.cdr_annotation.cdr3;
+        let seq = TEST_SEQUENCE.as_bytes();
+        let junction = vregion_annotation.imgt_junction(seq).unwrap();
+        assert_eq!(junction, "CARMDVW");
+        assert_eq!(junction.as_bytes(), &seq[cdr3.start - 1..cdr3.end + 1]);
+        assert_eq!(vregion_annotation.imgt_junction(&seq[..cdr3.end]), None);
+
+        // A truncated sequence has no junction.
+        let truncated = &TEST_SEQUENCE[..TEST_SEQUENCE.find("WGQG").unwrap()];
+        let vregion_annotation = align(truncated).partial_vregion_annotation().unwrap();
+        assert_eq!(vregion_annotation.imgt_junction(truncated.as_bytes()), None);
+    }
+
+    #[test]
+    fn test_non_standard_residues() {
+        // A selenocysteine as the 2nd-CYS (YYCAR -> YYUAR) and a pyrrolysine in FR1 (VKK -> VOK).
+        let sequence = TEST_SEQUENCE
+            .replace("YYCAR", "YYUAR")
+            .replace("VKK", "VOK");
+        let reference_alignment = align(&sequence);
+        assert_eq!(
+            reference_alignment.alignment,
+            align(TEST_SEQUENCE).alignment
+        );
+        let numbering = reference_alignment
+            .numbered(&NumberingScheme::default())
+            .unwrap();
+        assert_eq!(
+            numbering,
+            align(TEST_SEQUENCE)
+                .numbered(&NumberingScheme::default())
+                .unwrap()
+        );
+        let second_cys = numbering
+            .iter()
+            .find(|annotation| annotation.name == "104")
+            .unwrap();
+        assert_eq!(sequence.as_bytes()[second_cys.start], b'U');
+    }
+
+    #[test]
+    fn test_n_terminal_modification() {
+        for modified in [
+            format!("<{}", &TEST_SEQUENCE[1..]),
+            format!("q{}", &TEST_SEQUENCE[1..]),
+        ] {
+            let reference_alignment = align(&modified);
+            assert!(reference_alignment.completeness().is_complete());
+            assert_eq!(reference_alignment.flags(), ["modified_n_terminus"]);
+            assert_eq!(
+                reference_alignment.alignment,
+                align(TEST_SEQUENCE).alignment
+            );
+            // The output keeps the modified residue.
+            let fr1 = reference_alignment.vregion_annotation().unwrap();
+            let fr1 = &fr1.framework_annotation.fr1;
+            assert_eq!(
+                reference_alignment.query_record.seq()[fr1.start],
+                modified.as_bytes()[0]
+            );
+        }
+
+        let reference_alignment = find_best_reference_sequence(
+            Query::new("query", format!("X{}", &TEST_SEQUENCE[1..]).as_bytes())
+                .with_n_terminal_marker(b'X'),
+            &test_ref_seqs(),
+        )
+        .unwrap();
+        assert!(reference_alignment.completeness().is_complete());
+        assert!(reference_alignment.modified_n_terminus);
+        assert!(!align(TEST_SEQUENCE).modified_n_terminus);
+    }
+
+    #[test]
+    fn test_complete_sequence() {
+        assert!(align(TEST_SEQUENCE).completeness().is_complete());
+    }
+
+    #[test]
+    fn test_numbered() {
+        let reference_alignment = align(TEST_SEQUENCE);
+        let scheme = NumberingScheme::default();
+        let expected = reference_alignment
+            .vregion_annotation()
+            .unwrap()
+            .number_regions(&reference_alignment, &scheme)
+            .unwrap();
+        assert_eq!(reference_alignment.numbered(&scheme).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_number_regions_iter() {
+        let reference_alignment = align(TEST_SEQUENCE);
+        let vregion_annotation = reference_alignment.vregion_annotation().unwrap();
+        let scheme = NumberingScheme::default();
+        let lazy: Result<Vec<_>, _> = vregion_annotation
+            .number_regions_iter(&reference_alignment, &scheme)
+            .collect();
+        assert_eq!(
+            lazy.unwrap(),
+            reference_alignment.numbered(&scheme).unwrap()
+        );
+
+        // Stops at the CDR3, after the regions before it.
+        let scheme = NumberingScheme {
+            max_cdr3_length: 1,
+            ..Default::default()
+        };
+        let results: Vec<_> = vregion_annotation
+            .number_regions_iter(&reference_alignment, &scheme)
+            .collect();
+        let (last, numbered) = results.split_last().unwrap();
+        assert!(matches!(last, Err(IMGTError::CDR3TooLong(..))));
+        assert_eq!(numbered.last().unwrap().as_ref().unwrap().name, "104");
+    }
+
+    #[test]
+    fn test_in_frame() {
+        let reference_alignment = align(TEST_SEQUENCE);
+        assert_eq!(reference_alignment.identity(), 1.0);
+        assert!(!reference_alignment.frame_check().likely_out_of_frame());
+        assert!(reference_alignment.flags().is_empty());
+    }
+
+    #[test]
+    fn test_stop_codon_is_out_of_frame() {
+        let with_stop = format!("{}*{}", &TEST_SEQUENCE[..50], &TEST_SEQUENCE[51..]);
+        let reference_alignment = align(&with_stop);
+        assert!(reference_alignment.frame_check().stop_codon);
+        assert_eq!(reference_alignment.flags(), vec!["likely_out_of_frame"]);
+    }
+
+    fn align_with_mode(sequence: &str, mode: AlignmentMode) -> ReferenceAlignment {
+        let ref_seqs = test_ref_seqs();
+        find_best_reference_sequence_with_mode(
+            Query::new("query", sequence.as_bytes()),
+            &ref_seqs,
+            &mut new_aligner(),
+            mode,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_alignment_modes() {
+        // Divergent first residues are left out by a local alignment.
+        let divergent_start = format!("EIA{}", &TEST_SEQUENCE[3..]);
+        let local = align_with_mode(&divergent_start, AlignmentMode::Local);
+        assert!(local.completeness().missing_fr1);
+        let semiglobal = align_with_mode(&divergent_start, AlignmentMode::Semiglobal);
+        assert!(semiglobal.completeness().is_complete());
+        let numbered = semiglobal.numbered(&NumberingScheme::default()).unwrap();
+        assert_eq!(numbered[0].name, "1");
+        assert_eq!(numbered[0].start, 0);
+
+        // A leader before the V-domain is not part of it with any mode.
+        let with_leader = format!("MKKLL{}", TEST_SEQUENCE);
+        for mode in [
+            AlignmentMode::Local,
+            AlignmentMode::Semiglobal,
+            AlignmentMode::Global,
+        ] {
+            let reference_alignment = align_with_mode(&with_leader, mode);
+            assert_eq!(reference_alignment.offsets().query, 5..with_leader.len());
+            assert_eq!(reference_alignment.identity(), 1.0);
+            let numbered = reference_alignment
+                .numbered(&NumberingScheme::default())
+                .unwrap();
+            assert_eq!((numbered[0].start, numbered[0].name.as_str()), (5, "1"));
+        }
+
+        // A truncated query has unaligned reference ends, even though a global
+        // alignment spans the whole reference.
+        let truncated = align_with_mode(&TEST_SEQUENCE[6..], AlignmentMode::Global);
+        assert_eq!(truncated.alignment.xstart, 0);
+        assert!(truncated.completeness().missing_fr1);
+        assert_eq!(truncated.offsets().reference.start, 6);
+    }
+
+    #[test]
+    fn test_vregion_boundaries_per_mode() {
+        // A leader before and a constant region after the V-domain, neither of which is part of it.
+        let leader = "MKKLL";
+        let extended = format!("{leader}{TEST_SEQUENCE}ASTKGPSVFPL");
+        let v_region = leader.len()..leader.len() + TEST_SEQUENCE.len();
+        for mode in [
+            AlignmentMode::Local,
+            AlignmentMode::Semiglobal,
+            AlignmentMode::Global,
+        ] {
+            let reference_alignment = align_with_mode(&extended, mode);
+            let vregion_annotation = reference_alignment.vregion_annotation().unwrap();
+            let framework = &vregion_annotation.framework_annotation;
+            assert_eq!(framework.fr1.start..framework.fr4.end, v_region, "{mode:?}");
+
+            // Without its first and last residues the V-region is what is left, even
+            // though a global alignment covers all of the reference.
+            let truncated = &TEST_SEQUENCE[3..TEST_SEQUENCE.len() - 3];
+            let reference_alignment = align_with_mode(truncated, mode);
+            let vregion_annotation = reference_alignment.vregion_annotation().unwrap();
+            let framework = &vregion_annotation.framework_annotation;
+            assert_eq!(
+                framework.fr1.start..framework.fr4.end,
+                0..truncated.len(),
+                "{mode:?}"
+            );
+        }
+
+        // The alignment of a reference to itself has no clips.
+        let reference = test_reference();
+        let framework = reference.get_vregion_annotation().framework_annotation;
+        assert_eq!(
+            framework.fr1.start..framework.fr4.end,
+            0..TEST_SEQUENCE.len()
+        );
+    }
+
+    #[test]
+    fn test_banded_aligner() {
+        // The test sequence with the CH1 of IgG1 attached and a longer CDR3 (ARMDV -> ARGGYMDV).
+        let long = format!(
+            "{}ASTKGPSVFPLAPSSKSTSGGTAALGCLVKDYFPEPVTVSWNSGALTSGVHTFPAVLQSSGLYSLSSVVTVPSSSLGTQTYICNVNHKPSNTKVDKKVEPKSC",
+            TEST_SEQUENCE.replace("ARMDV", "ARGGYMDV")
+        );
+        let ref_seqs = test_ref_seqs();
+        for mode in [AlignmentMode::Local, AlignmentMode::Semiglobal] {
+            let full = align_with_mode(&long, mode);
+            let banded = find_best_reference_sequence_with_mode(
+                Query::new("query", long.as_bytes()),
+                &ref_seqs,
+                &mut new_banded_aligner(8),
+                mode,
+            )
+            .unwrap();
+            assert_eq!(banded.alignment.score, full.alignment.score);
+            assert_eq!(
+                banded.numbered(&NumberingScheme::default()).unwrap(),
+                full.numbered(&NumberingScheme::default()).unwrap()
             );
-            ReferenceAlignment {
-                // Cloning here should not be a huge problem, since we only clone once per query sequence.
-                reference: reference.clone(),
-                alignment,
-                query_record: record.clone(),
+        }
+    }
+
+    #[test]
+    fn test_banded_aligner_short_queries() {
+        let reference = TEST_SEQUENCE.as_bytes();
+        for query in [&b""[..], b"Q", b"QV"] {
+            for mode in [
+                AlignmentMode::Local,
+                AlignmentMode::Semiglobal,
+                AlignmentMode::Global,
+            ] {
+                assert_eq!(
+                    mode.align(&mut new_banded_aligner(4), reference, query)
+                        .score,
+                    mode.align(&mut new_aligner(), reference, query).score
+                );
             }
-        })
-        .ok_or(RefSeqErr::NoReferenceSequenceFound(record))
+        }
+    }
+
+    #[test]
+    fn test_reused_aligner() {
+        let light = "DIQMTQSPSSVSASVGDRVTITCRASQGI------SSWLAWYQQKPGKAPKLLIYAA-------SSLQSGVP-SRFSGSG--SGTDFTLTISSLQPEDFATYYCQQAN-------FTFGPGTKVDIK-";
+        let ref_seqs = ref_seqs([
+            named_reference("heavy"),
+            ReferenceSequence::new("light", light.as_bytes()).unwrap(),
+        ]);
+        let queries = [
+            TEST_SEQUENCE.to_string(),
+            light.replace('-', ""),
+            TEST_SEQUENCE.replace("ARMDV", "ARGGYMDV"),
+            TEST_SEQUENCE[20..].to_string(),
+        ];
+        let mut sized = new_aligner_for(&ref_seqs);
+        for _ in 0..2 {
+            for (i, query) in queries.iter().enumerate() {
+                let record = || Query::new(&i.to_string(), query.as_bytes());
+                let fresh = find_best_reference_sequence_with_aligner(
+                    record(),
+                    &ref_seqs,
+                    &mut new_aligner(),
+                )
+                .unwrap();
+                for reused in [
+                    find_best_reference_sequence(record(), &ref_seqs).unwrap(),
+                    find_best_reference_sequence_with_aligner(record(), &ref_seqs, &mut sized)
+                        .unwrap(),
+                ] {
+                    assert_eq!(reused.reference, fresh.reference);
+                    assert_eq!(reused.alignment, fresh.alignment);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_best_reference_ties_broken_by_name() {
+        let names = ["test_b", "test_a", "test_c"];
+        let ref_seqs = ref_seqs(names.map(named_reference));
+        let reference_alignment =
+            find_best_reference_sequence(Query::new("query", TEST_SEQUENCE.as_bytes()), &ref_seqs)
+                .unwrap();
+        assert_eq!(reference_alignment.reference.name, "test_a");
+    }
+
+    #[test]
+    fn test_tied_chain_alignments() {
+        let light = "DIQMTQSPSSVSASVGDRVTITCRASQGI------SSWLAWYQQKPGKAPKLLIYAA-------SSLQSGVP-SRFSGSG--SGTDFTLTISSLQPEDFATYYCQQAN-------FTFGPGTKVDIK-";
+        let heavy = named_reference("Homo_sapiens_IGHV1-18*01_IGHJ6*01");
+        let tied_chains = |ref_seqs: ReferenceIndex| {
+            find_tied_chain_alignments_with_mode(
+                Query::new("query", TEST_SEQUENCE.as_bytes()),
+                &ref_seqs,
+                &mut new_aligner(),
+                AlignmentMode::Local,
+            )
+            .unwrap()
+            .into_iter()
+            .map(|reference_alignment| reference_alignment.reference.name)
+            .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            tied_chains(ref_seqs([
+                heavy.clone(),
+                ReferenceSequence::new("Homo_sapiens_IGKV1-12*01_IGKJ3*01", light.as_bytes())
+                    .unwrap(),
+            ])),
+            ["Homo_sapiens_IGHV1-18*01_IGHJ6*01"]
+        );
+        // A kappa reference with the same sequence as the heavy one matches just as well.
+        assert_eq!(
+            tied_chains(ref_seqs([
+                named_reference("Homo_sapiens_IGKV0-1*01_IGKJ1*01"),
+                heavy,
+            ])),
+            [
+                "Homo_sapiens_IGHV1-18*01_IGHJ6*01",
+                "Homo_sapiens_IGKV0-1*01_IGKJ1*01"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cdr3_longer_than_maximum() {
+        // A CDR3-IMGT of 12 residues (ARMDV -> ARGGGGGGGMDV), longer than a maximum of 10.
+        let long_cdr3 = TEST_SEQUENCE.replace("ARMDV", "ARGGGGGGGMDV");
+        let reference_alignment = align(&long_cdr3);
+        let scheme = NumberingScheme {
+            max_cdr3_length: 10,
+            ..Default::default()
+        };
+
+        let error = reference_alignment.numbered(&scheme).unwrap_err();
+        assert!(matches!(
+            error,
+            NumberingError::Annotation(IMGTError::CDR3TooLong(12, 10))
+        ));
+        assert!(error.to_string().contains("12 residues"));
+        assert!(reference_alignment
+            .numbered(&NumberingScheme::default())
+            .is_ok());
+    }
+
+    #[test]
+    fn test_is_productive() {
+        assert!(align(TEST_SEQUENCE).is_productive());
+        // Truncated at either end.
+        assert!(!align(&TEST_SEQUENCE[6..]).is_productive());
+        assert!(!align(&TEST_SEQUENCE[..TEST_SEQUENCE.len() - 12]).is_productive());
+        let with_stop = format!("{}*{}", &TEST_SEQUENCE[..50], &TEST_SEQUENCE[51..]);
+        assert!(!align(&with_stop).is_productive());
+    }
+
+    #[test]
+    fn test_min_score() {
+        let thresholds = Thresholds {
+            min_score: Some(1.0),
+            ..Default::default()
+        };
+        assert!(thresholds.check(align(TEST_SEQUENCE)).is_ok());
+        // The normalized score does not depend on the length of the query.
+        assert!(thresholds.check(align(&TEST_SEQUENCE[20..])).is_ok());
+
+        // Above the best achievable score.
+        let thresholds = Thresholds {
+            min_score: Some(1.5),
+            ..Default::default()
+        };
+        assert!(matches!(
+            thresholds.check(align(TEST_SEQUENCE)),
+            Err(RefSeqErr::ScoreTooLow(_, score, _)) if score == 1.0
+        ));
+    }
+
+    #[test]
+    fn test_normalized_score() {
+        let reference_alignment = align(TEST_SEQUENCE);
+        assert_eq!(reference_alignment.alignment.score, 112);
+        assert_eq!(reference_alignment.normalized_score(), 1.0);
+    }
+
+    #[test]
+    fn test_min_identity() {
+        let mutated = format!("{}A{}", &TEST_SEQUENCE[..50], &TEST_SEQUENCE[51..]);
+        let thresholds = Thresholds {
+            min_identity: Some(1.0),
+            ..Default::default()
+        };
+        assert!(thresholds.check(align(TEST_SEQUENCE)).is_ok());
+        assert!(matches!(
+            thresholds.check(align(&mutated)),
+            Err(RefSeqErr::IdentityTooLow(..))
+        ));
+    }
+
+    #[test]
+    fn test_extend_truncated_fr1() {
+        // Missing the first four residues, with the next two (5 and 6) diverged from the germline.
+        let truncated = format!("GG{}", &TEST_SEQUENCE[6..]);
+        let reference_alignment = align(&truncated);
+        assert_eq!(reference_alignment.unaligned_fr1_residues(), 2);
+        let names = |scheme: &NumberingScheme| -> Vec<_> {
+            reference_alignment
+                .numbered(scheme)
+                .unwrap()
+                .into_iter()
+                .map(|annotation| (annotation.start, annotation.name))
+                .collect()
+        };
+        let aligned = names(&NumberingScheme::default());
+        assert_eq!(aligned[0], (2, "7".to_string()));
+
+        let scheme = NumberingScheme {
+            extend_truncated_fr1: true,
+            ..Default::default()
+        };
+        let extended = names(&scheme);
+        assert_eq!(
+            extended[..3],
+            [
+                (0, "5".to_string()),
+                (1, "6".to_string()),
+                (2, "7".to_string())
+            ]
+        );
+        assert_eq!(extended[2..], aligned);
+        let vregion_annotation = reference_alignment.vregion_annotation_for(&scheme).unwrap();
+        assert_eq!(vregion_annotation.framework_annotation.fr1.start, 0);
+
+        // More leading residues than missing positions: only the last ones are numbered.
+        let reference_alignment = align(&format!("WWWWWWGG{}", &TEST_SEQUENCE[6..]));
+        assert_eq!(reference_alignment.unaligned_fr1_residues(), 6);
+        let numbering = reference_alignment.numbered(&scheme).unwrap();
+        assert_eq!((numbering[0].start, numbering[0].name.as_str()), (2, "1"));
+    }
+
+    #[test]
+    fn test_missing_fr4_anchor() {
+        // Ends in the CDR3, before the J-Trp.
+        let truncated = &TEST_SEQUENCE[..TEST_SEQUENCE.len() - 12];
+        let reference_alignment = align(truncated);
+        assert!(reference_alignment
+            .numbered(&NumberingScheme::default())
+            .is_err());
+
+        let scheme = NumberingScheme {
+            allow_missing_fr4: true,
+            ..Default::default()
+        };
+        let vregion_annotation = reference_alignment.vregion_annotation_for(&scheme).unwrap();
+        assert!(!vregion_annotation.has_fr4_anchor());
+        let fr4 = &vregion_annotation.framework_annotation.fr4;
+        assert_eq!(fr4.start, fr4.end);
+
+        let names: Vec<_> = reference_alignment
+            .numbered(&scheme)
+            .unwrap()
+            .into_iter()
+            .map(|annotation| annotation.name)
+            .collect();
+        assert_eq!(names.first().unwrap(), "1");
+        // Every residue of the CDR3 (ARMD) is numbered.
+        assert_eq!(names.len(), truncated.len());
+        assert_eq!(
+            &names[names.len() - 5..],
+            ["104", "105", "106", "107", "108"]
+        );
+    }
+
+    #[test]
+    fn test_strict_anchors() {
+        // A serine instead of the 2nd-CYS (104), without a cysteine nearby to take its place.
+        let reference_alignment = align(&TEST_SEQUENCE.replace("YYCAR", "YYSAR"));
+        let lenient = NumberingScheme::default();
+        let numbering = reference_alignment.numbered(&lenient).unwrap();
+        assert!(numbering.iter().any(|annotation| annotation.name == "104"));
+
+        let strict = NumberingScheme {
+            strict_anchors: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            reference_alignment.numbered(&strict),
+            Err(NumberingError::Transfer(TransferErr::NonCanonicalAnchor(
+                104, b'S'
+            )))
+        ));
+        assert!(align(TEST_SEQUENCE).numbered(&strict).is_ok());
+    }
+
+    #[test]
+    fn test_missing_fr1() {
+        let completeness = align(&TEST_SEQUENCE[6..]).completeness();
+        assert!(completeness.missing_fr1);
+        assert!(!completeness.missing_fr4);
+        assert_eq!(completeness.flags(), vec!["missing_fr1"]);
+    }
+
+    #[test]
+    fn test_missing_fr1_positions() {
+        assert!(align(TEST_SEQUENCE).missing_fr1_positions().is_empty());
+        // Position 10 is a gap in the reference, so it is not missing from the query.
+        let partial = align(&TEST_SEQUENCE[10..]);
+        assert_eq!(
+            partial.missing_fr1_positions(),
+            [1, 2, 3, 4, 5, 6, 7, 8, 9, 11]
+        );
+
+        // The local alignment only starts at reference position 5.
+        let divergent = align(&format!("WWWW{}", &TEST_SEQUENCE[4..]));
+        assert_eq!(divergent.alignment.xstart, 4);
+        assert_eq!(divergent.missing_fr1_positions(), [1, 2, 3, 4]);
+        let numbering = divergent.numbered(&NumberingScheme::default()).unwrap();
+        assert_eq!(numbering[0].start, 4);
+        assert_eq!(numbering[0].name, "5");
+    }
+
+    #[test]
+    fn test_offsets_of_partial_alignment() {
+        let reference_alignment = align(&TEST_SEQUENCE[6..]);
+        let offsets = reference_alignment.offsets();
+        assert_eq!(
+            offsets.reference,
+            reference_alignment.alignment.xstart..reference_alignment.alignment.xend
+        );
+        assert_eq!(offsets.reference, 6..TEST_SEQUENCE.len());
+        assert_eq!(offsets.query, 0..TEST_SEQUENCE.len() - 6);
+        assert_eq!(
+            offsets.fields(CoordinateBase::Zero),
+            [
+                "reference_start=6",
+                "reference_end=112",
+                "query_start=0",
+                "query_end=106"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_germline_columns_line_up() {
+        let full = align(TEST_SEQUENCE).germline_columns();
+        // Missing the first six residues and with a substitution in the CDR2.
+        let partial = align(&format!(
+            "{}A{}",
+            &TEST_SEQUENCE[6..52],
+            &TEST_SEQUENCE[53..]
+        ))
+        .germline_columns();
+
+        assert_eq!(full[9].name, "11");
+        assert_eq!(full.len(), TEST_SEQUENCE.len());
+        for annotation in &partial {
+            let shared = full
+                .iter()
+                .find(|other| other.start == annotation.start + 6)
+                .unwrap();
+            assert_eq!(shared.name, annotation.name);
+        }
+    }
+
+    #[test]
+    fn test_missing_fr4() {
+        let completeness = align(&TEST_SEQUENCE[..TEST_SEQUENCE.len() - 4]).completeness();
+        assert!(!completeness.missing_fr1);
+        assert!(completeness.missing_fr4);
+    }
 }
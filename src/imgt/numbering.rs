@@ -1,13 +1,273 @@
 use bio::alignment::AlignmentOperation;
+use bio::io::fasta;
+use itertools::{Either, Itertools};
 
 /// Numbering of single amino acids.
 ///
 /// Mapping according to [this](https://www.imgt.org/IMGTScientificChart/Numbering/IMGTIGVLsuperfamily.html) IMGT scientific chart.
-use super::annotations::{Annotation, VRegionAnnotation};
+use super::annotations::{region_positions, Annotation, VRegionAnnotation};
+use super::consensus::NumberedSequence;
+use super::input::is_gap;
 use super::{IMGTError, ReferenceAlignment};
 use crate::imgt;
 use std::collections::HashMap;
 
+/// Check that no position is given to more than one residue of a numbering.
+///
+/// A duplicate points to a bug in the numbering tables or an off-by-one in the
+/// transfer of the conserved residues, so it fails on the first duplicate label.
+pub fn check_unique_positions(numbering: &[Annotation]) -> Result<(), IMGTError> {
+    match numbering
+        .iter()
+        .map(|annotation| annotation.name.as_str())
+        .duplicates()
+        .next()
+    {
+        Some(name) => Err(IMGTError::DuplicatePosition(name.to_string())),
+        None => Ok(()),
+    }
+}
+
+/// Which residues make up the CDR3 when numbering.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Cdr3Definition {
+    /// CDR3-IMGT, positions 105 to 117.
+    #[default]
+    Imgt,
+    /// IMGT JUNCTION, positions 104 to 118. This is the CDR3-IMGT including
+    /// the conserved 2nd-CYS and J-TRP or J-PHE.
+    Junction,
+}
+
+/// How insertions in a CDR3 longer than 13 residues are split over 111 and 112.
+///
+/// The 13 positions 105 to 117 are always numbered, 105 to 111 from the start of
+/// the CDR3 and 112 to 117 from its end. Both policies place insertions alternately
+/// on 111 and 112, numbering them outwards (`111.1`, `111.2`, ..., `112.2`, `112.1`).
+/// They only differ for an odd number of insertions: IMGT puts the extra insertion
+/// on 112, ANARCI puts it on 111. For example a CDR3 of 14 residues gets `112.1`
+/// with IMGT and `111.1` with ANARCI, while a CDR3 of 15 residues gets `111.1` and
+/// `112.1` with both.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Cdr3InsertionPolicy {
+    #[default]
+    Imgt,
+    Anarci,
+}
+
+/// Where the insertions of a CDR longer than its IMGT positions go, see [`number_long_cdr`].
+///
+/// The insertions are divided between the two positions at the top of the loop,
+/// and with an odd number of them one of the two gets the extra one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LoopInsertions {
+    /// The second position gets the extra insertion, as with [`Cdr3InsertionPolicy::Imgt`].
+    ExtraOnSecond,
+    /// The first position gets the extra insertion, as with [`Cdr3InsertionPolicy::Anarci`].
+    ExtraOnFirst,
+}
+
+impl From<Cdr3InsertionPolicy> for LoopInsertions {
+    fn from(policy: Cdr3InsertionPolicy) -> Self {
+        match policy {
+            Cdr3InsertionPolicy::Imgt => LoopInsertions::ExtraOnSecond,
+            Cdr3InsertionPolicy::Anarci => LoopInsertions::ExtraOnFirst,
+        }
+    }
+}
+
+/// The insertions of a long CDR1-IMGT (on 32 and 33) or CDR2-IMGT (on 60 and 61).
+///
+/// IMGT places these like those of a long CDR3-IMGT, which is not configurable
+/// as [`Cdr3InsertionPolicy`] is.
+const CDR1_CDR2_INSERTIONS: LoopInsertions = LoopInsertions::ExtraOnSecond;
+
+/// The error for a CDR of a length that `table` has no numbering for, such as a
+/// CDR1-IMGT shorter than its shortest numbering.
+fn region_length_error(region: &str, size: usize, table: &HashMap<usize, Vec<usize>>) -> IMGTError {
+    if table.keys().all(|&length| size < length) {
+        IMGTError::RegionTooShort(region.to_string(), size)
+    } else {
+        IMGTError::RegionTooLong(region.to_string(), size)
+    }
+}
+
+/// A region of the V-domain, to select which regions are numbered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImgtRegion {
+    Fr1,
+    Cdr1,
+    Fr2,
+    Cdr2,
+    Fr3,
+    /// The CDR3-IMGT, or the JUNCTION with [`Cdr3Definition::Junction`].
+    Cdr3,
+    Fr4,
+}
+
+impl ImgtRegion {
+    /// All regions, in the order of [`VRegionAnnotation::region_annotations`] (and of their discriminants).
+    pub const ALL: [ImgtRegion; 7] = [
+        ImgtRegion::Fr1,
+        ImgtRegion::Cdr1,
+        ImgtRegion::Fr2,
+        ImgtRegion::Cdr2,
+        ImgtRegion::Fr3,
+        ImgtRegion::Cdr3,
+        ImgtRegion::Fr4,
+    ];
+
+    /// The region of an IMGT number (with or without insertion code, such as
+    /// `111.1`), with the CDR3-IMGT as CDR3. `None` for names that are not IMGT numbers.
+    pub fn of_position(name: &str) -> Option<ImgtRegion> {
+        let position: usize = name.split('.').next()?.parse().ok()?;
+        Some(match position {
+            0 => return None,
+            position if position < imgt::CDR1_START => ImgtRegion::Fr1,
+            position if position < imgt::FR2_START => ImgtRegion::Cdr1,
+            position if position < imgt::CDR2_START => ImgtRegion::Fr2,
+            position if position < imgt::FR3_START => ImgtRegion::Cdr2,
+            position if position < imgt::CDR3_START => ImgtRegion::Fr3,
+            position if position < imgt::FR4_START => ImgtRegion::Cdr3,
+            position if position <= imgt::FR4_END => ImgtRegion::Fr4,
+            _ => return None,
+        })
+    }
+
+    /// The name of the region, such as `CDR1`.
+    pub fn name(self) -> &'static str {
+        match self {
+            ImgtRegion::Fr1 => "FR1",
+            ImgtRegion::Cdr1 => "CDR1",
+            ImgtRegion::Fr2 => "FR2",
+            ImgtRegion::Cdr2 => "CDR2",
+            ImgtRegion::Fr3 => "FR3",
+            ImgtRegion::Cdr3 => "CDR3",
+            ImgtRegion::Fr4 => "FR4",
+        }
+    }
+
+    pub fn is_cdr(self) -> bool {
+        matches!(self, ImgtRegion::Cdr1 | ImgtRegion::Cdr2 | ImgtRegion::Cdr3)
+    }
+}
+
+impl std::fmt::Display for ImgtRegion {
+    /// The name of the region as on the command line, such as `CDR1`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Longest CDR3-IMGT that is numbered by default.
+///
+/// Human CDR3s rarely exceed 30 residues, so a longer one is most likely an
+/// alignment artifact. The ultralong CDR3s of bovine heavy chains run to about 70
+/// residues and need a higher [`NumberingScheme::max_cdr3_length`].
+pub const DEFAULT_MAX_CDR3_LENGTH: usize = 40;
+
+/// Shortest CDR3-IMGT that is numbered, the 5 positions 105 to 107, 116 and 117.
+pub const MIN_CDR3_LENGTH: usize = 5;
+
+/// Settings that determine how a sequence is numbered.
+#[derive(Clone, Debug)]
+pub struct NumberingScheme {
+    pub cdr3_definition: Cdr3Definition,
+    pub cdr3_insertion_policy: Cdr3InsertionPolicy,
+    /// Number queries that end before the J-Trp/Phe (118) up to their CDR3, instead
+    /// of failing. FR4 is then empty, see [`VRegionAnnotation::has_fr4_anchor`].
+    pub allow_missing_fr4: bool,
+    /// Number the residues of a truncated FR1 that precede the first aligned residue
+    /// (such as a divergent start of a partial read) by the germline positions before
+    /// it, instead of leaving them out of FR1, see
+    /// [`ReferenceAlignment::unaligned_fr1_residues`].
+    pub extend_truncated_fr1: bool,
+    /// Fail on a CDR3-IMGT with more residues than this, instead of numbering it
+    /// with many insertions.
+    pub max_cdr3_length: usize,
+    /// Fail on a query of which a transferred conserved residue is not the canonical
+    /// one (such as a serine at 104), instead of numbering it by the aligned residue,
+    /// see [`ConservedResidues::check_canonical`](imgt::conserved_residues::ConservedResidues::check_canonical).
+    pub strict_anchors: bool,
+    /// Only number these regions, or all regions when `None`. Errors in numbering
+    /// the other regions (such as a CDR1 that is too long) are then not reported.
+    pub regions: Option<Vec<ImgtRegion>>,
+}
+
+impl NumberingScheme {
+    /// Whether the residues of a region are numbered, see [`Self::regions`].
+    pub fn numbers_region(&self, region: ImgtRegion) -> bool {
+        self.regions
+            .as_ref()
+            .is_none_or(|regions| regions.contains(&region))
+    }
+
+    /// The lengths of a CDR3-IMGT that are numbered, from [`MIN_CDR3_LENGTH`] to
+    /// [`Self::max_cdr3_length`].
+    pub fn cdr3_lengths(&self) -> std::ops::RangeInclusive<usize> {
+        MIN_CDR3_LENGTH..=self.max_cdr3_length
+    }
+}
+
+impl Default for NumberingScheme {
+    fn default() -> Self {
+        Self {
+            cdr3_definition: Cdr3Definition::default(),
+            cdr3_insertion_policy: Cdr3InsertionPolicy::default(),
+            allow_missing_fr4: false,
+            extend_truncated_fr1: false,
+            max_cdr3_length: DEFAULT_MAX_CDR3_LENGTH,
+            strict_anchors: false,
+            regions: None,
+        }
+    }
+}
+
+/// Annotate each query position with the IMGT number it is paired with.
+///
+/// Every annotation covers a single residue, so `end` is `start + 1`. Numbers or
+/// positions beyond the shorter of the two are left out.
+pub fn annotations_from_numbers(
+    numbers: impl Iterator<Item = String>,
+    positions: impl Iterator<Item = usize>,
+) -> impl Iterator<Item = Annotation> {
+    numbers.zip(positions).map(|(name, position)| Annotation {
+        start: position,
+        end: position + 1,
+        name,
+    })
+}
+
+/// Number a query that is already aligned to the IMGT positions, see
+/// [`GappedQueryMode::PreAligned`](imgt::input::GappedQueryMode::PreAligned).
+///
+/// Column `i` (zero based) of the query is position `i + 1`, as in the curated
+/// reference alignments, so a pre-aligned query has no insertions and its CDR3
+/// is numbered by its columns rather than from both ends. The numbering is of
+/// the query without its gaps.
+pub fn number_pre_aligned(record: &fasta::Record) -> Result<NumberedSequence, IMGTError> {
+    let columns = record.seq().len();
+    if columns > imgt::FR4_END {
+        return Err(IMGTError::PreAlignedTooLong(columns));
+    }
+    let (numbers, residues): (Vec<_>, Vec<_>) = record
+        .seq()
+        .iter()
+        .enumerate()
+        .filter(|(_column, &residue)| !is_gap(residue))
+        .map(|(column, &residue)| ((column + 1).to_string(), residue))
+        .unzip();
+    let numbering = annotations_from_numbers(numbers.into_iter(), 0..residues.len()).collect();
+    Ok(NumberedSequence::new(
+        fasta::Record::with_attrs(record.id(), record.desc(), &residues),
+        numbering,
+    ))
+}
+
+/// Number a CDR1-IMGT, leaving gaps at the top of the loop (33, 32, 34, 31, ...).
+///
+/// The lengths of 5 to 12 cover the CDR1 of heavy chains (mostly 8), of kappa
+/// chains (5 to 12, the long ones in IGKV2 and IGKV4) and of lambda chains (6 to 9).
 fn number_cdr1(start: usize, end: usize) -> Result<Vec<Annotation>, IMGTError> {
     let cdr1_size = end - start;
     let cdr1_length_ranges_mapping: HashMap<usize, Vec<usize>> = [
@@ -23,20 +283,30 @@ fn number_cdr1(start: usize, end: usize) -> Result<Vec<Annotation>, IMGTError> {
     .into_iter()
     .collect();
 
-    Ok(cdr1_length_ranges_mapping
+    if cdr1_size > 12 {
+        return Ok(number_long_cdr(
+            start,
+            end,
+            27,
+            32,
+            38,
+            CDR1_CDR2_INSERTIONS,
+        ));
+    }
+
+    let numbers = cdr1_length_ranges_mapping
         .get(&cdr1_size)
-        .ok_or(IMGTError::RegionTooLong("CDR1-IMGT".to_string(), cdr1_size))?
-        .into_iter()
-        .map(|number| number.to_string())
-        .zip(start..end)
-        .map(|(name, position)| Annotation {
-            start: position,
-            end: position + 1,
-            name,
-        })
-        .collect())
+        .ok_or_else(|| region_length_error("CDR1-IMGT", cdr1_size, &cdr1_length_ranges_mapping))?;
+    Ok(
+        annotations_from_numbers(numbers.iter().map(|number| number.to_string()), start..end)
+            .collect(),
+    )
 }
 
+/// Number a CDR2-IMGT, leaving gaps at the top of the loop (61, 60, 62, 59, ...).
+///
+/// Most light chains have a CDR2 of 3 residues (56, 57 and 65), but some lambda
+/// chains (such as IGLV4 and IGLV5) have one of 7 or 9.
 fn number_cdr2(start: usize, end: usize) -> Result<Vec<Annotation>, IMGTError> {
     let cdr2_size = end - start;
     let cdr2_length_ranges_mapping: HashMap<usize, Vec<usize>> = [
@@ -55,23 +325,96 @@ fn number_cdr2(start: usize, end: usize) -> Result<Vec<Annotation>, IMGTError> {
     .into_iter()
     .collect();
 
-    Ok(cdr2_length_ranges_mapping
+    if cdr2_size > 10 {
+        return Ok(number_long_cdr(
+            start,
+            end,
+            56,
+            60,
+            65,
+            CDR1_CDR2_INSERTIONS,
+        ));
+    }
+
+    let numbers = cdr2_length_ranges_mapping
         .get(&cdr2_size)
-        .ok_or(IMGTError::RegionTooLong("CDR2-IMGT".to_string(), cdr2_size))?
-        .into_iter()
+        .ok_or_else(|| region_length_error("CDR2-IMGT", cdr2_size, &cdr2_length_ranges_mapping))?;
+    Ok(
+        annotations_from_numbers(numbers.iter().map(|number| number.to_string()), start..end)
+            .collect(),
+    )
+}
+
+fn number_cdr3(
+    start: usize,
+    end: usize,
+    scheme: &NumberingScheme,
+    truncated: bool,
+) -> Result<Vec<Annotation>, IMGTError> {
+    let cdr3_size = match scheme.cdr3_definition {
+        Cdr3Definition::Imgt => end - start,
+        Cdr3Definition::Junction => (end - start).saturating_sub(2),
+    };
+    if cdr3_size > scheme.max_cdr3_length {
+        return Err(IMGTError::CDR3TooLong(cdr3_size, scheme.max_cdr3_length));
+    }
+    if truncated {
+        return Ok(number_truncated_cdr3(start, end, scheme.cdr3_definition));
+    }
+    match scheme.cdr3_definition {
+        Cdr3Definition::Imgt => number_cdr3_imgt(start, end, scheme.cdr3_insertion_policy),
+        Cdr3Definition::Junction => {
+            // The junction is the CDR3-IMGT flanked by position 104 and 118.
+            if end - start < 2 {
+                return Err(IMGTError::CDR3TooShort(0));
+            }
+            let flank = |position: usize, number: usize| {
+                annotations_from_numbers(
+                    std::iter::once(number.to_string()),
+                    std::iter::once(position),
+                )
+            };
+            Ok(flank(start, 104)
+                .chain(number_cdr3_imgt(
+                    start + 1,
+                    end - 1,
+                    scheme.cdr3_insertion_policy,
+                )?)
+                .chain(flank(end - 1, imgt::FR4_START))
+                .collect())
+        }
+    }
+}
+
+/// Number a CDR3 of which the end is missing.
+///
+/// Without the end, the length of the CDR3 is unknown. Only 105, 106 and 107 are
+/// at the start of every CDR3-IMGT, so the residues after these are numbered as
+/// the start of a CDR3 that is long enough to hold them: 108 to 111, followed by
+/// insertions on 111 (`111.1`, `111.2`, ...). That is their numbering when the
+/// CDR3 is more than twice as long as the part that is there.
+fn number_truncated_cdr3(
+    start: usize,
+    end: usize,
+    cdr3_definition: Cdr3Definition,
+) -> Vec<Annotation> {
+    let first_number = match cdr3_definition {
+        Cdr3Definition::Imgt => imgt::CDR3_START,
+        Cdr3Definition::Junction => imgt::CDR3_START - 1,
+    };
+    let numbers = (first_number..=111)
         .map(|number| number.to_string())
-        .zip(start..end)
-        .map(|(name, position)| Annotation {
-            start: position,
-            end: position + 1,
-            name,
-        })
-        .collect())
+        .chain((1..).map(|insertion| format!("111.{}", insertion)));
+    annotations_from_numbers(numbers, start..end).collect()
 }
 
-fn number_cdr3(start: usize, end: usize) -> Result<Vec<Annotation>, IMGTError> {
+fn number_cdr3_imgt(
+    start: usize,
+    end: usize,
+    insertion_policy: Cdr3InsertionPolicy,
+) -> Result<Vec<Annotation>, IMGTError> {
     let cdr3_size = end - start;
-    if cdr3_size < 5 {
+    if cdr3_size < MIN_CDR3_LENGTH {
         return Err(IMGTError::CDR3TooShort(cdr3_size));
     }
 
@@ -101,127 +444,686 @@ fn number_cdr3(start: usize, end: usize) -> Result<Vec<Annotation>, IMGTError> {
     .collect();
 
     if cdr3_size <= 13 {
-        return Ok(cdr3_length_ranges_mapping
-            .get(&cdr3_size)
-            .unwrap()
-            .into_iter()
-            .map(|number| number.to_string())
-            .zip(start..end)
-            .map(|(name, position)| Annotation {
-                start: position,
-                end: position + 1,
-                name,
-            })
-            .collect());
+        let numbers = cdr3_length_ranges_mapping.get(&cdr3_size).unwrap();
+        return Ok(annotations_from_numbers(
+            numbers.iter().map(|number| number.to_string()),
+            start..end,
+        )
+        .collect());
     }
 
-    // TODO: extract this range-zip-map stuff to a function for DRY.
-    Ok((imgt::CDR3_START..=111)
-        .zip(start..start + 6)
-        .map(|(number, position)| Annotation {
-            start: position,
-            end: position + 1,
-            name: number.to_string(),
-        })
-        .chain(additional_positions_between_111_and_112(start + 6, end - 5).into_iter())
-        .chain(
-            (113..imgt::FR4_START)
-                .zip(end - 5..end)
-                .map(|(number, position)| Annotation {
-                    start: position,
-                    end: position + 1,
-                    name: number.to_string(),
-                }),
-        )
-        .collect())
+    Ok(number_long_cdr(
+        start,
+        end,
+        imgt::CDR3_START,
+        111,
+        117,
+        insertion_policy.into(),
+    ))
 }
 
-/// Additional positions between 111 and 112 in the CDR3-IMGT region.
-fn additional_positions_between_111_and_112(start: usize, end: usize) -> Vec<Annotation> {
+/// Number a CDR that is longer than all of its IMGT positions.
+///
+/// The residues from `first` to `split` are numbered from the start of the CDR
+/// and the residues from `split + 1` to `last` from the end. The residues in
+/// between are insertions on `split` and `split + 1`, such as 111 and 112 for
+/// the CDR3, 32 and 33 for the CDR1 and 60 and 61 for the CDR2.
+fn number_long_cdr(
+    start: usize,
+    end: usize,
+    first: usize,
+    split: usize,
+    last: usize,
+    insertions: LoopInsertions,
+) -> Vec<Annotation> {
+    let n_start = split + 1 - first;
+    let n_end = last - split;
+    let numbered = |numbers: std::ops::RangeInclusive<usize>, positions| {
+        annotations_from_numbers(numbers.map(|number| number.to_string()), positions)
+    };
+
+    numbered(first..=split, start..start + n_start)
+        .chain(additional_positions_between(
+            split,
+            start + n_start,
+            end - n_end,
+            insertions,
+        ))
+        .chain(numbered(split + 1..=last, end - n_end..end))
+        .collect()
+}
+
+/// Insertions between `split` and `split + 1`, such as between 111 and 112 in the CDR3-IMGT.
+fn additional_positions_between(
+    split: usize,
+    start: usize,
+    end: usize,
+    insertions: LoopInsertions,
+) -> Vec<Annotation> {
     let n_extra_positions = end - start;
-    let n_extra_positions_111 = (n_extra_positions as f64 / 2.0).floor() as usize;
-    let n_extra_positions_112 = (n_extra_positions as f64 / 2.0).ceil() as usize;
+    let (n_extra_positions_before, n_extra_positions_after) = match insertions {
+        LoopInsertions::ExtraOnSecond => (n_extra_positions / 2, n_extra_positions.div_ceil(2)),
+        LoopInsertions::ExtraOnFirst => (n_extra_positions.div_ceil(2), n_extra_positions / 2),
+    };
 
-    let extra_positions_111 = (0..n_extra_positions_111)
-        .into_iter()
-        .map(|i| format!("111.{}", i));
-    let extra_positions_112 = (0..n_extra_positions_112)
-        .into_iter()
-        .map(|i| format!("112.{}", i))
+    let extra_positions_before = (1..=n_extra_positions_before).map(|i| format!("{}.{}", split, i));
+    let extra_positions_after = (1..=n_extra_positions_after)
+        .map(|i| format!("{}.{}", split + 1, i))
         .rev();
 
-    extra_positions_111
-        .chain(extra_positions_112)
-        .zip(start..end)
-        .map(|(name, position)| Annotation {
-            start: position,
-            end: position + 1,
-            name,
-        })
-        .collect()
+    annotations_from_numbers(
+        extra_positions_before.chain(extra_positions_after),
+        start..end,
+    )
+    .collect()
 }
 
+/// Number the residues of a framework region through the alignment with the reference.
+///
+/// Only residues that fall within `region` (the query range of the framework) are
+/// numbered, and only with the positions of the framework. With the junction
+/// definition these leave out 104 and 118, which the junction numbers.
+///
+/// The 2nd-CYS (104) and J-Trp/Phe (118) border the CDR3, so the boundaries of
+/// `region` already hold them: they are the last residue of FR3 and the first of
+/// FR4. These are numbered from the boundaries instead of the alignment. When the
+/// query has an insertion next to an anchor that could be aligned on either side
+/// (such as `CC` for the 2nd-CYS), the alignment can put the anchor on the other
+/// residue, which would leave the anchor unnumbered. Per IMGT the insertion then
+/// belongs to the CDR3, which the region boundaries already account for.
 fn number_framework(
     reference_alignment: &ReferenceAlignment,
-    framework: imgt::Framework,
-) -> Vec<Annotation> {
-    let range = match framework {
-        imgt::Framework::FR1 => imgt::FR1,
-        imgt::Framework::FR2 => imgt::FR2,
-        imgt::Framework::FR3 => imgt::FR3,
-        imgt::Framework::FR4 => imgt::FR4,
+    region: Annotation,
+    cdr3_definition: Cdr3Definition,
+) -> impl Iterator<Item = Annotation> + '_ {
+    let range =
+        region_positions(&region.name, cdr3_definition).expect("Frameworks have IMGT positions.");
+    let has_position = |number: usize| {
+        range.contains(&number)
+            && reference_alignment
+                .reference
+                .get_sequence_position(number)
+                .is_some()
+    };
+    // The anchor goes last in FR3 and first in FR4, like its number.
+    let (anchor_before, anchor_after) = if region.start == region.end {
+        (None, None)
+    } else if has_position(imgt::CDR3_START - 1) {
+        (None, Some((imgt::CDR3_START - 1, region.end - 1)))
+    } else if has_position(imgt::FR4_START) {
+        (Some((imgt::FR4_START, region.start)), None)
+    } else {
+        (None, None)
+    };
+    let anchor = anchor_before.or(anchor_after);
+    let annotation = |(number, position): (usize, usize)| Annotation {
+        start: position,
+        end: position + 1,
+        name: number.to_string(),
+    };
+
+    let path = reference_alignment.alignment.path();
+    // Only with `NumberingScheme::extend_truncated_fr1` does FR1 start before the
+    // first aligned residue. Those residues take the germline positions before it.
+    let first_aligned = path
+        .iter()
+        .find(|(_x, _y, op)| matches!(op, AlignmentOperation::Match | AlignmentOperation::Subst))
+        .map_or(region.start, |(_x, y, _op)| y - 1);
+    let unaligned: Vec<_> = if region.name == "FR1-IMGT" && region.start < first_aligned {
+        let missing = reference_alignment.missing_fr1_positions();
+        let count = (first_aligned - region.start).min(missing.len());
+        missing[missing.len() - count..]
+            .iter()
+            .copied()
+            .zip(first_aligned - count..first_aligned)
+            .collect()
+    } else {
+        vec![]
     };
-    let numbers = range.clone().filter(|pos| {
-        reference_alignment
+    let (start, end) = (region.start, region.end);
+    let aligned_position = move |number: usize| {
+        let reference_position = reference_alignment
             .reference
-            .get_missing_positions_in_framework(&framework)
-            .contains(pos)
+            .get_sequence_position(number)?;
+        path.iter()
+            .find(|(x, _y, op)| {
+                *x == reference_position
+                    && matches!(op, AlignmentOperation::Match | AlignmentOperation::Subst)
+            })
+            // Path starts at one, where as annotations are zero based.
+            .map(|(_x, y, _op)| (number, y - 1))
+            .filter(|(_number, position)| (start..end).contains(position))
+            .filter(|(number, position)| {
+                anchor.is_none_or(|(anchor_number, anchor_position)| {
+                    *number != anchor_number && *position != anchor_position
+                })
+            })
+    };
+    // FR4 can run past the last aligned residue when its J-Trp/Phe was found by its
+    // motif. Those residues take the germline positions after the last numbered one,
+    // which is the last aligned residue or else the anchor, as FR4 has no other.
+    let trailing = (region.name == "FR4-IMGT").then(|| {
+        let last_numbered = range.clone().rev().find_map(&aligned_position).or(anchor);
+        let last_number = last_numbered.map_or(0, |(number, _position)| number);
+        let first_unnumbered = last_numbered.map_or(start, |(_number, position)| position + 1);
+        (last_number + 1..=imgt::FR4_END)
+            .filter(|number| {
+                reference_alignment
+                    .reference
+                    .get_sequence_position(*number)
+                    .is_some()
+            })
+            .zip(first_unnumbered..end)
     });
-    reference_alignment
-        .alignment
-        .path()
+    let aligned = range.filter_map(aligned_position);
+    anchor_before
         .into_iter()
-        .filter(|(x, _y, _op)| range.contains(&x))
-        .flat_map(|(_x, y, op)| match op {
-            AlignmentOperation::Match => Some(y),
-            AlignmentOperation::Subst => Some(y),
-            AlignmentOperation::Del => None,
-            AlignmentOperation::Ins => None,
-            AlignmentOperation::Xclip(_) => None,
-            AlignmentOperation::Yclip(_) => None,
-        })
-        .zip(numbers)
-        .map(|(number, position)| Annotation {
-            // Path starts at one, where as annotations are zero based.
-            start: position - 1,
-            end: position,
-            name: number.to_string(),
-        })
-        .collect()
+        .chain(unaligned)
+        .chain(aligned)
+        .chain(anchor_after)
+        .chain(trailing.into_iter().flatten())
+        .map(annotation)
 }
 
 impl VRegionAnnotation {
     pub fn number_regions(
         &self,
         reference_alignment: &ReferenceAlignment,
+        scheme: &NumberingScheme,
     ) -> Result<Vec<Annotation>, IMGTError> {
-        Ok(number_framework(&reference_alignment, imgt::Framework::FR1)
+        let mut annotations = Vec::new();
+        self.number_regions_into(reference_alignment, scheme, &mut annotations)?;
+        Ok(annotations)
+    }
+
+    /// Number all regions, appending the annotations to an existing buffer.
+    ///
+    /// Allows reusing the same buffer when numbering many sequences. When an error
+    /// is returned, the buffer may contain the annotations of the preceding regions.
+    pub fn number_regions_into(
+        &self,
+        reference_alignment: &ReferenceAlignment,
+        scheme: &NumberingScheme,
+        annotations: &mut Vec<Annotation>,
+    ) -> Result<(), IMGTError> {
+        for annotation in self.number_regions_iter(reference_alignment, scheme) {
+            annotations.push(annotation?);
+        }
+        Ok(())
+    }
+
+    /// Number a single region, without numbering the others.
+    ///
+    /// The numbering is the same as that of the region within [`Self::number_regions`].
+    pub fn number_region(
+        &self,
+        reference_alignment: &ReferenceAlignment,
+        region: ImgtRegion,
+        scheme: &NumberingScheme,
+    ) -> Result<Vec<Annotation>, IMGTError> {
+        let annotation = self
+            .region_annotations(scheme.cdr3_definition)
+            .swap_remove(region as usize);
+        number_region(
+            reference_alignment,
+            region,
+            annotation,
+            scheme,
+            !self.has_fr4_anchor(),
+        )
+        .collect()
+    }
+
+    /// Number all regions lazily, in the same order as [`Self::number_regions`].
+    ///
+    /// A region is only numbered once the iteration reaches it, and framework
+    /// residues are numbered one at a time. After an error nothing else is yielded.
+    /// Regions that are not selected by [`NumberingScheme::regions`] are skipped.
+    pub fn number_regions_iter<'a>(
+        &self,
+        reference_alignment: &'a ReferenceAlignment,
+        scheme: &'a NumberingScheme,
+    ) -> impl Iterator<Item = Result<Annotation, IMGTError>> + 'a {
+        let truncated = !self.has_fr4_anchor();
+
+        ImgtRegion::ALL
             .into_iter()
-            .chain(
-                number_cdr1(self.cdr_annotation.cdr1.start, self.cdr_annotation.cdr1.end)?
-                    .into_iter(),
-            )
-            .chain(number_framework(&reference_alignment, imgt::Framework::FR2))
-            .chain(
-                number_cdr2(self.cdr_annotation.cdr2.start, self.cdr_annotation.cdr2.end)?
-                    .into_iter(),
+            .zip(self.region_annotations(scheme.cdr3_definition))
+            .filter(|(region, _annotation)| scheme.numbers_region(*region))
+            .flat_map(move |(region, annotation)| {
+                number_region(reference_alignment, region, annotation, scheme, truncated)
+            })
+            .scan(false, |failed, result| {
+                if *failed {
+                    return None;
+                }
+                *failed = result.is_err();
+                Some(result)
+            })
+    }
+}
+
+/// Number the residues of a region lazily, see [`VRegionAnnotation::number_regions_iter`].
+///
+/// `truncated` is whether the query lacks the J-Trp/Phe, see [`VRegionAnnotation::has_fr4_anchor`].
+fn number_region<'a>(
+    reference_alignment: &'a ReferenceAlignment,
+    region: ImgtRegion,
+    annotation: Annotation,
+    scheme: &NumberingScheme,
+    truncated: bool,
+) -> impl Iterator<Item = Result<Annotation, IMGTError>> + 'a {
+    let cdr = match region {
+        ImgtRegion::Fr1 | ImgtRegion::Fr2 | ImgtRegion::Fr3 | ImgtRegion::Fr4 => {
+            return Either::Left(
+                number_framework(reference_alignment, annotation, scheme.cdr3_definition).map(Ok),
             )
-            .chain(number_framework(&reference_alignment, imgt::Framework::FR3))
-            .chain(
-                number_cdr3(self.cdr_annotation.cdr3.start, self.cdr_annotation.cdr3.end)?
-                    .into_iter(),
+        }
+        ImgtRegion::Cdr1 => number_cdr1(annotation.start, annotation.end),
+        ImgtRegion::Cdr2 => number_cdr2(annotation.start, annotation.end),
+        ImgtRegion::Cdr3 => number_cdr3(annotation.start, annotation.end, scheme, truncated),
+    };
+    Either::Right(match cdr {
+        Ok(annotations) => Either::Left(annotations.into_iter().map(Ok)),
+        Err(error) => Either::Right(std::iter::once(Err(error))),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::imgt::fixtures::{test_ref_seqs, TEST_SEQUENCE};
+
+    fn names(annotations: Vec<Annotation>) -> Vec<String> {
+        annotations.into_iter().map(|ann| ann.name).collect()
+    }
+
+    fn scheme(
+        cdr3_definition: Cdr3Definition,
+        cdr3_insertion_policy: Cdr3InsertionPolicy,
+    ) -> NumberingScheme {
+        NumberingScheme {
+            cdr3_definition,
+            cdr3_insertion_policy,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_annotations_from_numbers() {
+        let annotations = annotations_from_numbers(
+            ["111", "111.1", "112"].into_iter().map(String::from),
+            10..20,
+        );
+        assert_eq!(
+            annotations.collect::<Vec<_>>(),
+            [
+                Annotation {
+                    start: 10,
+                    end: 11,
+                    name: "111".to_string()
+                },
+                Annotation {
+                    start: 11,
+                    end: 12,
+                    name: "111.1".to_string()
+                },
+                Annotation {
+                    start: 12,
+                    end: 13,
+                    name: "112".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_region_of_position() {
+        assert_eq!(ImgtRegion::of_position("1"), Some(ImgtRegion::Fr1));
+        assert_eq!(ImgtRegion::of_position("38"), Some(ImgtRegion::Cdr1));
+        assert_eq!(ImgtRegion::of_position("104"), Some(ImgtRegion::Fr3));
+        assert_eq!(ImgtRegion::of_position("111.1"), Some(ImgtRegion::Cdr3));
+        assert_eq!(ImgtRegion::of_position("128"), Some(ImgtRegion::Fr4));
+        assert_eq!(ImgtRegion::of_position("129"), None);
+        assert_eq!(ImgtRegion::of_position("CDR3-IMGT"), None);
+        assert_eq!(ImgtRegion::Cdr2.to_string(), "CDR2");
+    }
+
+    #[test]
+    fn test_check_unique_positions() {
+        let numbering: Vec<_> = ["104", "105", "111.1", "112.1", "112"]
+            .iter()
+            .enumerate()
+            .map(|(i, name)| Annotation {
+                start: i,
+                end: i + 1,
+                name: name.to_string(),
+            })
+            .collect();
+        assert!(check_unique_positions(&numbering).is_ok());
+
+        let mut duplicated = numbering.clone();
+        duplicated[3].name = "111.1".to_string();
+        assert!(matches!(
+            check_unique_positions(&duplicated),
+            Err(IMGTError::DuplicatePosition(name)) if name == "111.1"
+        ));
+    }
+
+    #[test]
+    fn test_insertion_before_second_cys() {
+        use crate::imgt::{find_best_reference_sequence, query::Query};
+        // The test sequence with an extra cysteine before the 2nd-CYS (YYCAR -> YYCCAR),
+        // which the alignment takes as an insertion before the anchor.
+        const SEQUENCE: &str = "QVQLVQSGAEVKKPGASVKVSCKASGYTFTSYGISWVRQAPGQGLEWMGWISAYNGNTNYAQKLQGRVTMTTDTSTSTAYMELRSLRSDDTAVYYCCARMDVWGQGTTVTVSS";
+        let ref_seqs = test_ref_seqs();
+        let reference_alignment =
+            find_best_reference_sequence(Query::new("query", SEQUENCE.as_bytes()), &ref_seqs)
+                .unwrap();
+        let vregion_annotation = reference_alignment.vregion_annotation().unwrap();
+        let regions = vregion_annotation.region_annotations(Cdr3Definition::Imgt);
+        let second_cys = SEQUENCE.find("YYCC").unwrap() + 2;
+        assert_eq!(regions[4].end, second_cys + 1);
+        assert_eq!(&SEQUENCE[regions[5].start..regions[5].end], "CARMDV");
+
+        for cdr3_definition in [Cdr3Definition::Imgt, Cdr3Definition::Junction] {
+            let numbering = vregion_annotation
+                .number_regions(
+                    &reference_alignment,
+                    &scheme(cdr3_definition, Cdr3InsertionPolicy::Imgt),
+                )
+                .unwrap();
+            check_unique_positions(&numbering).unwrap();
+            let name = |position: usize| {
+                numbering
+                    .iter()
+                    .find(|annotation| annotation.start == position)
+                    .map(|annotation| annotation.name.as_str())
+            };
+            assert_eq!(name(second_cys - 1), Some("103"));
+            assert_eq!(name(second_cys), Some("104"));
+            // The inserted cysteine is the first residue of the CDR3.
+            assert_eq!(name(second_cys + 1), Some("105"));
+        }
+    }
+
+    #[test]
+    fn test_number_selected_regions() {
+        use crate::imgt::{find_best_reference_sequence, query::Query};
+        // The test sequence with a CDR1 that is too short to number (GYTFTSYG -> GYTF).
+        const SEQUENCE: &str = "QVQLVQSGAEVKKPGASVKVSCKASGYTFISWVRQAPGQGLEWMGWISAYNGNTNYAQKLQGRVTMTTDTSTSTAYMELRSLRSDDTAVYYCARMDVWGQGTTVTVSS";
+        let ref_seqs = test_ref_seqs();
+        let reference_alignment =
+            find_best_reference_sequence(Query::new("query", SEQUENCE.as_bytes()), &ref_seqs)
+                .unwrap();
+        let vregion_annotation = reference_alignment.vregion_annotation().unwrap();
+        assert!(matches!(
+            vregion_annotation.number_regions(&reference_alignment, &NumberingScheme::default()),
+            Err(IMGTError::RegionTooShort(..))
+        ));
+
+        let cdr3_and_fr4 = NumberingScheme {
+            regions: Some(vec![ImgtRegion::Cdr3, ImgtRegion::Fr4]),
+            ..Default::default()
+        };
+        let numbering = vregion_annotation
+            .number_regions(&reference_alignment, &cdr3_and_fr4)
+            .unwrap();
+        let numbered: String = numbering
+            .iter()
+            .map(|annotation| SEQUENCE.as_bytes()[annotation.start] as char)
+            .collect();
+        assert_eq!(numbered, "ARMDVWGQGTTVTVSS");
+        assert_eq!(numbering[0].name, "105");
+        assert_eq!(numbering.last().unwrap().name, "128");
+    }
+
+    #[test]
+    fn test_number_region() {
+        use crate::imgt::{find_best_reference_sequence, query::Query};
+        let ref_seqs = test_ref_seqs();
+        let reference_alignment =
+            find_best_reference_sequence(Query::new("query", TEST_SEQUENCE.as_bytes()), &ref_seqs)
+                .unwrap();
+        let vregion_annotation = reference_alignment.vregion_annotation().unwrap();
+        let scheme = NumberingScheme::default();
+
+        let numbering = vregion_annotation
+            .number_regions(&reference_alignment, &scheme)
+            .unwrap();
+        let cdr2 = vregion_annotation
+            .number_region(&reference_alignment, ImgtRegion::Cdr2, &scheme)
+            .unwrap();
+        let cdr2_region = &vregion_annotation.cdr_annotation.cdr2;
+        let cdr2_slice: Vec<_> = numbering
+            .into_iter()
+            .filter(|annotation| (cdr2_region.start..cdr2_region.end).contains(&annotation.start))
+            .collect();
+        assert_eq!(cdr2, cdr2_slice);
+        assert_eq!(names(cdr2)[0], "56");
+    }
+
+    #[test]
+    fn test_number_frameworks_by_reference() {
+        use crate::imgt::{find_best_reference_sequence, query::Query};
+        let ref_seqs = test_ref_seqs();
+        let reference_alignment =
+            find_best_reference_sequence(Query::new("query", TEST_SEQUENCE.as_bytes()), &ref_seqs)
+                .unwrap();
+        let vregion_annotation = reference_alignment.vregion_annotation().unwrap();
+        let numbering = names(
+            vregion_annotation
+                .number_regions(&reference_alignment, &NumberingScheme::default())
+                .unwrap(),
+        );
+
+        // Every residue is numbered, with the gaps of the reference (10 and 73 in the
+        // frameworks) left out instead of shifting the residues after them.
+        assert_eq!(numbering.len(), TEST_SEQUENCE.len());
+        for gap in ["10", "73"] {
+            assert!(!numbering.iter().any(|name| name == gap), "{gap}");
+        }
+        let fr3_start = numbering.iter().position(|name| name == "66").unwrap();
+        assert_eq!(&TEST_SEQUENCE[fr3_start..fr3_start + 8], "NYAQKLQG");
+        assert_eq!(numbering[fr3_start + 7], "74");
+        // FR4 runs from the J-Trp (118) up to and including 128.
+        let fr4: Vec<_> = (118..=128).map(|number| number.to_string()).collect();
+        assert_eq!(numbering[numbering.len() - 11..], fr4[..]);
+        assert_eq!(&TEST_SEQUENCE[TEST_SEQUENCE.len() - 11..], "WGQGTTVTVSS");
+    }
+
+    #[test]
+    fn test_junction_is_cdr3_with_flanks() {
+        let imgt = scheme(Cdr3Definition::Imgt, Cdr3InsertionPolicy::Imgt);
+        let junction = scheme(Cdr3Definition::Junction, Cdr3InsertionPolicy::Imgt);
+        let cdr3 = names(number_cdr3(11, 23, &imgt, false).unwrap());
+        let junction = names(number_cdr3(10, 24, &junction, false).unwrap());
+
+        assert_eq!(junction.first().unwrap(), "104");
+        assert_eq!(junction.last().unwrap(), "118");
+        assert_eq!(junction[1..junction.len() - 1], cdr3[..]);
+    }
+
+    #[test]
+    fn test_max_cdr3_length() {
+        let imgt = scheme(Cdr3Definition::Imgt, Cdr3InsertionPolicy::Imgt);
+        assert_eq!(
+            number_cdr3(0, DEFAULT_MAX_CDR3_LENGTH, &imgt, false)
+                .unwrap()
+                .len(),
+            DEFAULT_MAX_CDR3_LENGTH
+        );
+        assert!(matches!(
+            number_cdr3(0, DEFAULT_MAX_CDR3_LENGTH + 1, &imgt, false),
+            Err(IMGTError::CDR3TooLong(41, 40))
+        ));
+
+        // The flanks of the junction do not count.
+        let junction = scheme(Cdr3Definition::Junction, Cdr3InsertionPolicy::Imgt);
+        assert!(number_cdr3(0, DEFAULT_MAX_CDR3_LENGTH + 2, &junction, false).is_ok());
+    }
+
+    #[test]
+    fn test_junction_too_short() {
+        let junction = scheme(Cdr3Definition::Junction, Cdr3InsertionPolicy::Imgt);
+        assert!(number_cdr3(10, 16, &junction, false).is_err());
+        assert!(number_cdr3(10, 17, &junction, false).is_ok());
+    }
+
+    #[test]
+    fn test_truncated_cdr3() {
+        // Every residue is numbered, also those after 107.
+        assert_eq!(
+            names(number_truncated_cdr3(0, 10, Cdr3Definition::Imgt)),
+            ["105", "106", "107", "108", "109", "110", "111", "111.1", "111.2", "111.3"]
+        );
+        assert_eq!(
+            names(number_truncated_cdr3(0, 5, Cdr3Definition::Junction)),
+            ["104", "105", "106", "107", "108"]
+        );
+    }
+
+    fn cdr3_insertions(length: usize, insertion_policy: Cdr3InsertionPolicy) -> Vec<String> {
+        names(
+            number_cdr3(
+                0,
+                length,
+                &scheme(Cdr3Definition::Imgt, insertion_policy),
+                false,
             )
-            .chain(number_framework(&reference_alignment, imgt::Framework::FR4))
-            .collect())
+            .unwrap(),
+        )
+        .into_iter()
+        .filter(|name| name.contains('.'))
+        .collect()
+    }
+
+    #[test]
+    fn test_cdr3_insertion_policies() {
+        // An even number of insertions is split evenly by both policies.
+        assert_eq!(
+            cdr3_insertions(15, Cdr3InsertionPolicy::Imgt),
+            vec!["111.1", "112.1"]
+        );
+        assert_eq!(
+            cdr3_insertions(15, Cdr3InsertionPolicy::Anarci),
+            vec!["111.1", "112.1"]
+        );
+
+        // With an odd number the policies place the extra insertion differently.
+        assert_eq!(
+            cdr3_insertions(14, Cdr3InsertionPolicy::Imgt),
+            vec!["112.1"]
+        );
+        assert_eq!(
+            cdr3_insertions(14, Cdr3InsertionPolicy::Anarci),
+            vec!["111.1"]
+        );
+        assert_eq!(
+            cdr3_insertions(16, Cdr3InsertionPolicy::Imgt),
+            vec!["111.1", "112.2", "112.1"]
+        );
+        assert_eq!(
+            cdr3_insertions(16, Cdr3InsertionPolicy::Anarci),
+            vec!["111.1", "111.2", "112.1"]
+        );
+    }
+
+    #[test]
+    fn test_cdr3_insertion_policies_by_length() {
+        let labels = |length, insertion_policy| {
+            names(number_cdr3_imgt(0, length, insertion_policy).unwrap())
+        };
+        // Two insertions are split evenly, so both policies agree.
+        let expected = [
+            "105", "106", "107", "108", "109", "110", "111", "111.1", "112.1", "112", "113", "114",
+            "115", "116", "117",
+        ];
+        assert_eq!(labels(15, Cdr3InsertionPolicy::Imgt), expected);
+        assert_eq!(labels(15, Cdr3InsertionPolicy::Anarci), expected);
+
+        // Of three insertions IMGT puts the extra one on 112, ANARCI on 111.
+        assert_eq!(
+            labels(16, Cdr3InsertionPolicy::Imgt),
+            [
+                "105", "106", "107", "108", "109", "110", "111", "111.1", "112.2", "112.1", "112",
+                "113", "114", "115", "116", "117",
+            ]
+        );
+        assert_eq!(
+            labels(16, Cdr3InsertionPolicy::Anarci),
+            [
+                "105", "106", "107", "108", "109", "110", "111", "111.1", "111.2", "112.1", "112",
+                "113", "114", "115", "116", "117",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_long_cdr3_tail() {
+        // The table and the long CDR3 path agree where they meet.
+        for insertion_policy in [Cdr3InsertionPolicy::Imgt, Cdr3InsertionPolicy::Anarci] {
+            assert_eq!(
+                names(number_cdr3_imgt(0, 13, insertion_policy).unwrap()),
+                names(number_long_cdr(
+                    0,
+                    13,
+                    imgt::CDR3_START,
+                    111,
+                    117,
+                    insertion_policy.into()
+                ))
+            );
+        }
+        // Every CDR3-IMGT ends with 113 to 117, never with 118 (the J-TRP/J-PHE), up to
+        // the ultralong bovine ones.
+        for length in 10..=70 {
+            let cdr3 = names(number_cdr3_imgt(0, length, Cdr3InsertionPolicy::Imgt).unwrap());
+            assert_eq!(
+                cdr3[length - 5..],
+                ["113", "114", "115", "116", "117"],
+                "CDR3 of {} residues",
+                length
+            );
+        }
+    }
+
+    #[test]
+    fn test_long_cdr1() {
+        let cdr1 = names(number_cdr1(0, 13).unwrap());
+        assert_eq!(
+            cdr1,
+            ["27", "28", "29", "30", "31", "32", "33.1", "33", "34", "35", "36", "37", "38"]
+        );
+        assert_eq!(names(number_cdr1(0, 14).unwrap())[6..8], ["32.1", "33.1"]);
+        assert!(matches!(
+            number_cdr1(0, 4),
+            Err(IMGTError::RegionTooShort(_, 4))
+        ));
+    }
+
+    #[test]
+    fn test_long_cdr2() {
+        let cdr2 = names(number_cdr2(0, 11).unwrap());
+        assert_eq!(
+            cdr2,
+            ["56", "57", "58", "59", "60", "61.1", "61", "62", "63", "64", "65"]
+        );
+    }
+
+    #[test]
+    fn test_region_length_error() {
+        let table = HashMap::from([
+            (5, vec![27, 28, 29, 37, 38]),
+            (6, vec![27, 28, 29, 36, 37, 38]),
+        ]);
+        assert!(matches!(
+            region_length_error("CDR1-IMGT", 4, &table),
+            IMGTError::RegionTooShort(_, 4)
+        ));
+        assert!(matches!(
+            region_length_error("CDR1-IMGT", 7, &table),
+            IMGTError::RegionTooLong(_, 7)
+        ));
     }
 }
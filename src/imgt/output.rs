@@ -0,0 +1,1128 @@
+use std::io::Write;
+
+use bio::io::fasta;
+use thiserror::Error;
+
+use super::{
+    annotations::{region_positions, Annotation},
+    consensus::imgt_position_key,
+    encoding::EncodedNumbering,
+    numbering::{Cdr3Definition, ImgtRegion},
+    reference::ReferenceSequence,
+    FR4_END,
+};
+
+/// Format in which annotations are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// One FASTA record per annotation. The description names the matched reference.
+    #[default]
+    Fasta,
+    /// One line per annotation with the columns: id, name, start, end, sequence and flags.
+    Tsv,
+    /// One line per sequence with the columns of the AIRR rearrangement schema, see [`super::airr`].
+    ///
+    /// Annotations have no AIRR equivalent, see [`OutputFormat::writes_annotations`].
+    Airr,
+    /// One aligned FASTA (A2M) record per sequence in the IMGT column frame, see [`a2m_sequence`].
+    ///
+    /// Annotations have no A2M equivalent, see [`OutputFormat::writes_annotations`].
+    A2m,
+    /// Two aligned FASTA records per sequence, its germline and itself in the same
+    /// IMGT frame, see [`germline_pair`].
+    ///
+    /// Annotations have no pair equivalent, see [`OutputFormat::writes_annotations`].
+    Pair,
+    /// One line per numbered residue with its region and its strand or loop of the
+    /// IMGT Collier de Perles, see [`super::collier::write_collier`].
+    ///
+    /// Annotations have no Collier de Perles equivalent, see [`OutputFormat::writes_annotations`].
+    Collier,
+}
+
+impl OutputFormat {
+    /// Whether annotations other than the numbering, such as the regions or the
+    /// CDR3 alone, can be written in this format. The formats with a record per
+    /// sequence or per residue cannot hold them.
+    pub fn writes_annotations(self) -> bool {
+        matches!(self, OutputFormat::Fasta | OutputFormat::Tsv)
+    }
+
+    /// Whether IMGT positions are written in this format, so that their insertions
+    /// can be written in an [`InsertionFormat`]. AIRR, A2M and pairs only write
+    /// sequences.
+    pub fn writes_positions(self) -> bool {
+        !matches!(
+            self,
+            OutputFormat::Airr | OutputFormat::A2m | OutputFormat::Pair
+        )
+    }
+
+    /// Whether records are written as FASTA, which have the matched reference in
+    /// their description, see [`ReferenceAlignment::reference_fields`](super::ReferenceAlignment::reference_fields).
+    pub fn is_fasta(self) -> bool {
+        matches!(
+            self,
+            OutputFormat::Fasta | OutputFormat::A2m | OutputFormat::Pair
+        )
+    }
+}
+
+/// How positions in the query are written, such as the start and end of annotations.
+///
+/// [`Annotation`] is always zero based and end exclusive, the base only applies
+/// to the output, see [`CoordinateBase::range`]. It is used for the descriptions
+/// of FASTA records, the columns of TSV, the `{start}` and `{end}` of an
+/// [`IdTemplate`], gapped regions and the offsets of alignments.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CoordinateBase {
+    /// Zero based and end exclusive, like [`Annotation`].
+    #[default]
+    Zero,
+    /// One based and end inclusive, as in GFF and SAM.
+    One,
+}
+
+impl CoordinateBase {
+    /// The start and end to write for a zero based, end exclusive range.
+    ///
+    /// In one based, end inclusive coordinates only the start shifts: the residues
+    /// 0 to 3 (exclusive) are written as 1 to 3.
+    pub fn range(self, start: usize, end: usize) -> (usize, usize) {
+        match self {
+            CoordinateBase::Zero => (start, end),
+            CoordinateBase::One => (start + 1, end),
+        }
+    }
+}
+
+/// How insertion codes are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InsertionFormat {
+    /// IMGT style, such as `111.1` and `112.1`.
+    #[default]
+    Dotted,
+    /// Kabat style letter suffixes, such as `111A` and `112A`.
+    Letter,
+}
+
+/// Write a position in the given insertion format.
+///
+/// Every insertion code is converted on its own, so the reversed order of the
+/// insertions on 112 (`112.2` before `112.1`) becomes `112B` before `112A`.
+pub fn format_position(name: &str, format: InsertionFormat) -> String {
+    match (format, name.split_once('.')) {
+        (InsertionFormat::Letter, Some((position, insertion))) => match insertion.parse() {
+            Ok(insertion) => format!("{position}{}", insertion_letters(insertion)),
+            Err(_) => name.to_string(),
+        },
+        _ => name.to_string(),
+    }
+}
+
+/// Convert the insertion codes of numbering annotations to the given format.
+pub fn format_positions(annotations: &mut [Annotation], format: InsertionFormat) {
+    if format == InsertionFormat::Dotted {
+        return;
+    }
+    for annotation in annotations {
+        annotation.name = format_position(&annotation.name, format);
+    }
+}
+
+/// Letters for a (one based) insertion: A to Z, followed by AA, AB, ...
+fn insertion_letters(mut insertion: usize) -> String {
+    let mut letters = Vec::new();
+    while insertion > 0 {
+        insertion -= 1;
+        letters.push(b'A' + (insertion % 26) as u8);
+        insertion /= 26;
+    }
+    letters.reverse();
+    String::from_utf8(letters).expect("Letters are ASCII.")
+}
+
+/// Writer that wraps the sequences of the FASTA records written through it.
+///
+/// Header lines (starting with `>`) are left as they are, and sequence lines are
+/// broken after every `width` residues. With a width of 0 nothing is wrapped, as
+/// all writers of this module write each sequence on a single line.
+pub struct WrappingFastaWriter<W> {
+    inner: W,
+    width: usize,
+    column: usize,
+    in_header: bool,
+}
+
+impl<W: Write> WrappingFastaWriter<W> {
+    pub fn new(inner: W, width: usize) -> Self {
+        Self {
+            inner,
+            width,
+            column: 0,
+            in_header: false,
+        }
+    }
+}
+
+impl<W: Write> Write for WrappingFastaWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.width == 0 {
+            return self.inner.write(buf);
+        }
+        let mut wrapped = Vec::with_capacity(buf.len() + buf.len() / self.width + 1);
+        for &byte in buf {
+            if byte == b'\n' {
+                self.in_header = false;
+                self.column = 0;
+            } else {
+                if self.column == 0 && byte == b'>' {
+                    self.in_header = true;
+                }
+                if !self.in_header && self.column == self.width {
+                    wrapped.push(b'\n');
+                    self.column = 0;
+                }
+                self.column += 1;
+            }
+            wrapped.push(byte);
+        }
+        self.inner.write_all(&wrapped)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Placeholders of an [`IdTemplate`].
+const ID_PLACEHOLDERS: [&str; 5] = ["id", "name", "region", "start", "end"];
+
+/// Error for id templates with unknown or unclosed placeholders.
+#[derive(Debug, Error)]
+pub enum IdTemplateErr {
+    #[error("Unknown placeholder '{{{0}}}' in id template, expected one of {{id}}, {{name}}, {{region}}, {{start}} and {{end}}.")]
+    UnknownPlaceholder(String),
+
+    #[error("Unclosed placeholder in id template '{0}'.")]
+    Unclosed(String),
+}
+
+/// How the ids of the FASTA records of annotations are built.
+///
+/// The placeholders are `{id}` (the id of the record), `{name}` (the name of the
+/// annotation, such as `104` or `CDR3-IMGT`), `{region}` (the region of the
+/// annotation, such as `FR3-IMGT` for `104`, see [`annotation_region`]) and
+/// `{start}` and `{end}` (the range in the record, in the [`CoordinateBase`] given to
+/// [`IdTemplate::render`]). The default is `{name}_{id}`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IdTemplate(String);
+
+impl Default for IdTemplate {
+    fn default() -> Self {
+        Self("{name}_{id}".to_string())
+    }
+}
+
+impl std::str::FromStr for IdTemplate {
+    type Err = IdTemplateErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rest = s;
+        while let Some(open) = rest.find('{') {
+            let Some(close) = rest[open..].find('}') else {
+                return Err(IdTemplateErr::Unclosed(s.to_string()));
+            };
+            let placeholder = &rest[open + 1..open + close];
+            if !ID_PLACEHOLDERS.contains(&placeholder) {
+                return Err(IdTemplateErr::UnknownPlaceholder(placeholder.to_string()));
+            }
+            rest = &rest[open + close + 1..];
+        }
+        Ok(Self(s.to_string()))
+    }
+}
+
+impl IdTemplate {
+    /// The id of the record of an annotation of a record with the given id.
+    ///
+    /// `{start}` and `{end}` are written in `coordinate_base`.
+    pub fn render(
+        &self,
+        id: &str,
+        annotation: &Annotation,
+        coordinate_base: CoordinateBase,
+    ) -> String {
+        let (start, end) = coordinate_base.range(annotation.start, annotation.end);
+        self.0
+            .replace("{id}", id)
+            .replace("{name}", &annotation.name)
+            .replace("{region}", &annotation_region(&annotation.name))
+            .replace("{start}", &start.to_string())
+            .replace("{end}", &end.to_string())
+    }
+}
+
+/// The region of an annotation, such as `FR3-IMGT` for the residue numbered `104`.
+///
+/// Residues are numbered with an IMGT position (in either [`InsertionFormat`]),
+/// see [`ImgtRegion::of_position`]. Other annotations, such as the regions
+/// themselves, are their own region.
+pub fn annotation_region(name: &str) -> String {
+    let position = name.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    match ImgtRegion::of_position(position) {
+        Some(region) => format!("{region}-IMGT"),
+        None => name.to_string(),
+    }
+}
+
+/// Apply all annotations of the a vregion to a record and write them to a writer.
+///
+/// Flags (such as `missing_fr1`) are appended to the description of every FASTA
+/// record, or written to the last column for TSV.
+pub fn write_annotations<W: Write>(
+    record: &fasta::Record,
+    annotations: &[Annotation],
+    flags: &[&str],
+    format: OutputFormat,
+    writer: W,
+) -> std::io::Result<()> {
+    write_annotations_with_template(
+        record,
+        annotations,
+        flags,
+        format,
+        &IdTemplate::default(),
+        CoordinateBase::default(),
+        writer,
+    )
+}
+
+/// Like [`write_annotations`], but build the ids of FASTA records from a template.
+///
+/// TSV lines have the id of the record and the name of the annotation in columns
+/// of their own, so these are not affected. The start and end of the annotations
+/// are written in `coordinate_base`.
+pub fn write_annotations_with_template<W: Write>(
+    record: &fasta::Record,
+    annotations: &[Annotation],
+    flags: &[&str],
+    format: OutputFormat,
+    id_template: &IdTemplate,
+    coordinate_base: CoordinateBase,
+    writer: W,
+) -> std::io::Result<()> {
+    let mut writer = std::io::BufWriter::new(writer);
+    annotations.iter().try_for_each(|ann| {
+        write_annotation(
+            &mut writer,
+            record,
+            ann,
+            flags,
+            format,
+            id_template,
+            coordinate_base,
+        )
+    })
+}
+
+/// The description of the FASTA record of an annotation, such as `IMGT Number 104 on query|95|96`.
+///
+/// Every FASTA output builds its descriptions here, so they all convert the
+/// coordinates in the same way.
+pub(crate) fn annotation_description(
+    record: &fasta::Record,
+    annotation: &Annotation,
+    coordinate_base: CoordinateBase,
+) -> String {
+    let (start, end) = coordinate_base.range(annotation.start, annotation.end);
+    format!(
+        "IMGT Number {} on {}|{}|{}",
+        annotation.name,
+        record.id(),
+        start,
+        end
+    )
+}
+
+/// Write a single annotation of a record without building intermediate records.
+pub(crate) fn write_annotation<W: Write>(
+    writer: &mut W,
+    record: &fasta::Record,
+    annotation: &Annotation,
+    flags: &[&str],
+    format: OutputFormat,
+    id_template: &IdTemplate,
+    coordinate_base: CoordinateBase,
+) -> std::io::Result<()> {
+    let sequence = &record.seq()[annotation.start..annotation.end];
+    let (start, end) = coordinate_base.range(annotation.start, annotation.end);
+    match format {
+        OutputFormat::Fasta | OutputFormat::A2m | OutputFormat::Pair => {
+            write_fasta_header(
+                writer,
+                format_args!(
+                    "{} {}",
+                    id_template.render(record.id(), annotation, coordinate_base),
+                    annotation_description(record, annotation, coordinate_base)
+                ),
+                flags,
+            )?;
+            write_fasta_sequence(writer, sequence)
+        }
+        OutputFormat::Tsv | OutputFormat::Airr | OutputFormat::Collier => {
+            write!(
+                writer,
+                "{}\t{}\t{}\t{}\t",
+                record.id(),
+                annotation.name,
+                start,
+                end
+            )?;
+            writer.write_all(sequence)?;
+            writer.write_all(b"\t")?;
+            write_flags(writer, flags)?;
+            writer.write_all(b"\n")
+        }
+    }
+}
+
+/// Write the header line of a FASTA record: the id and description in `header`,
+/// followed by the flags (see [`write_flags`]) when there are any.
+fn write_fasta_header<W: Write>(
+    writer: &mut W,
+    header: std::fmt::Arguments,
+    flags: &[&str],
+) -> std::io::Result<()> {
+    write!(writer, ">{}", header)?;
+    if !flags.is_empty() {
+        writer.write_all(b" ")?;
+        write_flags(writer, flags)?;
+    }
+    writer.write_all(b"\n")
+}
+
+/// Write the sequence line of a FASTA record.
+///
+/// An empty sequence (such as a CDR2 of length zero) gets no line at all, instead
+/// of a blank line that some FASTA readers reject. The record still reads back
+/// with an empty sequence.
+fn write_fasta_sequence<W: Write>(writer: &mut W, sequence: &[u8]) -> std::io::Result<()> {
+    if sequence.is_empty() {
+        return Ok(());
+    }
+    writer.write_all(sequence)?;
+    writer.write_all(b"\n")
+}
+
+/// Write flags separated by commas.
+fn write_flags<W: Write>(writer: &mut W, flags: &[&str]) -> std::io::Result<()> {
+    flags.iter().enumerate().try_for_each(|(i, flag)| {
+        if i > 0 {
+            writer.write_all(b",")?;
+        }
+        writer.write_all(flag.as_bytes())
+    })
+}
+
+/// Write the concatenated CDRs (the paratope, see [`VRegionAnnotation::cdr_concatenation`]) of a record.
+///
+/// For FASTA this is a single record named `PARATOPE_{id}`, for TSV a line with
+/// the columns: id, `PARATOPE`, sequence and flags.
+///
+/// [`VRegionAnnotation::cdr_concatenation`]: super::annotations::VRegionAnnotation::cdr_concatenation
+pub fn write_paratope<W: Write>(
+    record: &fasta::Record,
+    paratope: &[u8],
+    flags: &[&str],
+    format: OutputFormat,
+    mut writer: W,
+) -> std::io::Result<()> {
+    match format {
+        OutputFormat::Fasta | OutputFormat::A2m | OutputFormat::Pair => {
+            write_fasta_header(
+                &mut writer,
+                format_args!(
+                    "PARATOPE_{} CDR1-IMGT, CDR2-IMGT and CDR3-IMGT of {}",
+                    record.id(),
+                    record.id()
+                ),
+                flags,
+            )?;
+            write_fasta_sequence(&mut writer, paratope)
+        }
+        OutputFormat::Tsv | OutputFormat::Airr | OutputFormat::Collier => {
+            write!(writer, "{}\tPARATOPE\t", record.id())?;
+            writer.write_all(paratope)?;
+            writer.write_all(b"\t")?;
+            write_flags(&mut writer, flags)?;
+            writer.write_all(b"\n")
+        }
+    }
+}
+
+/// Write the run-length encoded numbering (see [`EncodedNumbering`]) of a record.
+///
+/// For FASTA this is a single record named `ENCODED_{id}` with the encoding as its
+/// sequence, for TSV a line with the columns: id, reference, encoding and flags.
+pub fn write_encoded_numbering<W: Write>(
+    record: &fasta::Record,
+    reference_name: &str,
+    encoded: &EncodedNumbering,
+    flags: &[&str],
+    format: OutputFormat,
+    mut writer: W,
+) -> std::io::Result<()> {
+    match format {
+        OutputFormat::Fasta | OutputFormat::A2m | OutputFormat::Pair => {
+            write_fasta_header(
+                &mut writer,
+                format_args!(
+                    "ENCODED_{} Numbering of {} relative to {}",
+                    record.id(),
+                    record.id(),
+                    reference_name
+                ),
+                flags,
+            )?;
+            writeln!(writer, "{}", encoded)
+        }
+        OutputFormat::Tsv | OutputFormat::Airr | OutputFormat::Collier => {
+            write!(writer, "{}\t{}\t{}\t", record.id(), reference_name, encoded)?;
+            write_flags(&mut writer, flags)?;
+            writer.write_all(b"\n")
+        }
+    }
+}
+
+/// The numbered residues of a record in the IMGT column frame, following the A2M convention.
+///
+/// Every IMGT position (1 to 128) is a match column, with the residue in uppercase
+/// or a `-` when the query has none. Insertions are insert columns in lowercase, so
+/// every record has the same 128 match columns however long its CDRs are. Residues
+/// that are not numbered are left out. Expects IMGT numbers with dotted insertions.
+pub fn a2m_sequence(record: &fasta::Record, numbering: &[Annotation]) -> Vec<u8> {
+    let mut sequence = Vec::new();
+    let mut next_position = 1;
+    for annotation in numbering {
+        let Some(residue) = record.seq().get(annotation.start) else {
+            continue;
+        };
+        let (position, insertion) = imgt_position_key(&annotation.name);
+        if position > FR4_END {
+            continue;
+        }
+        // Insertions on 112 precede it, all others follow their position.
+        let last_match = if insertion > 0 {
+            position
+        } else {
+            position - 1
+        };
+        while next_position <= last_match {
+            sequence.push(b'-');
+            next_position += 1;
+        }
+        if insertion == 0 {
+            sequence.push(residue.to_ascii_uppercase());
+            next_position = position + 1;
+        } else {
+            sequence.push(residue.to_ascii_lowercase());
+        }
+    }
+    while next_position <= FR4_END {
+        sequence.push(b'-');
+        next_position += 1;
+    }
+    sequence
+}
+
+/// Write a numbered record as an A2M record, see [`a2m_sequence`].
+///
+/// The record keeps its id, with the flags as description.
+pub fn write_a2m<W: Write>(
+    record: &fasta::Record,
+    numbering: &[Annotation],
+    flags: &[&str],
+    mut writer: W,
+) -> std::io::Result<()> {
+    write_fasta_header(&mut writer, format_args!("{}", record.id()), flags)?;
+    writer.write_all(&a2m_sequence(record, numbering))?;
+    writer.write_all(b"\n")
+}
+
+/// The germline and the numbered residues of a record in the same IMGT frame.
+///
+/// The frame is that of [`a2m_sequence`]: a column for every IMGT position and every
+/// insertion of the record. The germline residues are taken from the IMGT-gapped
+/// alignment of the reference, with a `-` for the insertions of the record. Both
+/// are in uppercase and have the same length, so they line up column by column.
+pub fn germline_pair(
+    record: &fasta::Record,
+    numbering: &[Annotation],
+    reference: &ReferenceSequence,
+) -> (Vec<u8>, Vec<u8>) {
+    let query = a2m_sequence(record, numbering);
+    let mut columns = reference.get_alignment().iter();
+    let germline = query
+        .iter()
+        .map(|residue| {
+            if residue.is_ascii_lowercase() {
+                b'-'
+            } else {
+                columns.next().copied().unwrap_or(b'-')
+            }
+        })
+        .collect();
+    (germline, query.to_ascii_uppercase())
+}
+
+/// Write the germline and a numbered record as two aligned FASTA records, see [`germline_pair`].
+///
+/// The germline record is named `GERMLINE_{id}` with the reference as description,
+/// the record keeps its id with the flags as description.
+pub fn write_germline_pair<W: Write>(
+    record: &fasta::Record,
+    numbering: &[Annotation],
+    reference: &ReferenceSequence,
+    flags: &[&str],
+    mut writer: W,
+) -> std::io::Result<()> {
+    let (germline, query) = germline_pair(record, numbering, reference);
+    write_fasta_header(
+        &mut writer,
+        format_args!("GERMLINE_{} {}", record.id(), reference.name),
+        &[],
+    )?;
+    writer.write_all(&germline)?;
+    writer.write_all(b"\n")?;
+    write_fasta_header(&mut writer, format_args!("{}", record.id()), flags)?;
+    writer.write_all(&query)?;
+    writer.write_all(b"\n")
+}
+
+/// The residues of the query in a region, both contiguous and IMGT-gapped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GappedRegion {
+    pub region: Annotation,
+    /// The query residues in the region.
+    pub sequence: Vec<u8>,
+    /// The query residues in the region with a `-` for every IMGT position without a residue.
+    pub gapped: Vec<u8>,
+}
+
+/// Collect the contiguous and IMGT-gapped sequence of every region.
+///
+/// The gapped sequence is built from the numbering, so insertions are included
+/// after (or for 112, before) the position they belong to.
+pub fn gapped_regions(
+    record: &fasta::Record,
+    regions: &[Annotation],
+    numbering: &[Annotation],
+    cdr3_definition: Cdr3Definition,
+) -> Vec<GappedRegion> {
+    regions
+        .iter()
+        .map(|region| {
+            let numbered: Vec<_> = numbering
+                .iter()
+                .filter(|number| region.start <= number.start && number.end <= region.end)
+                .collect();
+            let gapped = region_positions(&region.name, cdr3_definition)
+                .into_iter()
+                .flatten()
+                .flat_map(|position| {
+                    let residues: Vec<_> = numbered
+                        .iter()
+                        .filter(|number| base_position(&number.name) == Some(position))
+                        .flat_map(|number| &record.seq()[number.start..number.end])
+                        .copied()
+                        .collect();
+                    if residues.is_empty() {
+                        vec![b'-']
+                    } else {
+                        residues
+                    }
+                })
+                .collect();
+            GappedRegion {
+                region: region.clone(),
+                sequence: record.seq()[region.start..region.end].to_vec(),
+                gapped,
+            }
+        })
+        .collect()
+}
+
+/// The IMGT position of a number, without its insertion code.
+fn base_position(name: &str) -> Option<usize> {
+    let end = name
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(name.len());
+    name[..end].parse().ok()
+}
+
+/// Write the contiguous and IMGT-gapped sequence of every region.
+///
+/// For FASTA every region gives two records, the second with `_gapped` added to the
+/// name. For TSV the gapped sequence is an extra column after the sequence. The
+/// start and end of the regions are written in `coordinate_base`.
+pub fn write_gapped_regions<W: Write>(
+    record: &fasta::Record,
+    regions: &[GappedRegion],
+    flags: &[&str],
+    format: OutputFormat,
+    coordinate_base: CoordinateBase,
+    writer: W,
+) -> std::io::Result<()> {
+    let mut writer = std::io::BufWriter::new(writer);
+    regions.iter().try_for_each(|gapped_region| {
+        let region = &gapped_region.region;
+        let (start, end) = coordinate_base.range(region.start, region.end);
+        match format {
+            OutputFormat::Fasta | OutputFormat::A2m | OutputFormat::Pair => {
+                for (suffix, sequence) in [
+                    ("", &gapped_region.sequence),
+                    ("_gapped", &gapped_region.gapped),
+                ] {
+                    write_fasta_header(
+                        &mut writer,
+                        format_args!(
+                            "{}{}_{} {}",
+                            region.name,
+                            suffix,
+                            record.id(),
+                            annotation_description(record, region, coordinate_base)
+                        ),
+                        flags,
+                    )?;
+                    write_fasta_sequence(&mut writer, sequence)?;
+                }
+                Ok(())
+            }
+            OutputFormat::Tsv | OutputFormat::Airr | OutputFormat::Collier => {
+                write!(
+                    writer,
+                    "{}\t{}\t{}\t{}\t",
+                    record.id(),
+                    region.name,
+                    start,
+                    end
+                )?;
+                writer.write_all(&gapped_region.sequence)?;
+                writer.write_all(b"\t")?;
+                writer.write_all(&gapped_region.gapped)?;
+                writer.write_all(b"\t")?;
+                write_flags(&mut writer, flags)?;
+                writer.write_all(b"\n")
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::imgt::{
+        find_best_reference_sequence,
+        fixtures::{ref_seqs, test_ref_seqs, test_reference, TEST_ALIGNMENT_STR, TEST_SEQUENCE},
+        numbering::{Cdr3Definition, ImgtRegion, NumberingScheme},
+        reference::ReferenceSequence,
+    };
+
+    #[test]
+    fn test_letter_insertion_format() {
+        let names = ["111", "111.1", "111.2", "112.3", "112.2", "112.1", "112"];
+        let letters: Vec<_> = names
+            .iter()
+            .map(|name| format_position(name, InsertionFormat::Letter))
+            .collect();
+        assert_eq!(
+            letters,
+            ["111", "111A", "111B", "112C", "112B", "112A", "112"]
+        );
+        assert_eq!(format_position("112.1", InsertionFormat::Dotted), "112.1");
+        assert_eq!(format_position("111.27", InsertionFormat::Letter), "111AA");
+        assert_eq!(base_position("112B"), Some(112));
+    }
+
+    #[test]
+    fn test_id_template() {
+        let annotation = Annotation {
+            start: 96,
+            end: 101,
+            name: "CDR3-IMGT".to_string(),
+        };
+        assert_eq!(
+            IdTemplate::default().render("query", &annotation, CoordinateBase::Zero),
+            "CDR3-IMGT_query"
+        );
+        let template: IdTemplate = "{id}|{region}|{start}-{end}".parse().unwrap();
+        assert_eq!(
+            template.render("query", &annotation, CoordinateBase::Zero),
+            "query|CDR3-IMGT|96-101"
+        );
+
+        let residue = Annotation {
+            start: 96,
+            end: 97,
+            name: "104".to_string(),
+        };
+        assert_eq!(
+            template.render("query", &residue, CoordinateBase::Zero),
+            "query|FR3-IMGT|96-97"
+        );
+        assert_eq!(annotation_region("111.1"), "CDR3-IMGT");
+        assert_eq!(annotation_region("111A"), "CDR3-IMGT");
+        assert_eq!(annotation_region("CDR3-IMGT"), "CDR3-IMGT");
+
+        assert!(matches!(
+            "{id}_{chain}".parse::<IdTemplate>(),
+            Err(IdTemplateErr::UnknownPlaceholder(placeholder)) if placeholder == "chain"
+        ));
+        assert!(matches!(
+            "{id".parse::<IdTemplate>(),
+            Err(IdTemplateErr::Unclosed(_))
+        ));
+
+        let record = fasta::Record::with_attrs("query", None, TEST_SEQUENCE.as_bytes());
+        let mut written = Vec::new();
+        write_annotations_with_template(
+            &record,
+            &[annotation],
+            &[],
+            OutputFormat::Fasta,
+            &template,
+            CoordinateBase::Zero,
+            &mut written,
+        )
+        .unwrap();
+        assert!(written.starts_with(b">query|CDR3-IMGT|96-101 IMGT Number CDR3-IMGT on query"));
+    }
+
+    #[test]
+    fn test_wrapping_fasta_writer() {
+        let wrapped = |width: usize| {
+            let mut writer = WrappingFastaWriter::new(Vec::new(), width);
+            // Written in parts, as the writers of this module do.
+            writer
+                .write_all(b">first a long description\nQVQLV")
+                .unwrap();
+            writer.write_all(b"QSGAE\n>second\nQVQ\n").unwrap();
+            String::from_utf8(writer.inner).unwrap()
+        };
+        assert_eq!(
+            wrapped(0),
+            ">first a long description\nQVQLVQSGAE\n>second\nQVQ\n"
+        );
+        assert_eq!(
+            wrapped(4),
+            ">first a long description\nQVQL\nVQSG\nAE\n>second\nQVQ\n"
+        );
+        assert_eq!(
+            wrapped(5),
+            ">first a long description\nQVQLV\nQSGAE\n>second\nQVQ\n"
+        );
+    }
+
+    #[test]
+    fn test_coordinate_base() {
+        let record = fasta::Record::with_attrs("query", None, TEST_SEQUENCE.as_bytes());
+        let annotation = Annotation {
+            start: 96,
+            end: 101,
+            name: "CDR3-IMGT".to_string(),
+        };
+        let written = |coordinate_base: CoordinateBase| {
+            let mut written = Vec::new();
+            write_annotations_with_template(
+                &record,
+                std::slice::from_ref(&annotation),
+                &[],
+                OutputFormat::Tsv,
+                &IdTemplate::default(),
+                coordinate_base,
+                &mut written,
+            )
+            .unwrap();
+            String::from_utf8(written).unwrap()
+        };
+
+        assert_eq!(
+            written(CoordinateBase::Zero),
+            "query\tCDR3-IMGT\t96\t101\tARMDV\t\n"
+        );
+        // One based and end inclusive, so only the start shifts.
+        assert_eq!(
+            written(CoordinateBase::One),
+            "query\tCDR3-IMGT\t97\t101\tARMDV\t\n"
+        );
+        let template: IdTemplate = "{start}-{end}".parse().unwrap();
+        assert_eq!(
+            template.render("query", &annotation, CoordinateBase::One),
+            "97-101"
+        );
+    }
+
+    #[test]
+    fn test_fasta_description_coordinates() {
+        let ref_seqs = test_ref_seqs();
+        let reference_alignment = find_best_reference_sequence(
+            fasta::Record::with_attrs("query", None, TEST_SEQUENCE.as_bytes()),
+            &ref_seqs,
+        )
+        .unwrap();
+        let record = &reference_alignment.query_record;
+        let annotations = reference_alignment
+            .vregion_annotation()
+            .unwrap()
+            .region_annotations(Cdr3Definition::Imgt);
+
+        for coordinate_base in [CoordinateBase::Zero, CoordinateBase::One] {
+            let mut written = Vec::new();
+            write_annotations_with_template(
+                record,
+                &annotations,
+                &[],
+                OutputFormat::Fasta,
+                &IdTemplate::default(),
+                coordinate_base,
+                &mut written,
+            )
+            .unwrap();
+            let records: Vec<_> = fasta::Reader::new(written.as_slice())
+                .records()
+                .map(|record| record.unwrap())
+                .collect();
+            assert_eq!(records.len(), annotations.len());
+            for record in records {
+                let (_, coordinates) = record.desc().unwrap().split_once('|').unwrap();
+                let (start, end) = coordinates.split_once('|').unwrap();
+                let (start, end): (usize, usize) = (start.parse().unwrap(), end.parse().unwrap());
+                let start = match coordinate_base {
+                    CoordinateBase::Zero => start,
+                    CoordinateBase::One => start - 1,
+                };
+                assert_eq!(
+                    &TEST_SEQUENCE.as_bytes()[start..end],
+                    record.seq(),
+                    "{} in base {coordinate_base:?}",
+                    record.id()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_a2m_match_columns() {
+        let ref_seqs = test_ref_seqs();
+        let mut a2m: Vec<_> = [TEST_SEQUENCE, &TEST_SEQUENCE[10..]]
+            .iter()
+            .map(|sequence| {
+                let reference_alignment = find_best_reference_sequence(
+                    fasta::Record::with_attrs("query", None, sequence.as_bytes()),
+                    &ref_seqs,
+                )
+                .unwrap();
+                let numbering = reference_alignment
+                    .numbered(&NumberingScheme::default())
+                    .unwrap();
+                a2m_sequence(&reference_alignment.query_record, &numbering)
+            })
+            .collect();
+        // Position 10 is a gap in the reference, and therefore in the query.
+        assert_eq!(&a2m[0][..11], b"QVQLVQSGA-E");
+        assert!(a2m[1].starts_with(b"-----------VKK"));
+
+        // Insertions on 111 follow it, those on 112 precede it.
+        let names = ["1", "2", "111", "111.1", "112.1", "112"];
+        let numbering: Vec<_> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| Annotation {
+                start: i,
+                end: i + 1,
+                name: name.to_string(),
+            })
+            .collect();
+        let record = fasta::Record::with_attrs("insertions", None, b"ACDEFG");
+        let with_insertions = a2m_sequence(&record, &numbering);
+        assert_eq!(
+            String::from_utf8(with_insertions.clone()).unwrap(),
+            format!("AC{}DefG{}", "-".repeat(108), "-".repeat(16))
+        );
+        a2m.push(with_insertions);
+
+        let match_columns = |sequence: &[u8]| {
+            sequence
+                .iter()
+                .filter(|residue| residue.is_ascii_uppercase() || **residue == b'-')
+                .count()
+        };
+        assert!(a2m
+            .iter()
+            .all(|sequence| match_columns(sequence) == FR4_END));
+    }
+
+    #[test]
+    fn test_germline_pair() {
+        // The test sequence with a substitution in the CDR2 (ISAYNGNT -> ISCYNGNT) and a longer CDR3.
+        const MUTATED_SEQUENCE: &str = "QVQLVQSGAEVKKPGASVKVSCKASGYTFTSYGISWVRQAPGQGLEWMGWISCYNGNTNYAQKLQGRVTMTTDTSTSTAYMELRSLRSDDTAVYYCARGGYMDVWGQGTTVTVSS";
+        let reference = test_reference();
+        let ref_seqs = ref_seqs([reference.clone()]);
+        let reference_alignment = find_best_reference_sequence(
+            fasta::Record::with_attrs("query", None, MUTATED_SEQUENCE.as_bytes()),
+            &ref_seqs,
+        )
+        .unwrap();
+        let numbering = reference_alignment
+            .numbered(&NumberingScheme::default())
+            .unwrap();
+
+        let (germline, query) =
+            germline_pair(&reference_alignment.query_record, &numbering, &reference);
+        assert_eq!(germline.len(), query.len());
+        assert_eq!(germline, TEST_ALIGNMENT_STR.as_bytes());
+
+        // Columns where both have a residue only differ at the substitution.
+        let mismatches: Vec<_> = germline
+            .iter()
+            .zip(&query)
+            .enumerate()
+            .filter(|(_column, (germline, query))| {
+                **germline != b'-' && **query != b'-' && germline != query
+            })
+            .map(|(column, _residues)| column + 1)
+            .collect();
+        assert_eq!(mismatches, [58]);
+        let substituted = numbering
+            .iter()
+            .find(|annotation| annotation.name == "58")
+            .unwrap()
+            .start;
+        assert!(reference_alignment
+            .alignment
+            .path()
+            .iter()
+            .any(|(_x, y, op)| *y == substituted + 1
+                && *op == bio::alignment::AlignmentOperation::Subst));
+
+        let mut written = Vec::new();
+        write_germline_pair(
+            &reference_alignment.query_record,
+            &numbering,
+            &reference,
+            &[],
+            &mut written,
+        )
+        .unwrap();
+        let records: Vec<_> = fasta::Reader::new(&written[..])
+            .records()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(records[0].id(), "GERMLINE_query");
+        assert_eq!(records[1].id(), "query");
+        assert_eq!(records[0].seq().len(), records[1].seq().len());
+    }
+
+    #[test]
+    fn test_gapped_region_has_gap_for_missing_position() {
+        let ref_seqs = test_ref_seqs();
+        let reference_alignment = find_best_reference_sequence(
+            fasta::Record::with_attrs("query", None, TEST_SEQUENCE.as_bytes()),
+            &ref_seqs,
+        )
+        .unwrap();
+        let scheme = NumberingScheme::default();
+        let regions = reference_alignment
+            .vregion_annotation()
+            .unwrap()
+            .region_annotations(scheme.cdr3_definition);
+        let numbering = reference_alignment.numbered(&scheme).unwrap();
+
+        let gapped = gapped_regions(
+            &reference_alignment.query_record,
+            &regions,
+            &numbering,
+            scheme.cdr3_definition,
+        );
+
+        // The reference, and therefore the query, has no residue at position 10.
+        let fr1 = &gapped[0];
+        assert_eq!(fr1.region.name, "FR1-IMGT");
+        assert_eq!(fr1.sequence, b"QVQLVQSGAEVKKPGASVKVSCKAS");
+        assert_eq!(fr1.gapped, b"QVQLVQSGA-EVKKPGASVKVSCKAS");
+        assert!(gapped.iter().all(|region| !region.sequence.contains(&b'-')));
+    }
+
+    #[test]
+    fn test_empty_cdr2() {
+        // A germline without CDR2 residues, positions 56 to 65 are all gaps.
+        let alignment_str = format!(
+            "{}----------{}",
+            &TEST_ALIGNMENT_STR[..55],
+            &TEST_ALIGNMENT_STR[65..]
+        );
+        let sequence = alignment_str.replace('-', "");
+        let reference = ReferenceSequence::new("test", alignment_str.as_bytes()).unwrap();
+        let ref_seqs = ref_seqs([reference]);
+        let reference_alignment = find_best_reference_sequence(
+            fasta::Record::with_attrs("query", None, sequence.as_bytes()),
+            &ref_seqs,
+        )
+        .unwrap();
+        let scheme = NumberingScheme::default();
+        let regions = reference_alignment
+            .vregion_annotation()
+            .unwrap()
+            .region_annotations(scheme.cdr3_definition);
+        let numbering = reference_alignment.numbered(&scheme).unwrap();
+        assert!(numbering
+            .iter()
+            .all(|annotation| ImgtRegion::of_position(&annotation.name) != Some(ImgtRegion::Cdr2)));
+        let record = &reference_alignment.query_record;
+        assert_eq!(regions[3].name, "CDR2-IMGT");
+        assert_eq!(regions[3].start, regions[3].end);
+
+        let mut fasta = Vec::new();
+        write_annotations(record, &regions, &[], OutputFormat::Fasta, &mut fasta).unwrap();
+        let fasta = String::from_utf8(fasta).unwrap();
+        assert!(!fasta.contains("\n\n"));
+        let records: Vec<_> = fasta::Reader::new(fasta.as_bytes())
+            .records()
+            .map(|record| record.unwrap())
+            .collect();
+        assert_eq!(records.len(), 7);
+        assert_eq!(records[3].id(), "CDR2-IMGT_query");
+        assert!(records[3].seq().is_empty());
+        assert_eq!(
+            records[4].seq(),
+            &sequence.as_bytes()[regions[4].start..regions[4].end]
+        );
+
+        let mut tsv = Vec::new();
+        write_annotations(record, &regions, &[], OutputFormat::Tsv, &mut tsv).unwrap();
+        let tsv = String::from_utf8(tsv).unwrap();
+        let cdr2_line: Vec<_> = tsv.lines().nth(3).unwrap().split('\t').collect();
+        assert_eq!(cdr2_line[..5], ["query", "CDR2-IMGT", "50", "50", ""]);
+
+        let gapped = gapped_regions(record, &regions, &numbering, scheme.cdr3_definition);
+        assert_eq!(gapped[3].gapped, b"----------");
+        let mut gapped_fasta = Vec::new();
+        write_gapped_regions(
+            record,
+            &gapped,
+            &[],
+            OutputFormat::Fasta,
+            CoordinateBase::Zero,
+            &mut gapped_fasta,
+        )
+        .unwrap();
+        assert_eq!(
+            fasta::Reader::new(gapped_fasta.as_slice())
+                .records()
+                .count(),
+            14
+        );
+    }
+}
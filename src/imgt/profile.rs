@@ -0,0 +1,216 @@
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    io::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// A stage of numbering, timed by a [`Profile`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// Loading (and validating) the reference sequences.
+    LoadReferences,
+    /// Aligning queries to all references to find the best one.
+    Alignment,
+    /// Transferring the numbering of the reference to the queries.
+    Numbering,
+    /// Formatting the numbering of the queries.
+    Output,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 4] = [
+        Stage::LoadReferences,
+        Stage::Alignment,
+        Stage::Numbering,
+        Stage::Output,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::LoadReferences => "load_references",
+            Stage::Alignment => "alignment",
+            Stage::Numbering => "numbering",
+            Stage::Output => "output",
+        }
+    }
+}
+
+/// The cumulative time spent in every [`Stage`], and the sequences that took longest.
+///
+/// A profile can be shared by several threads, in which case the time of a stage
+/// is summed over the threads and can be longer than the time it took.
+#[derive(Debug, Default)]
+pub struct Profile {
+    nanos: [AtomicU64; Stage::ALL.len()],
+    /// How many of the slowest sequences are kept, see [`Self::add_sequence`].
+    n_slowest: usize,
+    /// The slowest sequences so far, with the fastest of them on top.
+    slowest: Mutex<BinaryHeap<Reverse<(Duration, String)>>>,
+}
+
+impl Profile {
+    /// A profile that also keeps the `n_slowest` sequences that took longest.
+    pub fn with_slowest(n_slowest: usize) -> Self {
+        Self {
+            n_slowest,
+            slowest: Mutex::new(BinaryHeap::with_capacity(n_slowest + 1)),
+            ..Self::default()
+        }
+    }
+
+    /// Add the time it took to number a sequence.
+    ///
+    /// Only the slowest sequences are kept, so this takes memory for at most
+    /// `n_slowest` ids, and the id is only copied when the sequence is kept.
+    pub fn add_sequence(&self, id: &str, duration: Duration) {
+        if self.n_slowest == 0 {
+            return;
+        }
+        let mut slowest = self
+            .slowest
+            .lock()
+            .expect("Profile should not be poisoned.");
+        if slowest.len() == self.n_slowest
+            && slowest
+                .peek()
+                .is_some_and(|Reverse((fastest, _id))| *fastest >= duration)
+        {
+            return;
+        }
+        slowest.push(Reverse((duration, id.to_string())));
+        if slowest.len() > self.n_slowest {
+            slowest.pop();
+        }
+    }
+
+    /// The slowest sequences with the time they took, the slowest first.
+    pub fn slowest(&self) -> Vec<(String, Duration)> {
+        let slowest = self
+            .slowest
+            .lock()
+            .expect("Profile should not be poisoned.");
+        let mut slowest: Vec<_> = slowest
+            .iter()
+            .map(|Reverse((duration, id))| (id.clone(), *duration))
+            .collect();
+        slowest.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        slowest
+    }
+
+    pub fn add(&self, stage: Stage, duration: Duration) {
+        self.nanos[stage as usize].fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn duration(&self, stage: Stage) -> Duration {
+        Duration::from_nanos(self.nanos[stage as usize].load(Ordering::Relaxed))
+    }
+
+    /// Write the seconds spent in every stage, with its share of the total, as lines of TSV.
+    pub fn write<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        let total: Duration = Stage::ALL.iter().map(|stage| self.duration(*stage)).sum();
+        writeln!(writer, "stage\tseconds\tshare")?;
+        for stage in Stage::ALL {
+            let duration = self.duration(stage);
+            let share = if total.is_zero() {
+                0.0
+            } else {
+                duration.as_secs_f64() / total.as_secs_f64()
+            };
+            writeln!(
+                writer,
+                "{}\t{:.3}\t{:.3}",
+                stage.name(),
+                duration.as_secs_f64(),
+                share
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Write the seconds every slowest sequence took, the slowest first, as lines of TSV.
+    ///
+    /// Nothing is written when the profile keeps no sequences.
+    pub fn write_slowest<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        if self.n_slowest == 0 {
+            return Ok(());
+        }
+        writeln!(writer, "sequence\tseconds")?;
+        for (id, duration) in self.slowest() {
+            writeln!(writer, "{id}\t{:.6}", duration.as_secs_f64())?;
+        }
+        Ok(())
+    }
+}
+
+/// Call `f`, adding the time it takes to `stage` of the profile if there is one.
+pub fn time_stage<T>(profile: Option<&Profile>, stage: Stage, f: impl FnOnce() -> T) -> T {
+    let Some(profile) = profile else {
+        return f();
+    };
+    let start = Instant::now();
+    let result = f();
+    profile.add(stage, start.elapsed());
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_profile() {
+        let profile = Profile::default();
+        profile.add(Stage::Alignment, Duration::from_millis(1500));
+        profile.add(Stage::Alignment, Duration::from_millis(1500));
+        profile.add(Stage::Output, Duration::from_secs(1));
+        assert_eq!(time_stage(Some(&profile), Stage::Numbering, || 42), 42);
+        assert_eq!(time_stage(None, Stage::Numbering, || 42), 42);
+        assert_eq!(profile.duration(Stage::Alignment), Duration::from_secs(3));
+
+        let mut written = Vec::new();
+        profile.write(&mut written).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        let lines: Vec<_> = written.lines().collect();
+        assert_eq!(lines.len(), 1 + Stage::ALL.len());
+        assert_eq!(lines[0], "stage\tseconds\tshare");
+        assert_eq!(lines[1], "load_references\t0.000\t0.000");
+        assert_eq!(lines[2], "alignment\t3.000\t0.750");
+        assert!(lines[3].starts_with("numbering\t0.000\t"));
+        assert_eq!(lines[4], "output\t1.000\t0.250");
+    }
+
+    #[test]
+    fn test_slowest_sequences() {
+        let profile = Profile::with_slowest(2);
+        profile.add_sequence("first", Duration::from_millis(3));
+        profile.add_sequence("second", Duration::from_millis(1));
+        profile.add_sequence("third", Duration::from_millis(5));
+        profile.add_sequence("fourth", Duration::from_millis(2));
+        assert_eq!(
+            profile.slowest(),
+            [
+                ("third".to_string(), Duration::from_millis(5)),
+                ("first".to_string(), Duration::from_millis(3))
+            ]
+        );
+
+        let mut written = Vec::new();
+        profile.write_slowest(&mut written).unwrap();
+        assert_eq!(
+            String::from_utf8(written).unwrap(),
+            "sequence\tseconds\nthird\t0.005000\nfirst\t0.003000\n"
+        );
+
+        let without = Profile::default();
+        without.add_sequence("first", Duration::from_millis(3));
+        assert!(without.slowest().is_empty());
+        let mut written = Vec::new();
+        without.write_slowest(&mut written).unwrap();
+        assert!(written.is_empty());
+    }
+}
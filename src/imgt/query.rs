@@ -0,0 +1,123 @@
+use std::borrow::Cow;
+
+use bio::io::fasta;
+use thiserror::Error;
+
+/// Marker for an N-terminal pyroglutamate, as it is written in peptide sequences (such as `<EHP`).
+///
+/// Unlike the `Z` that is also used, it is not an IUPAC residue code (`Z` is
+/// glutamic acid or glutamine), so it cannot be mistaken for a residue.
+pub const DEFAULT_N_TERMINAL_MARKER: u8 = b'<';
+
+/// The standard amino acid a residue is aligned as.
+///
+/// Selenocysteine (U) and pyrrolysine (O) are aligned as the cysteine and lysine
+/// they are incorporated instead of, so a selenocysteine can be a conserved
+/// cysteine. Other residues are returned as they are.
+pub fn standard_residue(residue: u8) -> u8 {
+    match residue {
+        b'U' => b'C',
+        b'O' => b'K',
+        residue => residue,
+    }
+}
+
+/// A sequence to number, with the id used to identify it in the output.
+///
+/// Can be created from a [`fasta::Record`], so records read with `bio` can be
+/// passed directly to the numbering functions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Query {
+    record: fasta::Record,
+    n_terminal_marker: u8,
+}
+
+/// Error for sequences that cannot be made into a query.
+#[derive(Debug, Error)]
+pub enum QueryErr {
+    #[error("Sequence of query '{0}' is not valid UTF-8.")]
+    InvalidUtf8(String),
+}
+
+impl Query {
+    /// Create a query from an id and a sequence.
+    ///
+    /// Panics when the sequence is not valid UTF-8, use [`Self::try_new`] for untrusted input.
+    pub fn new(id: &str, seq: &[u8]) -> Self {
+        fasta::Record::with_attrs(id, None, seq).into()
+    }
+
+    /// Create a query from an id and a sequence, which may be any bytes.
+    pub fn try_new(id: &str, seq: &[u8]) -> Result<Self, QueryErr> {
+        std::str::from_utf8(seq).map_err(|_| QueryErr::InvalidUtf8(id.to_string()))?;
+        Ok(Self::new(id, seq))
+    }
+
+    /// Use a different marker for a modified (pyroglutamate) N-terminal residue.
+    pub fn with_n_terminal_marker(mut self, marker: u8) -> Self {
+        self.n_terminal_marker = marker;
+        self
+    }
+
+    pub fn id(&self) -> &str {
+        self.record.id()
+    }
+
+    pub fn seq(&self) -> &[u8] {
+        self.record.seq()
+    }
+
+    pub fn record(&self) -> &fasta::Record {
+        &self.record
+    }
+
+    /// Whether the first residue is modified, written as the N-terminal marker or in lowercase.
+    pub fn has_modified_n_terminus(&self) -> bool {
+        self.record
+            .seq()
+            .first()
+            .is_some_and(|&first| first == self.n_terminal_marker || first.is_ascii_lowercase())
+    }
+
+    /// The sequence as it is aligned to the reference sequences.
+    ///
+    /// A modified first residue, written as the N-terminal marker or in lowercase,
+    /// is aligned as the glutamine (or uppercase residue) it came from, and
+    /// non-standard residues as their [`standard_residue`]. The record itself is
+    /// left as is, so the output still shows the modified residues.
+    pub fn alignment_seq(&self) -> Cow<'_, [u8]> {
+        let seq = self.record.seq();
+        let modified_first = self.has_modified_n_terminus();
+        if !modified_first
+            && seq
+                .iter()
+                .all(|&residue| standard_residue(residue) == residue)
+        {
+            return Cow::Borrowed(seq);
+        }
+        let mut seq: Vec<u8> = seq.iter().copied().map(standard_residue).collect();
+        if modified_first {
+            seq[0] = if seq[0] == self.n_terminal_marker {
+                b'Q'
+            } else {
+                seq[0].to_ascii_uppercase()
+            };
+        }
+        Cow::Owned(seq)
+    }
+}
+
+impl From<fasta::Record> for Query {
+    fn from(record: fasta::Record) -> Self {
+        Self {
+            record,
+            n_terminal_marker: DEFAULT_N_TERMINAL_MARKER,
+        }
+    }
+}
+
+impl From<Query> for fasta::Record {
+    fn from(query: Query) -> Self {
+        query.record
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+
+use thiserror::Error;
+use tracing::warn;
+
+use super::ReferenceSequence;
+use crate::imgt::{RefSeqErr, ReferenceAlignment};
+
+/// The functionality of a germline gene, as IMGT assigns it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Functionality {
+    Functional,
+    /// Open reading frame, a gene that could be functional but is not known to be used.
+    Orf,
+    Pseudogene,
+}
+
+impl std::str::FromStr for Functionality {
+    type Err = ();
+
+    /// Parse `F`, `ORF` or `P`, also with the parentheses or brackets IMGT adds for
+    /// genes that are not in the genome assembly or have an unusual sequence.
+    fn from_str(functionality: &str) -> Result<Self, Self::Err> {
+        match functionality.trim_matches(['(', ')', '[', ']']) {
+            "F" => Ok(Functionality::Functional),
+            "ORF" => Ok(Functionality::Orf),
+            "P" => Ok(Functionality::Pseudogene),
+            _ => Err(()),
+        }
+    }
+}
+
+impl std::fmt::Display for Functionality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Functionality::Functional => "functional gene",
+            Functionality::Orf => "ORF",
+            Functionality::Pseudogene => "pseudogene",
+        })
+    }
+}
+
+/// Error thrown when parsing a gene functionality file.
+#[derive(Debug, Error)]
+pub enum FunctionalityErr {
+    #[error(
+        "Line {0} of the gene functionality file is not a gene followed by F, ORF or P: '{1}'"
+    )]
+    InvalidLine(usize, String),
+}
+
+/// What to do with a query assigned to a gene that is not functional, see [`GeneFunctionality::check`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NonFunctionalPolicy {
+    /// Number it, and log a warning.
+    #[default]
+    Warn,
+    /// Fail with [`RefSeqErr::NonFunctionalGene`].
+    Reject,
+}
+
+/// The functionality of germline genes, from a file that accompanies the references.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GeneFunctionality {
+    genes: HashMap<String, Functionality>,
+}
+
+impl GeneFunctionality {
+    /// Parse lines of a gene (such as `IGHV1-18*01` or `IGHV1-18`) followed by its
+    /// functionality (`F`, `ORF` or `P`).
+    ///
+    /// Empty lines and lines starting with `#` are skipped.
+    pub fn parse(data: &str) -> Result<Self, FunctionalityErr> {
+        let mut genes = HashMap::new();
+        for (i, line) in data.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || FunctionalityErr::InvalidLine(i + 1, line.to_string());
+            let mut fields = line.split_ascii_whitespace();
+            let (Some(gene), Some(functionality), None) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                return Err(invalid());
+            };
+            genes.insert(
+                gene.to_string(),
+                functionality.parse().map_err(|_| invalid())?,
+            );
+        }
+        Ok(Self { genes })
+    }
+
+    /// The functionality of a gene, or of the gene of an allele that is not listed itself.
+    pub fn get(&self, gene: &str) -> Option<Functionality> {
+        self.genes.get(gene).copied().or_else(|| {
+            let (gene, _allele) = gene.split_once('*')?;
+            self.genes.get(gene).copied()
+        })
+    }
+
+    /// The V and J genes of a reference that are listed as not functional.
+    pub fn nonfunctional_genes<'a>(
+        &self,
+        reference: &'a ReferenceSequence,
+    ) -> Vec<(&'a str, Functionality)> {
+        [reference.v_call(), reference.j_call()]
+            .into_iter()
+            .flatten()
+            .filter_map(|gene| Some((gene, self.get(gene)?)))
+            .filter(|(_gene, functionality)| *functionality != Functionality::Functional)
+            .collect()
+    }
+
+    /// Check that a query is not assigned to a reference with a gene that is not functional.
+    ///
+    /// Pseudogenes and ORFs are rarely expressed, so a query that aligns best to
+    /// one is more likely a sequencing artifact or missing from the references.
+    pub fn check(
+        &self,
+        reference_alignment: ReferenceAlignment,
+        policy: NonFunctionalPolicy,
+    ) -> Result<ReferenceAlignment, RefSeqErr> {
+        for (gene, functionality) in self.nonfunctional_genes(&reference_alignment.reference) {
+            match policy {
+                NonFunctionalPolicy::Warn => warn!(
+                    query = reference_alignment.query_record.id(),
+                    gene,
+                    functionality = functionality.to_string(),
+                    "Query is assigned to a gene that is not functional."
+                ),
+                NonFunctionalPolicy::Reject => {
+                    return Err(RefSeqErr::NonFunctionalGene(
+                        reference_alignment.query_record,
+                        gene.to_string(),
+                        functionality,
+                    ))
+                }
+            }
+        }
+        Ok(reference_alignment)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tracing_test::traced_test;
+
+    use super::*;
+    use crate::imgt::{
+        find_best_reference_sequence,
+        fixtures::{named_reference, ref_seqs, TEST_SEQUENCE},
+        query::Query,
+    };
+
+    #[test]
+    fn test_parse_gene_functionality() {
+        let functionality = GeneFunctionality::parse(
+            "# gene\tfunctionality\nIGHV1-18*01\tF\nIGHV1-2\t(ORF)\n\nIGHJ6*01 [P]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            functionality.get("IGHV1-18*01"),
+            Some(Functionality::Functional)
+        );
+        assert_eq!(functionality.get("IGHV1-2*04"), Some(Functionality::Orf));
+        assert_eq!(
+            functionality.get("IGHJ6*01"),
+            Some(Functionality::Pseudogene)
+        );
+        assert_eq!(functionality.get("IGHJ6*02"), None);
+        assert!(matches!(
+            GeneFunctionality::parse("IGHV1-18*01\tF\nIGHV1-2\n"),
+            Err(FunctionalityErr::InvalidLine(2, _))
+        ));
+        assert!(matches!(
+            GeneFunctionality::parse("IGHV1-18*01 functional"),
+            Err(FunctionalityErr::InvalidLine(1, _))
+        ));
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_pseudogene() {
+        let name = "Homo_sapiens_IGHV1-18*01_IGHJ6*01";
+        let ref_seqs = ref_seqs([named_reference(name)]);
+        let align = || {
+            find_best_reference_sequence(Query::new("query", TEST_SEQUENCE.as_bytes()), &ref_seqs)
+                .unwrap()
+        };
+
+        let functional = GeneFunctionality::parse("IGHV1-18\tF\n").unwrap();
+        assert!(functional
+            .check(align(), NonFunctionalPolicy::Reject)
+            .is_ok());
+        assert!(!logs_contain("not functional"));
+
+        let pseudogene = GeneFunctionality::parse("IGHV1-18*01\tP\n").unwrap();
+        assert!(pseudogene.check(align(), NonFunctionalPolicy::Warn).is_ok());
+        assert!(logs_contain(
+            "Query is assigned to a gene that is not functional."
+        ));
+        assert!(logs_contain("pseudogene"));
+        assert!(matches!(
+            pseudogene.check(align(), NonFunctionalPolicy::Reject),
+            Err(RefSeqErr::NonFunctionalGene(_, gene, Functionality::Pseudogene)) if gene == "IGHV1-18*01"
+        ));
+    }
+}
@@ -0,0 +1,219 @@
+use std::{
+    io::{Read, Write},
+    path::PathBuf,
+};
+
+use thiserror::Error;
+
+use super::{
+    source::{ReferenceSource, ReferenceSourceErr},
+    ReferenceIndex, ReferenceSequence,
+};
+use crate::imgt::conserved_residues::{ConservedResidueSpec, ConservedResidues};
+
+/// First bytes of a reference index file.
+const INDEX_MAGIC: &[u8; 8] = b"NUMIDX\0\0";
+
+/// Version of the reference index format, bumped on every change to it.
+pub const INDEX_VERSION: u32 = 2;
+
+/// Error thrown when reading a reference index.
+#[derive(Debug, Error)]
+pub enum IndexErr {
+    #[error("Could not read reference index: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Not a reference index.")]
+    NotAnIndex,
+
+    #[error("Reference index of version {0}, expected version {INDEX_VERSION}. Rebuild it with build-index.")]
+    UnsupportedVersion(u32),
+
+    #[error("Reference index has a name or alignment that is not valid UTF-8.")]
+    InvalidUtf8(#[from] std::string::FromUtf8Error),
+}
+
+fn write_u64<W: Write>(writer: &mut W, value: u64) -> std::io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> std::io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn write_bytes<W: Write>(writer: &mut W, value: &[u8]) -> std::io::Result<()> {
+    write_u64(writer, value.len() as u64)?;
+    writer.write_all(value)
+}
+
+fn write_str<W: Write>(writer: &mut W, value: &str) -> std::io::Result<()> {
+    write_bytes(writer, value.as_bytes())
+}
+
+fn read_bytes<R: Read>(reader: &mut R) -> std::io::Result<Vec<u8>> {
+    let length = read_u64(reader)?;
+    let mut bytes = Vec::new();
+    // Not allocated up front, so a corrupt length fails at the end of the file instead.
+    reader.by_ref().take(length).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != length {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+fn read_string<R: Read>(reader: &mut R) -> Result<String, IndexErr> {
+    Ok(String::from_utf8(read_bytes(reader)?)?)
+}
+
+/// Write references as a binary index, which loads without parsing and validating them.
+///
+/// The index starts with a magic number and [`INDEX_VERSION`], followed by the
+/// number of references and for every reference (ordered by name) its name, its
+/// IMGT-gapped alignment, the positions of its conserved residues and its
+/// [`ConservedResidueSpec`]: the residues allowed at each conserved position and
+/// the anchor window. Numbers are little endian `u64`, and strings are prefixed
+/// with their length.
+pub fn write_index<W: Write>(ref_seqs: &ReferenceIndex, mut writer: W) -> std::io::Result<()> {
+    writer.write_all(INDEX_MAGIC)?;
+    writer.write_all(&INDEX_VERSION.to_le_bytes())?;
+    write_u64(&mut writer, ref_seqs.len() as u64)?;
+
+    let mut references: Vec<_> = ref_seqs.values().collect();
+    references.sort_by(|a, b| a.name.cmp(&b.name));
+    for reference in references {
+        write_str(&mut writer, &reference.name)?;
+        write_str(&mut writer, &reference.alignment)?;
+        let conserved = &reference.conserved_residues;
+        for position in [
+            conserved.first_cys,
+            conserved.conserved_trp,
+            conserved.hydrophobic_89,
+            conserved.second_cys,
+            conserved.j_trp_or_phe,
+        ] {
+            write_u64(&mut writer, position as u64)?;
+        }
+        let spec = reference.get_spec();
+        for residues in [
+            &spec.first_cys,
+            &spec.conserved_trp,
+            &spec.hydrophobic_89,
+            &spec.second_cys,
+            &spec.j_trp_or_phe,
+        ] {
+            write_bytes(&mut writer, residues)?;
+        }
+        write_u64(&mut writer, spec.anchor_window as u64)?;
+    }
+    writer.flush()
+}
+
+/// Read references from a binary index, see [`write_index`].
+pub fn read_index<R: Read>(mut reader: R) -> Result<ReferenceIndex, IndexErr> {
+    let mut magic = [0; INDEX_MAGIC.len()];
+    reader.read_exact(&mut magic)?;
+    if &magic != INDEX_MAGIC {
+        return Err(IndexErr::NotAnIndex);
+    }
+    let mut version = [0; 4];
+    reader.read_exact(&mut version)?;
+    let version = u32::from_le_bytes(version);
+    if version != INDEX_VERSION {
+        return Err(IndexErr::UnsupportedVersion(version));
+    }
+
+    let n_references = read_u64(&mut reader)?;
+    let mut ref_seqs = ReferenceIndex::new();
+    for _ in 0..n_references {
+        let name = read_string(&mut reader)?;
+        let alignment = read_string(&mut reader)?;
+        let mut positions = [0; 5];
+        for position in &mut positions {
+            *position = read_u64(&mut reader)? as usize;
+        }
+        let [first_cys, conserved_trp, hydrophobic_89, second_cys, j_trp_or_phe] = positions;
+        // Fields are read in the order they are written.
+        let spec = ConservedResidueSpec {
+            first_cys: read_bytes(&mut reader)?,
+            conserved_trp: read_bytes(&mut reader)?,
+            hydrophobic_89: read_bytes(&mut reader)?,
+            second_cys: read_bytes(&mut reader)?,
+            j_trp_or_phe: read_bytes(&mut reader)?,
+            anchor_window: read_u64(&mut reader)? as usize,
+        };
+        ref_seqs.insert(
+            name.clone(),
+            ReferenceSequence {
+                alignment,
+                name,
+                conserved_residues: ConservedResidues {
+                    first_cys,
+                    conserved_trp,
+                    hydrophobic_89,
+                    second_cys,
+                    j_trp_or_phe,
+                    hydrophobic_89_shift: 0,
+                },
+                spec,
+            },
+        );
+    }
+    Ok(ref_seqs)
+}
+
+/// Reference sequences in a binary index file, see [`write_index`].
+#[derive(Clone, Debug)]
+pub struct IndexReferences {
+    pub path: PathBuf,
+}
+
+impl IndexReferences {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl ReferenceSource for IndexReferences {
+    fn load(&self) -> Result<ReferenceIndex, ReferenceSourceErr> {
+        let file = std::fs::File::open(&self.path)?;
+        let references = read_index(std::io::BufReader::new(file))?;
+        if references.is_empty() {
+            return Err(ReferenceSourceErr::NoReferences(
+                self.path.display().to_string(),
+            ));
+        }
+        Ok(references)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::imgt::reference::initialize_reference_sequences;
+
+    #[test]
+    fn test_index_round_trip() {
+        let ref_seqs = initialize_reference_sequences();
+        let mut index = Vec::new();
+        write_index(&ref_seqs, &mut index).unwrap();
+        assert_eq!(read_index(index.as_slice()).unwrap(), ref_seqs);
+
+        let mut outdated = index.clone();
+        outdated[INDEX_MAGIC.len()..INDEX_MAGIC.len() + 4]
+            .copy_from_slice(&(INDEX_VERSION + 1).to_le_bytes());
+        assert!(matches!(
+            read_index(outdated.as_slice()),
+            Err(IndexErr::UnsupportedVersion(version)) if version == INDEX_VERSION + 1
+        ));
+        assert!(matches!(
+            read_index(&b"test QVQLVQSGA"[..]),
+            Err(IndexErr::NotAnIndex)
+        ));
+        assert!(matches!(
+            read_index(&index[..index.len() - 1]),
+            Err(IndexErr::Io(_))
+        ));
+    }
+}
@@ -0,0 +1,146 @@
+use itertools::Itertools;
+
+use super::{
+    is_valid_alignment,
+    stockholm::{parse_stockholm, InsertStates},
+};
+
+/// The reference files produced from a Stockholm file of curated alignments.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InstalledReferences {
+    /// The ungapped reference sequences as FASTA.
+    pub fasta: String,
+    /// Lines of an id followed by its IMGT-gapped alignment, see [`super::parse_reference_alignments`].
+    pub alignments: String,
+    /// Number of alignment lines in the Stockholm file, valid or not.
+    pub n_lines: usize,
+    /// Number of alignments with the conserved residues, which are installed.
+    pub n_valid: usize,
+}
+
+/// Whether a line of a Stockholm file holds an alignment, rather than markup.
+pub fn is_alignment_line(line: &str) -> bool {
+    (!line.starts_with("//")) && (!line.starts_with('#')) && (!line.is_empty())
+}
+
+/// Whether an alignment has the conserved residues in the expected places.
+fn is_valid_alignment_str(alignment: &str) -> bool {
+    is_valid_alignment(alignment.as_bytes()).is_some()
+}
+
+/// Ids of the alignment lines of a Stockholm file that would be skipped when installing.
+pub fn invalid_alignment_ids(stockholm_data: &str) -> Vec<&str> {
+    invalid_alignment_ids_with(stockholm_data, InsertStates::Reference)
+}
+
+/// Like [`invalid_alignment_ids`], but recognise the insert columns as asked.
+pub fn invalid_alignment_ids_with(stockholm_data: &str, insert_states: InsertStates) -> Vec<&str> {
+    parse_stockholm(stockholm_data)
+        .match_state_alignments_with(insert_states)
+        .into_iter()
+        .filter(|(_id, alignment)| !is_valid_alignment_str(alignment))
+        .map(|(id, _alignment)| id)
+        .collect()
+}
+
+/// Convert a Stockholm file into the reference files, keeping only the valid alignments.
+///
+/// With a `#=GC RF` line the alignments are installed with only their match
+/// columns, see [`StockholmAlignment::match_state_alignments`](super::stockholm::StockholmAlignment::match_state_alignments).
+pub fn install_references(stockholm_data: &str) -> InstalledReferences {
+    install_references_with(stockholm_data, InsertStates::Reference)
+}
+
+/// Like [`install_references`], but recognise the insert columns as asked.
+///
+/// With [`InsertStates::Lowercase`] the insert columns of a file without a
+/// `#=GC RF` line are dropped as well, see
+/// [`StockholmAlignment::match_columns_with`](super::stockholm::StockholmAlignment::match_columns_with).
+pub fn install_references_with(
+    stockholm_data: &str,
+    insert_states: InsertStates,
+) -> InstalledReferences {
+    // TODO: Right now it uses the Anarci
+    // build_pipeline/curated_alignments/ALL.stockholm file.
+    // naturaly it should download this itself.
+    let alignments = parse_stockholm(stockholm_data).match_state_alignments_with(insert_states);
+    let n_lines = alignments.len();
+    // Identify valid sequences.
+    let valid: Vec<_> = alignments
+        .into_iter()
+        .filter(|(_id, alignment)| is_valid_alignment_str(alignment))
+        .collect();
+
+    let fasta = valid
+        .iter()
+        .map(|(id, alignment)| {
+            let sequence: String = alignment.chars().filter(|char| *char != '-').collect();
+            format!(">{}\n{}\n", id, sequence)
+        })
+        .collect();
+
+    let alignments = valid
+        .iter()
+        .map(|(id, alignment)| format!("{} {}", id, alignment))
+        .join("\n");
+
+    InstalledReferences {
+        fasta,
+        alignments,
+        n_lines,
+        n_valid: valid.len(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::imgt::fixtures::{TEST_ALIGNMENT_STR, TEST_SEQUENCE};
+
+    #[test]
+    fn test_install_skips_invalid_alignments() {
+        let stockholm = format!(
+            "# STOCKHOLM 1.0\n\ntest {TEST_ALIGNMENT_STR}\nbroken {}\n//\n",
+            &TEST_ALIGNMENT_STR[..100]
+        );
+        let installed = install_references(&stockholm);
+
+        assert_eq!(installed.n_lines, 2);
+        assert_eq!(installed.n_valid, 1);
+        assert_eq!(installed.fasta, format!(">test\n{TEST_SEQUENCE}\n"));
+        assert_eq!(installed.alignments, format!("test {TEST_ALIGNMENT_STR}"));
+        assert_eq!(invalid_alignment_ids(&stockholm), ["broken"]);
+    }
+
+    #[test]
+    fn test_install_match_columns() {
+        // An insert column after position 5, which would shift the conserved residues.
+        let with_insert = format!("{}.{}", &TEST_ALIGNMENT_STR[..5], &TEST_ALIGNMENT_STR[5..]);
+        let reference_annotation = format!("{}.{}", "x".repeat(5), "x".repeat(123));
+        let stockholm =
+            format!("# STOCKHOLM 1.0\n\ntest {with_insert}\n#=GC RF {reference_annotation}\n//\n");
+        assert!(is_valid_alignment(with_insert.as_bytes()).is_none());
+
+        let installed = install_references(&stockholm);
+        assert_eq!(installed.n_valid, 1);
+        assert_eq!(installed.alignments, format!("test {TEST_ALIGNMENT_STR}"));
+        assert!(invalid_alignment_ids(&stockholm).is_empty());
+    }
+
+    #[test]
+    fn test_install_lowercase_insert_states() {
+        // An insert residue after position 5 without a `#=GC RF` line.
+        let with_insert = format!("{}k{}", &TEST_ALIGNMENT_STR[..5], &TEST_ALIGNMENT_STR[5..]);
+        let other = format!("{}.{}", &TEST_ALIGNMENT_STR[..5], &TEST_ALIGNMENT_STR[5..]);
+        let stockholm = format!("# STOCKHOLM 1.0\n\ntest {with_insert}\nother {other}\n//\n");
+        assert_eq!(install_references(&stockholm).n_valid, 0);
+
+        let installed = install_references_with(&stockholm, InsertStates::Lowercase);
+        assert_eq!(installed.n_valid, 2);
+        assert_eq!(
+            installed.alignments,
+            format!("test {TEST_ALIGNMENT_STR}\nother {TEST_ALIGNMENT_STR}")
+        );
+        assert!(invalid_alignment_ids_with(&stockholm, InsertStates::Lowercase).is_empty());
+    }
+}
@@ -1,65 +1,170 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
-use bio::alignment::AlignmentOperation;
+use bio::{
+    alignment::{Alignment, AlignmentOperation},
+    io::fasta,
+};
 use itertools::Itertools;
+use rayon::prelude::*;
+use tracing::warn;
 
-use super::{annotations::VRegionAnnotation, conserved_residues::ConservedResidues, IMGTError};
+use self::stockholm::parse_stockholm;
+use super::{
+    annotations::{Annotation, VRegionAnnotation},
+    conserved_residues::{
+        count_gaps_in_sequence_before_index, ConservedResidueSpec, ConservedResidues,
+    },
+    input::is_gap,
+    numbering::{check_unique_positions, NumberingScheme},
+    IMGTError, NumberingError, ReferenceAlignment,
+};
 use crate::imgt;
 
-pub fn is_valid_alignment(alignment: &[u8]) -> Option<ConservedResidues> {
-    let (&aa_23, &aa_41, &aa_89, &aa_104, &aa_118) = match alignment
-        .into_iter()
-        .enumerate()
-        .filter_map(|(position, char)| {
-            [23, 41, 89, 104, 118]
-                .contains(&(position + 1))
-                .then(|| char)
-        })
-        .collect_tuple()
-    {
-        Some(tup) => tup,
-        None => return None,
-    };
+pub mod functionality;
+pub mod index;
+pub mod install;
+pub mod source;
+pub mod stockholm;
 
-    if aa_23 == b'C'
-        && aa_41 == b'W'
-        && aa_104 == b'C'
-        && [b'F', b'W'].contains(&aa_118)
-        && [b'A', b'I', b'L', b'M', b'F', b'W', b'Y', b'V'].contains(&aa_89)
-    {
-        Some(ConservedResidues::from(alignment))
-    } else {
-        None
-    }
+/// Reference sequences by their name.
+pub type ReferenceIndex = HashMap<String, ReferenceSequence>;
+
+/// J genes by their name, such as `Homo_sapiens_IGHJ2*01`, with their columns of
+/// the IMGT alignment from [`STITCH_POSITION`] to the end of FR4, see [`stitch_references`].
+pub type JGeneIndex = HashMap<String, Vec<u8>>;
+
+const EMBEDDED_STOCKHOLM: &str = include_str!("reference.stockholm");
+const EMBEDDED_BLACKLIST: &str = include_str!("blacklist.txt");
+
+/// Find the conserved residues of an alignment with the IMGT conserved residues, see [`ConservedResidueSpec`].
+pub fn is_valid_alignment(alignment: &[u8]) -> Option<ConservedResidues> {
+    ConservedResidueSpec::default().validate(alignment)
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct ReferenceSequence {
     alignment: String,
     pub name: String,
     conserved_residues: ConservedResidues,
+    spec: ConservedResidueSpec,
 }
 
 impl ReferenceSequence {
     pub fn new(name: &str, alignment: &[u8]) -> Result<Self, IMGTError> {
+        Self::with_spec(name, alignment, &ConservedResidueSpec::default())
+    }
+
+    /// Like [`Self::new`], but check the conserved residues against a custom spec.
+    ///
+    /// Anchors that the spec finds off their IMGT column are moved onto it (see
+    /// [`ConservedResidueSpec::align_anchors`]), so they are numbered by their IMGT
+    /// position. An alignment of which an anchor cannot be moved is not a reference.
+    /// An alignment of only gaps is never a reference, even for a spec that allows
+    /// gaps at the conserved positions: its [`Self::get_sequence`] is empty, so it
+    /// cannot be annotated.
+    pub fn with_spec(
+        name: &str,
+        alignment: &[u8],
+        spec: &ConservedResidueSpec,
+    ) -> Result<Self, IMGTError> {
+        if alignment.iter().all(|residue| is_gap(*residue)) {
+            return Err(IMGTError::InvalidAlignment);
+        }
+        let conserved_residues = spec
+            .validate(alignment)
+            .ok_or(IMGTError::InvalidAlignment)?;
+        let alignment = spec
+            .align_anchors(alignment)
+            .ok_or(IMGTError::InvalidAlignment)?;
         Ok(Self {
-            alignment: std::str::from_utf8(alignment)
-                .map_err(|e| IMGTError::from(e))?
+            alignment: std::str::from_utf8(&alignment)
+                .map_err(IMGTError::from)?
                 .to_string(),
             name: name.to_string(),
-            conserved_residues: is_valid_alignment(alignment).ok_or(IMGTError::InvalidAlignment)?,
+            conserved_residues,
+            spec: spec.clone(),
         })
     }
 
+    /// Build a reference from an ungapped sequence, such as a germline from a FASTA file.
+    ///
+    /// The sequence is numbered with the references of `frame` (usually the embedded
+    /// ones, see [`initialize_reference_sequences`]) and every residue is put in the
+    /// column of its IMGT position. Residues that are not numbered are left out, and
+    /// a sequence with an insertion (such as `111A`) cannot be a reference, since
+    /// the alignment of a reference has a column per IMGT position.
+    pub fn from_record(
+        record: &fasta::Record,
+        frame: &ReferenceIndex,
+    ) -> Result<Self, NumberingError> {
+        let annotations = imgt::try_number(record.clone(), frame, &NumberingScheme::default())?;
+        let mut alignment = vec![b'-'; imgt::FR4_END];
+        for annotation in annotations {
+            let column = annotation
+                .name
+                .parse::<usize>()
+                .ok()
+                .filter(|position| (1..=imgt::FR4_END).contains(position))
+                .ok_or(IMGTError::InsertionInReference(annotation.name))?;
+            alignment[column - 1] = record.seq()[annotation.start];
+        }
+        Ok(Self::new(record.id(), &alignment)?)
+    }
+
+    /// The V gene of the reference, such as `IGHV1-18*01` for `Homo_sapiens_IGHV1-18*01_IGHJ6*01`
+    /// or for `Homo_sapiens_IGHV1-18*01`.
+    pub fn v_call(&self) -> Option<&str> {
+        self.name
+            .rsplit('_')
+            .nth(self.j_call().map_or(0, |_j_call| 1))
+            .filter(|gene| gene.get(3..4) == Some("V"))
+    }
+
+    /// The J gene of the reference, such as `IGHJ6*01` for `Homo_sapiens_IGHV1-18*01_IGHJ6*01`.
+    pub fn j_call(&self) -> Option<&str> {
+        self.name
+            .rsplit('_')
+            .next()
+            .filter(|gene| gene.get(3..4) == Some("J"))
+    }
+
+    /// The locus (chain type) of the reference, such as `IGH` for `Homo_sapiens_IGHV1-18*01_IGHJ6*01`.
+    pub fn locus(&self) -> Option<&str> {
+        self.v_call().map(|v_call| &v_call[..3])
+    }
+
+    /// The family of the V gene of the reference, such as `IGHV1` for `Homo_sapiens_IGHV1-18*01_IGHJ6*01`.
+    ///
+    /// See [`gene_family`].
+    pub fn gene_family(&self) -> Option<&str> {
+        self.v_call().and_then(gene_family)
+    }
+
+    /// The species (and strain) of the reference, such as `Homo_sapiens` for `Homo_sapiens_IGHV1-18*01_IGHJ6*01`.
+    ///
+    /// Only for references named after their V gene, with or without their J gene.
+    pub fn species(&self) -> Option<&str> {
+        self.v_call()?;
+        let genes = self.j_call().map_or(1, |_j_call| 2);
+        self.name.rsplitn(genes + 1, '_').nth(genes)
+    }
+
     pub fn get_conserved_residues(&self) -> &ConservedResidues {
         &self.conserved_residues
     }
 
+    /// The spec that the conserved residues were checked with, see [`Self::with_spec`].
+    ///
+    /// The residues of the spec are also the ones expected in queries, see [`ConservedResidues::transfer`].
+    pub fn get_spec(&self) -> &ConservedResidueSpec {
+        &self.spec
+    }
+
     pub fn get_sequence(&self) -> Vec<u8> {
         self.alignment
             .as_bytes()
-            .into_iter()
-            .map(|&b| b)
+            .iter()
+            .copied()
             .filter(|c| *c != b'-')
             .collect()
     }
@@ -72,67 +177,368 @@ impl ReferenceSequence {
             imgt::Framework::FR4 => imgt::FR4,
         };
 
-        self.alignment[(range.start - 1)..range.end]
-            .chars()
-            .zip(range)
-            .flat_map(|(c, pos)| (c == '-').then_some(pos))
+        range
+            .filter(|pos| self.get_sequence_position(*pos).is_none())
             .collect()
     }
 
+    /// The IMGT positions of all frameworks that the reference lacks, in order.
+    ///
+    /// Queries assigned to the reference are not numbered at these positions either.
+    /// Gaps in the CDRs are left out, as they follow from the length of the CDR.
+    pub fn missing_positions(&self) -> Vec<usize> {
+        [
+            imgt::Framework::FR1,
+            imgt::Framework::FR2,
+            imgt::Framework::FR3,
+            imgt::Framework::FR4,
+        ]
+        .iter()
+        .flat_map(|framework| self.get_missing_positions_in_framework(framework))
+        .collect()
+    }
+
+    /// Find the (one based) position in the reference sequence of an IMGT position.
+    ///
+    /// Returns `None` if the reference has a gap at this IMGT position.
+    pub fn get_sequence_position(&self, imgt_position: usize) -> Option<usize> {
+        let alignment = self.get_alignment();
+        (*alignment.get(imgt_position.checked_sub(1)?)? != b'-')
+            .then(|| imgt_position - count_gaps_in_sequence_before_index(alignment, imgt_position))
+    }
+
+    /// Find the (one based) column of the alignment that holds a (one based) position of the reference sequence.
+    ///
+    /// The inverse of [`Self::get_sequence_position`].
+    pub fn get_alignment_column(&self, sequence_position: usize) -> Option<usize> {
+        self.get_alignment()
+            .iter()
+            .enumerate()
+            .filter(|(_column, residue)| **residue != b'-')
+            .nth(sequence_position.checked_sub(1)?)
+            .map(|(column, _residue)| column + 1)
+    }
+
+    /// Count the residues of the reference in a range of IMGT positions.
+    pub fn count_residues_in_range(&self, range: std::ops::Range<usize>) -> usize {
+        range
+            .filter(|pos| self.get_sequence_position(*pos).is_some())
+            .count()
+    }
+
     pub fn get_alignment(&self) -> &[u8] {
         self.alignment.as_bytes()
     }
 
-    pub fn get_vregion_annotation(&self) -> VRegionAnnotation {
+    /// The alignment of the reference with itself, as if it were a query.
+    fn self_alignment(&self) -> Alignment {
         let length = self.get_sequence().len();
+        Alignment {
+            score: 0,
+            ystart: 0,
+            xstart: 0,
+            yend: length,
+            xend: length,
+            ylen: length,
+            xlen: length,
+            operations: vec![AlignmentOperation::Match; length],
+            mode: bio::alignment::AlignmentMode::Local,
+        }
+    }
+
+    pub fn get_vregion_annotation(&self) -> VRegionAnnotation {
+        VRegionAnnotation::try_from(&self.conserved_residues, &self.self_alignment(), self)
+            .expect("Should always be able to annotate reference sequences.")
+    }
 
-        VRegionAnnotation::try_from(
+    /// Number the reference as if it were a query aligned to itself.
+    ///
+    /// Fails for references that cannot number any query, see [`check_reference_set`].
+    pub fn number_self(&self) -> Result<Vec<Annotation>, IMGTError> {
+        let reference_alignment = ReferenceAlignment {
+            reference: self.clone(),
+            query_record: fasta::Record::with_attrs(&self.name, None, &self.get_sequence()),
+            alignment: self.self_alignment(),
+            modified_n_terminus: false,
+        };
+        let numbering = VRegionAnnotation::try_from(
             &self.conserved_residues,
-            &bio::alignment::Alignment {
-                score: 0,
-                ystart: 0,
-                xstart: 0,
-                yend: length,
-                xend: length,
-                ylen: length,
-                xlen: length,
-                operations: self
-                    .get_sequence()
-                    .into_iter()
-                    .map(|_| AlignmentOperation::Match)
-                    .collect(),
-                mode: bio::alignment::AlignmentMode::Local,
-            },
-        )
-        .expect("Should always be able to annotate reference sequences.")
+            &reference_alignment.alignment,
+            self,
+        )?
+        .number_regions(&reference_alignment, &NumberingScheme::default())?;
+        check_unique_positions(&numbering)?;
+        Ok(numbering)
     }
 }
 
-/// Load the precomputed and curated reference sequences.
-pub fn initialize_reference_sequences() -> HashMap<&'static str, ReferenceSequence> {
-    // TODO: Write a proper stockholm reader.
-    let stockholm_data = include_str!("reference.stockholm");
-    let blacklist: Vec<_> = include_str!("blacklist.txt")
-        .split_ascii_whitespace()
+/// Number every reference against itself and return the ones that fail, by name.
+///
+/// Custom references (see [`source::ReferenceSource`]) only need the conserved
+/// residues to load, so this checks that they can be used for numbering as well.
+pub fn check_reference_set(ref_seqs: &ReferenceIndex) -> Vec<(String, IMGTError)> {
+    let mut failures: Vec<_> = ref_seqs
+        .par_iter()
+        .filter_map(|(name, ref_seq)| {
+            ref_seq
+                .number_self()
+                .err()
+                .map(|error| (name.clone(), error))
+        })
         .collect();
+    failures.sort_by(|a, b| a.0.cmp(&b.0));
+    failures
+}
 
-    stockholm_data
-        .split_ascii_whitespace()
-        .tuples()
-        .filter_map(|(id, alignment)| {
-            Some((id, ReferenceSequence::new(id, alignment.as_bytes()).ok()?))
+/// The family of a gene: its locus and segment followed by the family number.
+///
+/// For example `IGHV1` for `IGHV1-2*02`, `IGKV1` for `IGKV1D-12*01` and `TRAV30`
+/// for `TRAV30*01`. `None` for names that do not start like that.
+pub fn gene_family(gene: &str) -> Option<&str> {
+    let prefix = gene.get(..4)?;
+    if !prefix.bytes().all(|byte| byte.is_ascii_uppercase()) {
+        return None;
+    }
+    let digits = gene[4..].bytes().take_while(u8::is_ascii_digit).count();
+    (digits > 0).then(|| &gene[..4 + digits])
+}
+
+/// First IMGT position of a stitched reference that is taken from its J gene, see [`stitch_vj`].
+///
+/// The V gene makes up the start of the CDR3 and the J gene its end, which
+/// meet at the insertions of a long CDR3 (111 and 112).
+pub const STITCH_POSITION: usize = 111;
+
+/// Stitch the V gene of a reference to a J gene, such as `IGHJ2*01`.
+///
+/// The alignment up to the middle of the CDR3 is taken from `v`, the end of the
+/// CDR3 and FR4 from `j` (its columns from [`STITCH_POSITION`], see [`JGeneIndex`]),
+/// so FR4 is the actual J gene. The result is named like the embedded references,
+/// such as `Homo_sapiens_IGHV1-18*01_IGHJ2*01`, and checked with the spec of `v`.
+/// `None` when the stitched alignment lacks the conserved residues.
+pub fn stitch_vj(v: &ReferenceSequence, j_call: &str, j: &[u8]) -> Option<ReferenceSequence> {
+    let v_name = match v.j_call() {
+        Some(v_j_call) => v.name.strip_suffix(v_j_call)?.to_string(),
+        None => format!("{}_", v.name),
+    };
+    let name = format!("{}{}", v_name, j_call);
+    let alignment = [v.get_alignment().get(..STITCH_POSITION - 1)?, j].concat();
+    ReferenceSequence::with_spec(&name, &alignment, v.get_spec()).ok()
+}
+
+/// The J genes of the references that pair a V with a J gene, such as
+/// `Homo_sapiens_IGHJ6*01` of `Homo_sapiens_IGHV1-18*01_IGHJ6*01`.
+///
+/// The first reference of a J gene by name provides its columns, so the result
+/// does not depend on the order of the index.
+pub fn j_genes(ref_seqs: &ReferenceIndex) -> JGeneIndex {
+    let mut j_genes = JGeneIndex::new();
+    for reference in ref_seqs.values().sorted_by(|a, b| a.name.cmp(&b.name)) {
+        let (Some(species), Some(j_call), Some(j)) = (
+            reference.species(),
+            reference.j_call(),
+            reference.get_alignment().get(STITCH_POSITION - 1..),
+        ) else {
+            continue;
+        };
+        j_genes
+            .entry(format!("{}_{}", species, j_call))
+            .or_insert_with(|| j.to_vec());
+    }
+    j_genes
+}
+
+/// Parse J genes from lines of an id (such as `Homo_sapiens_IGHJ2*01`) followed by
+/// its IMGT-gapped alignment from [`STITCH_POSITION`] to the end of FR4, see [`JGeneIndex`].
+///
+/// As for [`parse_reference_alignments`], only the match columns are taken with
+/// a `#=GC RF` line. Alignments of another length are skipped.
+pub fn parse_j_gene_alignments(stockholm_data: &str) -> JGeneIndex {
+    parse_stockholm(stockholm_data)
+        .match_state_alignments()
+        .into_iter()
+        .filter(|(_id, alignment)| alignment.len() == imgt::FR4_END + 1 - STITCH_POSITION)
+        .map(|(id, alignment)| (id.to_string(), alignment.as_bytes().to_vec()))
+        .collect()
+}
+
+/// Add a reference for every pairing of a V gene with a J gene of the same species and locus.
+///
+/// The V genes are taken from `v_refs`, which can pair them with a J gene or not,
+/// and the J genes from `j_genes`, such as those of [`j_genes`]. References that
+/// already have a pairing are kept as they are, so this only adds the missing
+/// pairings. The genes are taken from the names, see [`ReferenceSequence::species`].
+pub fn stitch_references(v_refs: &ReferenceIndex, j_genes: &JGeneIndex) -> ReferenceIndex {
+    let mut named: Vec<_> = v_refs
+        .values()
+        .filter_map(|reference| Some((reference.species()?, reference.v_call()?, reference)))
+        .collect();
+    // The first reference of a gene provides its sequence, so sort to not depend on the order of the index.
+    named.sort_by(|a, b| a.2.name.cmp(&b.2.name));
+
+    let mut stitched = v_refs.clone();
+    for (v_species, v_call, v) in named
+        .iter()
+        .unique_by(|(species, v_call, _)| (*species, *v_call))
+    {
+        for (j_name, j) in j_genes {
+            let Some((j_species, j_call)) = j_name.rsplit_once('_') else {
+                continue;
+            };
+            if j_species != *v_species || j_call.get(..3) != v_call.get(..3) {
+                continue;
+            }
+            let name = format!("{}_{}_{}", v_species, v_call, j_call);
+            if stitched.contains_key(&name) {
+                continue;
+            }
+            if let Some(reference) = stitch_vj(v, j_call, j) {
+                stitched.insert(name, reference);
+            }
+        }
+    }
+    stitched
+}
+
+/// Name of the reference built by [`build_consensus_reference`].
+pub const CONSENSUS_REFERENCE_NAME: &str = "consensus";
+
+/// Build a single reference with the most frequent residue (or gap) of every
+/// column of the alignments of the references.
+///
+/// Aligning a query to only this reference, instead of to every reference to find
+/// the best one, is much faster for large reference sets but less accurate. The
+/// consensus has the most common CDR lengths and framework residues, so queries
+/// align to it with lower scores and more gaps, which can move the boundaries of
+/// the CDRs. It follows the chain type with the most references (the T cell
+/// receptor alpha chains of the embedded references), to which other chain types
+/// may not align at all, so it works best for references of a single chain type.
+/// It has no V or J gene either, so the germline of a query is not known. Ties go to the first residue in byte order (where a gap
+/// precedes every residue), so the consensus does not depend on the order of the index.
+///
+/// The consensus is checked with the spec of the references (of the first by name,
+/// as a set of references is loaded with a single spec). Fails with
+/// [`IMGTError::InvalidAlignment`] when the consensus lacks a conserved residue,
+/// such as for an empty set of references.
+pub fn build_consensus_reference(
+    ref_seqs: &ReferenceIndex,
+) -> Result<ReferenceSequence, IMGTError> {
+    let columns = ref_seqs
+        .values()
+        .map(|reference| reference.get_alignment().len())
+        .max()
+        .unwrap_or_default();
+    let mut counts = vec![BTreeMap::<u8, usize>::new(); columns];
+    for reference in ref_seqs.values() {
+        for (column, column_counts) in counts.iter_mut().enumerate() {
+            let residue = reference
+                .get_alignment()
+                .get(column)
+                .copied()
+                .unwrap_or(b'-');
+            *column_counts.entry(residue).or_default() += 1;
+        }
+    }
+    let alignment: Vec<u8> = counts
+        .into_iter()
+        .map(|column_counts| {
+            column_counts
+                .into_iter()
+                // On equal counts `max_by_key` takes the last, so reverse to take the first residue.
+                .rev()
+                .max_by_key(|(_residue, count)| *count)
+                .map(|(residue, _count)| residue)
+                .expect("Every column has a residue or gap of every reference.")
         })
+        .collect();
+    let spec = ref_seqs
+        .values()
+        .min_by_key(|reference| &reference.name)
+        .map(|reference| reference.get_spec().clone())
+        .unwrap_or_default();
+    ReferenceSequence::with_spec(CONSENSUS_REFERENCE_NAME, &alignment, &spec)
+}
+
+/// Species the embedded reference sequences are restricted to by the `species-*` features.
+///
+/// When no such feature is enabled this is empty and all species are used.
+const SPECIES: &[&str] = &[
+    #[cfg(feature = "species-human")]
+    "Homo_sapiens",
+    #[cfg(feature = "species-mouse")]
+    "Mus_musculus",
+];
+
+/// Whether a reference id is of one of the selected species.
+fn is_selected_species(id: &str) -> bool {
+    SPECIES.is_empty() || SPECIES.iter().any(|species| id.starts_with(species))
+}
+
+/// Load the precomputed and curated reference sequences.
+pub fn initialize_reference_sequences() -> ReferenceIndex {
+    let blacklist: Vec<_> = EMBEDDED_BLACKLIST.split_ascii_whitespace().collect();
+    parse_reference_alignments(EMBEDDED_STOCKHOLM, &blacklist)
+        .into_iter()
+        .filter(|(id, _)| is_selected_species(id))
+        .collect()
+}
+
+/// Ids that occur more than once in reference alignments, see [`parse_reference_alignments`].
+pub fn duplicate_reference_ids(stockholm_data: &str) -> Vec<&str> {
+    parse_stockholm(stockholm_data)
+        .alignments
+        .into_iter()
+        .map(|(id, _alignment)| id)
+        .duplicates()
+        .collect()
+}
+
+/// Parse reference alignments from lines of an id followed by its IMGT-gapped alignment.
+///
+/// With a `#=GC RF` line only the match columns are taken as IMGT positions, see
+/// [`StockholmAlignment::match_state_alignments`](stockholm::StockholmAlignment::match_state_alignments).
+/// Alignments without the conserved residues and ids on the blacklist are skipped.
+/// When an id occurs more than once, a warning is logged and the last valid
+/// alignment with that id is used.
+pub fn parse_reference_alignments(stockholm_data: &str, blacklist: &[&str]) -> ReferenceIndex {
+    parse_reference_alignments_with_spec(stockholm_data, blacklist, &Default::default())
+}
+
+/// Like [`parse_reference_alignments`], but check the conserved residues against a custom spec.
+pub fn parse_reference_alignments_with_spec(
+    stockholm_data: &str,
+    blacklist: &[&str],
+    spec: &ConservedResidueSpec,
+) -> ReferenceIndex {
+    let duplicates = duplicate_reference_ids(stockholm_data);
+    if !duplicates.is_empty() {
+        warn!(
+            ids = duplicates.join(","),
+            "Duplicate reference ids, only the last alignment of each is used."
+        );
+    }
+    parse_stockholm(stockholm_data)
+        .match_state_alignments()
+        .into_iter()
         .filter(|(id, _)| !blacklist.contains(id))
+        .filter_map(|(id, alignment)| {
+            Some((
+                id.to_string(),
+                ReferenceSequence::with_spec(id, alignment.as_bytes(), spec).ok()?,
+            ))
+        })
         .collect()
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use rayon::prelude::*;
     use tracing::trace;
     use tracing_test::traced_test;
-    const TEST_ALIGNMENT_STR: &str = "QVQLVQSGA-EVKKPGASVKVSCKASGYTF----TSYGISWVRQAPGQGLEWMGWISAY--NGNTNYAQKLQ-GRVTMTTDTSTSTAYMELRSLRSDDTAVYYCAR--------MDVWGQGTTVTVSS";
+
+    use crate::imgt::fixtures::{
+        named_reference, test_reference, TEST_ALIGNMENT_STR, TEST_SEQUENCE,
+    };
 
     #[test]
     #[traced_test]
@@ -140,6 +546,88 @@ mod test {
         assert!(is_valid_alignment(TEST_ALIGNMENT_STR.as_bytes()).is_some())
     }
 
+    #[test]
+    fn test_reference_of_only_gaps() {
+        let gaps = "-".repeat(imgt::FR4_END);
+        assert!(matches!(
+            ReferenceSequence::new("gaps", gaps.as_bytes()),
+            Err(IMGTError::InvalidAlignment)
+        ));
+        // Also when the spec would accept gaps as the conserved residues.
+        let any_residue = ConservedResidueSpec {
+            first_cys: b"C-".to_vec(),
+            conserved_trp: b"W-".to_vec(),
+            hydrophobic_89: b"AILMFWYV-".to_vec(),
+            second_cys: b"C-".to_vec(),
+            j_trp_or_phe: b"FW-".to_vec(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            ReferenceSequence::with_spec("gaps", gaps.as_bytes(), &any_residue),
+            Err(IMGTError::InvalidAlignment)
+        ));
+        assert!(matches!(
+            ReferenceSequence::with_spec(
+                "dots",
+                ".".repeat(imgt::FR4_END).as_bytes(),
+                &any_residue
+            ),
+            Err(IMGTError::InvalidAlignment)
+        ));
+        assert!(
+            ReferenceSequence::with_spec("test", TEST_ALIGNMENT_STR.as_bytes(), &any_residue)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_build_consensus_reference() {
+        // Two of the three references have the residues of the test alignment.
+        let mutated = TEST_ALIGNMENT_STR
+            .replacen("QVQLV", "EVQLV", 1)
+            .replace("GYTF----TSYG", "GYTFS---TSYG");
+        let ref_seqs = parse_reference_alignments(
+            &format!(
+                "first {TEST_ALIGNMENT_STR}
+second {TEST_ALIGNMENT_STR}
+third {mutated}
+"
+            ),
+            &[],
+        );
+        assert_eq!(ref_seqs.len(), 3);
+        let consensus = build_consensus_reference(&ref_seqs).unwrap();
+        assert_eq!(consensus.name, CONSENSUS_REFERENCE_NAME);
+        assert_eq!(consensus.get_alignment(), TEST_ALIGNMENT_STR.as_bytes());
+        assert!(matches!(
+            build_consensus_reference(&ReferenceIndex::new()),
+            Err(IMGTError::InvalidAlignment)
+        ));
+
+        // The consensus of the heavy chains still finds the CDR3 of the test sequence.
+        let heavy_chains: ReferenceIndex = initialize_reference_sequences()
+            .into_iter()
+            .filter(|(_name, reference)| reference.locus() == Some("IGH"))
+            .collect();
+        let consensus = build_consensus_reference(&heavy_chains).unwrap();
+        let universal = ReferenceIndex::from([(consensus.name.clone(), consensus)]);
+        let numbering = imgt::try_number(
+            imgt::query::Query::new("query", TEST_SEQUENCE.as_bytes()),
+            &universal,
+            &Default::default(),
+        )
+        .unwrap();
+        let cdr3: String = numbering
+            .iter()
+            .filter(|annotation| {
+                imgt::numbering::ImgtRegion::of_position(&annotation.name)
+                    == Some(imgt::numbering::ImgtRegion::Cdr3)
+            })
+            .map(|annotation| TEST_SEQUENCE.as_bytes()[annotation.start] as char)
+            .collect();
+        assert_eq!(cdr3, "ARMDV");
+    }
+
     #[test]
     fn test_validity_of_references() {
         let ref_seqs = initialize_reference_sequences();
@@ -148,6 +636,71 @@ mod test {
             .for_each(|rec| assert!(is_valid_alignment(rec.get_alignment()).is_some()))
     }
 
+    #[test]
+    fn test_species_selection() {
+        let ref_seqs = initialize_reference_sequences();
+        assert!(!ref_seqs.is_empty());
+        assert!(ref_seqs.keys().all(|id| is_selected_species(id)));
+        if SPECIES.is_empty() {
+            assert!(ref_seqs.keys().any(|id| id.starts_with("Homo_sapiens")));
+            assert!(ref_seqs.keys().any(|id| id.starts_with("Mus_musculus")));
+        }
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_duplicate_reference_ids() {
+        let stockholm = format!(
+            "first {TEST_ALIGNMENT_STR}\nduplicate {TEST_ALIGNMENT_STR}\nduplicate {TEST_ALIGNMENT_STR}\n"
+        );
+        assert_eq!(duplicate_reference_ids(&stockholm), ["duplicate"]);
+
+        let ref_seqs = parse_reference_alignments(&stockholm, &[]);
+        assert_eq!(ref_seqs.len(), 2);
+        assert!(logs_contain("Duplicate reference ids"));
+        assert!(logs_contain("duplicate"));
+    }
+
+    #[test]
+    fn test_gene_calls() {
+        let ref_seq = named_reference("Mus_musculus_C57BL/6_IGKV4-55*01_IGKJ2*01");
+        assert_eq!(ref_seq.v_call(), Some("IGKV4-55*01"));
+        assert_eq!(ref_seq.j_call(), Some("IGKJ2*01"));
+
+        let v_only = named_reference("Homo_sapiens_IGHV1-18*01");
+        assert_eq!(
+            (v_only.species(), v_only.v_call(), v_only.j_call()),
+            (Some("Homo_sapiens"), Some("IGHV1-18*01"), None)
+        );
+
+        let unnamed = test_reference();
+        assert_eq!((unnamed.v_call(), unnamed.j_call()), (None, None));
+    }
+
+    #[test]
+    fn test_reference_from_record() {
+        let reference = test_reference();
+        let frame = ReferenceIndex::from([("test".to_string(), reference)]);
+        let record = fasta::Record::with_attrs("germline", None, TEST_SEQUENCE.as_bytes());
+        let from_record = ReferenceSequence::from_record(&record, &frame).unwrap();
+        assert_eq!(from_record.name, "germline");
+        assert_eq!(from_record.get_sequence(), TEST_SEQUENCE.as_bytes());
+        assert_eq!(from_record.missing_positions(), [10, 73]);
+        assert_eq!(
+            from_record.number_self().unwrap(),
+            frame["test"].number_self().unwrap()
+        );
+
+        let long_cdr3 = TEST_SEQUENCE.replace("ARMDV", "ARGGYYYYYYYMDV");
+        let record = fasta::Record::with_attrs("long", None, long_cdr3.as_bytes());
+        assert!(matches!(
+            ReferenceSequence::from_record(&record, &frame),
+            Err(NumberingError::Annotation(IMGTError::InsertionInReference(
+                _
+            )))
+        ));
+    }
+
     #[test]
     fn test_new_reference_sequence() {
         let ref_seq_res = ReferenceSequence::new("test", TEST_ALIGNMENT_STR.as_bytes());
@@ -169,9 +722,266 @@ mod test {
             });
     }
 
+    /// Germlines of which the CDR3, without a D gene, is shorter than the shortest CDR3-IMGT.
+    const SHORT_CDR3_REFERENCES: [&str; 4] = [
+        "Mus_musculus_C57BL/6_IGHV6-3*01_IGHJ1*01",
+        "Mus_musculus_C57BL/6_IGHV6-3*01_IGHJ2*01",
+        "Mus_musculus_C57BL/6_IGHV6-3*01_IGHJ3*01",
+        "Mus_musculus_C57BL/6_IGHV6-3*01_IGHJ4*01",
+    ];
+
+    #[test]
+    fn test_check_reference_set() {
+        let failures = check_reference_set(&initialize_reference_sequences());
+        assert!(failures
+            .iter()
+            .all(|(_name, error)| matches!(error, IMGTError::CDR3TooShort(4))));
+        assert_eq!(
+            failures
+                .iter()
+                .map(|(name, _error)| name)
+                .collect::<Vec<_>>(),
+            SHORT_CDR3_REFERENCES
+                .iter()
+                .filter(|name| is_selected_species(name))
+                .collect::<Vec<_>>()
+        );
+
+        // Has the conserved residues, but a CDR3 of only two residues.
+        let short_cdr3 = TEST_ALIGNMENT_STR.replace("CAR--------MDVW", "CA-----------VW");
+        let ref_seqs = parse_reference_alignments(
+            &format!(
+                "test {TEST_ALIGNMENT_STR}
+short {short_cdr3}
+"
+            ),
+            &[],
+        );
+        assert_eq!(ref_seqs.len(), 2);
+        let failures = check_reference_set(&ref_seqs);
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, "short");
+        assert!(matches!(failures[0].1, IMGTError::CDR3TooShort(2)));
+    }
+
+    #[test]
+    fn test_number_light_chains() {
+        // A kappa chain with a long CDR1 and a lambda chain with a long CDR2, see
+        // the IMGT gaps of light chains in `numbering`.
+        let kappa = "Homo_sapiens_IGKV2-28*01_IGKJ1*01";
+        let lambda = "Homo_sapiens_IGLV5-37*01_IGLJ2*01";
+        let ref_seqs: ReferenceIndex = parse_reference_alignments(EMBEDDED_STOCKHOLM, &[])
+            .into_iter()
+            .filter(|(id, _)| [kappa, lambda].contains(&id.as_str()))
+            .collect();
+        assert_eq!(ref_seqs.len(), 2);
+
+        let region_numbers = |name: &str, first: usize, last: usize| {
+            let numbering = imgt::try_number(
+                imgt::query::Query::new("query", &ref_seqs[name].get_sequence()),
+                &ref_seqs,
+                &Default::default(),
+            )
+            .unwrap();
+            numbering
+                .into_iter()
+                .map(|annotation| annotation.name)
+                .filter(|number| {
+                    number
+                        .parse()
+                        .is_ok_and(|number: usize| (first..=last).contains(&number))
+                })
+                .collect::<Vec<_>>()
+        };
+        // CDR1 of 11 residues and CDR2 of 3.
+        assert_eq!(
+            region_numbers(kappa, 27, 38),
+            ["27", "28", "29", "30", "31", "32", "34", "35", "36", "37", "38"]
+        );
+        assert_eq!(region_numbers(kappa, 56, 65), ["56", "57", "65"]);
+        // CDR1 of 9 residues and CDR2 of 7.
+        assert_eq!(
+            region_numbers(lambda, 27, 38),
+            ["27", "28", "29", "30", "31", "35", "36", "37", "38"]
+        );
+        assert_eq!(
+            region_numbers(lambda, 56, 65),
+            ["56", "57", "58", "59", "63", "64", "65"]
+        );
+    }
+
+    #[test]
+    fn test_numbering_skips_fr1_deletion() {
+        let name = "Homo_sapiens_TRAV30*01_TRAJ24*01";
+        let ref_seqs: ReferenceIndex = parse_reference_alignments(EMBEDDED_STOCKHOLM, &[])
+            .into_iter()
+            .filter(|(id, _)| id == name)
+            .collect();
+        let reference = &ref_seqs[name];
+        assert_eq!(
+            reference.get_missing_positions_in_framework(&imgt::Framework::FR1),
+            [5]
+        );
+
+        // The germline with a substitution after the deletion (QSP -> QSA, position 8).
+        let mut sequence = reference.get_sequence();
+        sequence[6] = b'A';
+        let numbering = imgt::try_number(
+            imgt::query::Query::new("query", &sequence),
+            &ref_seqs,
+            &Default::default(),
+        )
+        .unwrap();
+
+        let fr1: Vec<_> = numbering
+            .iter()
+            .take_while(|annotation| annotation.name != imgt::CDR1_START.to_string())
+            .collect();
+        assert!(fr1.iter().all(|annotation| annotation.name != "5"));
+        assert_eq!(fr1.len(), 25);
+        // Every residue keeps the number of its germline column, instead of shifting after 5.
+        assert!(fr1.iter().all(|annotation| {
+            reference.get_sequence_position(annotation.name.parse().unwrap())
+                == Some(annotation.start + 1)
+        }));
+    }
+
+    #[test]
+    fn test_gene_family() {
+        for (gene, family) in [
+            ("IGHV1-2*02", Some("IGHV1")),
+            ("IGHV3-23D*01", Some("IGHV3")),
+            ("IGKV1D-12*01", Some("IGKV1")),
+            ("IGLV10-54*01", Some("IGLV10")),
+            ("TRAV30*01", Some("TRAV30")),
+            ("IGHV1/OR15-1*01", Some("IGHV1")),
+            ("IGHV", None),
+            ("test", None),
+            ("", None),
+        ] {
+            assert_eq!(gene_family(gene), family, "{}", gene);
+        }
+        let reference = named_reference("Homo_sapiens_IGHV1-18*01_IGHJ6*01");
+        assert_eq!(reference.gene_family(), Some("IGHV1"));
+        let reference = test_reference();
+        assert_eq!(reference.gene_family(), None);
+    }
+
+    #[test]
+    fn test_stitch_references() {
+        let embedded = parse_reference_alignments(EMBEDDED_STOCKHOLM, &[]);
+        let ref_seqs: ReferenceIndex = embedded
+            .iter()
+            .filter(|(id, _)| {
+                [
+                    "Homo_sapiens_IGHV1-18*01_IGHJ6*01",
+                    "Homo_sapiens_IGHV1-2*01_IGHJ2*01",
+                ]
+                .contains(&id.as_str())
+            })
+            .map(|(id, reference)| (id.clone(), reference.clone()))
+            .collect();
+        let stitched = stitch_references(&ref_seqs, &j_genes(&ref_seqs));
+        assert_eq!(stitched.len(), 4);
+        // Stitching gives the same germline as the curated pairing.
+        let name = "Homo_sapiens_IGHV1-18*01_IGHJ2*01";
+        assert_eq!(stitched[name], embedded[name]);
+
+        // IGHV1-18 with the FR4 of IGHJ2 (MDVWGQGTTVTVSS -> FDLWGRGTLVTVSS).
+        let sequence = TEST_SEQUENCE.replace("MDVWGQGTTVTVSS", "FDLWGRGTLVTVSS");
+        let germline_fr4 = |ref_seqs: &ReferenceIndex| {
+            let reference_alignment = imgt::find_best_reference_sequence(
+                imgt::query::Query::new("query", sequence.as_bytes()),
+                ref_seqs,
+            )
+            .unwrap();
+            let fr4 = &reference_alignment
+                .vregion_annotation()
+                .unwrap()
+                .framework_annotation
+                .fr4;
+            assert_eq!(&sequence[fr4.start..fr4.end], "WGRGTLVTVSS");
+            let germline =
+                &reference_alignment.reference.get_alignment()[imgt::FR4_START - 1..imgt::FR4_END];
+            (
+                reference_alignment.reference.name.clone(),
+                String::from_utf8(germline.to_vec()).unwrap(),
+            )
+        };
+        // Without stitching FR4 is aligned to the J gene of the closest V gene.
+        assert_eq!(
+            germline_fr4(&ref_seqs),
+            (
+                "Homo_sapiens_IGHV1-18*01_IGHJ6*01".to_string(),
+                "WGQGTTVTVSS".to_string()
+            )
+        );
+        assert_eq!(
+            germline_fr4(&stitched),
+            (name.to_string(), "WGRGTLVTVSS".to_string())
+        );
+    }
+
+    #[test]
+    fn test_stitch_separate_j_genes() {
+        // A V gene without a J gene in its name still has the germline FR4 of some J gene.
+        let v_refs = ReferenceIndex::from([(
+            "Homo_sapiens_IGHV1-18*01".to_string(),
+            named_reference("Homo_sapiens_IGHV1-18*01"),
+        )]);
+        // A J gene of which FR4 lacks 128, like those of light chains.
+        let j_genes = parse_j_gene_alignments(
+            "Homo_sapiens_IGHJ9*01 -YWYFDLWGRGTLVTV-S
+Homo_sapiens_IGHJ8*01 WGRG
+Homo_sapiens_IGKJ1*01 ----WTFGQGTKVEIK--
+",
+        );
+        assert_eq!(j_genes.len(), 2);
+        let stitched = stitch_references(&v_refs, &j_genes);
+        assert_eq!(
+            stitched.keys().sorted().collect::<Vec<_>>(),
+            [
+                "Homo_sapiens_IGHV1-18*01",
+                "Homo_sapiens_IGHV1-18*01_IGHJ9*01"
+            ]
+        );
+
+        // The FR4 of the sequence is followed by the start of the constant region.
+        let sequence = format!(
+            "{}DGGYWYFDLWGRGTLVTVSASTKGPS",
+            &TEST_SEQUENCE[..TEST_SEQUENCE.find("CAR").unwrap() + 3]
+        );
+        let fr4 = |ref_seqs: &ReferenceIndex| {
+            let fr4 = imgt::find_best_reference_sequence(
+                imgt::query::Query::new("query", sequence.as_bytes()),
+                ref_seqs,
+            )
+            .unwrap()
+            .vregion_annotation()
+            .unwrap()
+            .framework_annotation
+            .fr4;
+            sequence[fr4.start..fr4.end].to_string()
+        };
+        // FR4 ends at the length of the FR4 of the V gene, or of the J gene once stitched.
+        assert_eq!(fr4(&v_refs), "WGRGTLVTVSA");
+        assert_eq!(fr4(&stitched), "WGRGTLVTVS");
+    }
+
+    #[test]
+    fn test_missing_positions() {
+        let ref_seq = test_reference();
+        assert_eq!(ref_seq.missing_positions(), [10, 73]);
+
+        // Positions 2 and 3 missing from FR1 as well.
+        let gapped = format!("Q--{}", &TEST_ALIGNMENT_STR[3..]);
+        let ref_seq = ReferenceSequence::new("gapped", gapped.as_bytes()).unwrap();
+        assert_eq!(ref_seq.missing_positions(), [2, 3, 10, 73]);
+    }
+
     #[test]
     fn test_get_missing_positions_in_framework() {
-        let ref_seq = ReferenceSequence::new("test", TEST_ALIGNMENT_STR.as_bytes()).unwrap();
+        let ref_seq = test_reference();
 
         assert_eq!(
             ref_seq.get_missing_positions_in_framework(&imgt::Framework::FR1),
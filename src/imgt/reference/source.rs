@@ -0,0 +1,257 @@
+use std::path::{Path, PathBuf};
+
+use bio::io::fasta;
+use thiserror::Error;
+use tracing::warn;
+
+use super::{
+    duplicate_reference_ids, index::IndexErr, initialize_reference_sequences,
+    parse_j_gene_alignments, parse_reference_alignments_with_spec, JGeneIndex, ReferenceIndex,
+    ReferenceSequence, EMBEDDED_BLACKLIST,
+};
+use crate::imgt::conserved_residues::ConservedResidueSpec;
+
+/// Error thrown when loading reference sequences.
+#[derive(Debug, Error)]
+pub enum ReferenceSourceErr {
+    #[error("Could not read reference sequences: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("No valid reference sequences in '{0}'.")]
+    NoReferences(String),
+
+    #[error("Duplicate reference ids: {}", .0.join(", "))]
+    DuplicateIds(Vec<String>),
+
+    #[error(transparent)]
+    Index(#[from] IndexErr),
+
+    #[error("The consensus of the reference sequences lacks a conserved residue.")]
+    InvalidConsensus,
+}
+
+/// Somewhere reference sequences can be loaded from.
+pub trait ReferenceSource {
+    fn load(&self) -> Result<ReferenceIndex, ReferenceSourceErr>;
+}
+
+/// The curated reference sequences that are embedded in the binary.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EmbeddedReferences;
+
+impl ReferenceSource for EmbeddedReferences {
+    fn load(&self) -> Result<ReferenceIndex, ReferenceSourceErr> {
+        Ok(initialize_reference_sequences())
+    }
+}
+
+/// Reference sequences in a local file, in the same format as the embedded ones.
+#[derive(Clone, Debug)]
+pub struct FileReferences {
+    pub path: PathBuf,
+    /// Ids of references to skip.
+    pub blacklist: Vec<String>,
+    /// Fail on duplicate ids, instead of only warning about them.
+    pub strict: bool,
+    /// The residues the references must have at the conserved positions.
+    pub spec: ConservedResidueSpec,
+}
+
+impl FileReferences {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            blacklist: Vec::new(),
+            strict: false,
+            spec: ConservedResidueSpec::default(),
+        }
+    }
+
+    /// Check the conserved residues of the references against a custom spec.
+    pub fn with_spec(mut self, spec: ConservedResidueSpec) -> Self {
+        self.spec = spec;
+        self
+    }
+
+    /// Fail to load a file with duplicate ids, see [`duplicate_reference_ids`].
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    /// Skip the same references as the embedded reference sequences do.
+    pub fn with_embedded_blacklist(mut self) -> Self {
+        self.blacklist = EMBEDDED_BLACKLIST
+            .split_ascii_whitespace()
+            .map(str::to_string)
+            .collect();
+        self
+    }
+}
+
+impl ReferenceSource for FileReferences {
+    fn load(&self) -> Result<ReferenceIndex, ReferenceSourceErr> {
+        let data = std::fs::read_to_string(&self.path)?;
+        if self.strict {
+            let duplicates = duplicate_reference_ids(&data);
+            if !duplicates.is_empty() {
+                return Err(ReferenceSourceErr::DuplicateIds(
+                    duplicates.into_iter().map(str::to_string).collect(),
+                ));
+            }
+        }
+        let blacklist: Vec<_> = self.blacklist.iter().map(String::as_str).collect();
+        let references = parse_reference_alignments_with_spec(&data, &blacklist, &self.spec);
+        if references.is_empty() {
+            return Err(ReferenceSourceErr::NoReferences(
+                self.path.display().to_string(),
+            ));
+        }
+        Ok(references)
+    }
+}
+
+/// Load the J genes of a local file, see [`parse_j_gene_alignments`].
+pub fn load_j_genes(path: &Path) -> Result<JGeneIndex, ReferenceSourceErr> {
+    let j_genes = parse_j_gene_alignments(&std::fs::read_to_string(path)?);
+    if j_genes.is_empty() {
+        return Err(ReferenceSourceErr::NoReferences(path.display().to_string()));
+    }
+    Ok(j_genes)
+}
+
+/// Ungapped reference sequences in a FASTA file, see [`ReferenceSequence::from_record`].
+#[derive(Clone, Debug)]
+pub struct FastaReferences {
+    pub path: PathBuf,
+    /// The references the sequences are numbered with, the embedded ones when `None`.
+    pub frame: Option<ReferenceIndex>,
+}
+
+impl FastaReferences {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            frame: None,
+        }
+    }
+
+    /// Number the sequences with these references instead of the embedded ones.
+    pub fn with_frame(mut self, frame: ReferenceIndex) -> Self {
+        self.frame = Some(frame);
+        self
+    }
+}
+
+impl ReferenceSource for FastaReferences {
+    /// Sequences that cannot be numbered, or that have an insertion, are skipped with a warning.
+    fn load(&self) -> Result<ReferenceIndex, ReferenceSourceErr> {
+        let data = std::fs::read(&self.path)?;
+        let embedded;
+        let frame = match &self.frame {
+            Some(frame) => frame,
+            None => {
+                embedded = initialize_reference_sequences();
+                &embedded
+            }
+        };
+        let mut references = ReferenceIndex::new();
+        for record in fasta::Reader::new(data.as_slice()).records() {
+            let record = record?;
+            match ReferenceSequence::from_record(&record, frame) {
+                Ok(reference) => {
+                    references.insert(record.id().to_string(), reference);
+                }
+                Err(error) => warn!(
+                    id = record.id(),
+                    error = error.to_string(),
+                    "Skipping reference sequence."
+                ),
+            }
+        }
+        if references.is_empty() {
+            return Err(ReferenceSourceErr::NoReferences(
+                self.path.display().to_string(),
+            ));
+        }
+        Ok(references)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{is_selected_species, EMBEDDED_STOCKHOLM, SPECIES};
+    use super::*;
+
+    #[test]
+    fn test_file_and_embedded_sources_are_equivalent() {
+        let path = std::env::temp_dir().join(format!(
+            "numerotator-references-{}.stockholm",
+            std::process::id()
+        ));
+        std::fs::write(&path, EMBEDDED_STOCKHOLM).unwrap();
+        let from_file = FileReferences::new(&path)
+            .with_embedded_blacklist()
+            .load()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let embedded = EmbeddedReferences.load().unwrap();
+        // The species features only restrict the embedded references.
+        let from_file: ReferenceIndex = from_file
+            .into_iter()
+            .filter(|(id, _)| is_selected_species(id))
+            .collect();
+        assert_eq!(embedded, from_file);
+        if SPECIES.is_empty() {
+            assert!(!embedded.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_strict_duplicate_ids() {
+        let path = std::env::temp_dir().join(format!(
+            "numerotator-duplicates-{}.stockholm",
+            std::process::id()
+        ));
+        let alignment = EMBEDDED_STOCKHOLM.lines().next().unwrap();
+        std::fs::write(&path, format!("{alignment}\n{alignment}\n")).unwrap();
+        let lenient = FileReferences::new(&path).load();
+        let strict = FileReferences::new(&path).strict().load();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(lenient.unwrap().len(), 1);
+        assert!(matches!(strict, Err(ReferenceSourceErr::DuplicateIds(ids)) if ids.len() == 1));
+    }
+
+    #[test]
+    fn test_fasta_references() {
+        let path = std::env::temp_dir().join(format!(
+            "numerotator-references-{}.fasta",
+            std::process::id()
+        ));
+        let alignment = EMBEDDED_STOCKHOLM.lines().next().unwrap();
+        let (id, gapped) = alignment.split_once(char::is_whitespace).unwrap();
+        let sequence = gapped.trim().replace('-', "");
+        std::fs::write(&path, format!(">{id}\n{sequence}\n>invalid\nAAAA\n")).unwrap();
+        let frame = ReferenceIndex::from([(
+            id.to_string(),
+            ReferenceSequence::new(id, gapped.trim().as_bytes()).unwrap(),
+        )]);
+        let references = FastaReferences::new(&path).with_frame(frame).load();
+        std::fs::remove_file(&path).unwrap();
+
+        let references = references.unwrap();
+        assert_eq!(references.len(), 1);
+        assert_eq!(references[id].get_sequence(), sequence.as_bytes());
+        assert_eq!(references[id].name, id);
+    }
+
+    #[test]
+    fn test_missing_file() {
+        assert!(matches!(
+            FileReferences::new("does/not/exist.stockholm").load(),
+            Err(ReferenceSourceErr::Io(_))
+        ));
+    }
+}
@@ -0,0 +1,218 @@
+use std::borrow::Cow;
+
+use itertools::Itertools;
+
+/// Feature of the `#=GC` line that marks the match columns of the alignment.
+const REFERENCE_ANNOTATION_FEATURE: &str = "RF";
+
+/// The alignment lines and reference annotation of a Stockholm file.
+///
+/// Each alignment line is taken as a whole alignment, as in the curated alignments
+/// of ANARCI, so alignments interleaved over several blocks are not supported.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StockholmAlignment<'a> {
+    /// The id and the alignment of every alignment line, in order of the file.
+    pub alignments: Vec<(&'a str, &'a str)>,
+    /// The `#=GC RF` line, with a character per column, see [`is_match_column`].
+    ///
+    /// When a file has several, they are concatenated.
+    pub reference_annotation: Option<String>,
+}
+
+/// Whether a column of the `#=GC RF` line is a match state.
+///
+/// Match columns have a residue or `x`, insert columns a gap (`.`, `-` or `~`).
+pub fn is_match_column(annotation: u8) -> bool {
+    !matches!(annotation, b'.' | b'-' | b'~')
+}
+
+/// How the insert columns of a Stockholm file are recognised.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InsertStates {
+    /// Only by the `#=GC RF` line, see [`is_match_column`].
+    #[default]
+    Reference,
+    /// By the `#=GC RF` line, or without one by the residues: columns with only
+    /// lowercase residues and `.` are insert columns, as in the A2M format that
+    /// ANARCI and HMMER write.
+    Lowercase,
+}
+
+/// Whether a residue of an alignment is in an insert state, see [`InsertStates::Lowercase`].
+fn is_insert_residue(residue: u8) -> bool {
+    residue == b'.' || residue.is_ascii_lowercase()
+}
+
+/// Parse the alignment lines and the `#=GC RF` line of a Stockholm file.
+///
+/// Other markup (lines starting with `#`), the `//` terminator and empty lines
+/// are skipped, so a file of only alignment lines parses as well.
+pub fn parse_stockholm(stockholm_data: &str) -> StockholmAlignment<'_> {
+    let mut stockholm = StockholmAlignment::default();
+    for line in stockholm_data.lines() {
+        let mut fields = line.split_ascii_whitespace();
+        match fields.next() {
+            None | Some("//") => {}
+            Some("#=GC") => {
+                if let Some((REFERENCE_ANNOTATION_FEATURE, annotation)) = fields.next_tuple() {
+                    stockholm
+                        .reference_annotation
+                        .get_or_insert_with(String::new)
+                        .push_str(annotation);
+                }
+            }
+            Some(markup) if markup.starts_with('#') => {}
+            Some(id) => {
+                if let Some(alignment) = fields.next() {
+                    stockholm.alignments.push((id, alignment));
+                }
+            }
+        }
+    }
+    stockholm
+}
+
+impl<'a> StockholmAlignment<'a> {
+    /// Whether every column is a match state, or `None` without a `#=GC RF` line.
+    pub fn match_columns(&self) -> Option<Vec<bool>> {
+        self.match_columns_with(InsertStates::Reference)
+    }
+
+    /// Like [`Self::match_columns`], but recognise the insert columns as asked.
+    ///
+    /// With [`InsertStates::Lowercase`] and without a `#=GC RF` line, this is
+    /// `None` when no column is an insert column.
+    pub fn match_columns_with(&self, insert_states: InsertStates) -> Option<Vec<bool>> {
+        if let Some(annotation) = &self.reference_annotation {
+            return Some(annotation.bytes().map(is_match_column).collect());
+        }
+        if insert_states == InsertStates::Reference || self.alignments.is_empty() {
+            return None;
+        }
+        let width = self
+            .alignments
+            .iter()
+            .map(|(_id, alignment)| alignment.len())
+            .max()
+            .unwrap_or_default();
+        let match_columns: Vec<bool> = (0..width)
+            .map(|column| {
+                !self.alignments.iter().all(|(_id, alignment)| {
+                    alignment
+                        .as_bytes()
+                        .get(column)
+                        .is_none_or(|&residue| is_insert_residue(residue))
+                })
+            })
+            .collect();
+        (!match_columns.iter().all(|is_match| *is_match)).then_some(match_columns)
+    }
+
+    /// The alignments with only their match columns, see [`Self::match_columns`].
+    ///
+    /// The match columns are the IMGT positions, so residues in insert columns are
+    /// dropped, and gaps written as `.` become `-`. Without a `#=GC RF` line every
+    /// column is a match column and the alignments are left as they are.
+    pub fn match_state_alignments(&self) -> Vec<(&'a str, Cow<'a, str>)> {
+        self.match_state_alignments_with(InsertStates::Reference)
+    }
+
+    /// Like [`Self::match_state_alignments`], with the match columns of [`Self::match_columns_with`].
+    pub fn match_state_alignments_with(
+        &self,
+        insert_states: InsertStates,
+    ) -> Vec<(&'a str, Cow<'a, str>)> {
+        let Some(match_columns) = self.match_columns_with(insert_states) else {
+            return self
+                .alignments
+                .iter()
+                .map(|(id, alignment)| (*id, Cow::Borrowed(*alignment)))
+                .collect();
+        };
+        self.alignments
+            .iter()
+            .map(|(id, alignment)| {
+                let matched = alignment
+                    .chars()
+                    .zip(match_columns.iter().chain(std::iter::repeat(&true)))
+                    .filter(|(_residue, is_match)| **is_match)
+                    .map(|(residue, _is_match)| if residue == '.' { '-' } else { residue })
+                    .collect();
+                (*id, Cow::Owned(matched))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_stockholm() {
+        let stockholm = parse_stockholm(
+            "# STOCKHOLM 1.0\n#=GF ID test\n\nfirst  AC-.DE\nsecond AC-GDE\n#=GC RF xx.x\n#=GC RF xx\n//\n",
+        );
+        assert_eq!(
+            stockholm.alignments,
+            [("first", "AC-.DE"), ("second", "AC-GDE")]
+        );
+        assert_eq!(stockholm.reference_annotation.as_deref(), Some("xx.xxx"));
+        assert_eq!(
+            stockholm.match_columns(),
+            Some(vec![true, true, false, true, true, true])
+        );
+        let match_states: Vec<_> = stockholm
+            .match_state_alignments()
+            .into_iter()
+            .map(|(id, alignment)| (id, alignment.into_owned()))
+            .collect();
+        assert_eq!(
+            match_states,
+            [
+                ("first", "AC-DE".to_string()),
+                ("second", "ACGDE".to_string())
+            ]
+        );
+
+        let without_annotation = parse_stockholm("first AC-DE\n");
+        assert_eq!(without_annotation.match_columns(), None);
+        assert_eq!(
+            without_annotation.match_state_alignments(),
+            [("first", Cow::Borrowed("AC-DE"))]
+        );
+    }
+
+    #[test]
+    fn test_lowercase_insert_states() {
+        let stockholm = parse_stockholm("first ACd.E\nsecond AC.gE\nthird AC..E\n");
+        assert_eq!(stockholm.match_columns_with(InsertStates::Reference), None);
+        assert_eq!(
+            stockholm.match_columns_with(InsertStates::Lowercase),
+            Some(vec![true, true, false, false, true])
+        );
+        let match_states: Vec<_> = stockholm
+            .match_state_alignments_with(InsertStates::Lowercase)
+            .into_iter()
+            .map(|(id, alignment)| (id, alignment.into_owned()))
+            .collect();
+        assert_eq!(
+            match_states,
+            [
+                ("first", "ACE".to_string()),
+                ("second", "ACE".to_string()),
+                ("third", "ACE".to_string())
+            ]
+        );
+
+        // The `#=GC RF` line takes precedence.
+        let annotated = parse_stockholm("first ACd.E\n#=GC RF xxx.x\n");
+        assert_eq!(
+            annotated.match_columns_with(InsertStates::Lowercase),
+            Some(vec![true, true, true, false, true])
+        );
+        // Without lowercase residues or `.` every column is a match column.
+        let uppercase = parse_stockholm("first AC-E\n");
+        assert_eq!(uppercase.match_columns_with(InsertStates::Lowercase), None);
+    }
+}
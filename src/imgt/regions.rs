@@ -2,8 +2,10 @@ use bio::alignment::{Alignment, AlignmentOperation};
 
 use super::{
     annotations::{Annotation, CDRAnnotation, FrameworkAnnotation, VRegionAnnotation},
+    reference::ReferenceSequence,
     ConservedResidues, IMGTError,
 };
+use crate::imgt;
 
 // TODO: Find a better name than try from.
 // TODO: Should there be an option to not use an alignment?
@@ -11,63 +13,83 @@ use super::{
 impl FrameworkAnnotation {
     /// Try to create the framework annotations.
     ///
-    /// Alignment assumes that sequence x was an IMGT reference sequence.
+    /// Alignment assumes that sequence x was an IMGT reference sequence. The
+    /// reference is used to find how many residues lie between the conserved
+    /// residues and the region boundaries, since references can have gaps there.
     fn try_from(
         conserved_residues: &ConservedResidues,
         alignment: &Alignment,
+        reference: &ReferenceSequence,
     ) -> Result<Self, IMGTError> {
-        let v_region_start_position = alignment
-            .path()
-            .into_iter()
-            .find(|(x, _, op)| {
-                *x == 1
-                    && match op {
-                        AlignmentOperation::Xclip(_) => false,
-                        AlignmentOperation::Yclip(_) => false,
-                        _ => true,
-                    }
-            })
-            .expect("Alignment should contain first amino acid of reference sequence.")
-            .1;
-
+        // When the start of FR1 is missing from the query (see `Completeness`), the
+        // V-region starts wherever the aligned residues start. Gaps at the ends of a
+        // (semi)global alignment are not part of it. The path accounts for the clips
+        // of local and custom alignments, so this holds whatever the alignment mode,
+        // also for alignments without clips such as `ReferenceSequence::self_alignment`.
+        let path = alignment.path();
+        let mut aligned = path.iter().filter(|(_x, _y, op)| {
+            matches!(op, AlignmentOperation::Match | AlignmentOperation::Subst)
+        });
+        let first = aligned.next().ok_or(IMGTError::InvalidAlignment)?;
         // bio::alignment::Alignment::path uses 1 based indexing.
-        let v_region_start = v_region_start_position - 1;
-
-        let v_region_end = alignment
-            .path()
-            .into_iter()
-            .find(|(x, _, op)| {
-                *x == alignment.xend
-                    && match op {
-                        AlignmentOperation::Xclip(_) => false,
-                        AlignmentOperation::Yclip(_) => false,
-                        _ => true,
-                    }
-            })
-            .expect("Alignment should contain first amino acid of reference sequence.")
-            .1;
+        let v_region_start = first.1 - 1;
+        let mut v_region_end = aligned.next_back().unwrap_or(first).1;
+        // A J-Trp/Phe past the aligned residues was found by its motif, see
+        // `ConservedResidues::transfer`. FR4 then takes as many residues as in the reference.
+        if (v_region_end + 1..=alignment.ylen).contains(&conserved_residues.j_trp_or_phe) {
+            v_region_end = (conserved_residues.j_trp_or_phe - 1
+                + reference.count_residues_in_range(imgt::FR4_START..imgt::FR4_END + 1))
+            .min(alignment.ylen);
+        }
 
+        // Conserved residues are one based, annotations are zero based. Positions
+        // before the start of the query mean the conserved residues are wrong.
+        let before = |position: usize, count: usize, name: &str| {
+            (position - 1)
+                .checked_sub(count)
+                .ok_or_else(|| IMGTError::RegionOutOfRange(name.to_string()))
+        };
         let fr1 = Annotation {
             start: v_region_start,
-            end: conserved_residues.first_cys + 3,
+            end: conserved_residues.first_cys - 1
+                + reference.count_residues_in_range(23..imgt::CDR1_START),
             name: "FR1-IMGT".to_string(),
         };
         let fr2 = Annotation {
-            start: conserved_residues.conserved_trp - 2,
-            end: conserved_residues.conserved_trp + 14,
+            start: before(
+                conserved_residues.conserved_trp,
+                reference.count_residues_in_range(imgt::FR2_START..41),
+                "FR2-IMGT",
+            )?,
+            end: conserved_residues.conserved_trp - 1
+                + reference.count_residues_in_range(41..imgt::CDR2_START),
             name: "FR2-IMGT".to_string(),
         };
         let fr3 = Annotation {
-            start: conserved_residues.hydrophobic_89 - 23,
-            end: conserved_residues.second_cys,
+            start: before(
+                conserved_residues.hydrophobic_89,
+                reference.count_residues_in_range(imgt::FR3_START..89),
+                "FR3-IMGT",
+            )?,
+            end: conserved_residues.second_cys - 1
+                + reference.count_residues_in_range(104..imgt::CDR3_START),
             name: "FR3-IMGT".to_string(),
         };
+        // Without a J-Trp/Phe in the query (see `ConservedResidues::transfer_allowing_missing_j_trp_or_phe`)
+        // the CDR3 runs to the end of the V-region and FR4 is empty.
         let fr4 = Annotation {
-            start: conserved_residues.j_trp_or_phe,
+            start: (conserved_residues.j_trp_or_phe - 1).min(v_region_end),
             end: v_region_end,
             name: "FR4-IMGT".to_string(),
         };
 
+        if let Some(region) = [&fr1, &fr2, &fr3, &fr4]
+            .into_iter()
+            .find(|region| region.start > region.end)
+        {
+            return Err(IMGTError::RegionOutOfRange(region.name.clone()));
+        }
+
         if fr1.end > fr2.start {
             return Err(IMGTError::OverlappingRegions(fr1.name, fr2.name));
         }
@@ -126,8 +148,10 @@ impl VRegionAnnotation {
     pub fn try_from(
         conserved_residues: &ConservedResidues,
         alignment: &Alignment,
+        reference: &ReferenceSequence,
     ) -> Result<Self, IMGTError> {
-        let framework_annotation = FrameworkAnnotation::try_from(conserved_residues, alignment)?;
+        let framework_annotation =
+            FrameworkAnnotation::try_from(conserved_residues, alignment, reference)?;
         let cdr_annotation = CDRAnnotation::try_from(framework_annotation.clone())?;
         Ok(Self {
             framework_annotation,
@@ -135,3 +159,53 @@ impl VRegionAnnotation {
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::imgt::{
+        fixtures::test_reference,
+        reference::{initialize_reference_sequences, ReferenceSequence},
+    };
+
+    /// The regions of a reference hold exactly the residues of their IMGT positions.
+    fn assert_regions_follow_reference(reference: &ReferenceSequence) {
+        let annotation = reference.get_vregion_annotation();
+        let framework = &annotation.framework_annotation;
+        let cdrs = &annotation.cdr_annotation;
+        for (region, positions) in [
+            (&framework.fr1, 1..27),
+            (&cdrs.cdr1, 27..39),
+            (&framework.fr2, 39..56),
+            (&cdrs.cdr2, 56..66),
+            (&framework.fr3, 66..105),
+            (&cdrs.cdr3, 105..118),
+            (&framework.fr4, 118..129),
+        ] {
+            assert_eq!(
+                (region.start, region.end),
+                (
+                    reference.count_residues_in_range(1..positions.start),
+                    reference.count_residues_in_range(1..positions.end)
+                ),
+                "{} of {}",
+                region.name,
+                reference.name
+            );
+        }
+    }
+
+    #[test]
+    fn test_region_boundaries_with_gap_in_fr3() {
+        // The gap at 73 leaves 22 residues between the start of FR3 and the hydrophobic 89.
+        let reference = test_reference();
+        assert_eq!(reference.count_residues_in_range(66..89), 22);
+        assert_regions_follow_reference(&reference);
+    }
+
+    #[test]
+    fn test_region_boundaries_of_references() {
+        initialize_reference_sequences()
+            .values()
+            .for_each(assert_regions_follow_reference);
+    }
+}
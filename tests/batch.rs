@@ -0,0 +1,68 @@
+//! Number a large batch in parallel and compare it with numbering one record at a time.
+use numerotator::imgt::{
+    batch::{number_batch, BatchOptions},
+    engine::{number_into, NumberingEngine},
+    numbering::NumberingScheme,
+    output::OutputFormat,
+    query::Query,
+};
+
+mod common;
+use common::{test_ref_seqs, TEST_SEQUENCE};
+
+const AMINO_ACIDS: &[u8] = b"ACDEFGHIKLMNPQRSTVWY";
+
+/// Queries with a different residue at a varying position, where every tenth one cannot be numbered.
+fn queries(n: usize) -> Vec<Query> {
+    (0..n)
+        .map(|i| {
+            if i % 10 == 9 {
+                return Query::new(&i.to_string(), b"NOTANANTIBODY");
+            }
+            let mut sequence = TEST_SEQUENCE.as_bytes().to_vec();
+            let position = i % sequence.len();
+            sequence[position] = AMINO_ACIDS[i % AMINO_ACIDS.len()];
+            Query::new(&i.to_string(), &sequence)
+        })
+        .collect()
+}
+
+#[test]
+fn test_batch_keeps_input_order() {
+    let ref_seqs = test_ref_seqs();
+    let mut engine = NumberingEngine::new(&ref_seqs, NumberingScheme::default());
+    let n = 3000;
+
+    let mut expected = Vec::new();
+    let mut expected_failures = Vec::new();
+    for query in queries(n) {
+        let id = query.id().to_string();
+        if number_into(query, &mut engine, &mut expected, OutputFormat::Tsv).is_err() {
+            expected_failures.push(id);
+        }
+    }
+
+    let mut actual = Vec::new();
+    let mut failures = Vec::new();
+    let summary = number_batch(
+        queries(n),
+        &engine,
+        OutputFormat::Tsv,
+        &BatchOptions {
+            threads: 4,
+            chunk_size: 7,
+            capacity: 2,
+        },
+        &mut actual,
+        |failure| failures.push(failure.record.id().to_string()),
+    )
+    .unwrap();
+
+    assert_eq!(summary.numbered + summary.failed, n);
+    assert_eq!(summary.failed, expected_failures.len());
+    assert_eq!(failures, expected_failures);
+    assert_eq!(
+        String::from_utf8(actual).unwrap(),
+        String::from_utf8(expected).unwrap()
+    );
+}
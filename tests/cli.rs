@@ -0,0 +1,1209 @@
+//! Run the subcommands of the `numerotator` binary.
+use std::process::Command;
+
+mod common;
+use common::{diverged, temp_file, write_reference_file, TEST_ALIGNMENT_STR, TEST_SEQUENCE};
+
+fn numerotator(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_numerotator"))
+        .args(args)
+        .output()
+        .expect("Could not run numerotator.");
+    assert!(output.status.success(), "numerotator {:?} failed.", args);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_list_references() {
+    let path = temp_file(
+        "list-references.stockholm",
+        &format!("second {TEST_ALIGNMENT_STR}\nfirst {TEST_ALIGNMENT_STR}\n"),
+    );
+    let listed = numerotator(&["list-references", "--references", path.to_str().unwrap()]);
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(listed, "first\nsecond\n");
+    assert!(!numerotator(&["list-references"]).is_empty());
+    assert_eq!(
+        numerotator(&["list-refs"]),
+        numerotator(&["list-references"])
+    );
+}
+
+#[test]
+fn test_stats() {
+    let references = write_reference_file("stats.stockholm", &[]);
+    let stats = numerotator(&[
+        "stats",
+        "--references",
+        references.to_str().unwrap(),
+        TEST_SEQUENCE,
+        TEST_SEQUENCE,
+        "AAAA",
+    ]);
+    std::fs::remove_file(&references).unwrap();
+
+    assert_eq!(
+        stats,
+        "numbered\t2\nfailed\t1\nchain=IGH\t2\ncdr3_length=5\t2\n"
+    );
+}
+
+#[test]
+fn test_profile() {
+    let references = write_reference_file("profile.stockholm", &[]);
+    let output = Command::new(env!("CARGO_BIN_EXE_numerotator"))
+        .args([
+            "--references",
+            references.to_str().unwrap(),
+            "--threads",
+            "2",
+            "--profile",
+            TEST_SEQUENCE,
+            TEST_SEQUENCE,
+        ])
+        .output()
+        .expect("Could not run numerotator.");
+    std::fs::remove_file(&references).unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.lines().count(), 2 * TEST_SEQUENCE.len() * 2);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let stages: Vec<_> = stderr
+        .lines()
+        .skip_while(|line| *line != "stage\tseconds\tshare")
+        .skip(1)
+        .take_while(|line| !line.is_empty())
+        .map(|line| line.split('\t').next().unwrap())
+        .collect();
+    assert_eq!(
+        stages,
+        ["load_references", "alignment", "numbering", "output"]
+    );
+}
+
+#[test]
+fn test_profile_slowest() {
+    let references = write_reference_file("slowest.stockholm", &[]);
+    let profile = |slowest: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_numerotator"))
+            .args([
+                "--references",
+                references.to_str().unwrap(),
+                "--threads",
+                "2",
+                "--profile",
+                "--slowest",
+                slowest,
+                TEST_SEQUENCE,
+                TEST_SEQUENCE,
+                &TEST_SEQUENCE[..60],
+            ])
+            .output()
+            .expect("Could not run numerotator.");
+        assert!(output.status.success());
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .lines()
+            .skip_while(|line| *line != "sequence\tseconds")
+            .skip(1)
+            .map(|line| line.split('\t').next().unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+    let two = profile("2");
+    let mut all = profile("10");
+    std::fs::remove_file(&references).unwrap();
+
+    assert_eq!(two.len(), 2);
+    // Sequences that fail to number are timed as well.
+    all.sort();
+    assert_eq!(all, ["0", "1", "2"]);
+}
+
+#[test]
+fn test_number_lines() {
+    let references = temp_file("lines.stockholm", &format!("test {TEST_ALIGNMENT_STR}\n"));
+    let sequences = temp_file(
+        "lines.txt",
+        &format!("{TEST_SEQUENCE}\n\n{}\n", &TEST_SEQUENCE[6..]),
+    );
+    let numbered = numerotator(&[
+        "--references",
+        references.to_str().unwrap(),
+        "--sequences-file",
+        sequences.to_str().unwrap(),
+        "--input-format",
+        "lines",
+        "--format",
+        "tsv",
+    ]);
+    std::fs::remove_file(&references).unwrap();
+    std::fs::remove_file(&sequences).unwrap();
+
+    let ids: Vec<_> = numbered
+        .lines()
+        .map(|line| line.split('\t').next().unwrap())
+        .collect();
+    // The empty second line is skipped, but still counts for the ids.
+    assert_eq!(
+        ids.iter().filter(|id| **id == "0").count(),
+        TEST_SEQUENCE.len()
+    );
+    assert_eq!(
+        ids.iter().filter(|id| **id == "2").count(),
+        TEST_SEQUENCE.len() - 6
+    );
+    assert_eq!(ids.len(), 2 * TEST_SEQUENCE.len() - 6);
+}
+
+#[test]
+fn test_sample() {
+    let references = temp_file("sample.stockholm", &format!("test {TEST_ALIGNMENT_STR}\n"));
+    let sequences = temp_file(
+        "sample.fasta",
+        &format!(">a\n{TEST_SEQUENCE}\n>b\n{TEST_SEQUENCE}\n>c\n{TEST_SEQUENCE}\n"),
+    );
+    let numbered = numerotator(&[
+        "--references",
+        references.to_str().unwrap(),
+        "--sequences-file",
+        sequences.to_str().unwrap(),
+        "--sample",
+        "2",
+        "--format",
+        "tsv",
+        TEST_SEQUENCE,
+    ]);
+    std::fs::remove_file(&references).unwrap();
+    std::fs::remove_file(&sequences).unwrap();
+
+    // The sequence from the command line comes first.
+    let mut ids: Vec<_> = numbered
+        .lines()
+        .map(|line| line.split('\t').next().unwrap())
+        .collect();
+    ids.dedup();
+    assert_eq!(ids, ["0", "a"]);
+}
+
+#[test]
+fn test_number_airr() {
+    let references = write_reference_file("airr.stockholm", &[]);
+    let rearrangements = numerotator(&[
+        "--references",
+        references.to_str().unwrap(),
+        "--format",
+        "airr",
+        TEST_SEQUENCE,
+        TEST_SEQUENCE,
+    ]);
+    std::fs::remove_file(&references).unwrap();
+
+    let lines: Vec<Vec<_>> = rearrangements
+        .lines()
+        .map(|line| line.split('\t').collect())
+        .collect();
+    // A single header, followed by a line per sequence.
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0][0], "sequence_id");
+    let v_call = lines[0]
+        .iter()
+        .position(|field| *field == "v_call")
+        .unwrap();
+    assert!(lines[1..].iter().all(|line| line[v_call] == "IGHV1-18*01"));
+}
+
+#[test]
+fn test_id_template() {
+    let references = temp_file(
+        "id-template.stockholm",
+        &format!("test {TEST_ALIGNMENT_STR}\n"),
+    );
+    let numbered = numerotator(&[
+        "--references",
+        references.to_str().unwrap(),
+        "--id-template",
+        "{id}|{name}|{region}",
+        TEST_SEQUENCE,
+    ]);
+    std::fs::remove_file(&references).unwrap();
+
+    let ids: Vec<_> = numbered
+        .lines()
+        .filter_map(|line| line.strip_prefix('>'))
+        .map(|header| header.split(' ').next().unwrap())
+        .collect();
+    assert_eq!(ids.len(), TEST_SEQUENCE.len());
+    assert_eq!(ids[0], "0|1|FR1-IMGT");
+    assert!(ids.contains(&"0|104|FR3-IMGT"));
+    assert!(ids.contains(&"0|105|CDR3-IMGT"));
+}
+
+#[test]
+fn test_max_cdr3_length_failure() {
+    let references = temp_file(
+        "max-cdr3.stockholm",
+        &format!("test {TEST_ALIGNMENT_STR}\n"),
+    );
+    let long_cdr3 = TEST_SEQUENCE.replace("ARMDV", "ARGGGGGGGMDV");
+    let failures = numerotator(&[
+        "--references",
+        references.to_str().unwrap(),
+        "--max-cdr3-length",
+        "10",
+        "--only-failures",
+        TEST_SEQUENCE,
+        &long_cdr3,
+    ]);
+    std::fs::remove_file(&references).unwrap();
+
+    // Only the long CDR3 fails, with its length in the reason.
+    let headers: Vec<_> = failures
+        .lines()
+        .filter(|line| line.starts_with('>'))
+        .collect();
+    assert_eq!(headers.len(), 1);
+    assert!(headers[0].starts_with(">1 "));
+    assert!(headers[0].contains("CDR3 region of 12 residues"));
+}
+
+#[test]
+fn test_strict_anchors() {
+    let references = temp_file(
+        "strict-anchors.stockholm",
+        &format!("test {TEST_ALIGNMENT_STR}\n"),
+    );
+    let serine_104 = TEST_SEQUENCE.replace("YYCAR", "YYSAR");
+    let failures = |extra: &[&str]| {
+        let mut args = vec![
+            "--references",
+            references.to_str().unwrap(),
+            "--only-failures",
+        ];
+        args.extend(extra);
+        args.extend([TEST_SEQUENCE, serine_104.as_str()]);
+        numerotator(&args)
+    };
+    let lenient = failures(&[]);
+    let strict = failures(&["--strict-anchors"]);
+    std::fs::remove_file(&references).unwrap();
+
+    assert_eq!(lenient, "");
+    let headers: Vec<_> = strict
+        .lines()
+        .filter(|line| line.starts_with('>'))
+        .collect();
+    assert_eq!(headers.len(), 1);
+    assert!(headers[0].starts_with(">1 "));
+    assert!(headers[0].contains("Conserved residue 104 is S"));
+}
+
+#[test]
+fn test_number_regions() {
+    let references = temp_file("regions.stockholm", &format!("test {TEST_ALIGNMENT_STR}\n"));
+    let numbered = numerotator(&[
+        "--references",
+        references.to_str().unwrap(),
+        "--regions",
+        "CDR3,FR4",
+        "--format",
+        "tsv",
+        TEST_SEQUENCE,
+    ]);
+    std::fs::remove_file(&references).unwrap();
+
+    let residues: String = numbered
+        .lines()
+        .map(|line| line.split('\t').nth(4).unwrap())
+        .collect();
+    assert_eq!(residues, "ARMDVWGQGTTVTVSS");
+}
+
+#[test]
+fn test_deleted_positions() {
+    let references = temp_file("deleted.stockholm", &format!("test {TEST_ALIGNMENT_STR}\n"));
+    let numbered = numerotator(&[
+        "--references",
+        references.to_str().unwrap(),
+        "--deleted-positions",
+        "--format",
+        "tsv",
+        TEST_SEQUENCE,
+    ]);
+    std::fs::remove_file(&references).unwrap();
+
+    assert!(numbered.lines().all(|line| line
+        .split('\t')
+        .nth(5)
+        .unwrap()
+        .contains("deleted=10,31,32,33,34,60,61,73,108,")));
+}
+
+#[test]
+fn test_germline_gaps() {
+    let references = temp_file(
+        "germline_gaps.stockholm",
+        &format!("test {TEST_ALIGNMENT_STR}\n"),
+    );
+    let numbered = numerotator(&[
+        "--references",
+        references.to_str().unwrap(),
+        "--germline-gaps",
+        "--format",
+        "tsv",
+        TEST_SEQUENCE,
+    ]);
+    std::fs::remove_file(&references).unwrap();
+
+    assert!(numbered
+        .lines()
+        .all(|line| line.split('\t').nth(5).unwrap() == "germline_gaps=10,73"));
+
+    // The threaded output has no flags, so the gaps cannot be written there.
+    let rejected = Command::new(env!("CARGO_BIN_EXE_numerotator"))
+        .args(["--germline-gaps", "--threads", "2", TEST_SEQUENCE])
+        .output()
+        .unwrap();
+    assert_eq!(rejected.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&rejected.stderr).contains("--threads"));
+}
+
+#[test]
+fn test_cysteines_in_insertion_format() {
+    let references = write_reference_file("cysteines.stockholm", &[]);
+    let long_cdr3 = TEST_SEQUENCE.replace("ARMDV", "ARDRGCSSGWYCYYGMDV");
+    let cysteines = numerotator(&[
+        "--references",
+        references.to_str().unwrap(),
+        "--cysteines",
+        "--insertion-format",
+        "letter",
+        "--format",
+        "tsv",
+        &long_cdr3,
+    ]);
+    std::fs::remove_file(&references).unwrap();
+
+    let positions: Vec<_> = cysteines
+        .lines()
+        .map(|line| line.split('\t').nth(1).unwrap())
+        .collect();
+    assert_eq!(positions, ["23", "104", "110", "112A"]);
+}
+
+#[test]
+fn test_crlf_and_byte_order_mark() {
+    let references = temp_file("crlf.stockholm", &format!("test {TEST_ALIGNMENT_STR}\n"));
+    let clean = format!(
+        ">first\n{}\n{}\n>second\n{}\n",
+        &TEST_SEQUENCE[..60],
+        &TEST_SEQUENCE[60..],
+        TEST_SEQUENCE
+    );
+    let number = |name: &str, contents: &str| {
+        let sequences = temp_file(name, contents);
+        let numbered = numerotator(&[
+            "--references",
+            references.to_str().unwrap(),
+            "--sequences-file",
+            sequences.to_str().unwrap(),
+        ]);
+        std::fs::remove_file(&sequences).unwrap();
+        numbered
+    };
+    let numbered = number("clean.fasta", &clean);
+    let windows = number(
+        "windows.fasta",
+        &format!("\u{feff}{}", clean.replace('\n', "\r\n")),
+    );
+    std::fs::remove_file(&references).unwrap();
+
+    assert!(!numbered.is_empty());
+    assert_eq!(windows, numbered);
+}
+
+#[test]
+fn test_group_by_family() {
+    let references = write_reference_file("family.stockholm", &[]);
+    let counts = numerotator(&[
+        "--references",
+        references.to_str().unwrap(),
+        "--group-by",
+        "family",
+        TEST_SEQUENCE,
+        TEST_SEQUENCE,
+    ]);
+    std::fs::remove_file(&references).unwrap();
+
+    assert_eq!(counts, "IGHV1\t2\n");
+}
+
+#[test]
+fn test_cluster_cdr3() {
+    let references = write_reference_file("cluster-cdr3.stockholm", &[]);
+    // CDR3s of ARMDV, AKMDV (one substitution away) and ARGGYYYYYYYMDV.
+    let similar = TEST_SEQUENCE.replace("CARMDVW", "CAKMDVW");
+    let long = TEST_SEQUENCE.replace("CARMDVW", "CARGGYYYYYYYMDVW");
+    let clustered = numerotator(&[
+        "--references",
+        references.to_str().unwrap(),
+        "--cluster-cdr3",
+        "1",
+        "--format",
+        "tsv",
+        TEST_SEQUENCE,
+        &long,
+        &similar,
+    ]);
+    std::fs::remove_file(&references).unwrap();
+
+    let clonotypes: Vec<_> = clustered
+        .lines()
+        .map(|line| {
+            let fields: Vec<_> = line.split('\t').collect();
+            (fields[0], fields[4], fields[5])
+        })
+        .collect();
+    assert_eq!(
+        clonotypes,
+        [
+            ("0", "ARMDV", "clonotype=0"),
+            ("1", "ARGGYYYYYYYMDV", "clonotype=1"),
+            ("2", "AKMDV", "clonotype=0"),
+        ]
+    );
+}
+
+#[test]
+fn test_universal_reference() {
+    let references = write_reference_file(
+        "universal.stockholm",
+        &[("Homo_sapiens_IGHV1-18*02_IGHJ6*01", TEST_ALIGNMENT_STR)],
+    );
+    let references_arg = references.to_str().unwrap();
+    let listed = numerotator(&[
+        "list-refs",
+        "--references",
+        references_arg,
+        "--universal-reference",
+    ]);
+    let numbered = numerotator(&[
+        "--references",
+        references_arg,
+        "--universal-reference",
+        TEST_SEQUENCE,
+    ]);
+    std::fs::remove_file(&references).unwrap();
+
+    assert_eq!(listed, "consensus\n");
+    assert!(numbered.starts_with(">1_0 "));
+    assert!(numbered.contains("reference=consensus"));
+}
+
+#[test]
+fn test_j_genes() {
+    let references = temp_file(
+        "v-genes.stockholm",
+        &format!("Homo_sapiens_IGHV1-18*01 {TEST_ALIGNMENT_STR}\n"),
+    );
+    let j_genes = temp_file(
+        "j-genes.stockholm",
+        "Homo_sapiens_IGHJ2*01 -YWYFDLWGRGTLVTVSS\n",
+    );
+    let listed = numerotator(&[
+        "list-refs",
+        "--references",
+        references.to_str().unwrap(),
+        "--use-vj",
+        "--j-genes",
+        j_genes.to_str().unwrap(),
+    ]);
+    std::fs::remove_file(&references).unwrap();
+    std::fs::remove_file(&j_genes).unwrap();
+
+    assert_eq!(
+        listed,
+        "Homo_sapiens_IGHV1-18*01\nHomo_sapiens_IGHV1-18*01_IGHJ2*01\n"
+    );
+}
+
+#[test]
+fn test_hydrophobic_89() {
+    // A threonine instead of the hydrophobic 89 (TAYMELR -> TAYTELR).
+    let threonine_89 = TEST_ALIGNMENT_STR.replace("TAYMELR", "TAYTELR");
+    let references = temp_file("hydrophobic-89.stockholm", &format!("t89 {threonine_89}\n"));
+    let references_arg = references.to_str().unwrap();
+    let rejected = Command::new(env!("CARGO_BIN_EXE_numerotator"))
+        .args(["--references", references_arg, TEST_SEQUENCE])
+        .output()
+        .unwrap();
+    let accepted = numerotator(&[
+        "--references",
+        references_arg,
+        "--hydrophobic-89",
+        "AILMFWYVT",
+        TEST_SEQUENCE,
+    ]);
+    // The threonine is expected at 89 of queries as well, instead of moving 89 to the tyrosine before it.
+    let threonine_query = TEST_SEQUENCE.replace("TAYMELR", "TAYTELR");
+    let query = numerotator(&[
+        "--references",
+        references_arg,
+        "--hydrophobic-89",
+        "AILMFWYVT",
+        "--strict-anchors",
+        "--format",
+        "tsv",
+        &threonine_query,
+    ]);
+    std::fs::remove_file(&references).unwrap();
+
+    assert!(!rejected.status.success());
+    assert!(String::from_utf8_lossy(&rejected.stderr).contains("No valid reference sequences"));
+    assert!(accepted.contains("reference=t89"));
+    let rows: Vec<Vec<&str>> = query
+        .lines()
+        .map(|line| line.split('\t').collect())
+        .collect();
+    assert_eq!(rows.len(), threonine_query.len());
+    assert!(rows.iter().any(|row| row[1] == "89" && row[4] == "T"));
+}
+
+#[test]
+fn test_on_ambiguous_chain() {
+    let references = write_reference_file(
+        "ambiguous-chain.stockholm",
+        &[("Homo_sapiens_IGKV1-18*01_IGKJ6*01", TEST_ALIGNMENT_STR)],
+    );
+    let number = |policy: &str, extra: &[&str]| {
+        let mut args = vec![
+            "--references",
+            references.to_str().unwrap(),
+            "--on-ambiguous-chain",
+            policy,
+            "--format",
+            "tsv",
+        ];
+        args.extend(extra);
+        args.push(TEST_SEQUENCE);
+        numerotator(&args)
+    };
+    let both = number("both", &[]);
+    let failures = number("fail", &["--only-failures"]);
+    std::fs::remove_file(&references).unwrap();
+
+    let chains: Vec<_> = both
+        .lines()
+        .map(|line| line.split('\t').nth(5).unwrap())
+        .collect();
+    assert_eq!(chains.len(), 2 * TEST_SEQUENCE.len());
+    assert!(chains[..TEST_SEQUENCE.len()]
+        .iter()
+        .all(|flags| flags.contains("chain=IGH")));
+    assert!(chains[TEST_SEQUENCE.len()..]
+        .iter()
+        .all(|flags| flags.contains("chain=IGK")));
+    assert!(failures.contains("IGH, IGK"));
+}
+
+#[test]
+fn test_gene_functionality() {
+    let references = write_reference_file("gene-functionality.stockholm", &[]);
+    let functionality = temp_file("gene-functionality.tsv", "IGHV1-18\tP\nIGHJ6*01\tF\n");
+    let number = |extra: &[&str]| {
+        let mut args = vec![
+            "--references",
+            references.to_str().unwrap(),
+            "--gene-functionality",
+            functionality.to_str().unwrap(),
+            "--format",
+            "tsv",
+        ];
+        args.extend(extra);
+        args.push(TEST_SEQUENCE);
+        Command::new(env!("CARGO_BIN_EXE_numerotator"))
+            .args(&args)
+            .output()
+            .expect("Could not run numerotator.")
+    };
+    let warned = number(&[]);
+    let rejected = number(&["--on-nonfunctional", "reject", "--only-failures"]);
+    std::fs::remove_file(&references).unwrap();
+    std::fs::remove_file(&functionality).unwrap();
+
+    assert!(warned.status.success());
+    assert_eq!(
+        String::from_utf8(warned.stdout).unwrap().lines().count(),
+        TEST_SEQUENCE.len()
+    );
+    assert!(String::from_utf8(warned.stderr)
+        .unwrap()
+        .contains("not functional"));
+    assert!(String::from_utf8(rejected.stdout)
+        .unwrap()
+        .contains("IGHV1-18*01, which is a pseudogene"));
+}
+
+#[test]
+fn test_coordinate_base() {
+    let references = temp_file(
+        "coordinate-base.stockholm",
+        &format!("test {TEST_ALIGNMENT_STR}\n"),
+    );
+    let regions = |coordinate_base: &str| {
+        let numbered = numerotator(&[
+            "--references",
+            references.to_str().unwrap(),
+            "--annotate-regions",
+            "--no-number",
+            "--format",
+            "tsv",
+            "--coordinate-base",
+            coordinate_base,
+            TEST_SEQUENCE,
+        ]);
+        numbered
+            .lines()
+            .map(|line| {
+                let columns: Vec<_> = line.split('\t').collect();
+                (
+                    columns[1].to_string(),
+                    columns[2].parse::<usize>().unwrap(),
+                    columns[3].parse::<usize>().unwrap(),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    let zero_based = regions("0");
+    let one_based = regions("1");
+    std::fs::remove_file(&references).unwrap();
+
+    assert_eq!(zero_based.len(), 7);
+    assert_eq!(zero_based[0], ("FR1-IMGT".to_string(), 0, 25));
+    for (zero, one) in zero_based.iter().zip(&one_based) {
+        assert_eq!(
+            (zero.0.as_str(), zero.1 + 1, zero.2),
+            (one.0.as_str(), one.1, one.2)
+        );
+    }
+}
+
+#[test]
+fn test_detect_chimeras() {
+    let diverged = diverged(TEST_ALIGNMENT_STR);
+    let references = write_reference_file(
+        "chimera.stockholm",
+        &[("Homo_sapiens_IGHV3-23*01_IGHJ6*01", &diverged)],
+    );
+    let chimeric = format!(
+        "{}{}",
+        &TEST_SEQUENCE[..56],
+        &diverged.replace('-', "")[56..]
+    );
+    let numbered = numerotator(&[
+        "--references",
+        references.to_str().unwrap(),
+        "--detect-chimeras",
+        "--format",
+        "tsv",
+        TEST_SEQUENCE,
+        &chimeric,
+    ]);
+    std::fs::remove_file(&references).unwrap();
+
+    let flags = |id: &str| {
+        numbered
+            .lines()
+            .find(|line| line.starts_with(&format!("{id}\t")))
+            .unwrap()
+            .split('\t')
+            .nth(5)
+            .unwrap()
+            .to_string()
+    };
+    assert!(!flags("0").contains("chimera"));
+    assert!(flags("1").contains("chimera,chimera_n=IGHV1-18*01,chimera_c=IGHV3-23*01"));
+}
+
+#[test]
+fn test_detect_cdr_grafts() {
+    let diverged = diverged(TEST_ALIGNMENT_STR);
+    let references = write_reference_file(
+        "graft.stockholm",
+        &[("Homo_sapiens_IGHV3-23*01_IGHJ6*01", &diverged)],
+    );
+    // The CDR1 (IMGT 27 to 38) and CDR2 (IMGT 56 to 65) of the second germline.
+    let grafted = TEST_SEQUENCE
+        .replace("GYTFTSYG", &diverged[26..38].replace('-', ""))
+        .replace("ISAYNGNT", &diverged[55..65].replace('-', ""));
+    let numbered = numerotator(&[
+        "--references",
+        references.to_str().unwrap(),
+        "--detect-cdr-grafts",
+        "--format",
+        "tsv",
+        TEST_SEQUENCE,
+        &grafted,
+    ]);
+    std::fs::remove_file(&references).unwrap();
+
+    let flags = |id: &str| {
+        numbered
+            .lines()
+            .find(|line| line.starts_with(&format!("{id}\t")))
+            .unwrap()
+            .split('\t')
+            .nth(5)
+            .unwrap()
+            .to_string()
+    };
+    assert!(!flags("0").contains("cdr_graft"));
+    assert!(flags("1").contains("cdr_graft,graft_fr=IGHV1-18*01,graft_cdr=IGHV3-23*01"));
+}
+
+#[test]
+fn test_sort_by_v_gene() {
+    let diverged = diverged(TEST_ALIGNMENT_STR);
+    let references = write_reference_file(
+        "sort.stockholm",
+        &[("Homo_sapiens_IGHV3-23*01_IGHJ6*01", &diverged)],
+    );
+    let diverged_sequence = diverged.replace('-', "");
+    let numbered = numerotator(&[
+        "--references",
+        references.to_str().unwrap(),
+        "--sort-by",
+        "v_gene",
+        "--format",
+        "tsv",
+        &diverged_sequence,
+        TEST_SEQUENCE,
+        &diverged_sequence,
+        TEST_SEQUENCE,
+    ]);
+    std::fs::remove_file(&references).unwrap();
+
+    let mut ids: Vec<_> = numbered
+        .lines()
+        .map(|line| line.split('\t').next().unwrap())
+        .collect();
+    ids.dedup();
+    // IGHV1-18 before IGHV3-23, in the order of the input per gene.
+    assert_eq!(ids, ["1", "3", "0", "2"]);
+}
+
+#[test]
+fn test_format_collier() {
+    let references = write_reference_file("collier.stockholm", &[]);
+    let numbered = numerotator(&[
+        "--references",
+        references.to_str().unwrap(),
+        "--format",
+        "collier",
+        TEST_SEQUENCE,
+    ]);
+    std::fs::remove_file(&references).unwrap();
+
+    let residues: Vec<Vec<&str>> = numbered
+        .lines()
+        .map(|line| line.split('\t').take(5).collect())
+        .collect();
+    assert_eq!(residues.len(), TEST_SEQUENCE.len());
+    for expected in [
+        ["0", "1", "Q", "FR1", "A"],
+        ["0", "23", "C", "FR1", "B"],
+        ["0", "41", "W", "FR2", "C"],
+        ["0", "104", "C", "FR3", "F"],
+        ["0", "118", "W", "FR4", "G"],
+    ] {
+        assert!(residues.contains(&expected.to_vec()), "{expected:?}");
+    }
+}
+
+#[test]
+fn test_fasta_width() {
+    let references = temp_file(
+        "fasta-width.stockholm",
+        &format!("test {TEST_ALIGNMENT_STR}\n"),
+    );
+    let numbered = |width: &str| {
+        numerotator(&[
+            "--references",
+            references.to_str().unwrap(),
+            "--annotate-regions",
+            "--no-number",
+            "--fasta-width",
+            width,
+            TEST_SEQUENCE,
+        ])
+    };
+    let unwrapped = numbered("0");
+    let wrapped = numbered("10");
+    std::fs::remove_file(&references).unwrap();
+
+    assert!(unwrapped
+        .lines()
+        .any(|line| line == "QVQLVQSGAEVKKPGASVKVSCKAS"));
+    let sequence_lines: Vec<_> = wrapped
+        .lines()
+        .filter(|line| !line.starts_with('>'))
+        .collect();
+    assert!(sequence_lines.iter().all(|line| line.len() <= 10));
+    assert_eq!(sequence_lines[..3], ["QVQLVQSGAE", "VKKPGASVKV", "SCKAS"]);
+    assert_eq!(wrapped.replace('\n', ""), unwrapped.replace('\n', ""));
+}
+
+#[test]
+fn test_build_index() {
+    let references = temp_file(
+        "build-index.stockholm",
+        &format!("test {TEST_ALIGNMENT_STR}\n"),
+    );
+    let index = std::env::temp_dir().join(format!(
+        "numerotator-build-index-{}.idx",
+        std::process::id()
+    ));
+    numerotator(&[
+        "build-index",
+        index.to_str().unwrap(),
+        "--references",
+        references.to_str().unwrap(),
+    ]);
+    let from_references =
+        numerotator(&["--references", references.to_str().unwrap(), TEST_SEQUENCE]);
+    let from_index = numerotator(&["--index", index.to_str().unwrap(), TEST_SEQUENCE]);
+    let listed = numerotator(&["list-references", "--index", index.to_str().unwrap()]);
+    std::fs::remove_file(&references).unwrap();
+    std::fs::remove_file(&index).unwrap();
+
+    assert!(!from_references.is_empty());
+    assert_eq!(from_index, from_references);
+    assert_eq!(listed, "test\n");
+}
+
+#[test]
+fn test_liabilities() {
+    let references = temp_file(
+        "liabilities.stockholm",
+        &format!("test {TEST_ALIGNMENT_STR}\n"),
+    );
+    let numbered = numerotator(&[
+        "--references",
+        references.to_str().unwrap(),
+        "--liabilities",
+        "--format",
+        "tsv",
+        TEST_SEQUENCE,
+    ]);
+    std::fs::remove_file(&references).unwrap();
+
+    let lines: Vec<Vec<_>> = numbered
+        .lines()
+        .map(|line| line.split('\t').collect())
+        .collect();
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0][1], "62");
+    assert_eq!(lines[0][4], "NG");
+    assert!(lines[0][5].ends_with("liability=deamidation,region=CDR2"));
+}
+
+#[test]
+fn test_gapped_queries() {
+    let references = temp_file(
+        "gapped_queries.stockholm",
+        &format!("test {TEST_ALIGNMENT_STR}\n"),
+    );
+    let number = |extra_args: &[&str], sequence: &str| {
+        let mut args = vec![
+            "--references",
+            references.to_str().unwrap(),
+            "--format",
+            "tsv",
+        ];
+        args.extend_from_slice(extra_args);
+        args.push(sequence);
+        numerotator(&args)
+    };
+    let stripped = number(&[], TEST_ALIGNMENT_STR);
+    let ungapped = number(&[], TEST_SEQUENCE);
+    let pre_aligned = number(&["--gapped-queries", "pre-aligned"], TEST_ALIGNMENT_STR);
+    std::fs::remove_file(&references).unwrap();
+
+    assert_eq!(stripped, ungapped);
+    let positions: Vec<_> = pre_aligned
+        .lines()
+        .map(|line| line.split('\t').nth(1).unwrap())
+        .collect();
+    assert_eq!(positions.len(), TEST_SEQUENCE.len());
+    assert_eq!(positions[54], "62");
+    // The CDR3 is numbered by its columns, after the 8 gaps of the alignment.
+    assert_eq!(positions[98], "115");
+}
+
+#[test]
+fn test_band_width_empty_sequence() {
+    let references = temp_file(
+        "band_empty.stockholm",
+        &format!("test {TEST_ALIGNMENT_STR}\n"),
+    );
+    let sequences = temp_file(
+        "band_empty.fasta",
+        &format!(">empty\n\n>query\n{TEST_SEQUENCE}\n"),
+    );
+    let output = Command::new(env!("CARGO_BIN_EXE_numerotator"))
+        .args([
+            "--references",
+            references.to_str().unwrap(),
+            "--band-width",
+            "4",
+            "--format",
+            "tsv",
+            "-s",
+            sequences.to_str().unwrap(),
+        ])
+        .output()
+        .expect("Could not run numerotator.");
+    std::fs::remove_file(&references).unwrap();
+    std::fs::remove_file(&sequences).unwrap();
+
+    // The empty sequence fails to number, the other is numbered.
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.lines().all(|line| line.starts_with("query\t")));
+    assert_eq!(stdout.lines().count(), TEST_SEQUENCE.len());
+}
+
+#[test]
+fn test_empty_cdr2() {
+    // A germline without CDR2 residues, positions 56 to 65 are all gaps.
+    let alignment_str = format!(
+        "{}----------{}",
+        &TEST_ALIGNMENT_STR[..55],
+        &TEST_ALIGNMENT_STR[65..]
+    );
+    let sequence = alignment_str.replace('-', "");
+    let references = temp_file("empty_cdr2.stockholm", &format!("test {alignment_str}\n"));
+    let number = |extra_args: &[&str]| {
+        let mut args = vec!["--references", references.to_str().unwrap()];
+        args.extend_from_slice(extra_args);
+        args.push(&sequence);
+        numerotator(&args)
+    };
+    let outputs: Vec<_> = [
+        &["--format", "fasta"][..],
+        &["--format", "tsv"],
+        &["--format", "a2m"],
+        &["--format", "pair"],
+        &["--annotate-regions", "--format", "fasta"],
+        &["--gapped-regions", "--format", "tsv"],
+        &["--paratope"],
+    ]
+    .into_iter()
+    .map(number)
+    .collect();
+    let airr = number(&["--format", "airr"]);
+    std::fs::remove_file(&references).unwrap();
+
+    for output in &outputs {
+        assert!(!output.is_empty());
+        assert!(!output.contains("\n\n"), "Blank line in:\n{output}");
+    }
+    let header: Vec<_> = airr.lines().next().unwrap().split('\t').collect();
+    let row: Vec<_> = airr.lines().nth(1).unwrap().split('\t').collect();
+    let column = |name: &str| row[header.iter().position(|&column| column == name).unwrap()];
+    assert_eq!(column("cdr2_aa"), "");
+    assert_eq!(column("fwr2_aa"), "ISWVRQAPGQGLEWMGW");
+}
+
+#[test]
+fn test_extend_truncated_fr1() {
+    let references = temp_file(
+        "extend_truncated_fr1.stockholm",
+        &format!("test {TEST_ALIGNMENT_STR}\n"),
+    );
+    // Starts at position 5, with 5 and 6 diverged from the germline.
+    let truncated = format!("GG{}", &TEST_SEQUENCE[6..]);
+    let first_number = |extra_args: &[&str]| {
+        let mut args = vec![
+            "--references",
+            references.to_str().unwrap(),
+            "--format",
+            "tsv",
+        ];
+        args.extend_from_slice(extra_args);
+        args.push(&truncated);
+        let numbered = numerotator(&args);
+        numbered
+            .lines()
+            .next()
+            .unwrap()
+            .split('\t')
+            .nth(1)
+            .unwrap()
+            .to_string()
+    };
+    let aligned = first_number(&[]);
+    let extended = first_number(&["--extend-truncated-fr1"]);
+    std::fs::remove_file(&references).unwrap();
+
+    assert_eq!(aligned, "7");
+    assert_eq!(extended, "5");
+}
+
+#[test]
+fn test_columns() {
+    let references = write_reference_file("columns.stockholm", &[]);
+    let table = numerotator(&[
+        "--references",
+        references.to_str().unwrap(),
+        "--columns",
+        "id,v_gene,cdr3,identity",
+        "--delimiter",
+        ",",
+        TEST_SEQUENCE,
+        TEST_SEQUENCE,
+    ]);
+    std::fs::remove_file(&references).unwrap();
+
+    assert_eq!(
+        table,
+        "id,v_gene,cdr3,identity\n0,IGHV1-18*01,ARMDV,1.000\n1,IGHV1-18*01,ARMDV,1.000\n"
+    );
+
+    let unknown = Command::new(env!("CARGO_BIN_EXE_numerotator"))
+        .args(["--columns", "id,cdr4", TEST_SEQUENCE])
+        .output()
+        .unwrap();
+    assert!(!unknown.status.success());
+    assert!(String::from_utf8_lossy(&unknown.stderr).contains("cdr4"));
+}
+
+#[test]
+fn test_fasta_names_reference() {
+    let references = write_reference_file("fasta_names_reference.stockholm", &[]);
+    let number = |format: &str| {
+        numerotator(&[
+            "--references",
+            references.to_str().unwrap(),
+            "--format",
+            format,
+            TEST_SEQUENCE,
+        ])
+    };
+    let fasta = number("fasta");
+    let tsv = number("tsv");
+    std::fs::remove_file(&references).unwrap();
+
+    let header = fasta.lines().next().unwrap();
+    assert!(
+        header.ends_with(" reference=Homo_sapiens_IGHV1-18*01_IGHJ6*01,score=112"),
+        "{header}"
+    );
+    assert!(!tsv.contains("reference="));
+}
+
+#[test]
+fn test_on_duplicate_id() {
+    let references = temp_file(
+        "duplicate_id.stockholm",
+        &format!("test {TEST_ALIGNMENT_STR}\n"),
+    );
+    let sequences = temp_file(
+        "duplicate_id.fasta",
+        &format!(">query\n{TEST_SEQUENCE}\n>query\n{TEST_SEQUENCE}\n"),
+    );
+    let args = |policy: &str| {
+        vec![
+            "--references".to_string(),
+            references.to_str().unwrap().to_string(),
+            "--sequences-file".to_string(),
+            sequences.to_str().unwrap().to_string(),
+            "--format".to_string(),
+            "tsv".to_string(),
+            "--on-duplicate-id".to_string(),
+            policy.to_string(),
+        ]
+    };
+    let ids = |policy: &str| {
+        let args = args(policy);
+        let numbered = numerotator(&args.iter().map(String::as_str).collect::<Vec<_>>());
+        let mut ids: Vec<_> = numbered
+            .lines()
+            .map(|line| line.split('\t').next().unwrap().to_string())
+            .collect();
+        ids.dedup();
+        ids
+    };
+    let renamed = ids("rename");
+    let allowed = ids("allow");
+    let failed = Command::new(env!("CARGO_BIN_EXE_numerotator"))
+        .args(args("fail"))
+        .output()
+        .unwrap();
+    std::fs::remove_file(&references).unwrap();
+    std::fs::remove_file(&sequences).unwrap();
+
+    assert_eq!(renamed, ["query", "query_2"]);
+    assert_eq!(allowed, ["query"]);
+    assert!(!failed.status.success());
+}
+
+#[test]
+fn test_n_terminal_marker() {
+    let references = temp_file(
+        "n-terminal-marker.stockholm",
+        &format!("test {TEST_ALIGNMENT_STR}\n"),
+    );
+    let modified = format!("<{}", &TEST_SEQUENCE[1..]);
+    let numbered = numerotator(&[
+        "--references",
+        references.to_str().unwrap(),
+        "--format",
+        "tsv",
+        &modified,
+    ]);
+    std::fs::remove_file(&references).unwrap();
+
+    let first: Vec<_> = numbered.lines().next().unwrap().split('\t').collect();
+    assert_eq!(first[1..5], ["1", "0", "1", "<"]);
+    assert_eq!(first[5], "modified_n_terminus");
+
+    for marker in ["é", "ZZ", "-"] {
+        let rejected = Command::new(env!("CARGO_BIN_EXE_numerotator"))
+            .args(["--n-terminal-marker", marker, TEST_SEQUENCE])
+            .output()
+            .unwrap();
+        assert_eq!(rejected.status.code(), Some(2), "{marker}");
+        assert!(String::from_utf8_lossy(&rejected.stderr).contains("--n-terminal-marker"));
+    }
+}
+
+#[test]
+fn test_cdr3_only() {
+    let references = temp_file(
+        "cdr3-only.stockholm",
+        &format!("test {TEST_ALIGNMENT_STR}\n"),
+    );
+    let cdr3_only = |extra: &[&str]| {
+        let mut args = vec![
+            "--references",
+            references.to_str().unwrap(),
+            "--format",
+            "tsv",
+            "--cdr3-only",
+        ];
+        args.extend_from_slice(extra);
+        args.push(TEST_SEQUENCE);
+        numerotator(&args)
+    };
+    let by_motifs = cdr3_only(&[]);
+    // The thresholds can only be checked on an alignment.
+    let by_numbering = cdr3_only(&["--min-identity", "0.5"]);
+    std::fs::remove_file(&references).unwrap();
+
+    let by_motifs: Vec<_> = by_motifs.trim_end().split('\t').collect();
+    let by_numbering: Vec<_> = by_numbering.trim_end().split('\t').collect();
+    assert_eq!(by_motifs[1..5], ["CDR3-IMGT", "96", "101", "ARMDV"]);
+    assert_eq!(by_motifs[5], "cdr3_by=motifs");
+    assert_eq!(by_numbering[1..5], by_motifs[1..5]);
+    assert_eq!(by_numbering[5], "cdr3_by=numbering");
+}
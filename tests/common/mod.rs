@@ -0,0 +1,31 @@
+//! The fixtures of the unit tests, see `src/imgt/fixtures.rs`, and helpers to write
+//! them to files. Not every test uses all of them.
+#![allow(dead_code)]
+use std::path::PathBuf;
+
+pub use numerotator::imgt::reference;
+
+#[path = "../../src/imgt/fixtures.rs"]
+mod fixtures;
+
+pub use fixtures::*;
+
+/// The name of the test reference in reference files, with its V and J gene.
+pub const TEST_REFERENCE_NAME: &str = "Homo_sapiens_IGHV1-18*01_IGHJ6*01";
+
+/// Write a temporary file for a test, named after the test so tests do not clash.
+pub fn temp_file(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("numerotator-{}-{}", name, std::process::id()));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+/// Write a temporary reference file with the test alignment as [`TEST_REFERENCE_NAME`],
+/// followed by the `others` (ids with their IMGT-gapped alignment).
+pub fn write_reference_file(name: &str, others: &[(&str, &str)]) -> PathBuf {
+    let mut contents = format!("{TEST_REFERENCE_NAME} {TEST_ALIGNMENT_STR}\n");
+    for (id, alignment) in others {
+        contents.push_str(&format!("{id} {alignment}\n"));
+    }
+    temp_file(name, &contents)
+}
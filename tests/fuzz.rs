@@ -0,0 +1,146 @@
+//! Number random and randomly mutated sequences, which should never panic.
+use std::collections::HashMap;
+
+use numerotator::imgt::{
+    annotations::apply_annotation,
+    find_best_reference_sequence_with_mode,
+    input::{read_records, InputFormat},
+    new_aligner, new_banded_aligner,
+    numbering::{check_unique_positions, Cdr3Definition, NumberingScheme},
+    output::{gapped_regions, CoordinateBase},
+    query::Query,
+    reference::ReferenceSequence,
+    try_number, AlignmentMode,
+};
+
+mod common;
+use common::{test_ref_seqs, TEST_SEQUENCE};
+
+const AMINO_ACIDS: &[u8] = b"ACDEFGHIKLMNPQRSTVWY";
+
+/// Small deterministic pseudo random number generator (xorshift), so failures are reproducible.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// Random bytes, random amino acids or the test sequence with random insertions, deletions and substitutions.
+fn random_sequence(rng: &mut Rng) -> Vec<u8> {
+    match rng.below(3) {
+        0 => (0..rng.below(200)).map(|_| rng.next() as u8).collect(),
+        1 => (0..rng.below(200))
+            .map(|_| AMINO_ACIDS[rng.below(AMINO_ACIDS.len())])
+            .collect(),
+        _ => {
+            let mut sequence = TEST_SEQUENCE.as_bytes().to_vec();
+            for _ in 0..rng.below(20) {
+                let position = rng.below(sequence.len() + 1);
+                let residue = AMINO_ACIDS[rng.below(AMINO_ACIDS.len())];
+                match rng.below(3) {
+                    0 => sequence.insert(position, residue),
+                    1 if position < sequence.len() => {
+                        sequence.remove(position);
+                    }
+                    _ if position < sequence.len() => sequence[position] = residue,
+                    _ => {}
+                }
+            }
+            sequence
+        }
+    }
+}
+
+/// Number a query in every way, which should not panic. Whether it was numbered.
+fn number_everything(
+    i: usize,
+    query: Query,
+    ref_seqs: &HashMap<String, ReferenceSequence>,
+) -> bool {
+    let scheme = NumberingScheme {
+        cdr3_definition: if i % 3 == 0 {
+            Cdr3Definition::Junction
+        } else {
+            Cdr3Definition::Imgt
+        },
+        allow_missing_fr4: i % 2 == 0,
+        ..Default::default()
+    };
+    let numbered = try_number(query.clone(), ref_seqs, &scheme).is_ok();
+
+    // Everything that is produced along the way, in any alignment mode and with either
+    // aligner, can be used without panicking as well.
+    let mode = [
+        AlignmentMode::Local,
+        AlignmentMode::Semiglobal,
+        AlignmentMode::Global,
+    ][(i / 3) % 3];
+    let reference_alignment = if i % 4 == 0 {
+        find_best_reference_sequence_with_mode(query, ref_seqs, &mut new_banded_aligner(4), mode)
+    } else {
+        find_best_reference_sequence_with_mode(query, ref_seqs, &mut new_aligner(), mode)
+    };
+    let Ok(reference_alignment) = reference_alignment else {
+        return numbered;
+    };
+    let record = &reference_alignment.query_record;
+    reference_alignment.flags();
+    let Ok(vregion_annotation) = reference_alignment.vregion_annotation_for(&scheme) else {
+        return numbered;
+    };
+    vregion_annotation.imgt_junction(record.seq());
+    let regions = vregion_annotation.region_annotations(scheme.cdr3_definition);
+    regions.iter().for_each(|region| {
+        apply_annotation(record, region, CoordinateBase::Zero);
+    });
+    if let Ok(numbering) = vregion_annotation.number_regions(&reference_alignment, &scheme) {
+        check_unique_positions(&numbering).unwrap();
+        numbering.iter().for_each(|number| {
+            apply_annotation(record, number, CoordinateBase::Zero);
+        });
+        gapped_regions(record, &regions, &numbering, scheme.cdr3_definition);
+    }
+    numbered
+}
+
+#[test]
+fn test_numbering_random_sequences_does_not_panic() {
+    let ref_seqs = test_ref_seqs();
+    let mut rng = Rng(0x5eed);
+    let mut numbered = 0;
+    let mut rejected = 0;
+
+    for i in 0..5000usize {
+        // The raw bytes go through the reader like an input file, so bytes that are not
+        // UTF-8 (or newlines and '>' that split the record) are handled as in the binary.
+        let (input, format) = if i % 5 == 0 {
+            (random_sequence(&mut rng), InputFormat::Lines)
+        } else {
+            let mut input = format!(">{i}\n").into_bytes();
+            input.extend(random_sequence(&mut rng));
+            (input, InputFormat::Fasta)
+        };
+        for record in read_records(std::io::Cursor::new(input), format) {
+            let Ok(record) = record else {
+                rejected += 1;
+                continue;
+            };
+            if number_everything(i, Query::from(record), &ref_seqs) {
+                numbered += 1;
+            }
+        }
+    }
+    // Make sure the mutated sequences get through the whole pipeline, and that the
+    // random bytes include some that are rejected by the reader.
+    assert!(numbered > 100, "Only {numbered} sequences were numbered.");
+    assert!(rejected > 0, "No sequences were rejected.");
+}
@@ -0,0 +1,97 @@
+//! Golden file tests for the numbering.
+//!
+//! Every FASTA file in `tests/data/inputs/` is numbered with the default scheme and
+//! compared against the TSV file with the same name in `tests/data/expected/`.
+//! Run with `NUMEROTATOR_REGENERATE_GOLDENS=1` to overwrite the expected files with
+//! the current output.
+use std::path::Path;
+
+use bio::io::fasta;
+use numerotator::imgt::{
+    annotations::Annotation,
+    find_best_reference_sequence,
+    numbering::NumberingScheme,
+    reference::{initialize_reference_sequences, ReferenceIndex},
+};
+
+const REGENERATE_VAR: &str = "NUMEROTATOR_REGENERATE_GOLDENS";
+
+fn number_record(
+    record: fasta::Record,
+    ref_seqs: &ReferenceIndex,
+) -> Result<Vec<Annotation>, anyhow::Error> {
+    Ok(find_best_reference_sequence(record, ref_seqs)?.numbered(&NumberingScheme::default())?)
+}
+
+/// Number all records in a FASTA file, one line per numbered residue.
+fn numbering_tsv(path: &Path, ref_seqs: &ReferenceIndex) -> String {
+    fasta::Reader::from_file(path)
+        .expect("Could not open input file.")
+        .records()
+        .map(|record| record.expect("Could not parse input record."))
+        .flat_map(|record| {
+            let id = record.id().to_string();
+            let seq = record.seq().to_vec();
+            match number_record(record, ref_seqs) {
+                Ok(annotations) => annotations
+                    .into_iter()
+                    .map(|annotation| {
+                        format!(
+                            "{}\t{}\t{}\n",
+                            id,
+                            annotation.name,
+                            std::str::from_utf8(&seq[annotation.start..annotation.end]).unwrap()
+                        )
+                    })
+                    .collect(),
+                Err(error) => vec![format!("{}\terror\t{}\n", id, error)],
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn test_golden_numbering() {
+    let data_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+    let regenerate = std::env::var_os(REGENERATE_VAR).is_some();
+    let ref_seqs = initialize_reference_sequences();
+
+    let mut inputs: Vec<_> = std::fs::read_dir(data_dir.join("inputs"))
+        .expect("Could not read inputs directory.")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "fasta"))
+        .collect();
+    inputs.sort();
+    assert!(!inputs.is_empty(), "No golden inputs found.");
+
+    let mismatches: Vec<_> = inputs
+        .iter()
+        .filter_map(|input| {
+            let expected_path = data_dir
+                .join("expected")
+                .join(input.with_extension("tsv").file_name().unwrap());
+            let actual = numbering_tsv(input, &ref_seqs);
+
+            if regenerate {
+                std::fs::write(&expected_path, actual).expect("Could not write golden file.");
+                return None;
+            }
+
+            let expected = std::fs::read_to_string(&expected_path).unwrap_or_else(|_| {
+                panic!(
+                    "Missing golden file {}, run with {}=1 to create it.",
+                    expected_path.display(),
+                    REGENERATE_VAR
+                )
+            });
+            (expected != actual).then(|| input.display().to_string())
+        })
+        .collect();
+
+    assert!(
+        mismatches.is_empty(),
+        "Numbering differs from the golden files for {:?}. If this is intended, run with {}=1.",
+        mismatches,
+        REGENERATE_VAR
+    );
+}
@@ -0,0 +1,99 @@
+//! Compare the streaming `number_into` with numbering and writing in separate steps.
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use bio::io::fasta;
+use numerotator::imgt::{
+    engine::{number_into, NumberingEngine},
+    find_best_reference_sequence,
+    numbering::NumberingScheme,
+    output::{write_annotations, OutputFormat},
+    reference::ReferenceIndex,
+};
+
+mod common;
+use common::{test_ref_seqs, TEST_SEQUENCE};
+
+/// Counts the number of allocations, so both paths can be compared.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn record() -> fasta::Record {
+    fasta::Record::with_attrs("test", None, TEST_SEQUENCE.as_bytes())
+}
+
+fn number_and_write(ref_seqs: &ReferenceIndex, format: OutputFormat, out: &mut Vec<u8>) {
+    let reference_alignment = find_best_reference_sequence(record(), ref_seqs).unwrap();
+    let annotations = reference_alignment
+        .vregion_annotation()
+        .unwrap()
+        .number_regions(&reference_alignment, &NumberingScheme::default())
+        .unwrap();
+    // FASTA records name the matched reference, as in the output of the command line.
+    let reference_fields = if format.is_fasta() {
+        reference_alignment.reference_fields().to_vec()
+    } else {
+        vec![]
+    };
+    let mut flags = reference_alignment.flags();
+    flags.extend(reference_fields.iter().map(String::as_str));
+    write_annotations(
+        &reference_alignment.query_record,
+        &annotations,
+        &flags,
+        format,
+        out,
+    )
+    .unwrap();
+}
+
+// Allocations are counted globally, so this is deliberately a single test.
+#[test]
+fn test_number_into_matches_and_allocates_less() {
+    let ref_seqs = test_ref_seqs();
+    let mut engine = NumberingEngine::new(&ref_seqs, NumberingScheme::default());
+
+    for format in [OutputFormat::Fasta, OutputFormat::Tsv] {
+        let mut expected = Vec::new();
+        number_and_write(&ref_seqs, format, &mut expected);
+        let mut actual = Vec::new();
+        number_into(record(), &mut engine, &mut actual, format).unwrap();
+        assert_eq!(
+            String::from_utf8(expected).unwrap(),
+            String::from_utf8(actual).unwrap()
+        );
+    }
+
+    // Both outputs are preallocated, so only the numbering itself is counted.
+    let mut out = Vec::with_capacity(1 << 16);
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    number_and_write(&ref_seqs, OutputFormat::Fasta, &mut out);
+    let allocating = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+    out.clear();
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    number_into(record(), &mut engine, &mut out, OutputFormat::Fasta).unwrap();
+    let streaming = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+    assert!(
+        streaming < allocating,
+        "number_into allocates {streaming} times, the allocating path {allocating} times."
+    );
+}
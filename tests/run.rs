@@ -0,0 +1,141 @@
+//! Run the command line in memory, see [`run`].
+use clap::Parser;
+use numerotator::cli::{run, Cli, RunError, RunStats};
+
+mod common;
+use common::{write_reference_file, TEST_SEQUENCE};
+
+/// Run the command line with the given arguments, returning its result and output.
+fn run_with(args: &[&str]) -> (Result<RunStats, RunError>, String) {
+    let (result, out, _err) = run_with_err(args);
+    (result, out)
+}
+
+/// Like [`run_with`], but also return what is written to stderr.
+fn run_with_err(args: &[&str]) -> (Result<RunStats, RunError>, String, String) {
+    let cli =
+        Cli::try_parse_from(std::iter::once("numerotator").chain(args.iter().copied())).unwrap();
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let result = run(cli, &mut out, &mut err);
+    (
+        result,
+        String::from_utf8(out).unwrap(),
+        String::from_utf8(err).unwrap(),
+    )
+}
+
+#[test]
+fn test_run() {
+    let references = write_reference_file("run.stockholm", &[]);
+    let references = references.to_str().unwrap();
+
+    let (result, out) = run_with(&[
+        "--references",
+        references,
+        "--columns",
+        "id,v_gene,cdr3",
+        TEST_SEQUENCE,
+        "AAAA",
+    ]);
+    assert_eq!(
+        result.unwrap(),
+        RunStats {
+            sequences: 2,
+            failures: 1
+        }
+    );
+    assert_eq!(out, "id\tv_gene\tcdr3\n0\tIGHV1-18*01\tARMDV\n");
+
+    let (result, out) = run_with(&["list-refs", "--references", references]);
+    assert_eq!(result.unwrap(), RunStats::default());
+    assert_eq!(out, "Homo_sapiens_IGHV1-18*01_IGHJ6*01\n");
+
+    let (result, _out) = run_with(&["--references", "does/not/exist.stockholm", TEST_SEQUENCE]);
+    assert!(matches!(result, Err(RunError::References(_))));
+    std::fs::remove_file(references).unwrap();
+
+    let (result, _out) = run_with(&["--threads", "1", "--format", "airr", TEST_SEQUENCE]);
+    assert!(matches!(result, Err(RunError::Usage(_))));
+    for format in ["airr", "a2m", "pair"] {
+        let (result, out) = run_with(&["--format", format, "--annotate-regions", TEST_SEQUENCE]);
+        assert!(matches!(result, Err(RunError::Usage(_))));
+        assert_eq!(out, "");
+    }
+    for option in [
+        "--cdr3-only",
+        "--annotate-regions",
+        "--cysteines",
+        "--liabilities",
+        "--paratope",
+        "--gapped-regions",
+        "--cluster-cdr3=1",
+    ] {
+        let (result, _out) = run_with(&["--format", "collier", option, TEST_SEQUENCE]);
+        assert!(matches!(result, Err(RunError::Usage(_))), "{option}");
+    }
+    // These write no IMGT positions, so their insertions cannot be formatted.
+    for option in ["--gapped-regions", "--germline-frame", "--run-length"] {
+        assert!(
+            Cli::try_parse_from(["numerotator", "--insertion-format", "letter", option, "A"])
+                .is_err(),
+            "{option}"
+        );
+    }
+    for format in ["airr", "a2m", "pair"] {
+        let (result, out) = run_with(&[
+            "--format",
+            format,
+            "--insertion-format",
+            "letter",
+            TEST_SEQUENCE,
+        ]);
+        assert!(matches!(result, Err(RunError::Usage(_))), "{format}");
+        assert_eq!(out, "");
+    }
+    // Each of these replaces the numbering, so only one can be written.
+    let outputs = [
+        "--gapped-regions",
+        "--germline-frame",
+        "--run-length",
+        "--cysteines",
+        "--liabilities",
+    ];
+    for (i, first) in outputs.iter().enumerate() {
+        for second in &outputs[i + 1..] {
+            assert!(
+                Cli::try_parse_from(["numerotator", first, second, "A"]).is_err(),
+                "{first} {second}"
+            );
+        }
+    }
+}
+
+#[test]
+fn test_show_alignment() {
+    let references = write_reference_file("show-alignment.stockholm", &[]);
+    let show = |show_alignment: &str| {
+        let (result, out, err) = run_with_err(&[
+            "--references",
+            references.to_str().unwrap(),
+            show_alignment,
+            "--alignment-width",
+            "60",
+            TEST_SEQUENCE,
+            TEST_SEQUENCE,
+        ]);
+        result.unwrap();
+        // The numbering is written as without --show-alignment.
+        assert!(out.starts_with(">1_0 "));
+        err
+    };
+    let all = show("--show-alignment");
+    let second = show("--show-alignment=1");
+    std::fs::remove_file(references).unwrap();
+
+    assert!(all.starts_with("# 0 aligned to Homo_sapiens_IGHV1-18*01_IGHJ6*01\n"));
+    assert!(all.contains("# 1 aligned to"));
+    assert!(all.lines().any(|line| line == "|".repeat(60)));
+    assert!(second.starts_with("# 1 aligned to"));
+    assert!(!second.contains("# 0 aligned to"));
+}